@@ -0,0 +1,82 @@
+//! Run two sensor hubs side by side for redundancy, one at each i2c
+//! address (SA0 low and high) of a Linux i2c bus:
+//!
+//! `cargo run --example linux_dual_imu --features linux -- /dev/i2c-1`
+//!
+//! Each hub has its own driver, with its own sequence numbers and
+//! decoded reports. The orientation is taken from whichever hub is still
+//! reporting, and the disagreement between the two is printed while both
+//! are healthy. Hubs on two buses work the same, with a second path.
+
+use bno080::interface::{I2cAddress, I2cInterface};
+use bno080::math::Quaternion;
+use bno080::wrapper::BNO080;
+use embedded_hal::delay::DelayNs;
+use linux_embedded_hal::{Delay, I2cdev};
+
+/// Interval between rotation vector reports (milliseconds)
+const REPORT_INTERVAL_MS: u16 = 50;
+
+fn open_imu(
+    path: &str,
+    address: I2cAddress,
+    delay: &mut Delay,
+) -> BNO080<I2cInterface<I2cdev>> {
+    let interface = I2cInterface::open(path, address)
+        .unwrap_or_else(|e| panic!("cannot open {}: {}", path, e));
+    let mut imu = BNO080::new_with_interface(interface);
+    imu.init(delay)
+        .unwrap_or_else(|e| panic!("init at {:?} failed: {}", address, e));
+    imu.enable_rotation_vector(REPORT_INTERVAL_MS)
+        .expect("cannot enable the rotation vector");
+    imu
+}
+
+/// Angle between two orientations (degrees)
+fn disagreement_degrees(a: &Quaternion, b: &Quaternion) -> f32 {
+    let dot = a.i * b.i + a.j * b.j + a.k * b.k + a.real * b.real;
+    (2.0 * dot.abs().min(1.0).acos()).to_degrees()
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let first_path = args.next().unwrap_or_else(|| "/dev/i2c-1".into());
+    let second_path = args.next().unwrap_or_else(|| first_path.clone());
+
+    let mut delay = Delay;
+    let mut imus = [
+        open_imu(&first_path, I2cAddress::Default, &mut delay),
+        open_imu(&second_path, I2cAddress::Alternate, &mut delay),
+    ];
+
+    loop {
+        let mut healthy = [false; 2];
+        for (imu, alive) in imus.iter_mut().zip(healthy.iter_mut()) {
+            imu.handle_all_messages(&mut delay, 10);
+            *alive = !imu.reports_stale(u32::from(REPORT_INTERVAL_MS), 50)
+                && imu.latest_rotation_vector().is_some();
+        }
+
+        match healthy {
+            [true, true] => {
+                let first = imus[0].quaternion();
+                let second = imus[1].quaternion();
+                println!(
+                    "yaw {:7.2} (degrees), hubs disagree by {:5.2} degrees",
+                    imus[0].euler_angles().yaw.to_degrees(),
+                    disagreement_degrees(&first, &second)
+                );
+            }
+            [true, false] | [false, true] => {
+                let index = if healthy[0] { 0 } else { 1 };
+                println!(
+                    "yaw {:7.2} (degrees), from hub {} only",
+                    imus[index].euler_angles().yaw.to_degrees(),
+                    index
+                );
+            }
+            [false, false] => println!("no hub is reporting"),
+        }
+        delay.delay_ms(50);
+    }
+}
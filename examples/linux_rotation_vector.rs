@@ -0,0 +1,42 @@
+//! Print the rotation vector of a sensor hub on a Linux i2c bus,
+//! e.g. on a Raspberry Pi:
+//!
+//! `cargo run --example linux_rotation_vector --features linux -- /dev/i2c-1`
+//!
+//! A hub strapped to the alternate address (SA0 high) is selected with
+//! `0x4b` after the bus.
+
+use bno080::interface::{I2cAddress, I2cInterface};
+use bno080::wrapper::BNO080;
+use embedded_hal::delay::DelayNs;
+use linux_embedded_hal::Delay;
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let path = args.next().unwrap_or_else(|| "/dev/i2c-1".into());
+    let address = match args.next().as_deref() {
+        None | Some("0x4a") | Some("0x4A") => I2cAddress::Default,
+        Some("0x4b") | Some("0x4B") => I2cAddress::Alternate,
+        Some(other) => panic!("unknown address {}: use 0x4a or 0x4b", other),
+    };
+    let interface = I2cInterface::open(&path, address)
+        .unwrap_or_else(|e| panic!("cannot open {}: {}", path, e));
+
+    let mut delay = Delay;
+    let mut imu = BNO080::new_with_interface(interface);
+    imu.init(&mut delay).expect("sensor hub init failed");
+    imu.enable_rotation_vector(50)
+        .expect("cannot enable the rotation vector");
+
+    loop {
+        imu.handle_all_messages(&mut delay, 10);
+        let angles = imu.euler_angles();
+        println!(
+            "roll {:7.2} pitch {:7.2} yaw {:7.2} (degrees)",
+            angles.roll.to_degrees(),
+            angles.pitch.to_degrees(),
+            angles.yaw.to_degrees()
+        );
+        delay.delay_ms(50);
+    }
+}
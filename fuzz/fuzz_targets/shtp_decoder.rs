@@ -0,0 +1,53 @@
+//! Feeds arbitrary packets through the driver and the report decoders.
+//!
+//! The input is a sequence of packets, each preceded by its length as two
+//! little-endian bytes. The corpus holds packets captured from a BNO080.
+//!
+//! `cargo fuzz run shtp_decoder`
+
+#![no_main]
+
+use bno080::capture::{Direction, PacketRecord, ReplayInterface};
+use bno080::interface::i2c::NoDelay;
+use bno080::reports::InputReports;
+use bno080::wrapper::BNO080;
+use libfuzzer_sys::fuzz_target;
+
+/// Length of the SHTP packet header
+const HEADER_LENGTH: usize = 4;
+
+fuzz_target!(|data: &[u8]| {
+    let mut trace = Vec::new();
+    let mut rest = data;
+    while rest.len() >= 2 {
+        let len = usize::from(u16::from_le_bytes([rest[0], rest[1]]))
+            .min(rest.len() - 2);
+        let packet = &rest[2..2 + len];
+        rest = &rest[2 + len..];
+        trace.push(PacketRecord {
+            direction: Direction::Received,
+            timestamp: 0,
+            bytes: packet,
+        });
+
+        // the report decoders on their own
+        if let Some(payload) = packet.get(HEADER_LENGTH..) {
+            let mut reports = InputReports::new(payload);
+            for report in reports.by_ref() {
+                let _ = report.decode();
+            }
+            let _ = reports.remaining();
+        }
+    }
+
+    for validation in [false, true] {
+        let mut hub = BNO080::new_with_interface(ReplayInterface::new(&trace));
+        hub.set_packet_validation(validation);
+        while !hub.sensor_interface().is_finished() {
+            hub.handle_one_message(&mut NoDelay, 1);
+        }
+        while hub.next_report().is_some() {}
+        let _ = hub.euler_angles();
+        let _ = hub.check_sequence();
+    }
+});
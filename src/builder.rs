@@ -0,0 +1,248 @@
+/*
+Copyright (c) 2020 Todd Stellanova
+LICENSE: BSD3 (see LICENSE file)
+*/
+
+//! Declaring the configuration of the driver up front, and bringing it up
+//! in one step with `Bno080Builder::build_and_init`.
+//!
+//! The sizes of the packet buffers are set at compile time, with
+//! `Bno080Builder::buffer_sizes`.
+
+use crate::interface::{I2cAddress, I2cInterface, SensorInterface};
+use crate::math::Frame;
+use crate::wrapper::{
+    FeatureConfig, MillisClock, RetryPolicy, Timeouts, UnknownReportHook,
+    WrapperError, BNO080, PACKET_RECV_BUF_LEN, PACKET_SEND_BUF_LEN,
+};
+
+use embedded_hal::delay::DelayNs;
+
+/// Maximum number of reports that can be enabled through the builder
+pub const BUILDER_MAX_REPORTS: usize = 8;
+
+/// Collects the configuration of a `BNO080` driver
+pub struct Bno080Builder<
+    SI,
+    const SEND_BUF_LEN: usize = PACKET_SEND_BUF_LEN,
+    const RECV_BUF_LEN: usize = PACKET_RECV_BUF_LEN,
+> {
+    sensor_interface: SI,
+    retry_policy: RetryPolicy,
+    timeouts: Timeouts,
+    unknown_report_hook: Option<UnknownReportHook>,
+    clock: Option<MillisClock>,
+    packet_validation: bool,
+    output_frame: Frame,
+    reports: heapless::Vec<(u8, FeatureConfig), BUILDER_MAX_REPORTS>,
+    /// more than `BUILDER_MAX_REPORTS` reports were declared
+    too_many_reports: bool,
+}
+
+impl<SI> Bno080Builder<SI> {
+    pub fn new(sensor_interface: SI) -> Self {
+        Self {
+            sensor_interface,
+            retry_policy: RetryPolicy::NONE,
+            timeouts: Timeouts::DEFAULT,
+            unknown_report_hook: None,
+            clock: None,
+            packet_validation: false,
+            output_frame: Frame::Enu,
+            reports: heapless::Vec::new(),
+            too_many_reports: false,
+        }
+    }
+}
+
+impl<SI, const SEND_BUF_LEN: usize, const RECV_BUF_LEN: usize>
+    Bno080Builder<SI, SEND_BUF_LEN, RECV_BUF_LEN>
+{
+    /// Sizes of the packet send and receive buffers, in place of
+    /// `PACKET_SEND_BUF_LEN` and `PACKET_RECV_BUF_LEN`
+    pub fn buffer_sizes<const SEND_LEN: usize, const RECV_LEN: usize>(
+        self,
+    ) -> Bno080Builder<SI, SEND_LEN, RECV_LEN> {
+        Bno080Builder {
+            sensor_interface: self.sensor_interface,
+            retry_policy: self.retry_policy,
+            timeouts: self.timeouts,
+            unknown_report_hook: self.unknown_report_hook,
+            clock: self.clock,
+            packet_validation: self.packet_validation,
+            output_frame: self.output_frame,
+            reports: self.reports,
+            too_many_reports: self.too_many_reports,
+        }
+    }
+
+    /// How transient communication errors are retried
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// How long to wait for the sensor hub to answer, during
+    /// initialization and for command responses
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Called for input reports whose ID this crate does not know
+    pub fn unknown_report_hook(mut self, hook: UnknownReportHook) -> Self {
+        self.unknown_report_hook = Some(hook);
+        self
+    }
+
+    /// Measure timeouts with this clock
+    pub fn clock(mut self, clock: MillisClock) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Check each packet received for consistency, discarding corrupt ones
+    pub fn packet_validation(mut self, enabled: bool) -> Self {
+        self.packet_validation = enabled;
+        self
+    }
+
+    /// Coordinate frame of the decoded outputs
+    pub fn output_frame(mut self, frame: Frame) -> Self {
+        self.output_frame = frame;
+        self
+    }
+
+    /// Enable a report once the sensor hub is initialized.
+    /// If more than `BUILDER_MAX_REPORTS` reports are declared, the
+    /// driver is not built, with `WrapperError::TooManyReports`.
+    pub fn report(mut self, report_id: u8, config: FeatureConfig) -> Self {
+        self.too_many_reports |=
+            self.reports.push((report_id, config)).is_err();
+        self
+    }
+
+    /// Enable a report at a fixed interval (milliseconds)
+    pub fn report_every_ms(self, report_id: u8, millis: u16) -> Self {
+        self.report_every_us(report_id, u32::from(millis) * 1000)
+    }
+
+    /// Enable a report at a fixed interval (microseconds)
+    pub fn report_every_us(self, report_id: u8, micros: u32) -> Self {
+        self.report(
+            report_id,
+            FeatureConfig {
+                report_interval_us: micros,
+                ..FeatureConfig::default()
+            },
+        )
+    }
+}
+
+impl<I2C> Bno080Builder<I2cInterface<I2C>> {
+    /// Communicate over i2c, at one of the standard addresses
+    pub fn i2c(i2c: I2C, address: I2cAddress) -> Self {
+        Self::new(I2cInterface::with_address(i2c, address))
+    }
+}
+
+impl<SI, SE, const SEND_BUF_LEN: usize, const RECV_BUF_LEN: usize>
+    Bno080Builder<SI, SEND_BUF_LEN, RECV_BUF_LEN>
+where
+    SI: SensorInterface<SensorError = SE>,
+    SE: core::fmt::Debug,
+{
+    /// Create the driver, without initializing the sensor hub
+    pub fn build(
+        self,
+    ) -> Result<BNO080<SI, SEND_BUF_LEN, RECV_BUF_LEN>, WrapperError<SE>> {
+        if self.too_many_reports {
+            return Err(WrapperError::TooManyReports);
+        }
+        let mut driver = BNO080::new_with_buffers(self.sensor_interface);
+        driver.set_retry_policy(self.retry_policy);
+        driver.set_timeouts(self.timeouts);
+        driver.set_unknown_report_hook(self.unknown_report_hook);
+        driver.set_clock(self.clock);
+        driver.set_packet_validation(self.packet_validation);
+        driver.set_output_frame(self.output_frame);
+        Ok(driver)
+    }
+
+    /// Create the driver, initialize the sensor hub, and enable the
+    /// declared reports
+    pub fn build_and_init(
+        self,
+        delay: &mut impl DelayNs,
+    ) -> Result<BNO080<SI, SEND_BUF_LEN, RECV_BUF_LEN>, WrapperError<SE>> {
+        let reports = self.reports.clone();
+        let mut driver = self.build()?;
+        driver.init(delay)?;
+        for (report_id, config) in &reports {
+            driver.configure_report(*report_id, config)?;
+        }
+        Ok(driver)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interface::mock_i2c_port::{FakeDelay, FakeI2cPort};
+
+    #[test]
+    fn test_build_and_init() {
+        let mut port = FakeI2cPort::new();
+        // product ID response
+        let pid_response: [u8; 20] = [
+            20, 0, 2, 1, 0xF8, 0, 3, 2, 0xFE, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        port.add_available_packet(&pid_response[..4]);
+        port.add_available_packet(&pid_response);
+        let result = Bno080Builder::i2c(port, I2cAddress::Default)
+            .retry_policy(RetryPolicy {
+                max_attempts: 2,
+                initial_backoff_ms: 1,
+            })
+            .report_every_ms(0x05, 10)
+            .build_and_init(&mut FakeDelay {});
+        let driver = result.unwrap();
+        assert!(driver.is_report_enabled(0x05));
+        assert!(!driver.is_report_enabled(0x04));
+    }
+
+    #[test]
+    fn test_buffer_sizes_and_timeouts() {
+        let mut port = FakeI2cPort::new();
+        let pid_response: [u8; 20] = [
+            20, 0, 2, 1, 0xF8, 0, 3, 2, 0xFE, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        port.add_available_packet(&pid_response[..4]);
+        port.add_available_packet(&pid_response);
+        let driver = Bno080Builder::i2c(port, I2cAddress::Default)
+            .buffer_sizes::<64, 128>()
+            .build_and_init(&mut FakeDelay {})
+            .unwrap();
+        assert_eq!(driver.max_write_len(), 64);
+
+        // a hub that never answers
+        let result =
+            Bno080Builder::i2c(FakeI2cPort::new(), I2cAddress::Default)
+                .timeouts(Timeouts {
+                    init_advertise_ms: 5,
+                    init_product_id_ms: 5,
+                    ..Timeouts::DEFAULT
+                })
+                .build_and_init(&mut FakeDelay {});
+        assert!(matches!(result, Err(WrapperError::Timeout)));
+    }
+
+    #[test]
+    fn test_too_many_reports() {
+        let builder = (0..=BUILDER_MAX_REPORTS as u8).fold(
+            Bno080Builder::i2c(FakeI2cPort::new(), I2cAddress::Default),
+            |builder, report_id| builder.report_every_ms(report_id, 10),
+        );
+        assert!(matches!(builder.build(), Err(WrapperError::TooManyReports)));
+    }
+}
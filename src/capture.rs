@@ -0,0 +1,322 @@
+/*
+Copyright (c) 2020 Todd Stellanova
+LICENSE: BSD3 (see LICENSE file)
+*/
+
+//! Capture of raw SHTP traffic, and replay of captured traffic
+//! through the driver, for reproducing problems seen in the field.
+
+use crate::interface::SensorInterface;
+use crate::Error;
+
+use embedded_hal::delay::DelayNs;
+
+/// Direction of a captured packet, relative to the host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Direction {
+    /// Sent from the host to the sensor hub
+    Sent,
+    /// Received by the host from the sensor hub
+    Received,
+}
+
+/// One captured packet, including its SHTP header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PacketRecord<'a> {
+    pub direction: Direction,
+    /// Time of capture, in units chosen by the `PacketLogger`
+    pub timestamp: u32,
+    pub bytes: &'a [u8],
+}
+
+/// A sink for captured packets
+pub trait PacketLogger {
+    /// The current time, used to stamp each record
+    fn timestamp(&mut self) -> u32 {
+        0
+    }
+
+    /// Record one packet
+    fn log_packet(&mut self, record: &PacketRecord<'_>);
+}
+
+/// Wraps a sensor interface, recording all traffic through it
+/// into a `PacketLogger`
+pub struct CapturingInterface<SI, L> {
+    inner: SI,
+    logger: L,
+}
+
+impl<SI, L> CapturingInterface<SI, L>
+where
+    L: PacketLogger,
+{
+    pub fn new(inner: SI, logger: L) -> Self {
+        Self { inner, logger }
+    }
+
+    /// Release the wrapped interface and the logger
+    pub fn free(self) -> (SI, L) {
+        (self.inner, self.logger)
+    }
+
+    pub fn logger(&mut self) -> &mut L {
+        &mut self.logger
+    }
+
+    fn log(&mut self, direction: Direction, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        let timestamp = self.logger.timestamp();
+        self.logger.log_packet(&PacketRecord {
+            direction,
+            timestamp,
+            bytes,
+        });
+    }
+
+    fn log_received(&mut self, recv_buf: &[u8], received_len: usize) {
+        let len = received_len.min(recv_buf.len());
+        self.log(Direction::Received, &recv_buf[..len]);
+    }
+}
+
+impl<SI, L> SensorInterface for CapturingInterface<SI, L>
+where
+    SI: SensorInterface,
+    L: PacketLogger,
+{
+    type SensorError = SI::SensorError;
+
+    fn setup(
+        &mut self,
+        delay_source: &mut impl DelayNs,
+    ) -> Result<(), Self::SensorError> {
+        self.inner.setup(delay_source)
+    }
+
+    fn write_packet(&mut self, packet: &[u8]) -> Result<(), Self::SensorError> {
+        self.log(Direction::Sent, packet);
+        self.inner.write_packet(packet)
+    }
+
+    fn read_packet(
+        &mut self,
+        recv_buf: &mut [u8],
+    ) -> Result<usize, Self::SensorError> {
+        let received_len = self.inner.read_packet(recv_buf)?;
+        self.log_received(recv_buf, received_len);
+        Ok(received_len)
+    }
+
+    fn read_packet_with_timeout(
+        &mut self,
+        recv_buf: &mut [u8],
+        delay_source: &mut impl DelayNs,
+        max_ms: u8,
+    ) -> Result<usize, Self::SensorError> {
+        let received_len = self.inner.read_packet_with_timeout(
+            recv_buf,
+            delay_source,
+            max_ms,
+        )?;
+        self.log_received(recv_buf, received_len);
+        Ok(received_len)
+    }
+
+    fn send_and_receive_packet(
+        &mut self,
+        send_buf: &[u8],
+        recv_buf: &mut [u8],
+    ) -> Result<usize, Self::SensorError> {
+        self.log(Direction::Sent, send_buf);
+        let received_len =
+            self.inner.send_and_receive_packet(send_buf, recv_buf)?;
+        self.log_received(recv_buf, received_len);
+        Ok(received_len)
+    }
+
+    fn data_ready(&mut self) -> bool {
+        self.inner.data_ready()
+    }
+
+    fn requires_soft_reset(&self) -> bool {
+        self.inner.requires_soft_reset()
+    }
+}
+
+/// A sensor interface that plays back the received packets of a
+/// captured trace, so the trace can be fed through the driver.
+/// Sent packets in the trace are skipped, and packets written
+/// by the driver are discarded.
+pub struct ReplayInterface<'a> {
+    trace: &'a [PacketRecord<'a>],
+    cursor: usize,
+    requires_soft_reset: bool,
+}
+
+impl<'a> ReplayInterface<'a> {
+    pub fn new(trace: &'a [PacketRecord<'a>]) -> Self {
+        Self {
+            trace,
+            cursor: 0,
+            requires_soft_reset: false,
+        }
+    }
+
+    /// Mimic a transport that requires a soft reset during init,
+    /// such as i2c, so that `init` follows the same sequence as
+    /// when the trace was captured
+    pub fn with_soft_reset(mut self) -> Self {
+        self.requires_soft_reset = true;
+        self
+    }
+
+    /// Have all received packets in the trace been played back?
+    pub fn is_finished(&self) -> bool {
+        !self.trace[self.cursor..]
+            .iter()
+            .any(|record| record.direction == Direction::Received)
+    }
+
+    fn next_received(&mut self, recv_buf: &mut [u8]) -> usize {
+        while let Some(record) = self.trace.get(self.cursor) {
+            self.cursor += 1;
+            if record.direction == Direction::Received {
+                let len = record.bytes.len().min(recv_buf.len());
+                recv_buf[..len].copy_from_slice(&record.bytes[..len]);
+                return len;
+            }
+        }
+        0
+    }
+}
+
+impl SensorInterface for ReplayInterface<'_> {
+    type SensorError = Error<(), ()>;
+
+    fn setup(
+        &mut self,
+        _delay_source: &mut impl DelayNs,
+    ) -> Result<(), Self::SensorError> {
+        Ok(())
+    }
+
+    fn write_packet(
+        &mut self,
+        _packet: &[u8],
+    ) -> Result<(), Self::SensorError> {
+        Ok(())
+    }
+
+    fn read_packet(
+        &mut self,
+        recv_buf: &mut [u8],
+    ) -> Result<usize, Self::SensorError> {
+        Ok(self.next_received(recv_buf))
+    }
+
+    fn read_packet_with_timeout(
+        &mut self,
+        recv_buf: &mut [u8],
+        _delay_source: &mut impl DelayNs,
+        _max_ms: u8,
+    ) -> Result<usize, Self::SensorError> {
+        Ok(self.next_received(recv_buf))
+    }
+
+    fn send_and_receive_packet(
+        &mut self,
+        _send_buf: &[u8],
+        recv_buf: &mut [u8],
+    ) -> Result<usize, Self::SensorError> {
+        Ok(self.next_received(recv_buf))
+    }
+
+    fn requires_soft_reset(&self) -> bool {
+        self.requires_soft_reset
+    }
+}
+
+#[cfg(all(test, feature = "report-rotation"))]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::interface::i2c::DEFAULT_ADDRESS;
+    use crate::interface::mock_i2c_port::{FakeDelay, FakeI2cPort};
+    use crate::interface::I2cInterface;
+    use crate::wrapper::BNO080;
+    use std::vec::Vec;
+
+    struct VecLogger {
+        clock: u32,
+        records: Vec<(Direction, u32, Vec<u8>)>,
+    }
+
+    impl PacketLogger for VecLogger {
+        fn timestamp(&mut self) -> u32 {
+            self.clock += 1;
+            self.clock
+        }
+
+        fn log_packet(&mut self, record: &PacketRecord<'_>) {
+            self.records.push((
+                record.direction,
+                record.timestamp,
+                record.bytes.to_vec(),
+            ));
+        }
+    }
+
+    // a sensor report packet carrying a rotation vector
+    const ROTATION_VECTOR_PACKET: [u8; 23] = [
+        23, 0, 3, 0, // header
+        0xFB, 0, 0, 0, 0, // base timestamp
+        0x05, 0x01, 0x03, 0x00, // report header
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x20, 0x00, 0x20, 0x00, 0x08,
+    ];
+
+    #[test]
+    fn test_capture_and_replay() {
+        let mut port = FakeI2cPort::new();
+        port.add_available_packet(&ROTATION_VECTOR_PACKET[..4]);
+        port.add_available_packet(&ROTATION_VECTOR_PACKET);
+        let logger = VecLogger {
+            clock: 0,
+            records: Vec::new(),
+        };
+        let capturing = CapturingInterface::new(
+            I2cInterface::new(port, DEFAULT_ADDRESS),
+            logger,
+        );
+        let mut shub = BNO080::new_with_interface(capturing);
+        assert!(shub.enable_rotation_vector(10).is_ok());
+        assert_eq!(shub.handle_one_message(&mut FakeDelay {}, 1), 1);
+
+        let (_, logger) = shub.free().free();
+        assert_eq!(logger.records.len(), 2);
+        assert_eq!(logger.records[0].0, Direction::Sent);
+        assert_eq!(logger.records[1].0, Direction::Received);
+        assert_eq!(logger.records[1].1, 2);
+        assert_eq!(logger.records[1].2, ROTATION_VECTOR_PACKET);
+
+        let trace: Vec<PacketRecord> = logger
+            .records
+            .iter()
+            .map(|(direction, timestamp, bytes)| PacketRecord {
+                direction: *direction,
+                timestamp: *timestamp,
+                bytes,
+            })
+            .collect();
+        let mut replay =
+            BNO080::new_with_interface(ReplayInterface::new(&trace));
+        assert_eq!(replay.handle_all_messages(&mut FakeDelay {}, 1), 1);
+        assert_eq!(replay.rotation_quaternion().unwrap(), [0.0, 0.0, 0.5, 0.5]);
+        assert!(replay.free().is_finished());
+    }
+}
@@ -0,0 +1,577 @@
+/*
+Copyright (c) 2020 Todd Stellanova
+LICENSE: BSD3 (see LICENSE file)
+*/
+
+//! Device firmware upgrade (DFU) through the BNO080 bootloader.
+//!
+//! The bootloader is entered by holding BOOTN low while the sensor hub
+//! comes out of reset. It then accepts a firmware image over i2c:
+//! the application size, the packet size, and then the application image
+//! in packets, each frame followed by its CRC-16 and acknowledged by
+//! the bootloader. Once the sensor hub is reset into the new application,
+//! `verify_update` checks that it reports the version just downloaded.
+//!
+//! `Dfu::download` takes the HCBIN firmware file as distributed, which
+//! `HcBin::parse` validates and splits up. All numbers are big-endian:
+//!
+//! | offset         | contents                                          |
+//! |----------------|---------------------------------------------------|
+//! | 0              | magic number `0x6572D028` (u32)                   |
+//! | 4              | length of the whole file, CRC included (u32)      |
+//! | 8              | offset of the application image (u32)             |
+//! | 12             | metadata: NUL-terminated key and value strings    |
+//! | image offset   | application image, up to the CRC                  |
+//! | length - 4     | CRC-32 of all the bytes before it (u32)           |
+//!
+//! The metadata must name the format `FW-Format` `BNO_V1`, the
+//! `SW-Part-Number` and the `SW-Version` (major.minor.patch) of the
+//! firmware, and may give the `Packet-Len` to download it in, which is
+//! otherwise `DFU_MAX_PACKET_LEN`.
+
+use crate::interface::SensorInterface;
+use crate::wrapper::{ProductId, WrapperError, BNO080};
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::OutputPin;
+use embedded_hal::i2c::I2c;
+
+/// i2c address of the bootloader, when SA0 is low
+pub const DFU_DEFAULT_ADDRESS: u8 = 0x28;
+/// i2c address of the bootloader, when SA0 is high
+pub const DFU_ALTERNATE_ADDRESS: u8 = 0x29;
+
+/// Largest packet size accepted by the bootloader
+pub const DFU_MAX_PACKET_LEN: usize = 64;
+
+/// Byte sent by the bootloader to acknowledge a frame
+const DFU_ACK: u8 = b's';
+/// Number of times a frame is sent before giving up
+const DFU_MAX_ATTEMPTS: usize = 5;
+
+/// First word of an HCBIN file
+const HCBIN_MAGIC: u32 = 0x6572_D028;
+/// Length of the fixed header of an HCBIN file, before the metadata
+const HCBIN_HEADER_LEN: usize = 12;
+/// Length of the CRC-32 at the end of an HCBIN file
+const HCBIN_CRC_LEN: usize = 4;
+/// The only firmware format accepted, as named in the metadata
+const HCBIN_FW_FORMAT: &str = "BNO_V1";
+
+/// Why an HCBIN file was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum HcBinError {
+    /// The magic number, a length or an offset is wrong
+    Malformed,
+    /// The CRC-32 does not match the contents
+    ChecksumMismatch,
+    /// A required metadata entry is missing or unreadable
+    MissingMetadata,
+    /// The firmware is not in the `BNO_V1` format
+    UnsupportedFormat,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DfuError<E> {
+    /// I2C bus error, or the driver error while verifying the update
+    CommError(E),
+    /// The firmware file was rejected
+    InvalidFile(HcBinError),
+    /// The bootloader did not acknowledge a frame, replying with this byte
+    NotAcknowledged(u8),
+    /// The packet size is zero or larger than `DFU_MAX_PACKET_LEN`
+    InvalidPacketLength,
+    /// The image is empty or too large to describe
+    InvalidImage,
+    /// After the update, the sensor hub reported another part number
+    /// or version than that of the firmware downloaded
+    VersionMismatch(ProductId),
+}
+
+impl<E: core::fmt::Debug> core::fmt::Display for DfuError<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::CommError(e) => write!(f, "communication error: {:?}", e),
+            Self::NotAcknowledged(reply) => {
+                write!(f, "frame not acknowledged, reply 0x{:x}", reply)
+            }
+            Self::InvalidFile(error) => {
+                write!(f, "invalid firmware file: {:?}", error)
+            }
+            Self::InvalidPacketLength => write!(f, "invalid packet length"),
+            Self::InvalidImage => write!(f, "invalid firmware image"),
+            Self::VersionMismatch(product_id) => write!(
+                f,
+                "sensor hub runs part {} version {}.{}.{}",
+                product_id.sw_part_number,
+                product_id.sw_version_major,
+                product_id.sw_version_minor,
+                product_id.sw_version_patch
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: core::fmt::Debug> std::error::Error for DfuError<E> {}
+
+/// A validated HCBIN firmware file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HcBin<'a> {
+    /// The application image, as downloaded to the bootloader
+    pub image: &'a [u8],
+    /// Size of the packets the image is downloaded in
+    pub packet_len: usize,
+    pub sw_part_number: u32,
+    pub sw_version_major: u8,
+    pub sw_version_minor: u8,
+    pub sw_version_patch: u16,
+    metadata: &'a [u8],
+}
+
+impl<'a> HcBin<'a> {
+    /// Validate the header and checksum of an HCBIN file, and extract
+    /// the application image and its metadata
+    pub fn parse(file: &'a [u8]) -> Result<Self, HcBinError> {
+        let word = |offset: usize| {
+            file.get(offset..offset + 4)
+                .and_then(|bytes| bytes.try_into().ok())
+                .map(u32::from_be_bytes)
+                .ok_or(HcBinError::Malformed)
+        };
+        let file_len =
+            usize::try_from(word(4)?).map_err(|_| HcBinError::Malformed)?;
+        let image_offset =
+            usize::try_from(word(8)?).map_err(|_| HcBinError::Malformed)?;
+        let crc_offset = file_len
+            .checked_sub(HCBIN_CRC_LEN)
+            .ok_or(HcBinError::Malformed)?;
+        if word(0)? != HCBIN_MAGIC
+            || file_len != file.len()
+            || !(HCBIN_HEADER_LEN..=crc_offset).contains(&image_offset)
+        {
+            return Err(HcBinError::Malformed);
+        }
+        let contents = file.get(..crc_offset).unwrap_or_default();
+        if crc32(contents) != word(crc_offset)? {
+            return Err(HcBinError::ChecksumMismatch);
+        }
+
+        let mut hcbin = Self {
+            image: contents.get(image_offset..).unwrap_or_default(),
+            packet_len: DFU_MAX_PACKET_LEN,
+            sw_part_number: 0,
+            sw_version_major: 0,
+            sw_version_minor: 0,
+            sw_version_patch: 0,
+            metadata: contents
+                .get(HCBIN_HEADER_LEN..image_offset)
+                .unwrap_or_default(),
+        };
+        if hcbin.metadata("FW-Format") != Some(HCBIN_FW_FORMAT) {
+            return Err(match hcbin.metadata("FW-Format") {
+                Some(_) => HcBinError::UnsupportedFormat,
+                None => HcBinError::MissingMetadata,
+            });
+        }
+        hcbin.sw_part_number = hcbin
+            .metadata("SW-Part-Number")
+            .and_then(|number| number.parse().ok())
+            .ok_or(HcBinError::MissingMetadata)?;
+        let mut version = hcbin
+            .metadata("SW-Version")
+            .ok_or(HcBinError::MissingMetadata)?
+            .split('.');
+        let mut next = || version.next().ok_or(HcBinError::MissingMetadata);
+        let invalid = |_| HcBinError::MissingMetadata;
+        hcbin.sw_version_major = next()?.parse().map_err(invalid)?;
+        hcbin.sw_version_minor = next()?.parse().map_err(invalid)?;
+        hcbin.sw_version_patch = next()?.parse().map_err(invalid)?;
+        if let Some(packet_len) = hcbin.metadata("Packet-Len") {
+            hcbin.packet_len = packet_len.parse().map_err(invalid)?;
+        }
+        Ok(hcbin)
+    }
+
+    /// The value of a metadata entry
+    pub fn metadata(&self, key: &str) -> Option<&'a str> {
+        let mut strings = self
+            .metadata
+            .split(|byte| *byte == 0)
+            .map(|string| core::str::from_utf8(string).ok());
+        while let (Some(name), Some(value)) = (strings.next(), strings.next()) {
+            if name == Some(key) {
+                return value;
+            }
+        }
+        None
+    }
+
+    /// Does `product_id` name this firmware's part number and version?
+    pub fn matches(&self, product_id: &ProductId) -> bool {
+        product_id.sw_part_number == self.sw_part_number
+            && product_id.sw_version_major == self.sw_version_major
+            && product_id.sw_version_minor == self.sw_version_minor
+            && product_id.sw_version_patch == self.sw_version_patch
+    }
+}
+
+/// Hold BOOTN low through a reset pulse on RSTN, so that the sensor hub
+/// starts its bootloader instead of the application
+pub fn enter_bootloader<BOOTN, RSTN, PinE>(
+    bootn: &mut BOOTN,
+    rstn: &mut RSTN,
+    delay: &mut impl DelayNs,
+) -> Result<(), PinE>
+where
+    BOOTN: OutputPin<Error = PinE>,
+    RSTN: OutputPin<Error = PinE>,
+{
+    bootn.set_low()?;
+    rstn.set_low()?;
+    delay.delay_ms(10);
+    rstn.set_high()?;
+    // give the bootloader time to start up
+    delay.delay_ms(200);
+    bootn.set_high()?;
+    Ok(())
+}
+
+/// Streams a firmware image to the bootloader
+pub struct Dfu<I2C> {
+    i2c: I2C,
+    address: u8,
+}
+
+impl<I2C> Dfu<I2C> {
+    pub fn new(i2c: I2C, address: u8) -> Self {
+        Self { i2c, address }
+    }
+
+    /// Release the i2c port
+    pub fn free(self) -> I2C {
+        self.i2c
+    }
+}
+
+impl<I2C, CommE> Dfu<I2C>
+where
+    I2C: I2c<Error = CommE>,
+{
+    /// Download the application image of the HCBIN file `hcbin` to the
+    /// bootloader, returning the parsed file once the bootloader has
+    /// acknowledged the final packet. The sensor hub then has to be
+    /// reset (with BOOTN high) to start the new firmware, and the update
+    /// confirmed with `verify_update`.
+    pub fn download<'f>(
+        &mut self,
+        hcbin: &'f [u8],
+    ) -> Result<HcBin<'f>, DfuError<CommE>> {
+        let firmware = HcBin::parse(hcbin).map_err(DfuError::InvalidFile)?;
+        self.download_image(firmware.image, firmware.packet_len)?;
+        Ok(firmware)
+    }
+
+    /// Download `image` to the bootloader in packets of `packet_len` bytes
+    fn download_image(
+        &mut self,
+        image: &[u8],
+        packet_len: usize,
+    ) -> Result<(), DfuError<CommE>> {
+        if packet_len == 0 || packet_len > DFU_MAX_PACKET_LEN {
+            return Err(DfuError::InvalidPacketLength);
+        }
+        let image_len =
+            u32::try_from(image.len()).map_err(|_| DfuError::InvalidImage)?;
+        if image_len == 0 {
+            return Err(DfuError::InvalidImage);
+        }
+
+        self.send_frame(&image_len.to_be_bytes())?;
+        self.send_frame(&[packet_len as u8])?;
+        for packet in image.chunks(packet_len) {
+            self.send_frame(packet)?;
+        }
+        debug!("dfu complete: {} bytes", image.len());
+        Ok(())
+    }
+
+    /// Send `data` followed by its CRC-16, and wait for the acknowledgement
+    fn send_frame(&mut self, data: &[u8]) -> Result<(), DfuError<CommE>> {
+        let mut frame = [0u8; DFU_MAX_PACKET_LEN + 2];
+        let frame_len = data.len() + 2;
+        frame[..data.len()].copy_from_slice(data);
+        frame[data.len()..frame_len]
+            .copy_from_slice(&crc16(data).to_be_bytes());
+
+        let mut reply = [0u8; 1];
+        for _ in 0..DFU_MAX_ATTEMPTS {
+            self.i2c
+                .write(self.address, &frame[..frame_len])
+                .map_err(DfuError::CommError)?;
+            self.i2c
+                .read(self.address, &mut reply)
+                .map_err(DfuError::CommError)?;
+            if reply[0] == DFU_ACK {
+                return Ok(());
+            }
+            trace!("dfu nak 0x{:x}", reply[0]);
+        }
+        Err(DfuError::NotAcknowledged(reply[0]))
+    }
+}
+
+/// Confirm that an update took: initialize the sensor hub, which has been
+/// reset into its new application, through `driver`, and check that its
+/// product ID names the part number and version of `firmware`
+pub fn verify_update<
+    SI,
+    SE,
+    const SEND_BUF_LEN: usize,
+    const RECV_BUF_LEN: usize,
+>(
+    driver: &mut BNO080<SI, SEND_BUF_LEN, RECV_BUF_LEN>,
+    firmware: &HcBin,
+    delay: &mut impl DelayNs,
+) -> Result<ProductId, DfuError<WrapperError<SE>>>
+where
+    SI: SensorInterface<SensorError = SE>,
+    SE: core::fmt::Debug,
+{
+    driver.init(delay).map_err(DfuError::CommError)?;
+    let product_id = driver
+        .product_id()
+        .ok_or(DfuError::CommError(WrapperError::NoDataAvailable))?;
+    if firmware.matches(&product_id) {
+        Ok(product_id)
+    } else {
+        Err(DfuError::VersionMismatch(product_id))
+    }
+}
+
+/// CRC-32 (polynomial 0x04C11DB7, reflected), as used by HCBIN files
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xFFFF_FFFF;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// CRC-16/CCITT (polynomial 0x1021, initial value 0xFFFF),
+/// as used by the bootloader
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use crate::interface::i2c::DEFAULT_ADDRESS;
+    use crate::interface::mock_i2c_port::{FakeDelay, FakeI2cPort};
+    use crate::interface::I2cInterface;
+    use std::vec::Vec;
+
+    const METADATA: [(&str, &str); 4] = [
+        ("FW-Format", "BNO_V1"),
+        ("SW-Part-Number", "10004563"),
+        ("SW-Version", "3.2.7"),
+        ("Packet-Len", "64"),
+    ];
+
+    /// An HCBIN file of `image`, with the metadata `entries`
+    fn hcbin(entries: &[(&str, &str)], image: &[u8]) -> Vec<u8> {
+        let mut metadata = Vec::new();
+        for (key, value) in entries {
+            metadata.extend(key.bytes().chain([0]));
+            metadata.extend(value.bytes().chain([0]));
+        }
+        let image_offset = HCBIN_HEADER_LEN + metadata.len();
+        let file_len = image_offset + image.len() + HCBIN_CRC_LEN;
+        let mut file = Vec::new();
+        file.extend(HCBIN_MAGIC.to_be_bytes());
+        file.extend((file_len as u32).to_be_bytes());
+        file.extend((image_offset as u32).to_be_bytes());
+        file.extend(metadata);
+        file.extend_from_slice(image);
+        let crc = crc32(&file);
+        file.extend(crc.to_be_bytes());
+        file
+    }
+
+    #[test]
+    fn test_crc16() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_parse_hcbin() {
+        let file = hcbin(&METADATA, &[1, 2, 3]);
+        let firmware = HcBin::parse(&file).unwrap();
+        assert_eq!(firmware.image, [1, 2, 3]);
+        assert_eq!(firmware.packet_len, 64);
+        assert_eq!(firmware.sw_part_number, 10004563);
+        assert_eq!(
+            (
+                firmware.sw_version_major,
+                firmware.sw_version_minor,
+                firmware.sw_version_patch
+            ),
+            (3, 2, 7)
+        );
+        assert_eq!(firmware.metadata("SW-Version"), Some("3.2.7"));
+        assert_eq!(firmware.metadata("Build-Date"), None);
+
+        // the packet length is optional
+        let file = hcbin(&METADATA[..3], &[1, 2, 3]);
+        let firmware = HcBin::parse(&file).unwrap();
+        assert_eq!(firmware.packet_len, DFU_MAX_PACKET_LEN);
+    }
+
+    #[test]
+    fn test_parse_malformed_hcbin() {
+        let file = hcbin(&METADATA, &[1, 2, 3]);
+        let malformed = Err(HcBinError::Malformed);
+        assert_eq!(HcBin::parse(&file[..10]), malformed);
+        assert_eq!(HcBin::parse(&file[..file.len() - 1]), malformed);
+
+        let mut bad_magic = file.clone();
+        bad_magic[0] ^= 0xFF;
+        assert_eq!(HcBin::parse(&bad_magic), malformed);
+
+        // the image would start within the CRC
+        let mut bad_offset = file.clone();
+        bad_offset[8..12].copy_from_slice(&(file.len() as u32).to_be_bytes());
+        assert_eq!(HcBin::parse(&bad_offset), malformed);
+
+        let file = hcbin(&METADATA[1..], &[1, 2, 3]);
+        assert_eq!(HcBin::parse(&file), Err(HcBinError::MissingMetadata));
+        let file = hcbin(&[("FW-Format", "BNO_V2")], &[1, 2, 3]);
+        assert_eq!(HcBin::parse(&file), Err(HcBinError::UnsupportedFormat));
+        let file = hcbin(&[METADATA[0], METADATA[1]], &[1, 2, 3]);
+        assert_eq!(HcBin::parse(&file), Err(HcBinError::MissingMetadata));
+    }
+
+    #[test]
+    fn test_parse_hcbin_bad_checksum() {
+        let mut file = hcbin(&METADATA, &[1, 2, 3]);
+        let image_byte = file.len() - HCBIN_CRC_LEN - 1;
+        file[image_byte] ^= 0x01;
+        assert_eq!(HcBin::parse(&file), Err(HcBinError::ChecksumMismatch));
+    }
+
+    #[test]
+    fn test_download() {
+        let file = hcbin(&METADATA, &[0xA5u8; 100]);
+        let mut port = FakeI2cPort::new();
+        // size, packet length, two packets, with one retry
+        for ack in [DFU_ACK, DFU_ACK, b'n', DFU_ACK, DFU_ACK] {
+            port.add_available_packet(&[ack]);
+        }
+        let mut dfu = Dfu::new(port, DFU_DEFAULT_ADDRESS);
+        let firmware = dfu.download(&file).unwrap();
+        assert_eq!(firmware.image.len(), 100);
+
+        let port = dfu.free();
+        let sent = &port.sent_packets;
+        assert_eq!(sent.len(), 5);
+        assert_eq!(sent[0].as_slice()[..4], [0, 0, 0, 100]);
+        assert_eq!(
+            sent[0].as_slice()[4..],
+            crc16(&[0, 0, 0, 100]).to_be_bytes()
+        );
+        assert_eq!(sent[1].as_slice()[0], 64);
+        assert_eq!(sent[2].len, 66);
+        assert_eq!(sent[2].as_slice(), sent[3].as_slice());
+        assert_eq!(sent[4].len, 38);
+        assert_eq!(sent[4].addr, DFU_DEFAULT_ADDRESS);
+    }
+
+    #[test]
+    fn test_download_not_acknowledged() {
+        let mut port = FakeI2cPort::new();
+        for _ in 0..DFU_MAX_ATTEMPTS {
+            port.add_available_packet(b"n");
+        }
+        let mut dfu = Dfu::new(port, DFU_DEFAULT_ADDRESS);
+        let file = hcbin(&METADATA, &[1, 2, 3]);
+        assert!(matches!(
+            dfu.download(&file),
+            Err(DfuError::NotAcknowledged(b'n'))
+        ));
+        let file = hcbin(
+            &[METADATA[0], METADATA[1], METADATA[2], ("Packet-Len", "65")],
+            &[1, 2, 3],
+        );
+        assert!(matches!(
+            dfu.download(&file),
+            Err(DfuError::InvalidPacketLength)
+        ));
+        assert!(matches!(
+            dfu.download(&file[1..]),
+            Err(DfuError::InvalidFile(HcBinError::Malformed))
+        ));
+        // nothing was sent for the rejected files
+        assert_eq!(dfu.free().sent_packets.len(), DFU_MAX_ATTEMPTS);
+    }
+
+    /// A driver whose sensor hub reports version 3.2.`patch` of the
+    /// BNO085 firmware once initialized
+    fn updated_hub(patch: u16) -> BNO080<I2cInterface<FakeI2cPort>> {
+        let mut pid_response = [0u8; 20];
+        pid_response[..8].copy_from_slice(&[20, 0, 2, 1, 0xF8, 0, 3, 2]);
+        pid_response[8..12].copy_from_slice(&10004563u32.to_le_bytes());
+        pid_response[16..18].copy_from_slice(&patch.to_le_bytes());
+        let mut port = FakeI2cPort::new();
+        port.add_available_packet(&pid_response[..4]);
+        port.add_available_packet(&pid_response);
+        BNO080::new_with_interface(I2cInterface::new(port, DEFAULT_ADDRESS))
+    }
+
+    #[test]
+    fn test_verify_update() {
+        let file = hcbin(&METADATA, &[1, 2, 3]);
+        let firmware = HcBin::parse(&file).unwrap();
+
+        let mut driver = updated_hub(7);
+        let product_id =
+            verify_update(&mut driver, &firmware, &mut FakeDelay {}).unwrap();
+        assert_eq!(product_id.sw_version_patch, 7);
+
+        // the sensor hub still runs the previous firmware
+        let mut driver = updated_hub(6);
+        assert!(matches!(
+            verify_update(&mut driver, &firmware, &mut FakeDelay {}),
+            Err(DfuError::VersionMismatch(ProductId {
+                sw_version_patch: 6,
+                ..
+            }))
+        ));
+    }
+}
@@ -0,0 +1,120 @@
+/*
+Copyright (c) 2020 Todd Stellanova
+LICENSE: BSD3 (see LICENSE file)
+*/
+
+//! Smoothing of the accelerometer and gyroscope outputs with single-pole
+//! IIR (exponential moving average) filters, for consumers such as
+//! displays that want steady values rather than every sample.
+//! Set a filter per report with `BNO080::set_smoothing`.
+
+use crate::ids::SensorReportId;
+use crate::reports::InputReport;
+use crate::wrapper::{q8_to_f32, q9_to_f32};
+
+/// The reports that can be smoothed: accelerometer, calibrated gyroscope,
+/// linear acceleration and uncalibrated gyroscope
+pub(crate) const SMOOTHED_REPORT_IDS: [u8; 4] = [
+    SensorReportId::Accelerometer as u8,
+    SensorReportId::GyroscopeCalibrated as u8,
+    SensorReportId::LinearAcceleration as u8,
+    SensorReportId::GyroscopeUncalibrated as u8,
+];
+
+/// Single-pole low-pass filter of a vector
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LowPass {
+    /// weight of each new sample
+    alpha: f32,
+    state: Option<[f32; 3]>,
+}
+
+impl LowPass {
+    /// A filter giving each new sample the weight `alpha`, from 0
+    /// (frozen) to 1 (no smoothing)
+    pub fn new(alpha: f32) -> Self {
+        Self {
+            alpha: alpha.clamp(0.0, 1.0),
+            state: None,
+        }
+    }
+
+    /// A filter with the -3 dB cutoff frequency `cutoff_hz`, for samples
+    /// arriving at `sample_rate_hz`
+    pub fn with_cutoff(cutoff_hz: f32, sample_rate_hz: f32) -> Self {
+        let rc = 1.0 / (2.0 * core::f32::consts::PI * cutoff_hz);
+        let dt = 1.0 / sample_rate_hz;
+        Self::new(dt / (rc + dt))
+    }
+
+    /// Weight of each new sample
+    pub fn alpha(&self) -> f32 {
+        self.alpha
+    }
+
+    /// Filter a sample, returning the smoothed value.
+    /// The first sample initializes the filter.
+    pub fn update(&mut self, sample: [f32; 3]) -> [f32; 3] {
+        let value = match self.state {
+            Some(state) => core::array::from_fn(|i| {
+                state[i] + self.alpha * (sample[i] - state[i])
+            }),
+            None => sample,
+        };
+        self.state = Some(value);
+        value
+    }
+
+    /// The smoothed value, once a sample has been filtered
+    pub fn value(&self) -> Option<[f32; 3]> {
+        self.state
+    }
+
+    /// Forget the samples filtered so far
+    pub fn reset(&mut self) {
+        self.state = None;
+    }
+}
+
+/// Filter a report into the filter of its ID in `filters`, which are
+/// in the order of `SMOOTHED_REPORT_IDS`
+pub(crate) fn smooth_report(
+    filters: &mut [Option<LowPass>; SMOOTHED_REPORT_IDS.len()],
+    report: &InputReport,
+) {
+    let Some(index) = SMOOTHED_REPORT_IDS
+        .iter()
+        .position(|report_id| *report_id == report.report_id())
+    else {
+        return;
+    };
+    if let Some(filter) = &mut filters[index] {
+        let convert = match report.report_id() {
+            0x01 | 0x04 => q8_to_f32,
+            _ => q9_to_f32,
+        };
+        let value = |index| convert(report.value(index).unwrap_or(0));
+        filter.update([value(0), value(1), value(2)]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_low_pass() {
+        let mut filter = LowPass::new(0.25);
+        assert_eq!(filter.value(), None);
+        assert_eq!(filter.update([4.0, 0.0, -4.0]), [4.0, 0.0, -4.0]);
+        assert_eq!(filter.update([0.0, 8.0, -4.0]), [3.0, 2.0, -4.0]);
+        filter.reset();
+        assert_eq!(filter.update([1.0, 1.0, 1.0]), [1.0, 1.0, 1.0]);
+
+        assert_eq!(LowPass::new(2.0).alpha(), 1.0);
+        // a cutoff at a sixth of the sample rate
+        let alpha = LowPass::with_cutoff(10.0, 60.0).alpha();
+        assert!((alpha - 0.5116).abs() < 1e-3);
+    }
+}
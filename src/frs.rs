@@ -0,0 +1,396 @@
+/*
+Copyright (c) 2020 Todd Stellanova
+LICENSE: BSD3 (see LICENSE file)
+*/
+
+//! Flash record system (FRS): configuration and calibration records kept
+//! in the sensor hub's flash. Each record is addressed by a 16 bit type,
+//! and read and written as 32 bit words with `BNO080::read_frs` and
+//! `BNO080::write_frs`. Writing an empty record erases it, so that the
+//! hub falls back to its default.
+
+/// Static calibration of the accelerometer, gyroscope and magnetometer
+pub const FRS_STATIC_CALIBRATION_AGM: u16 = 0x7979;
+/// Nominal (factory default) calibration of the accelerometer, gyroscope
+/// and magnetometer, in the same layout as the static calibration
+pub const FRS_NOMINAL_CALIBRATION_AGM: u16 = 0x4D4D;
+/// Serial number of the sensor hub, one word
+pub const FRS_SERIAL_NUMBER: u16 = 0x4B4B;
+/// Configuration of the gyro-integrated rotation vector
+pub const FRS_GYRO_INTEGRATED_RV_CONFIG: u16 = 0xA1A2;
+/// Selection of the output format on the UART, one word
+pub const FRS_UART_OUTPUT_FORMAT: u16 = 0xA1A1;
+
+/// Status of an FRS read response: more data follows
+const FRS_READ_STATUS_OK: u8 = 0;
+/// Status of an FRS read response: the whole record has been read
+const FRS_READ_STATUS_RECORD_COMPLETED: u8 = 3;
+/// Status of an FRS read response: the record is empty
+const FRS_READ_STATUS_RECORD_EMPTY: u8 = 5;
+/// Status of an FRS read response: the requested block has been read
+const FRS_READ_STATUS_BLOCK_COMPLETED: u8 = 6;
+/// Status of an FRS read response: the block and the record have been read
+const FRS_READ_STATUS_BLOCK_RECORD_COMPLETED: u8 = 7;
+
+/// Status of an FRS write response: data words received
+pub(crate) const FRS_WRITE_STATUS_RECEIVED: u8 = 0;
+/// Status of an FRS write response: busy, the request should be sent again
+pub(crate) const FRS_WRITE_STATUS_BUSY: u8 = 2;
+/// Status of an FRS write response: the write has completed
+pub(crate) const FRS_WRITE_STATUS_COMPLETED: u8 = 3;
+/// Status of an FRS write response: ready to receive data
+pub(crate) const FRS_WRITE_STATUS_READY: u8 = 4;
+/// Status of an FRS write response: the record passed validation
+pub(crate) const FRS_WRITE_STATUS_RECORD_VALID: u8 = 8;
+
+/// Progress of an FRS read, from the status of a read response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FrsReadStatus {
+    /// More data follows
+    Reading,
+    /// The record has been read completely
+    Completed,
+    /// The record is empty
+    Empty,
+    /// The read failed with this status
+    Failed(u8),
+}
+
+/// FRS read response (0xF3): up to two data words of a record
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct FrsReadResponse {
+    pub status: u8,
+    /// Number of valid words in `data`
+    pub data_len: u8,
+    /// Offset of the first data word in the record (words)
+    pub offset: u16,
+    pub data: [u32; 2],
+    pub frs_type: u16,
+}
+
+impl FrsReadResponse {
+    /// Parse the payload of a read response,
+    /// treating any missing trailing fields as zero
+    pub fn parse(payload: &[u8]) -> Self {
+        let mut bytes = [0u8; 16];
+        let len = payload.len().min(bytes.len());
+        bytes[..len].copy_from_slice(&payload[..len]);
+        let word = |index: usize| {
+            u32::from_le_bytes([
+                bytes[index],
+                bytes[index + 1],
+                bytes[index + 2],
+                bytes[index + 3],
+            ])
+        };
+        Self {
+            status: bytes[1] & 0x0F,
+            data_len: bytes[1] >> 4,
+            offset: u16::from_le_bytes([bytes[2], bytes[3]]),
+            data: [word(4), word(8)],
+            frs_type: u16::from_le_bytes([bytes[12], bytes[13]]),
+        }
+    }
+
+    pub fn read_status(&self) -> FrsReadStatus {
+        match self.status {
+            FRS_READ_STATUS_OK => FrsReadStatus::Reading,
+            FRS_READ_STATUS_RECORD_COMPLETED
+            | FRS_READ_STATUS_BLOCK_COMPLETED
+            | FRS_READ_STATUS_BLOCK_RECORD_COMPLETED => {
+                FrsReadStatus::Completed
+            }
+            FRS_READ_STATUS_RECORD_EMPTY => FrsReadStatus::Empty,
+            status => FrsReadStatus::Failed(status),
+        }
+    }
+}
+
+/// FRS write response (0xF5)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct FrsWriteResponse {
+    pub status: u8,
+    /// Offset of the last word received (words)
+    pub offset: u16,
+}
+
+impl FrsWriteResponse {
+    pub fn parse(payload: &[u8]) -> Self {
+        let byte = |index: usize| payload.get(index).copied().unwrap_or(0);
+        Self {
+            status: byte(1),
+            offset: u16::from_le_bytes([byte(2), byte(3)]),
+        }
+    }
+}
+
+/// Calibration of one sensor in a static calibration record
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SensorCalibration {
+    /// Scale (diagonal) and skew (off-diagonal) matrix, row by row
+    pub scale: [[i32; 3]; 3],
+    /// Offset of each axis
+    pub offset: [i32; 3],
+}
+
+/// Number of words of one sensor's calibration
+const SENSOR_CALIBRATION_WORDS: usize = 12;
+
+impl SensorCalibration {
+    fn from_words(words: &[u32]) -> Self {
+        let value = |index: usize| words[index] as i32;
+        Self {
+            scale: [
+                [value(0), value(1), value(2)],
+                [value(3), value(4), value(5)],
+                [value(6), value(7), value(8)],
+            ],
+            offset: [value(9), value(10), value(11)],
+        }
+    }
+
+    fn write_words(&self, words: &mut [u32]) {
+        let values = self.scale.iter().flatten().chain(self.offset.iter());
+        for (word, value) in words.iter_mut().zip(values) {
+            *word = *value as u32;
+        }
+    }
+}
+
+/// Number of words of the static calibration record
+pub const STATIC_CALIBRATION_AGM_WORDS: usize = 3 * SENSOR_CALIBRATION_WORDS;
+
+/// Static calibration record (`FRS_STATIC_CALIBRATION_AGM`), as provisioned
+/// during production test. The values are the raw fixed-point words of
+/// the record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StaticCalibrationAgm {
+    pub accel: SensorCalibration,
+    pub gyro: SensorCalibration,
+    pub mag: SensorCalibration,
+}
+
+impl StaticCalibrationAgm {
+    /// Parse the words of the record, or `None` if it is too short
+    pub fn from_words(words: &[u32]) -> Option<Self> {
+        if words.len() < STATIC_CALIBRATION_AGM_WORDS {
+            return None;
+        }
+        let sensor = |index: usize| {
+            let start = index * SENSOR_CALIBRATION_WORDS;
+            SensorCalibration::from_words(
+                &words[start..start + SENSOR_CALIBRATION_WORDS],
+            )
+        };
+        Some(Self {
+            accel: sensor(0),
+            gyro: sensor(1),
+            mag: sensor(2),
+        })
+    }
+
+    /// The words of the record
+    pub fn to_words(&self) -> [u32; STATIC_CALIBRATION_AGM_WORDS] {
+        let mut words = [0u32; STATIC_CALIBRATION_AGM_WORDS];
+        let sensors = words.chunks_exact_mut(SENSOR_CALIBRATION_WORDS);
+        for (chunk, calibration) in
+            sensors.zip([&self.accel, &self.gyro, &self.mag])
+        {
+            calibration.write_words(chunk);
+        }
+        words
+    }
+}
+
+/// Output format of the sensor hub's UART, as selected by the UART output
+/// format record (`FRS_UART_OUTPUT_FORMAT`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UartOutputFormat {
+    /// SHTP packets, as handled by `UartInterface` (the default)
+    Shtp,
+    /// The fixed UART-RVC frames, as parsed by `RvcParser`
+    Rvc,
+    /// A format this driver does not know, by the word of the record
+    Unknown(u32),
+}
+
+impl UartOutputFormat {
+    /// Parse the word of the record
+    pub fn from_word(word: u32) -> Self {
+        match word {
+            0 => Self::Shtp,
+            1 => Self::Rvc,
+            word => Self::Unknown(word),
+        }
+    }
+
+    /// The word of the record
+    pub fn to_word(self) -> u32 {
+        match self {
+            Self::Shtp => 0,
+            Self::Rvc => 1,
+            Self::Unknown(word) => word,
+        }
+    }
+}
+
+/// Number of words of the gyro-integrated rotation vector configuration
+pub const GYRO_INTEGRATED_RV_CONFIG_WORDS: usize = 7;
+
+/// Orientation that the gyro-integrated rotation vector is corrected
+/// towards, by its sensor ID
+const GYRO_RV_REFERENCE_GAME_RV: u32 = 0x0207;
+const GYRO_RV_REFERENCE_RV: u32 = 0x0204;
+
+/// Orientation that the gyro-integrated rotation vector follows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum GyroRvReference {
+    /// The game rotation vector, without magnetometer
+    GameRotationVector,
+    /// The rotation vector, referenced to magnetic north
+    RotationVector,
+}
+
+/// Configuration record of the gyro-integrated rotation vector
+/// (`FRS_GYRO_INTEGRATED_RV_CONFIG`), which trades latency for jitter:
+/// a longer prediction hides more of the latency of the output, and
+/// smaller gains correct it towards the reference more smoothly
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GyroIntegratedRvConfig {
+    pub reference: GyroRvReference,
+    /// Interval at which the output is corrected towards the reference
+    /// (microseconds)
+    pub sync_interval_us: u32,
+    /// Largest difference from the reference before the output snaps
+    /// to it (radians)
+    pub max_error: f32,
+    /// How far ahead the output is predicted (seconds)
+    pub prediction: f32,
+    /// Gains of the prediction filter
+    pub alpha: f32,
+    pub beta: f32,
+    pub gamma: f32,
+}
+
+/// Signed fixed point word with `q` fractional bits
+fn fixed_to_f32(word: u32, q: u8) -> f32 {
+    word as i32 as f32 / (1u32 << q) as f32
+}
+
+/// Convert to a fixed point word, saturating outside of its range
+fn f32_to_fixed(value: f32, q: u8) -> u32 {
+    let scaled = libm::roundf(value * (1u32 << q) as f32);
+    scaled.clamp(i32::MIN as f32, i32::MAX as f32) as i32 as u32
+}
+
+impl GyroIntegratedRvConfig {
+    /// Parse the words of the record, or `None` if it is too short or
+    /// names an unknown reference
+    pub fn from_words(words: &[u32]) -> Option<Self> {
+        if words.len() < GYRO_INTEGRATED_RV_CONFIG_WORDS {
+            return None;
+        }
+        let reference = match words[0] {
+            GYRO_RV_REFERENCE_GAME_RV => GyroRvReference::GameRotationVector,
+            GYRO_RV_REFERENCE_RV => GyroRvReference::RotationVector,
+            _ => return None,
+        };
+        Some(Self {
+            reference,
+            sync_interval_us: words[1],
+            max_error: fixed_to_f32(words[2], 29),
+            prediction: fixed_to_f32(words[3], 10),
+            alpha: fixed_to_f32(words[4], 20),
+            beta: fixed_to_f32(words[5], 20),
+            gamma: fixed_to_f32(words[6], 20),
+        })
+    }
+
+    /// The words of the record
+    pub fn to_words(&self) -> [u32; GYRO_INTEGRATED_RV_CONFIG_WORDS] {
+        let reference = match self.reference {
+            GyroRvReference::GameRotationVector => GYRO_RV_REFERENCE_GAME_RV,
+            GyroRvReference::RotationVector => GYRO_RV_REFERENCE_RV,
+        };
+        [
+            reference,
+            self.sync_interval_us,
+            f32_to_fixed(self.max_error, 29),
+            f32_to_fixed(self.prediction, 10),
+            f32_to_fixed(self.alpha, 20),
+            f32_to_fixed(self.beta, 20),
+            f32_to_fixed(self.gamma, 20),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_read_response() {
+        let payload: [u8; 16] = [
+            0xF3, 0x23, 0x02, 0x00, 1, 0, 0, 0, 2, 0, 0, 0, 0x79, 0x79, 0, 0,
+        ];
+        let response = FrsReadResponse::parse(&payload);
+        assert_eq!(response.data_len, 2);
+        assert_eq!(response.read_status(), FrsReadStatus::Completed);
+        assert_eq!(response.offset, 2);
+        assert_eq!(response.data, [1, 2]);
+        assert_eq!(response.frs_type, FRS_STATIC_CALIBRATION_AGM);
+    }
+
+    #[test]
+    fn test_static_calibration_words() {
+        let mut calibration = StaticCalibrationAgm::default();
+        calibration.accel.scale[0][0] = 1 << 30;
+        calibration.gyro.offset[2] = -5;
+        calibration.mag.scale[1][2] = 7;
+        let words = calibration.to_words();
+        assert_eq!(words[0], 1 << 30);
+        assert_eq!(words[12 + 11], -5i32 as u32);
+        assert_eq!(words[24 + 5], 7);
+        assert_eq!(StaticCalibrationAgm::from_words(&words), Some(calibration));
+        assert_eq!(StaticCalibrationAgm::from_words(&words[1..]), None);
+    }
+
+    #[test]
+    fn test_gyro_integrated_rv_config_words() {
+        // reference, sync interval, max error (Q29), prediction (Q10),
+        // alpha, beta, gamma (Q20)
+        let words = [
+            0x0207,
+            10_000,
+            0x0100_0000,
+            0x0000_0400,
+            0x0003_3333,
+            0x0002_0000,
+            0x0000_8000,
+        ];
+        let config = GyroIntegratedRvConfig::from_words(&words).unwrap();
+        assert_eq!(config.reference, GyroRvReference::GameRotationVector);
+        assert_eq!(config.sync_interval_us, 10_000);
+        assert_eq!(config.max_error, 1.0 / 32.0);
+        assert_eq!(config.prediction, 1.0);
+        assert!((config.alpha - 0.2).abs() < 1e-6);
+        assert_eq!(config.beta, 0.125);
+        assert_eq!(config.gamma, 1.0 / 32.0);
+        assert_eq!(config.to_words(), words);
+
+        let mut unknown = words;
+        unknown[0] = 0x0208;
+        assert_eq!(GyroIntegratedRvConfig::from_words(&unknown), None);
+        assert_eq!(GyroIntegratedRvConfig::from_words(&words[1..]), None);
+    }
+}
@@ -0,0 +1,250 @@
+/*
+Copyright (c) 2020 Todd Stellanova
+LICENSE: BSD3 (see LICENSE file)
+*/
+
+//! Gestures as input events: taps, double taps, shakes and circles
+//! detected by the sensor hub, each enabled on its own, debounced, and
+//! delivered to a registered callback and as a pending flag.
+//! Enable them with `BNO080::enable_gesture`.
+
+use crate::ids::SensorReportId;
+use crate::reports::SensorReport;
+
+/// A gesture that can be delivered as an event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Gesture {
+    Tap = 0,
+    DoubleTap = 1,
+    Shake = 2,
+    Circle = 3,
+}
+
+impl Gesture {
+    /// The input report in which the sensor hub reports this gesture
+    pub fn report_id(self) -> SensorReportId {
+        match self {
+            Self::Tap | Self::DoubleTap => SensorReportId::TapDetector,
+            Self::Shake => SensorReportId::ShakeDetector,
+            Self::Circle => SensorReportId::CircleDetector,
+        }
+    }
+
+    /// The gesture reported in a decoded report, if any
+    pub fn from_report(report: &SensorReport) -> Option<Self> {
+        match report {
+            SensorReport::Tap { double: true, .. } => Some(Self::DoubleTap),
+            SensorReport::Tap { .. } => Some(Self::Tap),
+            SensorReport::Shake(_) => Some(Self::Shake),
+            SensorReport::Circle => Some(Self::Circle),
+            _ => None,
+        }
+    }
+}
+
+/// Called with each gesture event and the report it was detected in
+pub type GestureCallback = fn(gesture: Gesture, report: &SensorReport);
+
+const GESTURES: usize = 4;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct GestureState {
+    enabled: bool,
+    debounce_ms: u32,
+    last_event_ms: Option<u32>,
+    pending: bool,
+    callback: Option<GestureCallback>,
+    /// the report of this gesture was enabled to detect it
+    enabled_report: bool,
+}
+
+/// Turns the gesture reports of the enabled gestures into events
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct GestureEvents {
+    states: [GestureState; GESTURES],
+}
+
+impl GestureEvents {
+    /// The gestures, in the order of their states
+    const ALL: [Gesture; GESTURES] = [
+        Gesture::Tap,
+        Gesture::DoubleTap,
+        Gesture::Shake,
+        Gesture::Circle,
+    ];
+
+    fn state(&mut self, gesture: Gesture) -> Option<&mut GestureState> {
+        self.states.get_mut(gesture as usize)
+    }
+
+    /// Deliver `gesture` as events at least `debounce_ms` apart
+    pub(crate) fn enable(&mut self, gesture: Gesture, debounce_ms: u32) {
+        if let Some(state) = self.state(gesture) {
+            state.enabled = true;
+            state.debounce_ms = debounce_ms;
+        }
+    }
+
+    pub(crate) fn disable(&mut self, gesture: Gesture) {
+        if let Some(state) = self.state(gesture) {
+            state.enabled = false;
+            state.pending = false;
+        }
+    }
+
+    /// Note that the report of `gesture` was enabled to detect it
+    pub(crate) fn set_enabled_report(&mut self, gesture: Gesture) {
+        if let Some(state) = self.state(gesture) {
+            state.enabled_report = true;
+        }
+    }
+
+    /// Should the report of `gesture` be disabled, now that it is? Only
+    /// if it was enabled to detect a gesture, and no enabled gesture
+    /// still uses it; it is then no longer noted as enabled.
+    pub(crate) fn release_report(&mut self, gesture: Gesture) -> bool {
+        let report_id = gesture.report_id();
+        if self.uses_report(report_id.into()) {
+            return false;
+        }
+        let mut enabled_report = false;
+        for (other, state) in Self::ALL.into_iter().zip(&mut self.states) {
+            if other.report_id() == report_id {
+                enabled_report |= core::mem::take(&mut state.enabled_report);
+            }
+        }
+        enabled_report
+    }
+
+    /// Is any enabled gesture reported in this input report?
+    pub(crate) fn uses_report(&self, report_id: u8) -> bool {
+        Self::ALL
+            .into_iter()
+            .zip(self.states)
+            .any(|(gesture, state)| {
+                state.enabled && u8::from(gesture.report_id()) == report_id
+            })
+    }
+
+    pub(crate) fn set_callback(
+        &mut self,
+        gesture: Gesture,
+        callback: Option<GestureCallback>,
+    ) {
+        if let Some(state) = self.state(gesture) {
+            state.callback = callback;
+        }
+    }
+
+    /// Has an event of `gesture` occurred since the last call?
+    pub(crate) fn take(&mut self, gesture: Gesture) -> bool {
+        self.state(gesture)
+            .is_some_and(|state| core::mem::take(&mut state.pending))
+    }
+
+    /// Forget the events so far, keeping the configuration
+    pub(crate) fn clear(&mut self) {
+        for state in &mut self.states {
+            state.pending = false;
+            state.last_event_ms = None;
+        }
+    }
+
+    /// Handle a decoded report received at `now_ms`, the time of the
+    /// driver's clock if it has one; without a clock every detection of
+    /// an enabled gesture is an event. Returns the event, if any.
+    pub(crate) fn handle(
+        &mut self,
+        report: &SensorReport,
+        now_ms: Option<u32>,
+    ) -> Option<Gesture> {
+        let gesture = Gesture::from_report(report)?;
+        let state = self.state(gesture).filter(|state| state.enabled)?;
+        if let (Some(now), Some(last)) = (now_ms, state.last_event_ms) {
+            if now.wrapping_sub(last) < state.debounce_ms {
+                return None;
+            }
+        }
+        state.last_event_ms = now_ms;
+        state.pending = true;
+        if let Some(callback) = state.callback {
+            callback(gesture, report);
+        }
+        Some(gesture)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reports::GestureAxes;
+    use core::sync::atomic::{AtomicU32, Ordering};
+
+    static CALLS: AtomicU32 = AtomicU32::new(0);
+
+    fn callback(gesture: Gesture, _report: &SensorReport) {
+        assert_eq!(gesture, Gesture::DoubleTap);
+        CALLS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn test_gesture_events() {
+        let double_tap = SensorReport::Tap {
+            axes: GestureAxes::Z,
+            positive: GestureAxes::default(),
+            double: true,
+        };
+        let shake = SensorReport::Shake(GestureAxes::X);
+        let mut events = GestureEvents::default();
+        assert_eq!(events.handle(&double_tap, Some(0)), None);
+        assert!(!events.uses_report(0x10));
+
+        events.enable(Gesture::DoubleTap, 300);
+        events.set_callback(Gesture::DoubleTap, Some(callback));
+        assert!(events.uses_report(0x10));
+        assert!(!events.uses_report(0x19));
+        assert_eq!(
+            events.handle(&double_tap, Some(1000)),
+            Some(Gesture::DoubleTap)
+        );
+        // repeats within the debounce window are ignored
+        assert_eq!(events.handle(&double_tap, Some(1200)), None);
+        assert_eq!(
+            events.handle(&double_tap, Some(1300)),
+            Some(Gesture::DoubleTap)
+        );
+        assert_eq!(CALLS.load(Ordering::Relaxed), 2);
+        assert!(events.take(Gesture::DoubleTap));
+        assert!(!events.take(Gesture::DoubleTap));
+        assert!(!events.take(Gesture::Tap));
+        assert_eq!(events.handle(&shake, None), None);
+
+        // without a clock, every detection is an event
+        events.enable(Gesture::Shake, 300);
+        assert_eq!(events.handle(&shake, None), Some(Gesture::Shake));
+        assert_eq!(events.handle(&shake, None), Some(Gesture::Shake));
+        events.disable(Gesture::Shake);
+        assert!(!events.take(Gesture::Shake));
+        assert!(!events.uses_report(0x19));
+    }
+
+    #[test]
+    fn test_release_report() {
+        let mut events = GestureEvents::default();
+        // the tap detector was enabled for taps, and shared by double taps
+        events.enable(Gesture::Tap, 0);
+        events.set_enabled_report(Gesture::Tap);
+        events.enable(Gesture::DoubleTap, 0);
+        events.disable(Gesture::Tap);
+        assert!(!events.release_report(Gesture::Tap));
+        events.disable(Gesture::DoubleTap);
+        assert!(events.release_report(Gesture::DoubleTap));
+        assert!(!events.release_report(Gesture::DoubleTap));
+
+        // the shake detector was already enabled
+        events.enable(Gesture::Shake, 0);
+        events.disable(Gesture::Shake);
+        assert!(!events.release_report(Gesture::Shake));
+    }
+}
@@ -0,0 +1,187 @@
+/*
+Copyright (c) 2020 Todd Stellanova
+LICENSE: BSD3 (see LICENSE file)
+*/
+
+//! Typed SHTP channels, report IDs and executable commands.
+//! Each converts into its `u8` wire value, and back with `TryFrom<u8>`,
+//! which returns the unknown byte as the error.
+
+/// Declares a `#[repr(u8)]` enum with `From<Enum> for u8` and
+/// `TryFrom<u8> for Enum` conversions
+macro_rules! u8_enum {
+    (
+        $(#[$meta:meta])*
+        pub enum $name:ident {
+            $( $(#[$vmeta:meta])* $variant:ident = $value:literal, )*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+        #[non_exhaustive]
+        #[repr(u8)]
+        pub enum $name {
+            $( $(#[$vmeta])* $variant = $value, )*
+        }
+
+        impl From<$name> for u8 {
+            fn from(value: $name) -> u8 {
+                value as u8
+            }
+        }
+
+        impl TryFrom<u8> for $name {
+            type Error = u8;
+
+            fn try_from(value: u8) -> Result<Self, u8> {
+                match value {
+                    $( $value => Ok(Self::$variant), )*
+                    other => Err(other),
+                }
+            }
+        }
+    };
+}
+
+u8_enum! {
+    /// The SHTP channels of the sensor hub. The numbers are those of the
+    /// BNO080 firmware; see `ChannelMap` for the numbers actually assigned
+    /// by the advertisement.
+    pub enum Channel {
+        /// SHTP command channel
+        Command = 0,
+        /// executable channel
+        Executable = 1,
+        /// sensor hub control channel
+        HubControl = 2,
+        /// input sensor reports (non-wake, not gyroRV)
+        SensorReports = 3,
+        /// wake input sensor reports (for sensors configured as wake up sensors)
+        WakeReports = 4,
+        /// gyro rotation vector (gyroRV)
+        GyroRotation = 5,
+    }
+}
+
+u8_enum! {
+    /// Input report IDs, from the SH-2 Reference Manual
+    pub enum SensorReportId {
+        /// Accelerometer (m/s^2 including gravity): Q point 8
+        Accelerometer = 0x01,
+        /// Gyroscope calibrated (rad/s): Q point 9
+        GyroscopeCalibrated = 0x02,
+        /// Magnetic field calibrated (uTesla): Q point 4
+        MagneticField = 0x03,
+        /// Linear acceleration (m/s^2 minus gravity): Q point 8
+        LinearAcceleration = 0x04,
+        /// Unit quaternion rotation vector: Q point 14, with heading
+        /// accuracy estimate (radians): Q point 12
+        RotationVector = 0x05,
+        /// Gravity (m/s^2): Q point 8
+        Gravity = 0x06,
+        /// Gyroscope uncalibrated (rad/s): Q point 9
+        GyroscopeUncalibrated = 0x07,
+        /// Game rotation vector: Q point 14
+        GameRotationVector = 0x08,
+        /// Geomagnetic rotation vector: Q point 14, with heading
+        /// accuracy estimate: Q point 12
+        GeomagneticRotationVector = 0x09,
+        /// Pressure (hectopascals) from external baro: Q point 20
+        Pressure = 0x0A,
+        /// Ambient light (lux) from external sensor: Q point 8
+        AmbientLight = 0x0B,
+        /// Humidity (percent) from external sensor: Q point 8
+        Humidity = 0x0C,
+        /// Proximity (centimeters) from external sensor: Q point 4
+        Proximity = 0x0D,
+        /// Temperature (degrees C) from external sensor: Q point 7
+        Temperature = 0x0E,
+        /// Magnetic field uncalibrated (uTesla): Q point 4
+        MagneticFieldUncalibrated = 0x0F,
+        TapDetector = 0x10,
+        StepCounter = 0x11,
+        SignificantMotion = 0x12,
+        StabilityClassifier = 0x13,
+        RawAccelerometer = 0x14,
+        RawGyroscope = 0x15,
+        RawMagnetometer = 0x16,
+        StepDetector = 0x18,
+        ShakeDetector = 0x19,
+        FlipDetector = 0x1A,
+        PickupDetector = 0x1B,
+        StabilityDetector = 0x1C,
+        PersonalActivityClassifier = 0x1E,
+        SleepDetector = 0x1F,
+        TiltDetector = 0x20,
+        PocketDetector = 0x21,
+        CircleDetector = 0x22,
+        HeartRateMonitor = 0x23,
+        /// ARVR-stabilized rotation vector
+        ArvrRotationVector = 0x28,
+        /// ARVR-stabilized game rotation vector
+        ArvrGameRotationVector = 0x29,
+        GyroIntegratedRotationVector = 0x2A,
+        /// Wheel encoder measurements injected by the host
+        WheelEncoder = 0x2E,
+    }
+}
+
+u8_enum! {
+    /// Report IDs on the sensor hub control channel
+    pub enum ControlReportId {
+        ForceSensorFlush = 0xF0,
+        CommandResponse = 0xF1,
+        CommandRequest = 0xF2,
+        FrsReadResponse = 0xF3,
+        FrsReadRequest = 0xF4,
+        FrsWriteResponse = 0xF5,
+        FrsWriteData = 0xF6,
+        FrsWriteRequest = 0xF7,
+        ProductIdResponse = 0xF8,
+        ProductIdRequest = 0xF9,
+        GetFeatureResponse = 0xFC,
+        SetFeatureCommand = 0xFD,
+        GetFeatureRequest = 0xFE,
+    }
+}
+
+u8_enum! {
+    /// Commands on the executable channel
+    /// (Figure 1-27: SHTP executable commands and response)
+    pub enum ExecutableCommand {
+        Reset = 1,
+        On = 2,
+        Sleep = 3,
+    }
+}
+
+u8_enum! {
+    /// Responses on the executable channel
+    pub enum ExecutableResponse {
+        /// Response to `ExecutableCommand::Reset`
+        ResetComplete = 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_u8_conversions() {
+        assert_eq!(u8::from(Channel::HubControl), 2);
+        assert_eq!(Channel::try_from(5), Ok(Channel::GyroRotation));
+        assert_eq!(Channel::try_from(6), Err(6));
+        assert_eq!(
+            SensorReportId::try_from(0x05),
+            Ok(SensorReportId::RotationVector)
+        );
+        assert_eq!(SensorReportId::try_from(0x1D), Err(0x1D));
+        assert_eq!(
+            ControlReportId::try_from(0xF8),
+            Ok(ControlReportId::ProductIdResponse)
+        );
+        assert_eq!(u8::from(ExecutableCommand::Sleep), 3);
+    }
+}
@@ -11,12 +11,124 @@ pub const DEFAULT_ADDRESS: u8 = 0x4A;
 /// alternate i2c address for BNO080
 pub const ALTERNATE_ADDRESS: u8 = 0x4B;
 
+/// The standard i2c addresses of the BNO080, selected by the SA0 pin
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum I2cAddress {
+    /// SA0 low: 0x4A
+    Default,
+    /// SA0 high: 0x4B
+    Alternate,
+}
+
+impl From<I2cAddress> for u8 {
+    fn from(address: I2cAddress) -> Self {
+        match address {
+            I2cAddress::Default => DEFAULT_ADDRESS,
+            I2cAddress::Alternate => ALTERNATE_ADDRESS,
+        }
+    }
+}
+
 /// Length of our receive buffer:
 /// Note that this likely needs to be < 256 to accommodate underlying HAL
 const SEG_RECV_BUF_LEN: usize = 240;
 const MAX_SEGMENT_READ: usize = SEG_RECV_BUF_LEN;
 
-pub struct I2cInterface<I2C> {
+/// Timing of the "clock stretch tolerant" mode.
+///
+/// The BNO080 stretches the i2c clock, sometimes for a long time, and
+/// some i2c peripherals (e.g. on the RP2040 or ESP32) mishandle it,
+/// returning errors or corrupted data. In this mode every bus transaction
+/// is preceded by a pause, so that the header and the body of a packet
+/// are read in separate transactions with a pause in between, and failed
+/// transactions are retried after a jittered delay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ClockStretchConfig {
+    /// Pause before each bus transaction (microseconds)
+    pub transaction_gap_us: u32,
+    /// Number of times a failed transaction is retried
+    pub max_retries: u8,
+    /// Pause before retrying a failed transaction (microseconds)
+    pub retry_delay_us: u32,
+    /// Upper bound of the random delay added to `retry_delay_us`
+    /// (microseconds), so that retries do not stay in lockstep with
+    /// the sensor hub
+    pub retry_jitter_us: u32,
+}
+
+impl Default for ClockStretchConfig {
+    fn default() -> Self {
+        Self {
+            transaction_gap_us: 100,
+            max_retries: 3,
+            retry_delay_us: 500,
+            retry_jitter_us: 250,
+        }
+    }
+}
+
+/// A delay that does not wait: the delay type of an `I2cInterface`
+/// that is not in clock stretch tolerant mode, and a stand-in wherever
+/// no delay source is available
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoDelay;
+
+impl DelayNs for NoDelay {
+    fn delay_ns(&mut self, _ns: u32) {}
+}
+
+/// Paces and retries bus transactions in clock stretch tolerant mode
+struct ClockStretch<D> {
+    config: ClockStretchConfig,
+    delay: D,
+    /// xorshift state for the retry jitter
+    jitter_state: u32,
+}
+
+impl<D: DelayNs> ClockStretch<D> {
+    fn transact<E>(
+        &mut self,
+        mut op: impl FnMut() -> Result<(), E>,
+    ) -> Result<(), E> {
+        self.delay.delay_us(self.config.transaction_gap_us);
+        let mut retries = 0;
+        loop {
+            match op() {
+                Ok(()) => return Ok(()),
+                Err(e) if retries >= self.config.max_retries => return Err(e),
+                Err(_) => {
+                    retries += 1;
+                    let jitter = self.next_jitter_us();
+                    self.delay.delay_us(
+                        self.config.retry_delay_us.saturating_add(jitter),
+                    );
+                }
+            }
+        }
+    }
+
+    fn next_jitter_us(&mut self) -> u32 {
+        if self.config.retry_jitter_us == 0 {
+            return 0;
+        }
+        let mut x = self.jitter_state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.jitter_state = x;
+        x % self.config.retry_jitter_us
+    }
+}
+
+/// Communicates with the sensor hub over i2c.
+///
+/// The i2c port only needs to implement the embedded-hal `I2c` trait,
+/// so the bus can be shared with other devices by handing in a proxy
+/// such as the `RefCellDevice` or `CriticalSectionDevice` from
+/// `embedded-hal-bus`, or a `&mut` reference to the bus.
+pub struct I2cInterface<I2C, D = NoDelay> {
     /// i2c port
     i2c_port: I2C,
     /// address for i2c communications with the sensor hub
@@ -26,12 +138,15 @@ pub struct I2cInterface<I2C> {
 
     /// number of packets received
     received_packet_count: usize,
+
+    /// probe the standard addresses during setup
+    auto_detect: bool,
+
+    /// pacing and retries of the clock stretch tolerant mode, if enabled
+    clock_stretch: Option<ClockStretch<D>>,
 }
 
-impl<I2C, CommE> I2cInterface<I2C>
-where
-    I2C: embedded_hal::i2c::I2c<Error = CommE>,
-{
+impl<I2C> I2cInterface<I2C> {
     pub fn default(i2c: I2C) -> Self {
         Self::new(i2c, DEFAULT_ADDRESS)
     }
@@ -46,23 +161,120 @@ where
             address: addr,
             seg_recv_buf: [0; SEG_RECV_BUF_LEN],
             received_packet_count: 0,
+            auto_detect: false,
+            clock_stretch: None,
+        }
+    }
+
+    pub fn with_address(i2c: I2C, address: I2cAddress) -> Self {
+        Self::new(i2c, address.into())
+    }
+
+    /// Probe both standard addresses during `setup` (called by
+    /// `BNO080::init`), and use the first one that answers.
+    /// Afterwards, `address` reports which one was found.
+    pub fn auto_detect(i2c: I2C) -> Self {
+        Self {
+            auto_detect: true,
+            ..Self::new(i2c, DEFAULT_ADDRESS)
+        }
+    }
+
+    /// Switch to the clock stretch tolerant mode, pausing and retrying
+    /// bus transactions as set out in `config`, using `delay` to wait
+    pub fn clock_stretch_tolerant<D: DelayNs>(
+        self,
+        config: ClockStretchConfig,
+        delay: D,
+    ) -> I2cInterface<I2C, D> {
+        I2cInterface {
+            i2c_port: self.i2c_port,
+            address: self.address,
+            seg_recv_buf: self.seg_recv_buf,
+            received_packet_count: self.received_packet_count,
+            auto_detect: self.auto_detect,
+            clock_stretch: Some(ClockStretch {
+                config,
+                delay,
+                jitter_state: 0x2545_F491,
+            }),
         }
     }
+}
+
+impl<I2C, D> I2cInterface<I2C, D> {
+    /// The i2c address used to communicate with the sensor hub
+    pub fn address(&self) -> u8 {
+        self.address
+    }
 
+    /// Release the i2c port, so it can be reused after the sensor is shut down
     pub fn free(self) -> I2C {
         self.i2c_port
     }
 
+    #[cfg(test)]
+    pub(crate) fn i2c_port(&self) -> &I2C {
+        &self.i2c_port
+    }
+
+    #[cfg(test)]
+    pub(crate) fn i2c_port_mut(&mut self) -> &mut I2C {
+        &mut self.i2c_port
+    }
+}
+
+impl<I2C, D, CommE> I2cInterface<I2C, D>
+where
+    I2C: embedded_hal::i2c::I2c<Error = CommE>,
+    D: DelayNs,
+{
+    /// Run one bus transaction, paced and retried in clock stretch
+    /// tolerant mode
+    fn transact(
+        clock_stretch: &mut Option<ClockStretch<D>>,
+        mut op: impl FnMut() -> Result<(), CommE>,
+    ) -> Result<(), Error<CommE, ()>> {
+        match clock_stretch {
+            Some(clock_stretch) => clock_stretch.transact(op),
+            None => op(),
+        }
+        .map_err(Error::Comm)
+    }
+
+    /// The sensor hub sends the packet header again at the start of the
+    /// read that follows the header read, so the cargo always starts at
+    /// `PACKET_HEADER_LENGTH` in `packet`, the bytes read in that second
+    /// read. Returns the number of valid bytes in `packet`: the repeated
+    /// header may announce a shorter packet than the first one did, or
+    /// none at all, if the hub has given up on sending it in the meantime.
+    fn repeated_header_len(packet: &[u8]) -> usize {
+        let repeated_len = SensorCommon::parse_packet_header(packet);
+        if repeated_len < packet.len() {
+            trace!(
+                "repeated header announces {} of {} bytes",
+                repeated_len,
+                packet.len()
+            );
+        }
+        repeated_len.min(packet.len())
+    }
+
     fn read_packet_header(&mut self) -> Result<(), Error<CommE, ()>> {
         self.zero_recv_packet_header();
-        self.i2c_port
-            .read(self.address, &mut self.seg_recv_buf[..PACKET_HEADER_LENGTH])
-            .map_err(Error::Comm)?;
+        Self::transact(&mut self.clock_stretch, || {
+            self.i2c_port.read(
+                self.address,
+                &mut self.seg_recv_buf[..PACKET_HEADER_LENGTH],
+            )
+        })?;
 
         Ok(())
     }
 
-    /// Read the remainder of the packet after the packet header, if any
+    /// Read the remainder of the packet after the packet header, if any.
+    /// Cargo that does not fit in `packet_recv_buf` is read and discarded,
+    /// and the full packet length is returned.
     fn read_sized_packet(
         &mut self,
         total_packet_len: usize,
@@ -73,23 +285,23 @@ where
         let mut already_read_len: usize = 0;
 
         // zero packet header receive buffer
-        for byte in &mut packet_recv_buf[..PACKET_HEADER_LENGTH] {
+        for byte in packet_recv_buf.iter_mut().take(PACKET_HEADER_LENGTH) {
             *byte = 0;
         }
 
         // #[cfg(feature = "rttdebug")]
         // rprintln!("r.t {}", total_packet_len);
 
-        if total_packet_len < MAX_SEGMENT_READ {
+        if let Some(packet) = packet_recv_buf
+            .get_mut(..total_packet_len)
+            .filter(|_| total_packet_len < MAX_SEGMENT_READ)
+        {
             //read directly into the provided receive buffer
             if total_packet_len > 0 {
-                self.i2c_port
-                    .read(
-                        self.address,
-                        &mut packet_recv_buf[..total_packet_len],
-                    )
-                    .map_err(Error::Comm)?;
-                already_read_len = total_packet_len;
+                Self::transact(&mut self.clock_stretch, || {
+                    self.i2c_port.read(self.address, packet)
+                })?;
+                already_read_len = Self::repeated_header_len(packet);
             }
         } else {
             while remaining_body_len > 0 {
@@ -105,12 +317,13 @@ where
                 // rprintln!("r.s {:x} {}", self.address, segment_read_len);
 
                 self.zero_recv_packet_header();
-                self.i2c_port
-                    .read(
-                        self.address,
-                        &mut self.seg_recv_buf[..segment_read_len],
-                    )
-                    .map_err(Error::Comm)?;
+                let segment = self
+                    .seg_recv_buf
+                    .get_mut(..segment_read_len)
+                    .unwrap_or_default();
+                Self::transact(&mut self.clock_stretch, || {
+                    self.i2c_port.read(self.address, segment)
+                })?;
 
                 let promised_packet_len = SensorCommon::parse_packet_header(
                     &self.seg_recv_buf[..PACKET_HEADER_LENGTH],
@@ -133,12 +346,19 @@ where
                 } else {
                     segment_read_len
                 };
-                packet_recv_buf
-                    [already_read_len..already_read_len + transcribe_len]
-                    .copy_from_slice(
-                        &self.seg_recv_buf[transcribe_start_idx
-                            ..transcribe_start_idx + transcribe_len],
-                    );
+                // keep only what fits in the receive buffer
+                let transcribed = self
+                    .seg_recv_buf
+                    .get(transcribe_start_idx..)
+                    .unwrap_or_default()
+                    .iter()
+                    .take(transcribe_len);
+                let kept = packet_recv_buf
+                    .get_mut(already_read_len..)
+                    .unwrap_or_default();
+                for (slot, byte) in kept.iter_mut().zip(transcribed) {
+                    *slot = *byte;
+                }
                 already_read_len += transcribe_len;
 
                 let body_read_len = segment_read_len - PACKET_HEADER_LENGTH;
@@ -149,6 +369,22 @@ where
         Ok(already_read_len)
     }
 
+    /// Find which of the standard addresses the sensor hub answers on
+    fn probe_address(&mut self) -> Result<(), Error<CommE, ()>> {
+        for address in [DEFAULT_ADDRESS, ALTERNATE_ADDRESS] {
+            let header = &mut self.seg_recv_buf[..PACKET_HEADER_LENGTH];
+            if Self::transact(&mut self.clock_stretch, || {
+                self.i2c_port.read(address, header)
+            })
+            .is_ok()
+            {
+                self.address = address;
+                return Ok(());
+            }
+        }
+        Err(Error::SensorUnresponsive)
+    }
+
     fn zero_recv_packet_header(&mut self) {
         Self::zero_buffer(&mut self.seg_recv_buf[..PACKET_HEADER_LENGTH]);
     }
@@ -160,9 +396,10 @@ where
     }
 }
 
-impl<I2C, CommE> SensorInterface for I2cInterface<I2C>
+impl<I2C, D, CommE> SensorInterface for I2cInterface<I2C, D>
 where
     I2C: embedded_hal::i2c::I2c<Error = CommE>,
+    D: DelayNs,
 {
     type SensorError = Error<CommE, ()>;
 
@@ -177,19 +414,21 @@ where
         // #[cfg(feature = "rttdebug")]
         // rprintln!("i2c setup");
         delay_source.delay_ms(5);
+        if self.auto_detect {
+            self.probe_address()?;
+        }
         Ok(())
     }
 
     fn write_packet(&mut self, packet: &[u8]) -> Result<(), Self::SensorError> {
         #[cfg(feature = "rttdebug")]
         rprintln!("w {:x} {}", self.address, packet.len());
-        self.i2c_port
-            .write(self.address, packet)
-            .map_err(Error::Comm)?;
-        Ok(())
+        Self::transact(&mut self.clock_stretch, || {
+            self.i2c_port.write(self.address, packet)
+        })
     }
 
-    fn read_with_timeout(
+    fn read_packet_with_timeout(
         &mut self,
         recv_buf: &mut [u8],
         delay_source: &mut impl DelayNs,
@@ -214,7 +453,10 @@ where
         Ok(0)
     }
 
-    /// Read one packet into the receive buffer
+    /// Read one packet into the receive buffer: the packet header first,
+    /// then a read sized from the header, so the bus carries only the
+    /// bytes of the packet. Packets of `SEG_RECV_BUF_LEN` bytes or more
+    /// are read in segments of that size.
     fn read_packet(
         &mut self,
         recv_buf: &mut [u8],
@@ -246,6 +488,39 @@ where
         Ok(received_len)
     }
 
+    /// Unlike `read_packet`, the packet is never split into segments
+    /// of `SEG_RECV_BUF_LEN` bytes, each of which costs another repeated
+    /// header on the bus and a copy through the segment buffer:
+    /// a packet of any size takes exactly two transactions, the second
+    /// reading straight into `recv_buf`. The HAL must support reads of
+    /// up to `recv_buf.len()` bytes. A packet larger than `recv_buf` is
+    /// truncated, and the sensor hub sends the remainder as a continuation
+    /// packet on the next read.
+    /// By an estimate from the bytes transferred (not a measurement, see
+    /// the README), this saves about 2% of the bus time of long packets
+    /// at 400 kHz; the larger gain is the CPU time freed by DMA.
+    fn read_packet_bulk(
+        &mut self,
+        recv_buf: &mut [u8],
+    ) -> Result<usize, Self::SensorError> {
+        self.read_packet_header()?;
+        let packet_len = SensorCommon::parse_packet_header(
+            &self.seg_recv_buf[..PACKET_HEADER_LENGTH],
+        );
+        if packet_len <= PACKET_HEADER_LENGTH {
+            return Ok(packet_len);
+        }
+
+        let read_len = packet_len.min(recv_buf.len());
+        let packet = recv_buf.get_mut(..read_len).unwrap_or_default();
+        Self::transact(&mut self.clock_stretch, || {
+            self.i2c_port.read(self.address, packet)
+        })?;
+        self.received_packet_count += 1;
+
+        Ok(Self::repeated_header_len(packet))
+    }
+
     fn send_and_receive_packet(
         &mut self,
         send_buf: &[u8],
@@ -254,17 +529,20 @@ where
         // Cannot use write_read with bno080,
         // because it does not support repeated start with i2c.
 
-        self.i2c_port
-            .write(self.address, send_buf)
-            .map_err(Error::Comm)?;
+        Self::transact(&mut self.clock_stretch, || {
+            self.i2c_port.write(self.address, send_buf)
+        })?;
 
         self.zero_recv_packet_header();
         //stall before attempted read?
         Self::zero_buffer(recv_buf);
 
-        self.i2c_port
-            .read(self.address, &mut self.seg_recv_buf[..PACKET_HEADER_LENGTH])
-            .map_err(Error::Comm)?;
+        Self::transact(&mut self.clock_stretch, || {
+            self.i2c_port.read(
+                self.address,
+                &mut self.seg_recv_buf[..PACKET_HEADER_LENGTH],
+            )
+        })?;
 
         let packet_len = SensorCommon::parse_packet_header(
             &self.seg_recv_buf[..PACKET_HEADER_LENGTH],
@@ -285,6 +563,253 @@ where
 
 #[cfg(test)]
 mod tests {
+    extern crate std;
+    use crate::interface::i2c::{
+        ClockStretchConfig, ALTERNATE_ADDRESS, DEFAULT_ADDRESS,
+    };
+    use crate::interface::mock_i2c_port::{FakeDelay, FakeI2cPort};
+    use crate::interface::{I2cInterface, SensorInterface};
+    use core::cell::RefCell;
+    use embedded_hal_bus::i2c::RefCellDevice;
+
+    #[test]
+    fn test_shared_bus() {
+        let bus = RefCell::new(FakeI2cPort::new());
+        let mut first =
+            I2cInterface::new(RefCellDevice::new(&bus), DEFAULT_ADDRESS);
+        let mut second =
+            I2cInterface::new(RefCellDevice::new(&bus), ALTERNATE_ADDRESS);
+
+        assert!(first.write_packet(&[5, 0, 2, 0, 0xF9]).is_ok());
+        assert!(second.write_packet(&[5, 0, 2, 0, 0xF9]).is_ok());
+
+        let sent = &bus.borrow().sent_packets;
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].addr, DEFAULT_ADDRESS);
+        assert_eq!(sent[1].addr, ALTERNATE_ADDRESS);
+    }
+
+    #[test]
+    fn test_auto_detect_address() {
+        let mut port = FakeI2cPort::new();
+        port.responding_address = Some(ALTERNATE_ADDRESS);
+        let mut i2c_int = I2cInterface::auto_detect(port);
+        assert!(i2c_int.setup(&mut FakeDelay {}).is_ok());
+        assert_eq!(i2c_int.address(), ALTERNATE_ADDRESS);
+
+        let mut port = FakeI2cPort::new();
+        port.responding_address = Some(0x10);
+        let mut i2c_int = I2cInterface::auto_detect(port);
+        assert!(i2c_int.setup(&mut FakeDelay {}).is_err());
+    }
+
+    #[test]
+    fn test_read_packet_bulk() {
+        let mut packet = [0u8; 300];
+        packet[0] = (packet.len() & 0xFF) as u8;
+        packet[1] = (packet.len() >> 8) as u8;
+        packet[2] = 3;
+        packet[299] = 0xAA;
+
+        let mut port = FakeI2cPort::new();
+        // header read, then the whole packet in one read
+        port.add_available_packet(&packet[..4]);
+        port.add_available_packet(&packet);
+        let mut i2c_int = I2cInterface::default(port);
+
+        let mut recv_buf = [0u8; 512];
+        let rc = i2c_int.read_packet_bulk(&mut recv_buf);
+        assert_eq!(rc.unwrap_or(0), packet.len());
+        assert_eq!(recv_buf[..packet.len()], packet);
+        assert!(i2c_int.i2c_port().available_packets.is_empty());
+    }
+
+    #[test]
+    fn test_read_sized_from_header() {
+        let packet: [u8; 20] = [
+            20, 0, 3, 1, 0xFB, 1, 2, 3, 4, 0x05, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        let mut port = FakeI2cPort::new();
+        port.add_available_packet(&packet[..4]);
+        port.add_available_packet(&packet);
+        let mut i2c_int = I2cInterface::default(port);
+
+        // the header, then exactly the packet, however large the buffer
+        let mut recv_buf = [0u8; 1024];
+        let rc = i2c_int.read_packet(&mut recv_buf);
+        assert_eq!(rc.unwrap_or(0), packet.len());
+        assert_eq!(recv_buf[..packet.len()], packet);
+        assert_eq!(i2c_int.i2c_port().read_lengths, [4, 20]);
+
+        // nothing to read: only the header is read
+        i2c_int.i2c_port_mut().read_lengths.clear();
+        i2c_int.i2c_port_mut().add_available_packet(&[0; 4]);
+        assert_eq!(i2c_int.read_packet(&mut recv_buf).unwrap_or(1), 0);
+        assert_eq!(i2c_int.i2c_port().read_lengths, [4]);
+    }
+
+    #[test]
+    fn test_bulk_read_transactions() {
+        let mut packet = [0u8; 500];
+        packet[..4].copy_from_slice(&[0xF4, 0x01, 3, 0]);
+        let mut port = FakeI2cPort::new();
+        for _ in 0..2 {
+            port.add_available_packet(&packet[..4]);
+            port.add_available_packet(&packet);
+        }
+        let mut i2c_int = I2cInterface::default(port);
+        let mut recv_buf = [0u8; 512];
+
+        // segments of at most 240 bytes, each with a repeated header
+        let rc = i2c_int.read_packet(&mut recv_buf);
+        assert_eq!(rc.unwrap_or(0), packet.len());
+        assert_eq!(i2c_int.i2c_port().read_lengths, [4, 240, 240, 28]);
+
+        i2c_int.i2c_port_mut().read_lengths.clear();
+        let rc = i2c_int.read_packet_bulk(&mut recv_buf);
+        assert_eq!(rc.unwrap_or(0), packet.len());
+        assert_eq!(i2c_int.i2c_port().read_lengths, [4, 500]);
+    }
+
+    #[test]
+    fn test_read_oversize_packet() {
+        let mut packet = [0u8; 300];
+        packet[0] = (packet.len() & 0xFF) as u8;
+        packet[1] = (packet.len() >> 8) as u8;
+        packet[2] = 3;
+        packet[4] = 0xAA;
+
+        let mut port = FakeI2cPort::new();
+        port.add_available_packet(&packet[..4]);
+        port.add_available_packet(&packet);
+        let mut i2c_int = I2cInterface::default(port);
+
+        // the whole packet is read, but only what fits is kept
+        let mut recv_buf = [0u8; 64];
+        let rc = i2c_int.read_packet(&mut recv_buf);
+        assert_eq!(rc.unwrap_or(0), packet.len());
+        assert_eq!(recv_buf[..], packet[..64]);
+        assert!(i2c_int.i2c_port().available_packets.is_empty());
+    }
+
+    #[test]
+    fn test_repeated_header() {
+        let packet: [u8; 9] = [9, 0, 2, 7, 0xF8, 1, 2, 3, 4];
+        let mut port = FakeI2cPort::new();
+        port.add_available_packet(&packet[..4]);
+        port.add_available_packet(&packet);
+        let mut i2c_int = I2cInterface::default(port);
+
+        // the cargo starts right after the repeated header
+        let mut recv_buf = [0u8; 32];
+        let rc = i2c_int.read_packet(&mut recv_buf);
+        assert_eq!(rc.unwrap_or(0), packet.len());
+        assert_eq!(recv_buf[..4], packet[..4]);
+        assert_eq!(recv_buf[4], 0xF8);
+
+        // the repeated header announces a shorter packet than the first
+        let mut shortened = packet;
+        shortened[0] = 6;
+        i2c_int.i2c_port_mut().add_available_packet(&packet[..4]);
+        i2c_int.i2c_port_mut().add_available_packet(&shortened);
+        let rc = i2c_int.read_packet(&mut recv_buf);
+        assert_eq!(rc.unwrap_or(0), 6);
+
+        // the hub has nothing left to send by the second read
+        let mut empty = packet;
+        empty[0] = 0;
+        i2c_int.i2c_port_mut().add_available_packet(&packet[..4]);
+        i2c_int.i2c_port_mut().add_available_packet(&empty);
+        let rc = i2c_int.read_packet_bulk(&mut recv_buf);
+        assert_eq!(rc.unwrap_or(1), 0);
+    }
+
+    /// Records the requested delays
+    struct RecordingDelay {
+        delays_ns: std::vec::Vec<u64>,
+    }
+
+    impl embedded_hal::delay::DelayNs for RecordingDelay {
+        fn delay_ns(&mut self, ns: u32) {
+            self.delays_ns.push(ns.into());
+        }
+
+        fn delay_us(&mut self, us: u32) {
+            self.delays_ns.push(u64::from(us) * 1000);
+        }
+    }
+
+    #[test]
+    fn test_clock_stretch_tolerant() {
+        let mut packet = [0u8; 8];
+        packet[0] = packet.len() as u8;
+        packet[2] = 3;
+        packet[7] = 0xAA;
+
+        let mut port = FakeI2cPort::new();
+        port.add_available_packet(&packet[..4]);
+        port.add_available_packet(&packet);
+        // the header read fails twice before succeeding
+        port.fail_transactions = 2;
+        let config = ClockStretchConfig {
+            transaction_gap_us: 50,
+            max_retries: 2,
+            retry_delay_us: 400,
+            retry_jitter_us: 100,
+        };
+        let mut i2c_int = I2cInterface::default(port).clock_stretch_tolerant(
+            config,
+            RecordingDelay {
+                delays_ns: std::vec::Vec::new(),
+            },
+        );
+
+        let mut recv_buf = [0u8; 16];
+        let rc = i2c_int.read_packet(&mut recv_buf);
+        assert_eq!(rc.unwrap_or(0), packet.len());
+        assert_eq!(recv_buf[..packet.len()], packet);
+
+        // gap, two jittered retries, then a gap before the body
+        let delays = &i2c_int.clock_stretch.as_ref().unwrap().delay.delays_ns;
+        assert_eq!(delays.len(), 4);
+        assert_eq!(delays[0], 50_000);
+        assert!((400_000..500_000).contains(&delays[1]));
+        assert!((400_000..500_000).contains(&delays[2]));
+        assert_eq!(delays[3], 50_000);
+
+        // retries are bounded
+        i2c_int.i2c_port_mut().fail_transactions = 3;
+        assert!(i2c_int.write_packet(&[5, 0, 2, 0, 0xF9]).is_err());
+    }
+
+    #[test]
+    fn test_clock_stretch_auto_detect() {
+        let mut port = FakeI2cPort::new();
+        port.responding_address = Some(ALTERNATE_ADDRESS);
+        let config = ClockStretchConfig {
+            transaction_gap_us: 50,
+            max_retries: 1,
+            retry_delay_us: u32::MAX,
+            retry_jitter_us: 100,
+        };
+        let mut i2c_int = I2cInterface::auto_detect(port)
+            .clock_stretch_tolerant(
+                config,
+                RecordingDelay {
+                    delays_ns: std::vec::Vec::new(),
+                },
+            );
+        assert!(i2c_int.setup(&mut FakeDelay {}).is_ok());
+        assert_eq!(i2c_int.address(), ALTERNATE_ADDRESS);
+
+        // each probe is paced, and the default address retried once,
+        // the retry delay saturating rather than overflowing
+        let delays = &i2c_int.clock_stretch.as_ref().unwrap().delay.delays_ns;
+        assert_eq!(delays[0], 50_000);
+        assert_eq!(delays[1], u64::from(u32::MAX) * 1000);
+        assert_eq!(delays[2], 50_000);
+    }
+
     // use crate::interface::i2c::DEFAULT_ADDRESS;
     // use crate::interface::mock_i2c_port::FakeI2cPort;
     // use crate::interface::I2cInterface;
@@ -0,0 +1,43 @@
+/*
+Copyright (c) 2020 Todd Stellanova
+LICENSE: BSD3 (see LICENSE file)
+*/
+
+//! Opening the sensor hub from Linux userspace, through the i2cdev and
+//! spidev kernel drivers and `linux-embedded-hal`, e.g. on a Raspberry Pi
+
+use super::{I2cAddress, I2cInterface};
+
+use linux_embedded_hal::i2cdev::linux::LinuxI2CError;
+use linux_embedded_hal::spidev::{SpiModeFlags, Spidev, SpidevOptions};
+use linux_embedded_hal::{I2cdev, SpidevDevice};
+use std::io;
+use std::path::Path;
+
+/// Maximum SPI clock rate of the sensor hub (Hz)
+pub const SPI_MAX_SPEED_HZ: u32 = 3_000_000;
+
+impl I2cInterface<I2cdev> {
+    /// Open an i2c bus device, such as `/dev/i2c-1`
+    pub fn open(
+        path: impl AsRef<Path>,
+        address: I2cAddress,
+    ) -> Result<Self, LinuxI2CError> {
+        Ok(Self::with_address(I2cdev::new(path)?, address))
+    }
+}
+
+/// Open a spidev device, such as `/dev/spidev0.0`, configured for the
+/// sensor hub: SPI mode 3, 8 bit words, at most `SPI_MAX_SPEED_HZ`.
+/// The returned device can be passed as the `spi` of `SpiControlLines`,
+/// with the interrupt and reset lines as `linux_embedded_hal::CdevPin`s.
+pub fn open_spidev(path: impl AsRef<Path>) -> io::Result<SpidevDevice> {
+    let mut spi = Spidev::open(path)?;
+    let options = SpidevOptions::new()
+        .bits_per_word(8)
+        .max_speed_hz(SPI_MAX_SPEED_HZ)
+        .mode(SpiModeFlags::SPI_MODE_3)
+        .build();
+    spi.configure(&options)?;
+    Ok(SpidevDevice(spi))
+}
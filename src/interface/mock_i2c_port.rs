@@ -3,16 +3,19 @@ extern crate std;
 use super::PACKET_HEADER_LENGTH;
 
 use core::ops::Shr;
-use embedded_hal::i2c::I2c;
+use embedded_hal::delay::DelayNs;
+use embedded_hal::i2c::{
+    ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation,
+};
 use std::collections::VecDeque;
 
-// struct FakeDelay {}
+pub struct FakeDelay {}
 
-// impl DelayMs<u8> for FakeDelay {
-//     fn delay_ms(&mut self, _ms: u8) {
-//         // no-op
-//     }
-// }
+impl DelayNs for FakeDelay {
+    fn delay_ns(&mut self, _ns: u32) {
+        // no-op
+    }
+}
 
 const MAX_FAKE_PACKET_SIZE: usize = 512;
 
@@ -31,14 +34,33 @@ impl FakePacket {
             len: src_len,
             buf: [0; MAX_FAKE_PACKET_SIZE],
         };
-        inst.buf[..src_len].copy_from_slice(&slice);
+        inst.buf[..src_len].copy_from_slice(slice);
         inst
     }
+
+    /// The bytes of the packet
+    pub fn as_slice(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
 }
 
+pub type FakePacketQueue = VecDeque<FakePacket>;
+
 pub struct FakeI2cPort {
-    pub available_packets: VecDeque<FakePacket>,
-    pub sent_packets: VecDeque<FakePacket>,
+    pub available_packets: FakePacketQueue,
+    pub sent_packets: FakePacketQueue,
+    /// Length of each read, in order
+    pub read_lengths: std::vec::Vec<usize>,
+    /// Number of upcoming transactions to fail with a NAK
+    pub fail_transactions: usize,
+    /// If set, transactions to any other address fail with a NAK
+    pub responding_address: Option<u8>,
+}
+
+impl Default for FakeI2cPort {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl FakeI2cPort {
@@ -46,6 +68,9 @@ impl FakeI2cPort {
         FakeI2cPort {
             available_packets: VecDeque::with_capacity(3),
             sent_packets: VecDeque::with_capacity(3),
+            read_lengths: std::vec::Vec::new(),
+            fail_transactions: 0,
+            responding_address: None,
         }
     }
 
@@ -56,18 +81,19 @@ impl FakeI2cPort {
     }
 }
 
-impl I2c for FakeI2cPort {
-    fn read(&mut self, addr: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+impl FakeI2cPort {
+    fn read(&mut self, addr: u8, buffer: &mut [u8]) {
+        self.read_lengths.push(buffer.len());
         let next_pack =
             self.available_packets.pop_front().unwrap_or(FakePacket {
-                addr: addr,
+                addr,
                 len: 0,
                 buf: [0; MAX_FAKE_PACKET_SIZE],
             });
 
         let src_len = next_pack.len;
         if src_len == 0 {
-            return Ok(());
+            return;
         }
 
         let dest_len = buffer.len();
@@ -79,7 +105,7 @@ impl I2c for FakeI2cPort {
             buffer[..read_len].copy_from_slice(&next_pack.buf[..read_len]);
             let remainder_len = src_len - read_len;
             let mut remainder_packet = FakePacket {
-                addr: addr,
+                addr,
                 len: remainder_len + 4,
                 buf: [0; MAX_FAKE_PACKET_SIZE],
             };
@@ -99,34 +125,38 @@ impl I2c for FakeI2cPort {
             // src_len < dest_len
             panic!("src_len {} dest_len {}", src_len, dest_len);
         }
-
-        Ok(())
     }
-}
 
-impl I2c for FakeI2cPort {
-    fn transaction(
-        &mut self,
-        address: u8,
-        operations: &mut [embedded_hal::i2c::Operation<'_>],
-    ) -> Result<(), Self::Error> {
-        let sent_pack = FakePacket::new_from_slice(_bytes);
+    fn write(&mut self, addr: u8, bytes: &[u8]) {
+        let mut sent_pack = FakePacket::new_from_slice(bytes);
+        sent_pack.addr = addr;
         self.sent_packets.push_back(sent_pack);
-        Ok(())
     }
 }
 
-impl WriteRead for FakeI2cPort {
-    type Error = ();
+impl ErrorType for FakeI2cPort {
+    type Error = ErrorKind;
+}
 
-    fn write_read(
+impl I2c for FakeI2cPort {
+    fn transaction(
         &mut self,
         address: u8,
-        send_buf: &[u8],
-        recv_buf: &mut [u8],
+        operations: &mut [Operation<'_>],
     ) -> Result<(), Self::Error> {
-        self.write(address, send_buf)?;
-        self.read(address, recv_buf)?;
+        if self.fail_transactions > 0 {
+            self.fail_transactions -= 1;
+            return Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address));
+        }
+        if self.responding_address.is_some_and(|addr| addr != address) {
+            return Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address));
+        }
+        for op in operations {
+            match op {
+                Operation::Read(buffer) => self.read(address, buffer),
+                Operation::Write(bytes) => self.write(address, bytes),
+            }
+        }
         Ok(())
     }
 }
@@ -0,0 +1,199 @@
+extern crate std;
+
+use super::mock_i2c_port::FakePacket;
+
+use core::cell::RefCell;
+use core::convert::Infallible;
+use embedded_hal::digital::{self, InputPin, OutputPin};
+use embedded_hal::spi::{self, ErrorKind, Operation, SpiDevice};
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+/// State shared between the fake SPI port and its control pins
+pub struct FakeSpiState {
+    pub available_packets: VecDeque<FakePacket>,
+    pub sent_packets: VecDeque<FakePacket>,
+    /// Levels returned by successive HINTN polls (true = asserted low).
+    /// Once the script runs out, HINTN is asserted whenever
+    /// a packet is available.
+    pub hintn_script: VecDeque<bool>,
+    /// Number of reset pulses seen on RSTN
+    pub reset_count: usize,
+    /// packet currently being clocked out, and the read offset within it
+    current_packet: Option<(FakePacket, usize)>,
+    reset_asserted: bool,
+}
+
+pub struct FakeSpiPort {
+    state: Rc<RefCell<FakeSpiState>>,
+}
+
+/// One of the control lines of the fake SPI port
+pub struct FakeSpiPin {
+    state: Rc<RefCell<FakeSpiState>>,
+    line: FakeSpiLine,
+}
+
+#[derive(Clone, Copy)]
+enum FakeSpiLine {
+    ChipSelect,
+    HostInterrupt,
+    Reset,
+}
+
+impl Default for FakeSpiPort {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FakeSpiPort {
+    pub fn new() -> Self {
+        FakeSpiPort {
+            state: Rc::new(RefCell::new(FakeSpiState {
+                available_packets: VecDeque::with_capacity(3),
+                sent_packets: VecDeque::with_capacity(3),
+                hintn_script: VecDeque::new(),
+                reset_count: 0,
+                current_packet: None,
+                reset_asserted: false,
+            })),
+        }
+    }
+
+    /// Access the state shared with the control pins
+    pub fn state(&self) -> Rc<RefCell<FakeSpiState>> {
+        self.state.clone()
+    }
+
+    /// Enqueue a packet to be received later
+    pub fn add_available_packet(&mut self, bytes: &[u8]) {
+        let pack = FakePacket::new_from_slice(bytes);
+        self.state.borrow_mut().available_packets.push_back(pack);
+    }
+
+    /// Script the levels returned by the next HINTN polls
+    pub fn script_hintn(&mut self, levels: &[bool]) {
+        self.state.borrow_mut().hintn_script.extend(levels);
+    }
+
+    pub fn csn(&self) -> FakeSpiPin {
+        self.pin(FakeSpiLine::ChipSelect)
+    }
+
+    pub fn hintn(&self) -> FakeSpiPin {
+        self.pin(FakeSpiLine::HostInterrupt)
+    }
+
+    pub fn reset(&self) -> FakeSpiPin {
+        self.pin(FakeSpiLine::Reset)
+    }
+
+    fn pin(&self, line: FakeSpiLine) -> FakeSpiPin {
+        FakeSpiPin {
+            state: self.state.clone(),
+            line,
+        }
+    }
+}
+
+impl FakeSpiState {
+    fn read(&mut self, buffer: &mut [u8]) {
+        if self.current_packet.is_none() {
+            self.current_packet =
+                self.available_packets.pop_front().map(|pack| (pack, 0));
+        }
+
+        for byte in buffer.iter_mut() {
+            *byte = 0;
+        }
+        if let Some((pack, offset)) = self.current_packet.as_mut() {
+            let read_len = buffer.len().min(pack.len - *offset);
+            buffer[..read_len]
+                .copy_from_slice(&pack.buf[*offset..*offset + read_len]);
+            *offset += read_len;
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.sent_packets
+            .push_back(FakePacket::new_from_slice(bytes));
+    }
+}
+
+impl spi::ErrorType for FakeSpiPort {
+    type Error = ErrorKind;
+}
+
+impl SpiDevice for FakeSpiPort {
+    fn transaction(
+        &mut self,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), Self::Error> {
+        let mut state = self.state.borrow_mut();
+        for op in operations {
+            match op {
+                Operation::Read(buffer) => state.read(buffer),
+                Operation::Write(bytes) => state.write(bytes),
+                Operation::Transfer(read, write) => {
+                    state.write(write);
+                    state.read(read);
+                }
+                Operation::TransferInPlace(buffer) => {
+                    state.write(buffer);
+                    state.read(buffer);
+                }
+                Operation::DelayNs(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl digital::ErrorType for FakeSpiPin {
+    type Error = Infallible;
+}
+
+impl OutputPin for FakeSpiPin {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        let mut state = self.state.borrow_mut();
+        match self.line {
+            FakeSpiLine::Reset => state.reset_asserted = true,
+            FakeSpiLine::ChipSelect | FakeSpiLine::HostInterrupt => {}
+        }
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        let mut state = self.state.borrow_mut();
+        match self.line {
+            FakeSpiLine::ChipSelect => {
+                // deselecting ends the transfer of the current packet
+                state.current_packet = None;
+            }
+            FakeSpiLine::Reset => {
+                if state.reset_asserted {
+                    state.reset_count += 1;
+                }
+                state.reset_asserted = false;
+            }
+            FakeSpiLine::HostInterrupt => {}
+        }
+        Ok(())
+    }
+}
+
+impl InputPin for FakeSpiPin {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        self.is_low().map(|low| !low)
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        let mut state = self.state.borrow_mut();
+        let asserted = match state.hintn_script.pop_front() {
+            Some(level) => level,
+            None => !state.available_packets.is_empty(),
+        };
+        Ok(asserted)
+    }
+}
@@ -1,8 +1,18 @@
+// buffers are only accessed through checked accesses, so that malformed
+// traffic from the sensor hub cannot cause a panic
+#![cfg_attr(not(test), deny(clippy::indexing_slicing))]
+
 pub mod i2c;
+#[cfg(all(feature = "linux", target_os = "linux"))]
+pub mod linux;
 pub mod spi;
+#[cfg(feature = "uart")]
+pub mod uart;
 
 #[cfg(test)]
 pub mod mock_i2c_port;
+#[cfg(test)]
+pub mod mock_spi_port;
 
 use core::ops::Shl;
 
@@ -10,36 +20,86 @@ use embedded_hal::delay::DelayNs;
 #[cfg(feature = "rttdebug")]
 use panic_rtt_core::rprintln;
 
-/// A method of communicating with the sensor
+/// A method of communicating with the sensor: the transport of SHTP
+/// packets between the host and the sensor hub.
+///
+/// The driver is generic over this trait, and it is not sealed: a custom
+/// transport (e.g. i2c through a multiplexer, or an FPGA bridge) works with
+/// `BNO080` once it implements this contract.
+///
+/// - A packet is a 4 byte SHTP header, whose first two bytes carry the
+///   total length of the packet including the header (bit 15 being the
+///   continuation flag), followed by its body.
+/// - Reads return the total length of the packet from its header, or zero
+///   if no packet was available. Zero is not an error.
+/// - Errors are reserved for failures of the bus or pins; the driver
+///   counts them and may retry the call according to its `RetryPolicy`.
 pub trait SensorInterface {
     /// Interface error type
     type SensorError;
 
-    /// give the sensor interface a chance to set up
+    /// Give the sensor interface a chance to set up, before any packet is
+    /// exchanged: e.g. reset the sensor hub and wait for it to wake, or
+    /// detect its address. Called once from `BNO080::init`.
     fn setup(
         &mut self,
         delay_source: &mut impl DelayNs,
     ) -> Result<(), Self::SensorError>;
 
-    /// Write the whole packet provided
+    /// Write the whole packet provided, header included, as one transfer
     fn write_packet(&mut self, packet: &[u8]) -> Result<(), Self::SensorError>;
 
-    /// Read the next packet from the sensor
-    /// Returns the size of the packet read (up to the size of the slice provided)
+    /// Read the next packet from the sensor, if one is available,
+    /// without waiting for one.
+    /// Returns the size of the packet read. A packet larger than the slice
+    /// provided is read in full, but only the part that fits is kept;
+    /// the returned size is then larger than the slice.
     fn read_packet(
         &mut self,
         recv_buf: &mut [u8],
     ) -> Result<usize, Self::SensorError>;
 
-    /// Wait for sensor to indicate it has data available before reading
+    /// Read the next packet using one bus transaction for the packet header,
+    /// then a single transaction, sized from the header, for the whole packet
+    /// into a contiguous region of `recv_buf`, so that HALs can service the
+    /// second transaction with DMA.
+    /// Returns the size of the packet read (up to the size of the slice provided)
+    fn read_packet_bulk(
+        &mut self,
+        recv_buf: &mut [u8],
+    ) -> Result<usize, Self::SensorError> {
+        self.read_packet(recv_buf)
+    }
+
+    /// Wait for sensor to indicate it has data available before reading,
+    /// as `read_packet` does. Returns zero if no packet arrived in time.
     /// - `max_ms` maximum number of milliseconds to wait for data
-    fn read_with_timeout(
+    fn read_packet_with_timeout(
         &mut self,
         recv_buf: &mut [u8],
         delay_source: &mut impl DelayNs,
         max_ms: u8,
     ) -> Result<usize, Self::SensorError>;
 
+    /// Wait for sensor to indicate it has data available before reading
+    #[deprecated(note = "renamed to `read_packet_with_timeout`")]
+    fn read_with_timeout(
+        &mut self,
+        recv_buf: &mut [u8],
+        delay_source: &mut impl DelayNs,
+        max_ms: u8,
+    ) -> Result<usize, Self::SensorError> {
+        self.read_packet_with_timeout(recv_buf, delay_source, max_ms)
+    }
+
+    /// Does the sensor hub have a packet ready to be read?
+    /// Interfaces with an interrupt line (HINTN) report its state;
+    /// the default, for interfaces without one, is always `true`,
+    /// and a read then returns zero if there was no packet after all.
+    fn data_ready(&mut self) -> bool {
+        true
+    }
+
     /// Send a packet and receive the response immediately
     fn send_and_receive_packet(
         &mut self,
@@ -48,11 +108,14 @@ pub trait SensorInterface {
     ) -> Result<usize, Self::SensorError>;
 
     /// Does this interface require a soft reset after init?
+    /// (i.e. `setup` cannot reset the sensor hub with a reset line)
     fn requires_soft_reset(&self) -> bool;
 }
 
-pub use self::i2c::I2cInterface;
+pub use self::i2c::{ClockStretchConfig, I2cAddress, I2cInterface, NoDelay};
 pub use self::spi::SpiInterface;
+#[cfg(feature = "uart")]
+pub use self::uart::UartInterface;
 
 pub(crate) const PACKET_HEADER_LENGTH: usize = 4;
 pub(crate) const MAX_CARGO_DATA_LENGTH: usize = 32766 - PACKET_HEADER_LENGTH;
@@ -63,13 +126,13 @@ impl SensorCommon {
     fn parse_packet_header(packet: &[u8]) -> usize {
         const CONTINUATION_FLAG_MASK: u16 = 0x80;
         const CONTINUATION_FLAG_CLEAR: u16 = !(CONTINUATION_FLAG_MASK);
-        if packet.len() < PACKET_HEADER_LENGTH {
+        let Some(header) = packet.first_chunk::<PACKET_HEADER_LENGTH>() else {
             return 0;
-        }
+        };
         //Bits 14:0 are used to indicate the total number of bytes in the body plus header
         //maximum packet length is ... PACKET_HEADER_LENGTH
-        let raw_pack_len: u16 = (packet[0] as u16)
-            + ((packet[1] as u16) & CONTINUATION_FLAG_CLEAR).shl(8);
+        let raw_pack_len: u16 = (header[0] as u16)
+            + ((header[1] as u16) & CONTINUATION_FLAG_CLEAR).shl(8);
 
         let mut packet_len: usize = raw_pack_len as usize;
         if packet_len > MAX_CARGO_DATA_LENGTH {
@@ -79,12 +142,7 @@ impl SensorCommon {
 
         if 0 == packet_len && 0 != raw_pack_len {
             #[cfg(feature = "rttdebug")]
-            rprintln!(
-                "pph: {:?} {} -> {}",
-                &packet[..PACKET_HEADER_LENGTH],
-                raw_pack_len,
-                packet_len
-            );
+            rprintln!("pph: {:?} {} -> {}", header, raw_pack_len, packet_len);
         } else {
             // hprintln!("pph: {:?} {} ", &packet[..PACKET_HEADER_LENGTH], packet_len).unwrap();
         }
@@ -115,7 +173,7 @@ mod tests {
         assert_eq!(size, long_packet_len, "verify > 255 packet length");
 
         //now set the continuation flag
-        raw_packet[1] = 0x80 | raw_packet[1];
+        raw_packet[1] |= 0x80;
         let size = SensorCommon::parse_packet_header(&raw_packet);
         assert_eq!(size, long_packet_len, "verify continuation packet");
 
@@ -129,17 +187,17 @@ mod tests {
         let size = SensorCommon::parse_packet_header(&raw_packet);
         assert_eq!(size, short_packet_len, "verify short packet");
 
-        raw_packet[1] = 0x80 | raw_packet[1];
+        raw_packet[1] |= 0x80;
         let size = SensorCommon::parse_packet_header(&raw_packet);
         assert_eq!(size, short_packet_len, "verify short packet continuation");
 
         // first (uncontinued) packet
-        raw_packet = [20 as u8, 1 as u8, 0, 0];
+        raw_packet = [20, 1, 0, 0];
         let size = SensorCommon::parse_packet_header(&raw_packet);
         assert_eq!(size, 276, "verify > 255 packet length");
 
         //from actual received packet
-        raw_packet = [19 as u8, 129 as u8, 0, 1];
+        raw_packet = [19, 129, 0, 1];
         let size = SensorCommon::parse_packet_header(&raw_packet);
         assert_eq!(size, 275, "verify > 255 packet length");
     }
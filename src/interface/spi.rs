@@ -11,6 +11,10 @@ use crate::Error::SensorUnresponsive;
 #[cfg(feature = "rttdebug")]
 use panic_rtt_core::rprintln;
 
+/// Size of the chunks in which cargo that does not fit the receive buffer
+/// is read and discarded
+const DISCARD_CHUNK_LEN: usize = 32;
+
 /// Encapsulates all the lines required to operate this sensor
 /// - SCK: clock line from master
 /// - MISO: Data input from the sensor to the master
@@ -54,6 +58,17 @@ where
         }
     }
 
+    /// Release the SPI peripheral and control pins,
+    /// so they can be reused after the sensor is shut down
+    pub fn free(self) -> SpiControlLines<SPI, CSN, IN, RSTN> {
+        SpiControlLines {
+            spi: self.spi,
+            csn: self.csn,
+            hintn: self.hintn,
+            reset: self.reset,
+        }
+    }
+
     /// Is the sensor indicating it has data available
     /// "In SPI and I2C mode the HOST_INTN signal is used by the BNO080 to
     /// indicate to the application processor that the BNO080 needs attention."
@@ -93,27 +108,43 @@ where
         false
     }
 
-    /// read the body ("cargo" or "payload") of a packet,
-    /// return the total packet length read
-    fn read_packet_cargo(&mut self, recv_buf: &mut [u8]) -> usize {
-        let mut packet_len = SensorCommon::parse_packet_header(
-            &recv_buf[..PACKET_HEADER_LENGTH],
-        );
+    /// Store the packet `header` just read at the start of `recv_buf`,
+    /// then read the body ("cargo" or "payload") of the packet after it,
+    /// and return the total packet length read.
+    /// Cargo that does not fit in `recv_buf` is read and discarded,
+    /// so that the next read starts at a packet boundary.
+    fn read_packet_cargo(
+        &mut self,
+        header: &[u8; PACKET_HEADER_LENGTH],
+        recv_buf: &mut [u8],
+    ) -> usize {
+        for (slot, byte) in recv_buf.iter_mut().zip(header) {
+            *slot = *byte;
+        }
+        let packet_len = SensorCommon::parse_packet_header(header);
+        if packet_len <= PACKET_HEADER_LENGTH {
+            return 0;
+        }
         // now get the body
-        if (packet_len > PACKET_HEADER_LENGTH) && (packet_len < recv_buf.len())
-        {
-            //exchange 0xFF bytes for whatever the sensor is sending
-            for w in recv_buf[PACKET_HEADER_LENGTH..packet_len].iter_mut() {
-                *w = 0xFF;
-            }
-            let rc = self
-                .spi
-                .read(&mut recv_buf[PACKET_HEADER_LENGTH..packet_len]);
-            if rc.is_err() {
-                packet_len = 0;
+        let cargo_len = packet_len - PACKET_HEADER_LENGTH;
+        let body = recv_buf.get_mut(PACKET_HEADER_LENGTH..).unwrap_or_default();
+        let kept_len = cargo_len.min(body.len());
+        let body = body.get_mut(..kept_len).unwrap_or_default();
+        //exchange 0xFF bytes for whatever the sensor is sending
+        body.fill(0xFF);
+        if self.spi.read(body).is_err() {
+            return 0;
+        }
+
+        let mut discard_buf = [0xFF; DISCARD_CHUNK_LEN];
+        let mut remaining_len = cargo_len - kept_len;
+        while remaining_len > 0 {
+            let chunk_len = remaining_len.min(DISCARD_CHUNK_LEN);
+            let chunk = discard_buf.get_mut(..chunk_len).unwrap_or_default();
+            if self.spi.read(chunk).is_err() {
+                return 0;
             }
-        } else {
-            packet_len = 0;
+            remaining_len -= chunk_len;
         }
 
         packet_len
@@ -174,16 +205,14 @@ where
         // select the sensor
         self.csn.set_low().map_err(Error::Pin)?;
         let rc = self.spi.write(send_buf).map_err(Error::Comm);
+        //release the sensor
         self.csn.set_high().map_err(Error::Pin)?;
-        if let Err(e) = rc {
-            //release the sensor
-            return Err(e);
-        }
+        rc?;
         #[cfg(feature = "rttdebug")]
         rprintln!("sent {}", send_buf.len());
 
         //zero the receive buffer
-        for i in recv_buf[..PACKET_HEADER_LENGTH].iter_mut() {
+        for i in recv_buf.iter_mut().take(PACKET_HEADER_LENGTH) {
             *i = 0;
         }
 
@@ -196,19 +225,17 @@ where
 
         self.csn.set_low().map_err(Error::Pin)?;
         // get just the header
-        let rc = self
-            .spi
-            .read(&mut recv_buf[..PACKET_HEADER_LENGTH])
-            .map_err(Error::Comm);
-        if rc.is_err() {
+        let mut header = [0u8; PACKET_HEADER_LENGTH];
+        let rc = self.spi.read(&mut header).map_err(Error::Comm);
+        if let Err(e) = rc {
             //release the sensor
             #[cfg(feature = "rttdebug")]
-            rprintln!("transfer err: {:?}", rc);
+            rprintln!("transfer err: {:?}", e);
             self.csn.set_high().map_err(Error::Pin)?;
-            return Err(rc.unwrap_err());
+            return Err(e);
         }
 
-        let packet_len = self.read_packet_cargo(recv_buf);
+        let packet_len = self.read_packet_cargo(&header, recv_buf);
 
         //release the sensor
         self.csn.set_high().map_err(Error::Pin)?;
@@ -224,12 +251,7 @@ where
         self.csn.set_low().map_err(Error::Pin)?;
         let rc = self.spi.write(packet).map_err(Error::Comm);
         self.csn.set_high().map_err(Error::Pin)?;
-
-        if let Err(e) = rc {
-            return Err(e);
-        }
-
-        Ok(())
+        rc
     }
 
     /// Read a complete packet from the sensor
@@ -241,25 +263,20 @@ where
         // As soon as host selects CSN, HINTN resets
 
         //Zero the header bytes are zeroed since we're not sending any data
-        for i in recv_buf[..PACKET_HEADER_LENGTH].iter_mut() {
-            *i = 0;
-        }
+        let mut header = [0u8; PACKET_HEADER_LENGTH];
 
         // grab this sensor
         self.csn.set_low().map_err(Error::Pin)?;
         // get just the header
-        let rc = self
-            .spi
-            .read(&mut recv_buf[..PACKET_HEADER_LENGTH])
-            .map_err(Error::Comm);
+        let rc = self.spi.read(&mut header).map_err(Error::Comm);
 
-        if rc.is_err() {
+        if let Err(e) = rc {
             //release the sensor
             self.csn.set_high().map_err(Error::Pin)?;
-            return Err(rc.unwrap_err());
+            return Err(e);
         }
 
-        let packet_len = self.read_packet_cargo(recv_buf);
+        let packet_len = self.read_packet_cargo(&header, recv_buf);
 
         //release the sensor
         self.csn.set_high().map_err(Error::Pin)?;
@@ -271,7 +288,7 @@ where
         Ok(packet_len)
     }
 
-    fn read_with_timeout(
+    fn read_packet_with_timeout(
         &mut self,
         recv_buf: &mut [u8],
         delay_source: &mut impl DelayNs,
@@ -282,4 +299,87 @@ where
         }
         Ok(0)
     }
+
+    fn data_ready(&mut self) -> bool {
+        self.hintn_signaled()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interface::mock_i2c_port::{FakeDelay, FakePacket};
+    use crate::interface::mock_spi_port::{FakeSpiPin, FakeSpiPort};
+    use crate::interface::spi::{SpiControlLines, SpiInterface};
+    use crate::interface::SensorInterface;
+    use crate::Error;
+
+    fn spi_interface(
+        port: FakeSpiPort,
+    ) -> SpiInterface<FakeSpiPort, FakeSpiPin, FakeSpiPin, FakeSpiPin> {
+        let (csn, hintn, reset) = (port.csn(), port.hintn(), port.reset());
+        SpiInterface::new(SpiControlLines {
+            spi: port,
+            csn,
+            hintn,
+            reset,
+        })
+    }
+
+    #[test]
+    fn test_setup_waits_for_wake() {
+        let mut port = FakeSpiPort::new();
+        // sensor takes a few polls to signal it is awake
+        port.script_hintn(&[false, false, false, true]);
+        let state = port.state();
+
+        let mut spi_int = spi_interface(port);
+        assert!(spi_int.setup(&mut FakeDelay {}).is_ok());
+        assert_eq!(state.borrow().reset_count, 1);
+        assert!(state.borrow().hintn_script.is_empty());
+    }
+
+    #[test]
+    fn test_setup_unresponsive() {
+        let port = FakeSpiPort::new();
+        let mut spi_int = spi_interface(port);
+        let rc = spi_int.setup(&mut FakeDelay {});
+        assert!(matches!(rc, Err(Error::SensorUnresponsive)));
+    }
+
+    #[test]
+    fn test_read_with_timeout() {
+        let mut port = FakeSpiPort::new();
+        let packet: [u8; 6] = [6, 0, 2, 0, 0xF1, 0x84];
+        port.add_available_packet(&packet);
+        let state = port.state();
+
+        let mut spi_int = spi_interface(port);
+        let mut recv_buf = [0u8; 32];
+        assert!(spi_int.data_ready());
+        let rc = spi_int.read_packet_with_timeout(
+            &mut recv_buf,
+            &mut FakeDelay {},
+            5,
+        );
+        assert_eq!(rc.unwrap_or(0), packet.len());
+        assert_eq!(recv_buf[..packet.len()], packet);
+
+        // nothing more to read
+        assert!(!spi_int.data_ready());
+        let rc = spi_int.read_packet_with_timeout(
+            &mut recv_buf,
+            &mut FakeDelay {},
+            5,
+        );
+        assert_eq!(rc.unwrap_or(1), 0);
+
+        // the deprecated name still works
+        state
+            .borrow_mut()
+            .available_packets
+            .push_back(FakePacket::new_from_slice(&packet));
+        #[allow(deprecated)]
+        let rc = spi_int.read_with_timeout(&mut recv_buf, &mut FakeDelay {}, 5);
+        assert_eq!(rc.unwrap_or(0), packet.len());
+    }
 }
@@ -0,0 +1,481 @@
+use super::{NoDelay, SensorInterface};
+use crate::Error;
+
+use embedded_hal::delay::DelayNs;
+use embedded_io::{Read, ReadReady, Write};
+
+/// Flag byte delimiting each frame
+const FRAME_FLAG: u8 = 0x7E;
+/// Escape byte: the following byte is XORed with `ESCAPE_XOR`
+const FRAME_ESCAPE: u8 = 0x7D;
+const ESCAPE_XOR: u8 = 0x20;
+/// Protocol ID of a buffer status frame: empty when the host queries
+/// the status, holding the free space in the hub's receive buffer, LSB
+/// first, when the hub reports it
+const PROTOCOL_BUFFER_STATUS: u8 = 0x00;
+/// Protocol ID of a frame carrying an SHTP packet
+const PROTOCOL_SHTP: u8 = 0x01;
+
+/// Queries of the hub's buffer status before a write gives up
+const BUFFER_STATUS_MAX_QUERIES: u32 = 10;
+/// Polls for the hub's answer to each buffer status query
+const BUFFER_STATUS_MAX_POLLS: u32 = 1000;
+/// Pause between the polls for the buffer status
+const BUFFER_STATUS_POLL_US: u32 = 100;
+/// Room for the packets that arrive while waiting for the buffer status
+const PENDING_BUF_LEN: usize = 512;
+
+/// SHTP over UART, on any `embedded-io` byte stream: a UART of an
+/// Embassy or other HAL, or a TCP stream to a serial bridge for
+/// hardware-in-the-loop testing.
+///
+/// Each packet is sent in a frame: the flag `0x7E`, the protocol ID `0x01`,
+/// the packet with any `0x7E` or `0x7D` escaped as `0x7D` followed by the
+/// byte XOR `0x20`, and the flag again. Before a packet is sent, the
+/// hub's buffer status (protocol ID `0x00`) is queried until the hub
+/// reports room for it; packets that arrive in the meantime are kept
+/// for the next reads. Frames of other protocols are skipped.
+pub struct UartInterface<S, D = NoDelay> {
+    link: Link<S>,
+    /// paces the bytes sent, as the hub needs time to handle each one,
+    /// and the polls for the hub's buffer status
+    delay: D,
+    byte_gap_us: u32,
+    /// free space in the hub's receive buffer: as last reported, less
+    /// what has been written since
+    hub_buffer_free: usize,
+    /// packets received while waiting for the buffer status, each
+    /// preceded by its length, LSB first
+    pending: [u8; PENDING_BUF_LEN],
+    pending_len: usize,
+}
+
+/// The serial port, read and written a frame at a time
+struct Link<S> {
+    serial: S,
+    /// the last byte read was a flag, which may start the next frame
+    after_flag: bool,
+}
+
+/// A complete frame read from the serial port
+enum Frame {
+    /// No frame is waiting
+    None,
+    /// An SHTP packet of this length
+    Shtp(usize),
+    /// The hub's report of the free space in its receive buffer
+    BufferStatus(usize),
+}
+
+impl<S> UartInterface<S> {
+    pub fn new(serial: S) -> Self {
+        Self {
+            link: Link {
+                serial,
+                after_flag: false,
+            },
+            delay: NoDelay,
+            byte_gap_us: 0,
+            hub_buffer_free: 0,
+            pending: [0; PENDING_BUF_LEN],
+            pending_len: 0,
+        }
+    }
+
+    /// Pause for `byte_gap_us` after each byte sent, using `delay`,
+    /// which also paces the polls for the hub's buffer status.
+    /// The BNO08x datasheet asks for 100 microseconds between bytes
+    /// written to the hub's UART; a bridge that paces the bytes itself
+    /// needs no gap.
+    pub fn with_byte_gap<D: DelayNs>(
+        self,
+        delay: D,
+        byte_gap_us: u32,
+    ) -> UartInterface<S, D> {
+        UartInterface {
+            link: self.link,
+            delay,
+            byte_gap_us,
+            hub_buffer_free: self.hub_buffer_free,
+            pending: self.pending,
+            pending_len: self.pending_len,
+        }
+    }
+}
+
+impl<S, D> UartInterface<S, D> {
+    /// Release the serial port
+    pub fn free(self) -> S {
+        self.link.serial
+    }
+
+    /// Take the oldest packet set aside while waiting for the buffer
+    /// status into `recv_buf`, returning its length
+    fn take_pending(&mut self, recv_buf: &mut [u8]) -> Option<usize> {
+        let pending = self.pending.get(..self.pending_len)?;
+        let (len_bytes, rest) = pending.split_first_chunk()?;
+        let len = usize::from(u16::from_le_bytes(*len_bytes));
+        for (slot, byte) in recv_buf.iter_mut().zip(rest.get(..len)?) {
+            *slot = *byte;
+        }
+        self.pending.copy_within(2 + len..self.pending_len, 0);
+        self.pending_len -= 2 + len;
+        Some(len)
+    }
+}
+
+impl<S, CommE> Link<S>
+where
+    S: Read<Error = CommE> + ReadReady + Write,
+{
+    /// Read one byte, or `None` at the end of the stream
+    fn read_byte(&mut self) -> Result<Option<u8>, Error<CommE, ()>> {
+        let mut byte = [0u8];
+        let read = self.serial.read(&mut byte).map_err(Error::Comm)?;
+        Ok((read == 1).then_some(byte[0]))
+    }
+
+    /// Read the rest of a frame after its protocol ID, un-escaping into
+    /// `recv_buf` as much of it as fits. Returns the length of the
+    /// un-escaped contents.
+    fn read_frame_body(
+        &mut self,
+        recv_buf: &mut [u8],
+    ) -> Result<usize, Error<CommE, ()>> {
+        let mut len = 0;
+        let mut escaped = false;
+        while let Some(byte) = self.read_byte()? {
+            if byte == FRAME_FLAG {
+                self.after_flag = true;
+                return Ok(len);
+            }
+            if byte == FRAME_ESCAPE {
+                escaped = true;
+                continue;
+            }
+            let byte = if escaped { byte ^ ESCAPE_XOR } else { byte };
+            escaped = false;
+            if let Some(slot) = recv_buf.get_mut(len) {
+                *slot = byte;
+            }
+            len += 1;
+        }
+        // the stream ended in the middle of the frame
+        Ok(0)
+    }
+
+    /// Read the next frame of interest, keeping an SHTP packet in
+    /// `recv_buf`. Once a frame has started arriving, the rest of it is
+    /// read without returning, as the hub sends it without pauses.
+    fn read_frame(
+        &mut self,
+        recv_buf: &mut [u8],
+    ) -> Result<Frame, Error<CommE, ()>> {
+        loop {
+            if !self.serial.read_ready().map_err(Error::Comm)? {
+                return Ok(Frame::None);
+            }
+            let Some(byte) = self.read_byte()? else {
+                return Ok(Frame::None);
+            };
+            if byte == FRAME_FLAG {
+                // an empty frame, or the end of the previous frame
+                self.after_flag = true;
+                continue;
+            }
+            if !self.after_flag {
+                // resynchronizing in the middle of a frame
+                continue;
+            }
+            self.after_flag = false;
+            match byte {
+                PROTOCOL_SHTP => {
+                    let len = self.read_frame_body(recv_buf)?;
+                    if len > 0 {
+                        return Ok(Frame::Shtp(len));
+                    }
+                }
+                PROTOCOL_BUFFER_STATUS => {
+                    let mut status = [0u8; 2];
+                    if self.read_frame_body(&mut status)? == status.len() {
+                        let free = u16::from_le_bytes(status);
+                        return Ok(Frame::BufferStatus(free.into()));
+                    }
+                }
+                _ => {
+                    self.read_frame_body(&mut [])?;
+                }
+            }
+        }
+    }
+}
+
+impl<S, D, CommE> UartInterface<S, D>
+where
+    S: Read<Error = CommE> + ReadReady + Write,
+    D: DelayNs,
+{
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Error<CommE, ()>> {
+        let serial = &mut self.link.serial;
+        if self.byte_gap_us == 0 {
+            return serial.write_all(bytes).map_err(Error::Comm);
+        }
+        for byte in bytes {
+            serial
+                .write_all(core::slice::from_ref(byte))
+                .map_err(Error::Comm)?;
+            serial.flush().map_err(Error::Comm)?;
+            self.delay.delay_us(self.byte_gap_us);
+        }
+        Ok(())
+    }
+
+    /// Query the hub's buffer status until it reports room for `len`
+    /// more bytes
+    fn wait_for_buffer_space(
+        &mut self,
+        len: usize,
+    ) -> Result<(), Error<CommE, ()>> {
+        for _ in 0..BUFFER_STATUS_MAX_QUERIES {
+            if self.hub_buffer_free >= len {
+                return Ok(());
+            }
+            self.write_bytes(&[
+                FRAME_FLAG,
+                PROTOCOL_BUFFER_STATUS,
+                FRAME_FLAG,
+            ])?;
+            self.read_buffer_status()?;
+        }
+        if self.hub_buffer_free >= len {
+            Ok(())
+        } else {
+            Err(Error::SensorUnresponsive)
+        }
+    }
+
+    /// Poll for the hub's answer to a buffer status query, setting aside
+    /// the packets that arrive in the meantime while there is room
+    fn read_buffer_status(&mut self) -> Result<(), Error<CommE, ()>> {
+        for _ in 0..BUFFER_STATUS_MAX_POLLS {
+            let start = self.pending_len;
+            let slot = self.pending.get_mut(start + 2..).unwrap_or_default();
+            match self.link.read_frame(slot)? {
+                Frame::None => self.delay.delay_us(BUFFER_STATUS_POLL_US),
+                Frame::Shtp(len) if len <= slot.len() => {
+                    if let Some(len_bytes) =
+                        self.pending.get_mut(start..start + 2)
+                    {
+                        len_bytes.copy_from_slice(&(len as u16).to_le_bytes());
+                        self.pending_len += 2 + len;
+                    }
+                }
+                // no room to set the packet aside
+                Frame::Shtp(_) => {}
+                Frame::BufferStatus(free) => {
+                    self.hub_buffer_free = free;
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<S, D, CommE> SensorInterface for UartInterface<S, D>
+where
+    S: Read<Error = CommE> + ReadReady + Write,
+    D: DelayNs,
+{
+    type SensorError = Error<CommE, ()>;
+
+    fn requires_soft_reset(&self) -> bool {
+        true
+    }
+
+    fn setup(
+        &mut self,
+        delay_source: &mut impl DelayNs,
+    ) -> Result<(), Self::SensorError> {
+        delay_source.delay_ms(5);
+        self.link.after_flag = false;
+        self.hub_buffer_free = 0;
+        self.pending_len = 0;
+        Ok(())
+    }
+
+    fn write_packet(&mut self, packet: &[u8]) -> Result<(), Self::SensorError> {
+        self.wait_for_buffer_space(packet.len())?;
+        self.write_bytes(&[FRAME_FLAG, PROTOCOL_SHTP])?;
+        for byte in packet {
+            if *byte == FRAME_FLAG || *byte == FRAME_ESCAPE {
+                self.write_bytes(&[FRAME_ESCAPE, byte ^ ESCAPE_XOR])?;
+            } else {
+                self.write_bytes(core::slice::from_ref(byte))?;
+            }
+        }
+        self.hub_buffer_free -= packet.len();
+        self.write_bytes(&[FRAME_FLAG])
+    }
+
+    fn read_packet(
+        &mut self,
+        recv_buf: &mut [u8],
+    ) -> Result<usize, Self::SensorError> {
+        if let Some(len) = self.take_pending(recv_buf) {
+            return Ok(len);
+        }
+        loop {
+            match self.link.read_frame(recv_buf)? {
+                Frame::None => return Ok(0),
+                Frame::Shtp(len) => return Ok(len),
+                Frame::BufferStatus(free) => self.hub_buffer_free = free,
+            }
+        }
+    }
+
+    fn read_packet_with_timeout(
+        &mut self,
+        recv_buf: &mut [u8],
+        delay_source: &mut impl DelayNs,
+        max_ms: u8,
+    ) -> Result<usize, Self::SensorError> {
+        for _ in 0..max_ms {
+            let read_size = self.read_packet(recv_buf)?;
+            if read_size > 0 {
+                return Ok(read_size);
+            }
+            delay_source.delay_ms(1);
+        }
+        Ok(0)
+    }
+
+    fn send_and_receive_packet(
+        &mut self,
+        send_buf: &[u8],
+        recv_buf: &mut [u8],
+    ) -> Result<usize, Self::SensorError> {
+        self.write_packet(send_buf)?;
+        self.read_packet(recv_buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use crate::interface::mock_i2c_port::FakeDelay;
+    use core::convert::Infallible;
+    use std::collections::VecDeque;
+    use std::vec::Vec;
+
+    #[derive(Default)]
+    struct FakeSerial {
+        rx: VecDeque<u8>,
+        tx: Vec<u8>,
+    }
+
+    impl embedded_io::ErrorType for FakeSerial {
+        type Error = Infallible;
+    }
+
+    impl Read for FakeSerial {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let len = buf.len().min(self.rx.len());
+            for slot in &mut buf[..len] {
+                *slot = self.rx.pop_front().unwrap_or(0);
+            }
+            Ok(len)
+        }
+    }
+
+    impl ReadReady for FakeSerial {
+        fn read_ready(&mut self) -> Result<bool, Self::Error> {
+            Ok(!self.rx.is_empty())
+        }
+    }
+
+    impl Write for FakeSerial {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.tx.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    /// The hub's report of `free` bytes in its receive buffer
+    fn buffer_status(free: u16) -> [u8; 5] {
+        let [lsb, msb] = free.to_le_bytes();
+        [0x7E, 0x00, lsb, msb, 0x7E]
+    }
+
+    const STATUS_QUERY: [u8; 3] = [0x7E, 0x00, 0x7E];
+
+    #[test]
+    fn test_write_escapes_packet() {
+        let mut serial = FakeSerial::default();
+        serial.rx.extend(buffer_status(8));
+        let mut uart =
+            UartInterface::new(serial).with_byte_gap(FakeDelay {}, 100);
+        uart.write_packet(&[6, 0, 2, 0, 0x7E, 0x7D]).unwrap();
+        let tx = uart.free().tx;
+        assert_eq!(tx[..3], STATUS_QUERY);
+        assert_eq!(
+            tx[3..],
+            [0x7E, 0x01, 6, 0, 2, 0, 0x7D, 0x5E, 0x7D, 0x5D, 0x7E]
+        );
+    }
+
+    #[test]
+    fn test_write_waits_for_buffer_space() {
+        let mut serial = FakeSerial::default();
+        serial.rx.extend(buffer_status(4));
+        // a packet arriving before the answer to the second query
+        serial.rx.extend([0x7E, 0x01, 5, 0, 2, 1, 0xF8, 0x7E]);
+        serial.rx.extend(buffer_status(16));
+        let mut uart = UartInterface::new(serial);
+        uart.write_packet(&[6, 0, 2, 0, 1, 2]).unwrap();
+        // the reported space is used up by the writes
+        uart.write_packet(&[6, 0, 2, 1, 1, 2]).unwrap();
+
+        let mut recv_buf = [0u8; 8];
+        assert_eq!(uart.read_packet(&mut recv_buf).unwrap(), 5);
+        assert_eq!(recv_buf[..5], [5, 0, 2, 1, 0xF8]);
+        assert_eq!(uart.read_packet(&mut recv_buf).unwrap(), 0);
+        assert!(matches!(
+            uart.write_packet(&[6, 0, 2, 2, 1, 2]),
+            Err(Error::SensorUnresponsive)
+        ));
+        let tx = uart.free().tx;
+        assert_eq!(tx[..6], [STATUS_QUERY, STATUS_QUERY].concat());
+        assert_eq!(tx[6..14], [0x7E, 0x01, 6, 0, 2, 0, 1, 2]);
+        assert_eq!(tx[15..17], [0x7E, 0x01]);
+        assert_eq!(tx[24..], STATUS_QUERY.repeat(10));
+    }
+
+    #[test]
+    fn test_read_frames() {
+        let mut serial = FakeSerial::default();
+        serial.rx.extend([
+            // the tail of a frame, then a buffer status frame
+            0x33, 0x7E, 0x7E, 0x00, 0x00, 0x04, 0x7E,
+            // an SHTP packet sharing its flags with the next frame
+            0x01, 6, 0, 2, 1, 0x7D, 0x5E, 0xF1, 0x7E, 0x01, 5, 0, 2, 2, 0xF8,
+            0x7E,
+        ]);
+        let mut uart = UartInterface::new(serial);
+        let mut recv_buf = [0u8; 8];
+        assert_eq!(uart.read_packet(&mut recv_buf).unwrap(), 6);
+        assert_eq!(recv_buf[..6], [6, 0, 2, 1, 0x7E, 0xF1]);
+        assert_eq!(
+            uart.read_packet_with_timeout(&mut recv_buf, &mut FakeDelay {}, 5)
+                .unwrap(),
+            5
+        );
+        assert_eq!(recv_buf[..5], [5, 0, 2, 2, 0xF8]);
+        assert_eq!(uart.read_packet(&mut recv_buf).unwrap(), 0);
+    }
+}
@@ -3,13 +3,33 @@ Copyright (c) 2020 Todd Stellanova
 LICENSE: BSD3 (see LICENSE file)
 */
 
-#![no_std]
+#![cfg_attr(not(feature = "std"), no_std)]
 
+mod fmt;
+
+pub mod builder;
+#[cfg(feature = "capture")]
+pub mod capture;
+pub mod dfu;
+#[cfg(feature = "filter")]
+pub mod filter;
+pub mod frs;
+#[cfg(feature = "report-gesture")]
+pub mod gesture;
+pub mod ids;
 pub mod interface;
+#[cfg(feature = "logger")]
+pub mod logger;
+pub mod math;
+pub mod reports;
+#[cfg(feature = "rvc")]
+pub mod rvc;
+pub mod split;
 pub mod wrapper;
 
 /// Errors in this crate
 #[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Error<CommE, PinE> {
     /// Sensor communication error
     Comm(CommE),
@@ -19,3 +39,21 @@ pub enum Error<CommE, PinE> {
     /// The sensor is not responding
     SensorUnresponsive,
 }
+
+impl<CommE: core::fmt::Debug, PinE: core::fmt::Debug> core::fmt::Display
+    for Error<CommE, PinE>
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Comm(e) => write!(f, "bus error: {:?}", e),
+            Self::Pin(e) => write!(f, "pin error: {:?}", e),
+            Self::SensorUnresponsive => write!(f, "sensor is not responding"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<CommE: core::fmt::Debug, PinE: core::fmt::Debug> std::error::Error
+    for Error<CommE, PinE>
+{
+}
@@ -0,0 +1,179 @@
+/*
+Copyright (c) 2020 Todd Stellanova
+LICENSE: BSD3 (see LICENSE file)
+*/
+
+//! Logging of decoded reports to any `embedded_io::Write` sink, such as
+//! a file on an SD card (`embedded-sdmmc`) or a UART, as a stream of
+//! COBS-framed postcard records that `postcard::from_bytes_cobs` reads
+//! back as `LogRecord`s.
+
+use crate::reports::SensorReport;
+
+/// Largest postcard encoding of a `u32`, as a varint
+const MAX_VARINT_U32_LEN: usize = 5;
+
+/// Largest postcard encoding of a `SensorReport`: the one-byte variant
+/// index and the seven `f32`s of a `GyroIntegratedRotationVector`
+const MAX_REPORT_LEN: usize = 1 + 7 * core::mem::size_of::<f32>();
+
+/// Largest encoded size of one record, including the COBS framing: one
+/// overhead byte per started 254 bytes and the terminating zero
+pub const MAX_RECORD_LEN: usize = {
+    let encoded = MAX_VARINT_U32_LEN + MAX_REPORT_LEN;
+    encoded + encoded.div_ceil(254) + 1
+};
+
+/// One logged report
+#[derive(
+    Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize,
+)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LogRecord {
+    /// Time of logging, in units chosen by the application
+    pub timestamp: u32,
+    pub report: SensorReport,
+}
+
+/// Errors while logging reports
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LoggerError<E> {
+    /// The record could not be encoded
+    Encode,
+    /// The sink failed to accept the record
+    Write(E),
+}
+
+/// Writes decoded reports to a sink, one record at a time
+pub struct ReportLogger<W> {
+    sink: W,
+    records: u32,
+}
+
+impl<W> ReportLogger<W>
+where
+    W: embedded_io::Write,
+{
+    pub fn new(sink: W) -> Self {
+        Self { sink, records: 0 }
+    }
+
+    /// Release the sink
+    pub fn free(self) -> W {
+        self.sink
+    }
+
+    /// Number of records written so far
+    pub fn records(&self) -> u32 {
+        self.records
+    }
+
+    /// Encode and write one report
+    pub fn log(
+        &mut self,
+        timestamp: u32,
+        report: &SensorReport,
+    ) -> Result<(), LoggerError<W::Error>> {
+        let record = LogRecord {
+            timestamp,
+            report: *report,
+        };
+        let mut buf = [0u8; MAX_RECORD_LEN];
+        let encoded = postcard::to_slice_cobs(&record, &mut buf)
+            .map_err(|_| LoggerError::Encode)?;
+        self.sink.write_all(encoded).map_err(LoggerError::Write)?;
+        self.records = self.records.wrapping_add(1);
+        Ok(())
+    }
+
+    /// Write all of `reports` with the same timestamp, e.g. those taken
+    /// with `BNO080::next_report` after handling a batch of messages.
+    /// Returns the number of reports written.
+    pub fn log_all(
+        &mut self,
+        timestamp: u32,
+        reports: impl IntoIterator<Item = SensorReport>,
+    ) -> Result<usize, LoggerError<W::Error>> {
+        let mut count = 0;
+        for report in reports {
+            self.log(timestamp, &report)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Flush the sink, e.g. before the SD card file is closed
+    pub fn flush(&mut self) -> Result<(), LoggerError<W::Error>> {
+        self.sink.flush().map_err(LoggerError::Write)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Quaternion;
+
+    #[test]
+    fn test_log_records() {
+        let reports = [
+            SensorReport::RotationVector {
+                quaternion: Quaternion::from([0.0, 0.0, 0.5, 0.5]),
+                accuracy: 0.25,
+            },
+            SensorReport::HeartRate(61),
+        ];
+        let mut buf = [0u8; 2 * MAX_RECORD_LEN];
+        let mut logger = ReportLogger::new(&mut buf[..]);
+        assert_eq!(logger.log_all(7, reports).unwrap(), 2);
+        assert_eq!(logger.records(), 2);
+        let unused = logger.free().len();
+        let written = buf.len() - unused;
+
+        // records are separated by the zero byte ending each COBS frame
+        let mut frames = buf[..written].split_mut(|&b| b == 0);
+        for report in reports {
+            let frame = frames.next().unwrap();
+            let record: LogRecord = postcard::from_bytes_cobs(frame).unwrap();
+            assert_eq!(
+                record,
+                LogRecord {
+                    timestamp: 7,
+                    report
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_log_largest_record() {
+        let report = SensorReport::GyroIntegratedRotationVector {
+            quaternion: Quaternion::from([0.1, -0.2, 0.3, -0.9]),
+            angular_velocity: [-31.9, 31.9, -0.5],
+        };
+        let mut buf = [0u8; MAX_RECORD_LEN];
+        let mut logger = ReportLogger::new(&mut buf[..]);
+        logger.log(u32::MAX, &report).unwrap();
+        assert_eq!(logger.free().len(), 0);
+
+        let record: LogRecord = postcard::from_bytes_cobs(&mut buf).unwrap();
+        assert_eq!(
+            record,
+            LogRecord {
+                timestamp: u32::MAX,
+                report
+            }
+        );
+    }
+
+    #[test]
+    fn test_log_sink_full() {
+        let mut buf = [0u8; 4];
+        let mut logger = ReportLogger::new(&mut buf[..]);
+        assert!(matches!(
+            logger.log(0, &SensorReport::Temperature(25.5)),
+            Err(LoggerError::Write(_))
+        ));
+        assert_eq!(logger.records(), 0);
+    }
+}
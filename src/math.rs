@@ -0,0 +1,376 @@
+/*
+Copyright (c) 2020 Todd Stellanova
+LICENSE: BSD3 (see LICENSE file)
+*/
+
+//! Conversions from the rotation vector quaternion to Euler angles,
+//! and between coordinate frames.
+
+use libm::{asinf, atan2f, fmodf};
+
+/// Unit quaternion, in the component order reported by the sensor hub
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Quaternion {
+    pub i: f32,
+    pub j: f32,
+    pub k: f32,
+    pub real: f32,
+}
+
+impl From<[f32; 4]> for Quaternion {
+    /// Convert from the `[i, j, k, real]` order used by the driver
+    fn from(q: [f32; 4]) -> Self {
+        Self {
+            i: q[0],
+            j: q[1],
+            k: q[2],
+            real: q[3],
+        }
+    }
+}
+
+impl From<Quaternion> for [f32; 4] {
+    fn from(q: Quaternion) -> Self {
+        [q.i, q.j, q.k, q.real]
+    }
+}
+
+impl Quaternion {
+    /// Compass heading of the device's Y axis (degrees clockwise from
+    /// north, range [0, 360)), for a rotation vector in the hub's
+    /// East-North-Up world frame. Adding `magnetic_declination` (degrees,
+    /// east positive) turns a magnetic heading into a true heading.
+    pub fn heading_degrees(&self, magnetic_declination: f32) -> f32 {
+        let (x, y, z, w) = (self.i, self.j, self.k, self.real);
+        // the device Y axis rotated into the world frame
+        let east = 2.0 * (x * y - w * z);
+        let north = 1.0 - 2.0 * (x * x + z * z);
+        let heading = fmodf(
+            atan2f(east, north).to_degrees() + magnetic_declination,
+            360.0,
+        );
+        if heading < 0.0 {
+            // a tiny negative angle rounds up to a full turn
+            let wrapped = heading + 360.0;
+            if wrapped < 360.0 {
+                wrapped
+            } else {
+                0.0
+            }
+        } else {
+            heading
+        }
+    }
+}
+
+/// Coordinate frame convention of the orientation and vector outputs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Frame {
+    /// The sensor hub's native frame: East-North-Up world frame, and the
+    /// device axes as marked on the chip
+    #[default]
+    Enu,
+    /// The aerospace convention: North-East-Down world frame, and a
+    /// Forward-Right-Down body frame, forward being the device Y axis.
+    /// Yaw of the Euler angles is then the heading from north.
+    Ned,
+}
+
+impl Frame {
+    /// Convert a vector in device axes (acceleration, angular rate,
+    /// magnetic field) from the hub's native frame into this frame
+    pub fn vector(self, v: [f32; 3]) -> [f32; 3] {
+        match self {
+            Frame::Enu => v,
+            Frame::Ned => [v[1], v[0], -v[2]],
+        }
+    }
+
+    /// Convert an orientation from the hub's native frame into this
+    /// frame. The same axis swap applies to the world and the body frame,
+    /// so it only moves the vector part of the quaternion.
+    pub fn quaternion(self, q: Quaternion) -> Quaternion {
+        let [i, j, k] = self.vector([q.i, q.j, q.k]);
+        Quaternion {
+            i,
+            j,
+            k,
+            real: q.real,
+        }
+    }
+}
+
+#[cfg(feature = "mint")]
+impl From<Quaternion> for mint::Quaternion<f32> {
+    fn from(q: Quaternion) -> Self {
+        mint::Quaternion {
+            v: mint::Vector3 {
+                x: q.i,
+                y: q.j,
+                z: q.k,
+            },
+            s: q.real,
+        }
+    }
+}
+
+#[cfg(feature = "nalgebra")]
+impl From<Quaternion> for nalgebra::UnitQuaternion<f32> {
+    /// Renormalizes, since the fixed-point values reported by the
+    /// sensor hub are not exactly unit length
+    fn from(q: Quaternion) -> Self {
+        nalgebra::UnitQuaternion::from_quaternion(nalgebra::Quaternion::new(
+            q.real, q.i, q.j, q.k,
+        ))
+    }
+}
+
+/// Tait-Bryan angles (radians), applied in yaw, pitch, roll order
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EulerAngles {
+    /// Rotation about the X axis, range ±π
+    pub roll: f32,
+    /// Rotation about the Y axis, range ±π/2
+    pub pitch: f32,
+    /// Rotation about the Z axis, range ±π
+    pub yaw: f32,
+    /// Heading accuracy estimate (radians) passed through from the
+    /// rotation vector report, or zero if not known
+    pub heading_accuracy: f32,
+}
+
+impl EulerAngles {
+    /// Convert a unit quaternion into Euler angles
+    pub fn from_quaternion(q: &Quaternion, heading_accuracy: f32) -> Self {
+        let (x, y, z, w) = (q.i, q.j, q.k, q.real);
+
+        let roll = atan2f(2.0 * (w * x + y * z), 1.0 - 2.0 * (x * x + y * y));
+        // clamp to avoid NaN when close to gimbal lock
+        let sin_pitch = (2.0 * (w * y - z * x)).clamp(-1.0, 1.0);
+        let pitch = asinf(sin_pitch);
+        let yaw = atan2f(2.0 * (w * z + x * y), 1.0 - 2.0 * (y * y + z * z));
+
+        Self {
+            roll,
+            pitch,
+            yaw,
+            heading_accuracy,
+        }
+    }
+}
+
+/// Tait-Bryan angles in Q13 fixed-point radians
+/// (so that ±π fits in an i16), computed without floating point
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FixedEulerAngles {
+    pub roll: i16,
+    pub pitch: i16,
+    pub yaw: i16,
+}
+
+/// Scale of the fixed-point angles produced by `FixedEulerAngles`
+pub const FIXED_ANGLE_Q: u8 = 13;
+
+/// One in the Q28 format resulting from multiplying two Q14 values
+const Q28_ONE: i64 = 1 << 28;
+/// π/2 in Q13 radians
+const HALF_PI_Q13: i32 = 12868;
+/// π in Q13 radians
+const PI_Q13: i32 = 25736;
+
+impl FixedEulerAngles {
+    /// Convert the raw Q14 quaternion values from a rotation vector report,
+    /// in `[i, j, k, real]` order, into Euler angles.
+    /// The arctangent approximation used is accurate to about 0.004 radians.
+    pub fn from_q14(q: [i16; 4]) -> Self {
+        let [x, y, z, w] = q.map(|v| v as i64);
+
+        let roll =
+            atan2_q13(2 * (w * x + y * z), Q28_ONE - 2 * (x * x + y * y));
+        let sin_pitch = (2 * (w * y - z * x)).clamp(-Q28_ONE, Q28_ONE);
+        let cos_pitch =
+            isqrt((Q28_ONE * Q28_ONE - sin_pitch * sin_pitch) as u64) as i64;
+        let pitch = atan2_q13(sin_pitch, cos_pitch);
+        let yaw = atan2_q13(2 * (w * z + x * y), Q28_ONE - 2 * (y * y + z * z));
+
+        Self { roll, pitch, yaw }
+    }
+}
+
+/// Integer square root
+fn isqrt(val: u64) -> u64 {
+    let mut result: u64 = 0;
+    let mut bit: u64 = 1 << 62;
+    let mut rem = val;
+    while bit > rem {
+        bit >>= 2;
+    }
+    while bit != 0 {
+        if rem >= result + bit {
+            rem -= result + bit;
+            result = (result >> 1) + bit;
+        } else {
+            result >>= 1;
+        }
+        bit >>= 2;
+    }
+    result
+}
+
+/// Approximate atan(z) for z in [0, 1] (Q15), result in Q13 radians
+fn atan_unit_q13(z: i64) -> i32 {
+    // atan(z) ≈ π/4 z + 0.273 z (1 - z)
+    const QUARTER_PI_Q13: i64 = 6434;
+    const COEFF_Q13: i64 = 2236;
+    let one = 1 << 15;
+    let z_one_minus_z = (z * (one - z)) >> 15;
+    ((QUARTER_PI_Q13 * z + COEFF_Q13 * z_one_minus_z) >> 15) as i32
+}
+
+/// Four-quadrant arctangent of fixed-point values of the same scale,
+/// result in Q13 radians
+fn atan2_q13(y: i64, x: i64) -> i16 {
+    let (ax, ay) = (x.abs(), y.abs());
+    if ax == 0 && ay == 0 {
+        return 0;
+    }
+    let mut angle = if ax >= ay {
+        atan_unit_q13((ay << 15) / ax)
+    } else {
+        HALF_PI_Q13 - atan_unit_q13((ax << 15) / ay)
+    };
+    if x < 0 {
+        angle = PI_Q13 - angle;
+    }
+    if y < 0 {
+        angle = -angle;
+    }
+    angle as i16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::f32::consts::FRAC_PI_2;
+
+    const EPSILON: f32 = 1e-4;
+
+    #[test]
+    fn test_euler_from_quaternion() {
+        let identity = Quaternion::from([0.0, 0.0, 0.0, 1.0]);
+        let angles = EulerAngles::from_quaternion(&identity, 0.1);
+        assert_eq!(angles.roll, 0.0);
+        assert_eq!(angles.pitch, 0.0);
+        assert_eq!(angles.yaw, 0.0);
+        assert_eq!(angles.heading_accuracy, 0.1);
+
+        // 90 degrees about Z
+        let half = FRAC_PI_2 / 2.0;
+        let yawed =
+            Quaternion::from([0.0, 0.0, libm::sinf(half), libm::cosf(half)]);
+        let angles = EulerAngles::from_quaternion(&yawed, 0.0);
+        assert!((angles.yaw - FRAC_PI_2).abs() < EPSILON);
+        assert!(angles.roll.abs() < EPSILON);
+        assert!(angles.pitch.abs() < EPSILON);
+
+        // gimbal lock: pitch of exactly 90 degrees must not be NaN
+        let pitched =
+            Quaternion::from([0.0, libm::sinf(half), 0.0, libm::cosf(half)]);
+        let angles = EulerAngles::from_quaternion(&pitched, 0.0);
+        assert!((angles.pitch - FRAC_PI_2).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_heading_degrees() {
+        let identity = Quaternion::from([0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(identity.heading_degrees(0.0), 0.0);
+        assert!((identity.heading_degrees(-3.5) - 356.5).abs() < EPSILON);
+        assert!((identity.heading_degrees(365.0) - 5.0).abs() < EPSILON);
+
+        // turning counterclockwise by 90 degrees faces west
+        let half = FRAC_PI_2 / 2.0;
+        let yawed =
+            Quaternion::from([0.0, 0.0, libm::sinf(half), libm::cosf(half)]);
+        assert!((yawed.heading_degrees(0.0) - 270.0).abs() < 1e-3);
+        assert!((yawed.heading_degrees(100.0) - 10.0).abs() < 1e-3);
+
+        // close to south, on either side of the discontinuity
+        for (angle, expected) in [(3.1f32, 182.38), (-3.1, 177.62)] {
+            let q = Quaternion::from([
+                0.0,
+                0.0,
+                libm::sinf(angle / 2.0),
+                libm::cosf(angle / 2.0),
+            ]);
+            assert!((q.heading_degrees(0.0) - expected).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn test_ned_frame() {
+        assert_eq!(Frame::Ned.vector([1.0, 2.0, 9.8]), [2.0, 1.0, -9.8]);
+        assert_eq!(Frame::Enu.vector([1.0, 2.0, 9.8]), [1.0, 2.0, 9.8]);
+
+        // turning counterclockwise to face west is a yaw of -90 degrees
+        let half = FRAC_PI_2 / 2.0;
+        let (sin, cos) = (libm::sinf(half), libm::cosf(half));
+        let yawed =
+            Frame::Ned.quaternion(Quaternion::from([0.0, 0.0, sin, cos]));
+        let angles = EulerAngles::from_quaternion(&yawed, 0.0);
+        assert!((angles.yaw + FRAC_PI_2).abs() < EPSILON);
+
+        // tilting the device Y axis up is a positive pitch
+        let tilted =
+            Frame::Ned.quaternion(Quaternion::from([sin, 0.0, 0.0, cos]));
+        let angles = EulerAngles::from_quaternion(&tilted, 0.0);
+        assert!((angles.pitch - FRAC_PI_2).abs() < 1e-3);
+        assert!(angles.yaw.abs() < EPSILON);
+    }
+
+    #[cfg(feature = "nalgebra")]
+    #[test]
+    fn test_nalgebra_conversion() {
+        let quat = Quaternion::from([0.1852, 0.2532, -0.7387, 0.5973]);
+        let unit: nalgebra::UnitQuaternion<f32> = quat.into();
+        let (roll, pitch, yaw) = unit.euler_angles();
+        let angles = EulerAngles::from_quaternion(&quat, 0.0);
+        // allow for the renormalization done by nalgebra
+        let tolerance = 0.005;
+        assert!((roll - angles.roll).abs() < tolerance);
+        assert!((pitch - angles.pitch).abs() < tolerance);
+        assert!((yaw - angles.yaw).abs() < tolerance);
+    }
+
+    #[test]
+    fn test_fixed_euler_matches_float() {
+        let q14_scale = (1 << 14) as f32;
+        let q13_scale = (1 << FIXED_ANGLE_Q) as f32;
+        // roughly -11 deg roll, 35 deg pitch, -105 deg yaw
+        let quat = Quaternion::from([0.1852, 0.2532, -0.7387, 0.5973]);
+        let float_angles = EulerAngles::from_quaternion(&quat, 0.0);
+
+        let raw: [f32; 4] = quat.into();
+        let fixed =
+            FixedEulerAngles::from_q14(raw.map(|v| (v * q14_scale) as i16));
+        let tolerance = 0.01;
+        assert!(
+            (fixed.roll as f32 / q13_scale - float_angles.roll).abs()
+                < tolerance
+        );
+        assert!(
+            (fixed.pitch as f32 / q13_scale - float_angles.pitch).abs()
+                < tolerance
+        );
+        assert!(
+            (fixed.yaw as f32 / q13_scale - float_angles.yaw).abs() < tolerance
+        );
+    }
+}
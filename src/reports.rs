@@ -0,0 +1,974 @@
+/*
+Copyright (c) 2020 Todd Stellanova
+LICENSE: BSD3 (see LICENSE file)
+*/
+
+//! Zero-copy views over the input reports contained in a
+//! sensor hub packet payload, and their decoded forms.
+
+use crate::math::{Frame, Quaternion};
+#[cfg(feature = "report-rotation")]
+use crate::wrapper::q10_to_f32;
+use crate::wrapper::{q12_to_f32, q14_to_f32, q8_to_f32, q9_to_f32};
+#[cfg(feature = "report-environment")]
+use crate::wrapper::{q20_to_f32, q4_to_f32, q7_to_f32, q8_u32_to_f32};
+
+/// Length of the common header at the start of every input report:
+/// report ID, sequence number, status, delay
+pub const INPUT_REPORT_HEADER_LENGTH: usize = 4;
+
+/// Q point of the rotation vector quaternion values
+pub const ROTATION_VECTOR_Q: u8 = 14;
+/// Q point of the rotation vector heading accuracy estimate (radians)
+pub const HEADING_ACCURACY_Q: u8 = 12;
+/// Q point of the angular velocity of the gyro-integrated rotation
+/// vector (rad/s)
+pub const ANGULAR_VELOCITY_Q: u8 = 10;
+/// Q point of accelerometer, linear acceleration and gravity values (m/s^2)
+pub const ACCEL_Q: u8 = 8;
+/// Q point of gyroscope values (rad/s)
+pub const GYRO_Q: u8 = 9;
+/// Q point of magnetic field values (uTesla)
+pub const MAG_Q: u8 = 4;
+/// Q point of the 32 bit pressure value (hectopascals)
+pub const PRESSURE_Q: u8 = 20;
+/// Q point of the 32 bit ambient light value (lux)
+pub const AMBIENT_LIGHT_Q: u8 = 8;
+/// Q point of humidity values (percent)
+pub const HUMIDITY_Q: u8 = 8;
+/// Q point of proximity values (centimeters)
+pub const PROXIMITY_Q: u8 = 4;
+/// Q point of temperature values (degrees Celsius)
+pub const TEMPERATURE_Q: u8 = 7;
+
+/// Report ID of the base timestamp reference record
+const REPORTID_BASE_TIMESTAMP: u8 = 0xFB;
+/// Report ID of the timestamp rebase record
+const REPORTID_TIMESTAMP_REBASE: u8 = 0xFA;
+/// Both timestamp records are a report ID followed by a 32 bit value
+const TIMESTAMP_RECORD_LENGTH: usize = 5;
+
+/// Length of the gyro-integrated rotation vector as sent on the hub's
+/// gyro rotation channel, without a report header
+pub(crate) const GYRO_INTEGRATED_RV_LENGTH: usize = 14;
+
+/// Total length (including header) of the input reports we know about,
+/// from the SH-2 Reference Manual
+pub(crate) fn input_report_length(report_id: u8) -> Option<usize> {
+    match report_id {
+        // accelerometer, gyro calibrated, mag calibrated, linear accel
+        0x01..=0x04 => Some(10),
+        // rotation vector
+        0x05 => Some(14),
+        // gravity
+        0x06 => Some(10),
+        // gyro uncalibrated
+        0x07 => Some(16),
+        // game rotation vector
+        0x08 => Some(12),
+        // geomagnetic rotation vector
+        0x09 => Some(14),
+        // pressure, ambient light
+        0x0A | 0x0B => Some(8),
+        // humidity, proximity, temperature
+        0x0C..=0x0E => Some(6),
+        // mag uncalibrated
+        0x0F => Some(16),
+        // tap detector
+        0x10 => Some(5),
+        // step counter
+        0x11 => Some(12),
+        // significant motion, stability classifier
+        0x12 | 0x13 => Some(6),
+        // raw accelerometer, raw gyroscope
+        0x14 | 0x15 => Some(16),
+        // raw magnetometer
+        0x16 => Some(14),
+        // step detector
+        0x18 => Some(8),
+        // shake detector, flip detector, pickup detector, stability detector
+        0x19..=0x1C => Some(6),
+        // personal activity classifier
+        0x1E => Some(16),
+        // sleep detector, tilt detector, pocket detector, circle detector,
+        // heart rate monitor
+        0x1F..=0x23 => Some(6),
+        // ARVR-stabilized rotation vector
+        0x28 => Some(14),
+        // ARVR-stabilized game rotation vector
+        0x29 => Some(12),
+        // gyro-integrated rotation vector, with angular velocity: sent on
+        // its own channel without the report header, which the driver adds
+        0x2A => Some(INPUT_REPORT_HEADER_LENGTH + GYRO_INTEGRATED_RV_LENGTH),
+        _ => None,
+    }
+}
+
+/// The range of report intervals a sensor supports (microseconds)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ReportIntervalLimits {
+    /// Shortest interval, at the sensor's maximum rate
+    pub min_us: u32,
+    /// Longest interval
+    pub max_us: u32,
+}
+
+impl ReportIntervalLimits {
+    /// Limits with no known maximum interval
+    const fn max_rate_hz(hz: u32) -> Self {
+        Self {
+            min_us: 1_000_000 / hz,
+            max_us: u32::MAX,
+        }
+    }
+
+    /// Is a report interval of `interval_us` within the limits?
+    pub fn contains(&self, interval_us: u32) -> bool {
+        (self.min_us..=self.max_us).contains(&interval_us)
+    }
+}
+
+/// Report interval limits of the continuous sensors, from the maximum
+/// rates in the BNO080 datasheet. The sensor hub would silently clamp
+/// a shorter interval. `None` for event-driven and unknown sensors.
+pub fn report_interval_limits(report_id: u8) -> Option<ReportIntervalLimits> {
+    let max_rate_hz = match report_id {
+        // accelerometer, raw accelerometer
+        0x01 | 0x14 => 500,
+        // gyroscope calibrated, linear acceleration, rotation vector,
+        // gravity, gyroscope uncalibrated, game rotation vector,
+        // raw gyroscope
+        0x02 | 0x04..=0x08 | 0x15 => 400,
+        // magnetic field calibrated and uncalibrated, raw magnetometer
+        0x03 | 0x0F | 0x16 => 100,
+        // geomagnetic rotation vector
+        0x09 => 90,
+        // ARVR-stabilized rotation vector, ARVR-stabilized game rotation vector
+        0x28 | 0x29 => 250,
+        // gyro-integrated rotation vector
+        0x2A => 1000,
+        _ => return None,
+    };
+    Some(ReportIntervalLimits::max_rate_hz(max_rate_hz))
+}
+
+/// A single input report, borrowed from the receive buffer
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct InputReport<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> InputReport<'a> {
+    /// The report ID, which identifies the sensor that produced this report
+    pub fn report_id(&self) -> u8 {
+        self.bytes[0]
+    }
+
+    /// Sequence number of this report, incremented by the hub per sensor
+    pub fn sequence_number(&self) -> u8 {
+        self.bytes[1]
+    }
+
+    /// Raw status byte: bits 1:0 carry the accuracy estimate,
+    /// bits 7:2 carry the upper bits of the report delay
+    pub fn status(&self) -> u8 {
+        self.bytes[2]
+    }
+
+    /// Accuracy estimate: 0 unreliable, 1 low, 2 medium, 3 high
+    pub fn accuracy(&self) -> u8 {
+        self.status() & 0x03
+    }
+
+    /// Delay from the base timestamp, in units of 100 microseconds
+    pub fn delay(&self) -> u16 {
+        (((self.status() & 0xFC) as u16) << 6) | (self.bytes[3] as u16)
+    }
+
+    /// The report data following the report header
+    pub fn data(&self) -> &'a [u8] {
+        &self.bytes[INPUT_REPORT_HEADER_LENGTH..]
+    }
+
+    /// The whole report, including its header
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    /// Read the fixed-point value at `index` (counted in 16 bit words
+    /// following the report header), if present
+    pub fn value(&self, index: usize) -> Option<i16> {
+        let start = index * 2;
+        let data = self.data();
+        if start + 2 > data.len() {
+            return None;
+        }
+        Some(i16::from_le_bytes([data[start], data[start + 1]]))
+    }
+
+    /// Read the unsigned 32 bit value starting at `index` (counted in
+    /// 16 bit words following the report header), if present, as in the
+    /// pressure and ambient light reports
+    pub fn value_u32(&self, index: usize) -> Option<u32> {
+        let start = index * 2;
+        let bytes = self.data().get(start..start + 4)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// The Q point of the fixed-point value at `index`, for the reports
+    /// whose values are fixed-point. Together with `value` this reads
+    /// reports without any floating point arithmetic.
+    pub fn q_point(&self, index: usize) -> Option<u8> {
+        match (self.report_id(), index) {
+            // accelerometer, linear acceleration, gravity
+            (0x01 | 0x04 | 0x06, 0..=2) => Some(ACCEL_Q),
+            // gyroscope calibrated; uncalibrated, with bias
+            (0x02, 0..=2) | (0x07, 0..=5) => Some(GYRO_Q),
+            // magnetic field calibrated; uncalibrated, with hard iron bias
+            (0x03, 0..=2) | (0x0F, 0..=5) => Some(MAG_Q),
+            // rotation vectors, with heading accuracy
+            (0x05 | 0x08 | 0x09 | 0x28 | 0x29 | 0x2A, 0..=3) => {
+                Some(ROTATION_VECTOR_Q)
+            }
+            (0x05 | 0x09 | 0x28, 4) => Some(HEADING_ACCURACY_Q),
+            (0x2A, 4..=6) => Some(ANGULAR_VELOCITY_Q),
+            // external sensors: pressure and ambient light are 32 bit
+            (0x0A, 0) => Some(PRESSURE_Q),
+            (0x0B, 0) => Some(AMBIENT_LIGHT_Q),
+            (0x0C, 0) => Some(HUMIDITY_Q),
+            (0x0D, 0) => Some(PROXIMITY_Q),
+            (0x0E, 0) => Some(TEMPERATURE_Q),
+            _ => None,
+        }
+    }
+}
+
+impl InputReport<'_> {
+    /// Decode this report into physical units. Only the decoders enabled
+    /// by the `report-*` cargo features are built in; reports without one
+    /// decode as `SensorReport::Other`.
+    pub fn decode(&self) -> SensorReport {
+        #[allow(unused_variables)]
+        let value = |index| self.value(index).unwrap_or(0);
+        match self.report_id() {
+            // Unit quaternion rotation vector: Q point 14,
+            // with heading accuracy estimate (radians): Q point 12
+            #[cfg(feature = "report-rotation")]
+            0x05 => SensorReport::RotationVector {
+                quaternion: Quaternion {
+                    i: q14_to_f32(value(0)),
+                    j: q14_to_f32(value(1)),
+                    k: q14_to_f32(value(2)),
+                    real: q14_to_f32(value(3)),
+                },
+                accuracy: q12_to_f32(value(4)),
+            },
+            // Gyro-integrated rotation vector: unit quaternion, Q point 14,
+            // with angular velocity (rad/s): Q point 10
+            #[cfg(feature = "report-rotation")]
+            0x2A => SensorReport::GyroIntegratedRotationVector {
+                quaternion: Quaternion {
+                    i: q14_to_f32(value(0)),
+                    j: q14_to_f32(value(1)),
+                    k: q14_to_f32(value(2)),
+                    real: q14_to_f32(value(3)),
+                },
+                angular_velocity: [
+                    q10_to_f32(value(4)),
+                    q10_to_f32(value(5)),
+                    q10_to_f32(value(6)),
+                ],
+            },
+            // Linear acceleration (m/s^2 minus gravity): Q point 8
+            #[cfg(feature = "report-accel")]
+            0x04 => SensorReport::LinearAccel([
+                q8_to_f32(value(0)),
+                q8_to_f32(value(1)),
+                q8_to_f32(value(2)),
+            ]),
+            // Gyroscope uncalibrated (rad/s): Q point 9
+            #[cfg(feature = "report-gyro")]
+            0x07 => SensorReport::Gyro([
+                q9_to_f32(value(0)),
+                q9_to_f32(value(1)),
+                q9_to_f32(value(2)),
+            ]),
+            // Pressure (hectopascals) from external baro: Q point 20
+            #[cfg(feature = "report-environment")]
+            0x0A => SensorReport::Pressure(q20_to_f32(
+                self.value_u32(0).unwrap_or(0),
+            )),
+            // Ambient light (lux) from external sensor: Q point 8
+            #[cfg(feature = "report-environment")]
+            0x0B => SensorReport::AmbientLight(q8_u32_to_f32(
+                self.value_u32(0).unwrap_or(0),
+            )),
+            // Humidity (percent) from external sensor: Q point 8
+            #[cfg(feature = "report-environment")]
+            0x0C => SensorReport::Humidity(q8_to_f32(value(0))),
+            // Proximity (centimeters) from external sensor: Q point 4
+            #[cfg(feature = "report-environment")]
+            0x0D => SensorReport::Proximity(q4_to_f32(value(0))),
+            // Temperature (degrees C) from external sensor: Q point 7
+            #[cfg(feature = "report-environment")]
+            0x0E => SensorReport::Temperature(q7_to_f32(value(0))),
+            // Heart rate (beats per minute) from an external monitor
+            #[cfg(feature = "report-heart-rate")]
+            0x23 => SensorReport::HeartRate(value(0) as u16),
+            // Tap detector: flags for X, X positive, Y, Y positive, Z,
+            // Z positive and double tap, from bit 0 up
+            #[cfg(feature = "report-gesture")]
+            0x10 => {
+                let flags = self.data().first().copied().unwrap_or(0);
+                let axes = |shift: u8| {
+                    GestureAxes((0..3).fold(0, |axes, axis| {
+                        axes | (((flags >> (2 * axis + shift)) & 1) << axis)
+                    }))
+                };
+                SensorReport::Tap {
+                    axes: axes(0),
+                    positive: axes(1),
+                    double: flags & 0x40 != 0,
+                }
+            }
+            // Shake detector: bits 0 to 2 for X, Y and Z
+            #[cfg(feature = "report-gesture")]
+            0x19 => SensorReport::Shake(GestureAxes(value(0) as u8 & 0x07)),
+            // Circle detector
+            #[cfg(feature = "report-gesture")]
+            0x22 => SensorReport::Circle,
+            report_id => SensorReport::Other { report_id },
+        }
+    }
+}
+
+/// Axes along which a gesture was detected, in the axes of the sensor hub
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GestureAxes(pub u8);
+
+impl GestureAxes {
+    pub const X: Self = Self(1 << 0);
+    pub const Y: Self = Self(1 << 1);
+    pub const Z: Self = Self(1 << 2);
+
+    /// Are all of `axes` in this set?
+    pub fn contains(&self, axes: Self) -> bool {
+        self.0 & axes.0 == axes.0
+    }
+}
+
+/// An input report decoded into physical units
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum SensorReport {
+    /// Rotation vector as unit quaternion,
+    /// with heading accuracy estimate (radians)
+    RotationVector {
+        quaternion: Quaternion,
+        accuracy: f32,
+    },
+    /// Gyro-integrated rotation vector as unit quaternion, with the
+    /// angular velocity (rad/s) of the same sample, for predicting the
+    /// orientation ahead
+    GyroIntegratedRotationVector {
+        quaternion: Quaternion,
+        angular_velocity: [f32; 3],
+    },
+    /// Linear acceleration (m/s^2)
+    LinearAccel([f32; 3]),
+    /// Gyroscope (rad/s)
+    Gyro([f32; 3]),
+    /// Pressure from an external barometer (hectopascals)
+    Pressure(f32),
+    /// Ambient light from an external sensor (lux)
+    AmbientLight(f32),
+    /// Humidity from an external sensor (percent)
+    Humidity(f32),
+    /// Proximity from an external sensor (centimeters)
+    Proximity(f32),
+    /// Temperature (degrees Celsius)
+    Temperature(f32),
+    /// Heart rate from an external monitor (beats per minute)
+    HeartRate(u16),
+    /// Single or double tap along `axes`, of which those in `positive`
+    /// were tapped in the positive direction
+    Tap {
+        axes: GestureAxes,
+        positive: GestureAxes,
+        double: bool,
+    },
+    /// Shake along `axes`
+    Shake(GestureAxes),
+    /// Circle gesture
+    Circle,
+    /// A report this crate does not decode
+    Other { report_id: u8 },
+}
+
+impl SensorReport {
+    /// Convert the orientation and vector values from the hub's native
+    /// frame into `frame`
+    pub fn in_frame(self, frame: Frame) -> Self {
+        match self {
+            SensorReport::RotationVector {
+                quaternion,
+                accuracy,
+            } => SensorReport::RotationVector {
+                quaternion: frame.quaternion(quaternion),
+                accuracy,
+            },
+            SensorReport::GyroIntegratedRotationVector {
+                quaternion,
+                angular_velocity,
+            } => SensorReport::GyroIntegratedRotationVector {
+                quaternion: frame.quaternion(quaternion),
+                angular_velocity: frame.vector(angular_velocity),
+            },
+            SensorReport::LinearAccel(v) => {
+                SensorReport::LinearAccel(frame.vector(v))
+            }
+            SensorReport::Gyro(v) => SensorReport::Gyro(frame.vector(v)),
+            other => other,
+        }
+    }
+}
+
+/// Accuracy status of the calibrated accelerometer, gyroscope and
+/// magnetometer reports: 0 unreliable, 1 low, 2 medium, 3 high
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CalibrationStatus {
+    pub accel: u8,
+    pub gyro: u8,
+    pub mag: u8,
+}
+
+/// Accuracy status value indicating high accuracy
+pub const ACCURACY_HIGH: u8 = 3;
+
+impl CalibrationStatus {
+    /// Have all three sensors reached high accuracy?
+    pub fn is_complete(&self) -> bool {
+        self.accel == ACCURACY_HIGH
+            && self.gyro == ACCURACY_HIGH
+            && self.mag == ACCURACY_HIGH
+    }
+}
+
+/// The value of the most recent report from one sensor, with the
+/// report's sequence number, accuracy and timestamp
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Sample<T> {
+    pub value: T,
+    /// Sequence number of the report, incremented by the hub per sensor
+    pub sequence: u8,
+    /// Accuracy estimate: 0 unreliable, 1 low, 2 medium, 3 high
+    pub accuracy: u8,
+    /// Time at which the sample was taken, in microseconds relative to
+    /// the host interrupt that announced its packet (usually negative)
+    pub timestamp_us: i32,
+    /// The base timestamp of the report's packet, in 100 microsecond
+    /// ticks relative to the host interrupt
+    pub timestamp_base: i32,
+    /// The report's raw delay field: the time from the base timestamp to
+    /// the sample, in 100 microsecond ticks. The reports of one packet
+    /// share their base timestamp, so the differences between their delays
+    /// are the intervals between the samples, free of the jitter of the
+    /// host interrupt.
+    pub delay: u16,
+}
+
+/// Number of report IDs, starting from zero, kept in the sample cache
+const CACHED_REPORT_IDS: usize = 0x0F;
+
+/// The raw values of a cached report
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct RawSample {
+    values: [i16; 4],
+    sequence: u8,
+    accuracy: u8,
+    timestamp_base: i32,
+    delay: u16,
+}
+
+impl RawSample {
+    /// Convert the raw values, keeping the report metadata
+    pub fn map<T>(&self, convert: impl FnOnce([i16; 4]) -> T) -> Sample<T> {
+        Sample {
+            value: convert(self.values),
+            sequence: self.sequence,
+            accuracy: self.accuracy,
+            timestamp_us: self
+                .timestamp_base
+                .wrapping_add(i32::from(self.delay))
+                .saturating_mul(100),
+            timestamp_base: self.timestamp_base,
+            delay: self.delay,
+        }
+    }
+}
+
+/// The most recent value of each report this crate decodes, kept as the
+/// raw fixed-point values so that receiving reports needs no floating point
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct LatestReports {
+    /// Rotation vector as unit quaternion, Q point 14
+    pub rotation_quaternion: [i16; 4],
+    /// Heading accuracy of rotation vector (radians), Q point 12
+    pub rot_quaternion_acc: i16,
+    /// Accuracy status bits of the rotation vector report
+    pub rot_quaternion_status: u8,
+    /// Linear acceleration vector, Q point 8
+    pub linear_accel: [i16; 3],
+    /// Gyroscope data, Q point 9
+    pub gyro: [i16; 3],
+    /// Accuracy of the calibrated sensor reports
+    pub calibration: CalibrationStatus,
+    /// Gyroscope bias (zero-rate offset) estimate of the uncalibrated
+    /// gyroscope report, Q point 9
+    pub gyro_bias: Option<RawSample>,
+    /// Heart rate monitor report (beats per minute)
+    pub heart_rate: Option<RawSample>,
+    /// The most recent report of each of the basic motion sensors and
+    /// external sensors, indexed by report ID
+    samples: [Option<RawSample>; CACHED_REPORT_IDS],
+}
+
+impl LatestReports {
+    /// Update from all input reports in a sensor report payload
+    pub fn update(&mut self, payload: &[u8]) {
+        let mut reports = InputReports::new(payload);
+        while let Some(report) = reports.next() {
+            self.update_report(&report, reports.timestamp_base());
+        }
+    }
+
+    /// Update from one input report of a packet with `timestamp_base`
+    pub fn update_report(&mut self, report: &InputReport, timestamp_base: i32) {
+        let value = |index| report.value(index).unwrap_or(0);
+        let raw_sample = |values| RawSample {
+            values,
+            sequence: report.sequence_number(),
+            accuracy: report.accuracy(),
+            timestamp_base,
+            delay: report.delay(),
+        };
+        if let Some(sample) = self.samples.get_mut(report.report_id() as usize)
+        {
+            *sample =
+                Some(raw_sample([value(0), value(1), value(2), value(3)]));
+        }
+        match report.report_id() {
+            // accelerometer, gyroscope calibrated, mag field calibrated
+            0x01 => self.calibration.accel = report.accuracy(),
+            0x02 => self.calibration.gyro = report.accuracy(),
+            0x03 => self.calibration.mag = report.accuracy(),
+            // linear acceleration
+            #[cfg(feature = "report-accel")]
+            0x04 => self.linear_accel = [value(0), value(1), value(2)],
+            // rotation vector
+            #[cfg(feature = "report-rotation")]
+            0x05 => {
+                self.rot_quaternion_status = report.accuracy();
+                self.rotation_quaternion =
+                    [value(0), value(1), value(2), value(3)];
+                self.rot_quaternion_acc = value(4);
+            }
+            // gyroscope uncalibrated, followed by its bias estimate
+            #[cfg(feature = "report-gyro")]
+            0x07 => {
+                self.gyro = [value(0), value(1), value(2)];
+                self.gyro_bias =
+                    Some(raw_sample([value(3), value(4), value(5), 0]));
+            }
+            // heart rate monitor
+            #[cfg(feature = "report-heart-rate")]
+            0x23 => {
+                self.heart_rate = Some(raw_sample([value(0), 0, 0, 0]));
+            }
+            _ => {
+                // debug_println!("uhr: {:X}", report_id);
+            }
+        }
+    }
+
+    /// Rotation vector as unit quaternion
+    pub fn quaternion(&self) -> [f32; 4] {
+        self.rotation_quaternion.map(q14_to_f32)
+    }
+
+    /// Heading accuracy of rotation vector (radians)
+    pub fn heading_accuracy(&self) -> f32 {
+        q12_to_f32(self.rot_quaternion_acc)
+    }
+
+    /// Linear acceleration (m/s^2)
+    pub fn linear_accel(&self) -> [f32; 3] {
+        self.linear_accel.map(q8_to_f32)
+    }
+
+    /// Gyroscope data (rad/s)
+    pub fn gyro(&self) -> [f32; 3] {
+        self.gyro.map(q9_to_f32)
+    }
+
+    /// The most recent report with this report ID, if it is cached
+    /// and has been received
+    pub fn sample(&self, report_id: u8) -> Option<&RawSample> {
+        self.samples.get(report_id as usize)?.as_ref()
+    }
+}
+
+/// Length of the longest input report this crate knows about
+pub const MAX_INPUT_REPORT_LENGTH: usize = 18;
+
+/// An input report copied out of the receive buffer, so that it can be
+/// queued and decoded later
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct OwnedInputReport {
+    bytes: [u8; MAX_INPUT_REPORT_LENGTH],
+    len: u8,
+    timestamp_base: i32,
+    /// time from the host interrupt to the arrival of the report's packet
+    /// (microseconds), if measured
+    bus_time_us: Option<u32>,
+}
+
+impl OwnedInputReport {
+    /// Copy `report`, along with the base timestamp of its packet
+    pub(crate) fn new(report: &InputReport, timestamp_base: i32) -> Self {
+        let source = report.as_bytes();
+        let len = source.len().min(MAX_INPUT_REPORT_LENGTH);
+        let mut bytes = [0u8; MAX_INPUT_REPORT_LENGTH];
+        bytes[..len].copy_from_slice(&source[..len]);
+        Self {
+            bytes,
+            len: len as u8,
+            timestamp_base,
+            bus_time_us: None,
+        }
+    }
+
+    /// Record the bus time of the report's packet
+    pub(crate) fn with_bus_time(mut self, bus_time_us: Option<u32>) -> Self {
+        self.bus_time_us = bus_time_us;
+        self
+    }
+
+    /// The gyro-integrated rotation vector `payload`, as received on the
+    /// gyro rotation channel, under a report header with `sequence`.
+    /// These packets carry no timestamp.
+    pub(crate) fn gyro_integrated_rv(
+        payload: &[u8],
+        sequence: u8,
+    ) -> Option<Self> {
+        let values = payload.first_chunk::<GYRO_INTEGRATED_RV_LENGTH>()?;
+        let mut bytes = [0u8; MAX_INPUT_REPORT_LENGTH];
+        let (header, data) = bytes.split_at_mut(INPUT_REPORT_HEADER_LENGTH);
+        header.copy_from_slice(&[0x2A, sequence, 0, 0]);
+        data.get_mut(..GYRO_INTEGRATED_RV_LENGTH)?
+            .copy_from_slice(values);
+        Some(Self {
+            bytes,
+            len: (INPUT_REPORT_HEADER_LENGTH + GYRO_INTEGRATED_RV_LENGTH) as u8,
+            timestamp_base: 0,
+            bus_time_us: None,
+        })
+    }
+
+    /// The base timestamp of the report's packet, in 100 microsecond
+    /// ticks relative to the host interrupt, as `InputReports::timestamp_base`
+    pub fn timestamp_base(&self) -> i32 {
+        self.timestamp_base
+    }
+
+    /// Time at which the report's sample was taken, in microseconds
+    /// relative to the host interrupt: the base timestamp plus the
+    /// report's `delay`
+    pub fn timestamp_us(&self) -> i32 {
+        self.timestamp_base
+            .wrapping_add(i32::from(self.as_report().delay()))
+            .saturating_mul(100)
+    }
+
+    /// Time from the host interrupt that announced the report's packet
+    /// to the packet's arrival (microseconds), if the interrupt was
+    /// timestamped with `BNO080::on_interrupt_at`
+    pub fn bus_time_us(&self) -> Option<u32> {
+        self.bus_time_us
+    }
+
+    /// End-to-end latency of the report (microseconds): the hub's delay
+    /// from the sample to the interrupt, plus the bus time, if measured
+    pub fn latency_us(&self) -> Option<u32> {
+        let hub_delay_us = self.timestamp_us().saturating_neg().max(0);
+        Some(
+            self.bus_time_us?
+                .saturating_add(hub_delay_us.unsigned_abs()),
+        )
+    }
+
+    /// Borrow the report, to read its header and raw values
+    pub fn as_report(&self) -> InputReport<'_> {
+        InputReport {
+            bytes: &self.bytes[..self.len as usize],
+        }
+    }
+}
+
+/// Iterator over the input reports contained in a sensor report payload.
+/// Timestamp records are skipped; iteration stops at the first report
+/// whose ID (and therefore length) is unknown, or which is truncated.
+#[derive(Debug, Clone)]
+pub struct InputReports<'a> {
+    payload: &'a [u8],
+    cursor: usize,
+    timestamp_base: i32,
+}
+
+impl<'a> InputReports<'a> {
+    /// Iterate over the reports in `payload` (the packet body, without
+    /// the SHTP header)
+    pub fn new(payload: &'a [u8]) -> Self {
+        Self {
+            payload,
+            cursor: 0,
+            timestamp_base: 0,
+        }
+    }
+
+    /// An iterator that yields no reports
+    pub fn empty() -> Self {
+        Self::new(&[])
+    }
+
+    /// The part of the payload not yet iterated over. Once iteration has
+    /// stopped, this is empty unless a report with an unknown ID was found,
+    /// in which case it starts with that report.
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.payload[self.cursor.min(self.payload.len())..]
+    }
+
+    /// Time of the base timestamp for the reports yielded since the last
+    /// timestamp record, in 100 microsecond ticks relative to the host
+    /// interrupt that announced the packet. Adding a report's `delay`
+    /// gives the time at which that report's sample was taken.
+    pub fn timestamp_base(&self) -> i32 {
+        self.timestamp_base
+    }
+
+    /// Continue iterating from where this iterator stopped, over `payload`,
+    /// the payload it was created with. This lets the caller release its
+    /// borrow of the payload between reports.
+    pub(crate) fn resume<'b>(&self, payload: &'b [u8]) -> InputReports<'b> {
+        InputReports {
+            payload,
+            cursor: self.cursor,
+            timestamp_base: self.timestamp_base,
+        }
+    }
+}
+
+impl<'a> Iterator for InputReports<'a> {
+    type Item = InputReport<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.cursor < self.payload.len() {
+            let report_id = self.payload[self.cursor];
+            let report_len = match report_id {
+                REPORTID_BASE_TIMESTAMP | REPORTID_TIMESTAMP_REBASE => {
+                    let end = self.cursor + TIMESTAMP_RECORD_LENGTH;
+                    if let Some(record) = self.payload.get(self.cursor + 1..end)
+                    {
+                        // signed, in 100 microsecond ticks
+                        let delta = i32::from_le_bytes([
+                            record[0], record[1], record[2], record[3],
+                        ]);
+                        self.timestamp_base =
+                            if report_id == REPORTID_BASE_TIMESTAMP {
+                                // the base timestamp is `delta` before the interrupt
+                                delta.wrapping_neg()
+                            } else {
+                                self.timestamp_base.wrapping_add(delta)
+                            };
+                    }
+                    self.cursor = end;
+                    continue;
+                }
+                _ => input_report_length(report_id)?,
+            };
+
+            let end = self.cursor + report_len;
+            if end > self.payload.len() {
+                // truncated report
+                self.cursor = self.payload.len();
+                return None;
+            }
+            let report = InputReport {
+                bytes: &self.payload[self.cursor..end],
+            };
+            self.cursor = end;
+            return Some(report);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_iterate_input_reports() {
+        let payload: [u8; 29] = [
+            // base timestamp reference
+            0xFB, 0x01, 0x00, 0x00, 0x00, // rotation vector
+            0x05, 0x07, 0x07, 0x00, 0x01, 0x00, 0x02, 0x00, 0x03, 0x00, 0x00,
+            0x40, 0x00, 0x10, // linear acceleration
+            0x04, 0x08, 0x02, 0x00, 0x00, 0x01, 0xFF, 0xFF, 0x00, 0x00,
+        ];
+        let mut reports = InputReports::new(&payload);
+
+        let rv = reports.next().unwrap();
+        assert_eq!(rv.report_id(), 0x05);
+        assert_eq!(rv.sequence_number(), 0x07);
+        assert_eq!(rv.accuracy(), 3);
+        assert_eq!(rv.delay(), 0x100);
+        assert_eq!(rv.value(3), Some(0x4000));
+        assert_eq!(rv.value(4), Some(0x1000));
+        assert_eq!(rv.value(5), None);
+        // base timestamp 100 us before the interrupt, report delay 25.6 ms
+        assert_eq!(reports.timestamp_base(), -1);
+
+        let la = reports.next().unwrap();
+        assert_eq!(la.report_id(), 0x04);
+        assert_eq!(la.value(0), Some(0x100));
+        assert_eq!(la.value(1), Some(-1));
+
+        assert!(reports.next().is_none());
+    }
+
+    #[cfg(feature = "report-rotation")]
+    #[test]
+    fn test_decode_rotation_vector() {
+        let payload: [u8; 14] = [
+            0x05, 0x01, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x20, 0x00,
+            0x20, 0x00, 0x08,
+        ];
+        let report = InputReports::new(&payload).next().unwrap();
+        assert_eq!(
+            report.decode(),
+            SensorReport::RotationVector {
+                quaternion: Quaternion {
+                    i: 0.0,
+                    j: 0.0,
+                    k: 0.5,
+                    real: 0.5,
+                },
+                accuracy: 0.5,
+            }
+        );
+    }
+
+    #[cfg(feature = "report-rotation")]
+    #[test]
+    fn test_decode_gyro_integrated_rv() {
+        // a quarter turn about Z, turning at 2 rad/s about Z
+        let payload: [u8; 14] = [
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x20, 0x00, 0x20, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x08,
+        ];
+        let owned = OwnedInputReport::gyro_integrated_rv(&payload, 7).unwrap();
+        let report = owned.as_report();
+        assert_eq!(report.report_id(), 0x2A);
+        assert_eq!(report.sequence_number(), 7);
+        assert_eq!(report.q_point(3), Some(ROTATION_VECTOR_Q));
+        assert_eq!(report.q_point(6), Some(ANGULAR_VELOCITY_Q));
+        assert_eq!(
+            report.decode(),
+            SensorReport::GyroIntegratedRotationVector {
+                quaternion: Quaternion {
+                    i: 0.0,
+                    j: 0.0,
+                    k: 0.5,
+                    real: 0.5,
+                },
+                angular_velocity: [0.0, 0.0, 2.0],
+            }
+        );
+        assert!(
+            OwnedInputReport::gyro_integrated_rv(&payload[1..], 0).is_none()
+        );
+    }
+
+    #[cfg(all(feature = "report-environment", feature = "report-heart-rate"))]
+    #[test]
+    fn test_decode_external_sensors() {
+        // pressure 1013.25 hPa, then humidity 50 %
+        let payload: [u8; 14] = [
+            0x0A, 0x01, 0x00, 0x00, 0x00, 0x00, 0x54, 0x3F, 0x0C, 0x02, 0x00,
+            0x00, 0x00, 0x32,
+        ];
+        let mut reports = InputReports::new(&payload);
+        let pressure = reports.next().unwrap();
+        assert_eq!(pressure.value_u32(0), Some(0x3F54_0000));
+        assert_eq!(pressure.decode(), SensorReport::Pressure(1013.25));
+        assert_eq!(pressure.q_point(0), Some(PRESSURE_Q));
+        let humidity = reports.next().unwrap();
+        assert_eq!(humidity.decode(), SensorReport::Humidity(50.0));
+        assert!(reports.next().is_none());
+
+        let payload: [u8; 6] = [0x23, 0x01, 0x00, 0x00, 72, 0x00];
+        let heart_rate = InputReports::new(&payload).next().unwrap();
+        assert_eq!(heart_rate.decode(), SensorReport::HeartRate(72));
+
+        // -12.5 degrees C
+        let payload: [u8; 6] = [0x0E, 0x01, 0x00, 0x00, 0xC0, 0xF9];
+        let temperature = InputReports::new(&payload).next().unwrap();
+        assert_eq!(temperature.decode(), SensorReport::Temperature(-12.5));
+    }
+
+    #[test]
+    fn test_unknown_report_remaining() {
+        let payload: [u8; 16] = [
+            0x04, 0x08, 0x02, 0x00, 0x00, 0x01, 0xFF, 0xFF, 0x00, 0x00,
+            // a report ID this crate does not know
+            0x7E, 0x01, 0x02, 0x03, 0x04, 0x05,
+        ];
+        let mut reports = InputReports::new(&payload);
+        assert_eq!(reports.by_ref().count(), 1);
+        assert_eq!(reports.remaining(), &payload[10..]);
+    }
+
+    #[test]
+    fn test_truncated_report() {
+        let payload: [u8; 6] = [0x05, 0x01, 0x00, 0x00, 0x01, 0x00];
+        assert!(InputReports::new(&payload).next().is_none());
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn test_decode_arbitrary_payload(
+            payload in proptest::collection::vec(0u8..=0xFF, 0..96)
+        ) {
+            let mut reports = InputReports::new(&payload);
+            for report in reports.by_ref() {
+                let _ = report.decode();
+                let _ = OwnedInputReport::new(&report, 0).as_report().decode();
+            }
+            let _ = reports.timestamp_base();
+            let _ = reports.remaining();
+            LatestReports::default().update(&payload);
+        }
+    }
+}
@@ -0,0 +1,178 @@
+/*
+Copyright (c) 2020 Todd Stellanova
+LICENSE: BSD3 (see LICENSE file)
+*/
+
+//! UART-RVC mode: when strapped into RVC mode, the sensor hub streams
+//! fixed 19-byte frames with heading, attitude and acceleration at 100 Hz
+//! over its UART, without any SHTP protocol.
+//!
+//! Frame layout: header `0xAA 0xAA`, index, yaw, pitch, roll (0.01 degree),
+//! X, Y and Z acceleration (mg), motion intent, motion request, a reserved
+//! byte, and a checksum which is the sum of the bytes from index through
+//! the reserved byte.
+
+use embedded_hal_nb::nb;
+use embedded_hal_nb::serial::Read;
+
+/// Length of an RVC frame, including header and checksum
+pub const RVC_FRAME_LENGTH: usize = 19;
+
+const RVC_HEADER_BYTE: u8 = 0xAA;
+/// Standard gravity, to convert mg into m/s^2
+const MILLI_G_TO_MPS2: f32 = 9.80665 / 1000.0;
+
+/// One reading from an RVC frame
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RvcReading {
+    /// Frame counter, incremented by the hub for every frame
+    pub index: u8,
+    /// Heading (degrees)
+    pub yaw: f32,
+    /// Pitch (degrees)
+    pub pitch: f32,
+    /// Roll (degrees)
+    pub roll: f32,
+    /// Acceleration (m/s^2), including gravity
+    pub accel: [f32; 3],
+    pub motion_intent: u8,
+    pub motion_request: u8,
+}
+
+impl RvcReading {
+    /// Parse a complete frame, returning `None` if the header or checksum
+    /// do not match
+    pub fn from_frame(frame: &[u8; RVC_FRAME_LENGTH]) -> Option<Self> {
+        if frame[0] != RVC_HEADER_BYTE || frame[1] != RVC_HEADER_BYTE {
+            return None;
+        }
+        let checksum = frame[2..RVC_FRAME_LENGTH - 1]
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        if checksum != frame[RVC_FRAME_LENGTH - 1] {
+            return None;
+        }
+
+        let value = |offset: usize| {
+            i16::from_le_bytes([frame[offset], frame[offset + 1]]) as f32
+        };
+        Some(Self {
+            index: frame[2],
+            yaw: value(3) * 0.01,
+            pitch: value(5) * 0.01,
+            roll: value(7) * 0.01,
+            accel: [
+                value(9) * MILLI_G_TO_MPS2,
+                value(11) * MILLI_G_TO_MPS2,
+                value(13) * MILLI_G_TO_MPS2,
+            ],
+            motion_intent: frame[15],
+            motion_request: frame[16],
+        })
+    }
+}
+
+/// Assembles frames from a byte stream, resynchronizing on the header
+#[derive(Debug, Clone, Default)]
+pub struct RvcParser {
+    frame: [u8; RVC_FRAME_LENGTH],
+    len: usize,
+}
+
+impl RvcParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add one received byte, returning a reading once a valid frame
+    /// is complete
+    pub fn push(&mut self, byte: u8) -> Option<RvcReading> {
+        if self.len < 2 && byte != RVC_HEADER_BYTE {
+            // not a header: wait for the start of the next frame
+            self.len = 0;
+            return None;
+        }
+        self.frame[self.len] = byte;
+        self.len += 1;
+        if self.len < RVC_FRAME_LENGTH {
+            return None;
+        }
+        self.len = 0;
+        RvcReading::from_frame(&self.frame)
+    }
+}
+
+/// Reads RVC frames from a serial port
+pub struct Rvc<S> {
+    serial: S,
+    parser: RvcParser,
+}
+
+impl<S> Rvc<S> {
+    pub fn new(serial: S) -> Self {
+        Self {
+            serial,
+            parser: RvcParser::new(),
+        }
+    }
+
+    /// Release the serial port
+    pub fn free(self) -> S {
+        self.serial
+    }
+}
+
+impl<S> Rvc<S>
+where
+    S: Read<u8>,
+{
+    /// Read the bytes available on the serial port, returning a reading
+    /// once a frame is complete, or `WouldBlock` while it is not
+    pub fn read(&mut self) -> nb::Result<RvcReading, S::Error> {
+        loop {
+            let byte = self.serial.read()?;
+            if let Some(reading) = self.parser.push(byte) {
+                return Ok(reading);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // yaw 0.01, pitch -1.10, roll 20.85 degrees; accel -371, -20, 977 mg
+    const FRAME: [u8; RVC_FRAME_LENGTH] = [
+        0xAA, 0xAA, 0xDE, 0x01, 0x00, 0x92, 0xFF, 0x25, 0x08, 0x8D, 0xFE, 0xEC,
+        0xFF, 0xD1, 0x03, 0x00, 0x00, 0x00, 0xE7,
+    ];
+
+    #[test]
+    fn test_parse_frame() {
+        let reading = RvcReading::from_frame(&FRAME).unwrap();
+        assert_eq!(reading.index, 0xDE);
+        assert!((reading.yaw - 0.01).abs() < 1e-4);
+        assert!((reading.pitch - -1.10).abs() < 1e-4);
+        assert!((reading.roll - 20.85).abs() < 1e-4);
+        assert!((reading.accel[0] - -371.0 * MILLI_G_TO_MPS2).abs() < 1e-4);
+        assert!((reading.accel[2] - 977.0 * MILLI_G_TO_MPS2).abs() < 1e-4);
+
+        let mut corrupted = FRAME;
+        corrupted[4] = 0x01;
+        assert!(RvcReading::from_frame(&corrupted).is_none());
+    }
+
+    #[test]
+    fn test_parser_resync() {
+        let mut parser = RvcParser::new();
+        // the tail end of a previous frame
+        for byte in [0x00, 0x12, 0xAA, 0x34] {
+            assert!(parser.push(byte).is_none());
+        }
+        let readings = FRAME.iter().filter_map(|byte| parser.push(*byte));
+        assert_eq!(readings.count(), 1);
+    }
+}
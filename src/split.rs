@@ -0,0 +1,309 @@
+/*
+Copyright (c) 2020 Todd Stellanova
+LICENSE: BSD3 (see LICENSE file)
+*/
+
+//! Splitting the driver into a `Transport` half, which moves packets
+//! from the sensor hub into a queue and is small enough to own from an
+//! interrupt handler, and a `Decoder` half, which parses the queued
+//! packets in thread context.
+
+use crate::interface::{SensorInterface, PACKET_HEADER_LENGTH};
+use crate::math::{EulerAngles, FixedEulerAngles, Frame, Quaternion};
+use crate::reports::{LatestReports, OwnedInputReport, SensorReport};
+use crate::wrapper::{OwnedPacket, Packet, ShtpHeader, WrapperError, BNO080};
+
+use heapless::spsc::{Consumer, Producer, Queue};
+
+/// Maximum length of a queued packet, including its SHTP header.
+/// Longer packets are counted as corrupt and dropped.
+pub const QUEUED_PACKET_LEN: usize = 256;
+
+/// A packet as received from the sensor hub, queued for the `Decoder`
+pub type QueuedPacket =
+    OwnedPacket<{ QUEUED_PACKET_LEN - PACKET_HEADER_LENGTH }>;
+
+/// Queue of packets passed from the `Transport` to the `Decoder`,
+/// holding at most `N - 1` packets.
+/// `PacketQueue::new()` is `const`, so the queue can live in a `static`.
+pub type PacketQueue<const N: usize> = Queue<QueuedPacket, N>;
+
+/// Receives packets from the sensor hub and queues them for the `Decoder`
+pub struct Transport<'q, SI, const N: usize> {
+    sensor_interface: SI,
+    producer: Producer<'q, QueuedPacket, N>,
+    dropped_packets: u32,
+    corrupt_packets: u32,
+}
+
+/// Parses the packets queued by the `Transport`
+pub struct Decoder<'q, const N: usize> {
+    consumer: Consumer<'q, QueuedPacket, N>,
+    latest: LatestReports,
+    /// the channels carrying sensor reports
+    input_channels: [u8; 2],
+    /// the channel carrying the gyro-integrated rotation vector
+    gyro_rv_channel: u8,
+    /// the most recent gyro-integrated rotation vector report
+    gyro_integrated_rv: Option<OwnedInputReport>,
+    /// coordinate frame of the decoded outputs, as set on the driver
+    frame: Frame,
+}
+
+impl<SI, const SEND_BUF_LEN: usize, const RECV_BUF_LEN: usize>
+    BNO080<SI, SEND_BUF_LEN, RECV_BUF_LEN>
+{
+    /// Split the driver into its `Transport` and `Decoder` halves,
+    /// connected by `queue`.
+    /// Initialize the driver and enable the reports of interest first:
+    /// after splitting, packets are only received, not sent.
+    pub fn split<const N: usize>(
+        self,
+        queue: &mut PacketQueue<N>,
+    ) -> (Transport<'_, SI, N>, Decoder<'_, N>) {
+        let (producer, consumer) = queue.split();
+        let channels = self.channels();
+        let frame = self.output_frame();
+        (
+            Transport {
+                sensor_interface: self.sensor_interface,
+                producer,
+                dropped_packets: 0,
+                corrupt_packets: 0,
+            },
+            Decoder {
+                consumer,
+                latest: LatestReports::default(),
+                input_channels: [channels.input_normal, channels.input_wake],
+                gyro_rv_channel: channels.input_gyro_rv,
+                gyro_integrated_rv: None,
+                frame,
+            },
+        )
+    }
+}
+
+impl<SI, SE, const N: usize> Transport<'_, SI, N>
+where
+    SI: SensorInterface<SensorError = SE>,
+{
+    /// Read one packet, without waiting for data, and queue it.
+    /// Call this when the host interrupt signals that data is available.
+    /// Returns whether a packet was queued; a packet that does not fit
+    /// in the queue is dropped and counted, as is a packet longer than
+    /// `QUEUED_PACKET_LEN`.
+    pub fn poll(&mut self) -> Result<bool, WrapperError<SE>> {
+        let mut recv_buf = [0u8; QUEUED_PACKET_LEN];
+        let received_len = self
+            .sensor_interface
+            .read_packet_bulk(&mut recv_buf)
+            .map_err(WrapperError::CommError)?;
+        if received_len <= PACKET_HEADER_LENGTH {
+            return Ok(false);
+        }
+        let header = ShtpHeader::parse(&recv_buf);
+        let packet = recv_buf
+            .get(PACKET_HEADER_LENGTH..received_len)
+            .filter(|_| usize::from(header.length) <= QUEUED_PACKET_LEN)
+            .and_then(|payload| {
+                QueuedPacket::from_packet(&Packet { header, payload })
+            });
+        let Some(packet) = packet else {
+            self.corrupt_packets = self.corrupt_packets.wrapping_add(1);
+            return Ok(false);
+        };
+        if self.producer.enqueue(packet).is_err() {
+            self.dropped_packets = self.dropped_packets.wrapping_add(1);
+            return Ok(false);
+        }
+        Ok(true)
+    }
+}
+
+impl<SI, const N: usize> Transport<'_, SI, N> {
+    /// Number of packets dropped because the queue was full
+    pub fn dropped_packets(&self) -> u32 {
+        self.dropped_packets
+    }
+
+    /// Number of packets dropped because they were longer than
+    /// `QUEUED_PACKET_LEN`
+    pub fn corrupt_packets(&self) -> u32 {
+        self.corrupt_packets
+    }
+
+    /// Returns the consumed sensor interface
+    pub fn free(self) -> SI {
+        self.sensor_interface
+    }
+}
+
+impl<const N: usize> Decoder<'_, N> {
+    /// Parse all queued packets, returning the number of packets handled.
+    /// Only sensor report packets, including those of the gyro-integrated
+    /// rotation vector channel, update the decoded values.
+    pub fn process_queued(&mut self) -> u32 {
+        let mut handled: u32 = 0;
+        while let Some(packet) = self.consumer.dequeue() {
+            handled += 1;
+            if self.input_channels.contains(&packet.header.channel) {
+                self.latest.update(&packet.payload);
+            } else if packet.header.channel == self.gyro_rv_channel {
+                let report = OwnedInputReport::gyro_integrated_rv(
+                    &packet.payload,
+                    packet.header.sequence,
+                );
+                if let Some(report) = report {
+                    self.latest.update_report(&report.as_report(), 0);
+                    self.gyro_integrated_rv = Some(report);
+                }
+            }
+        }
+        handled
+    }
+
+    /// The most recent gyro-integrated rotation vector report, if any
+    /// was received
+    pub fn gyro_integrated_rv(&self) -> Option<SensorReport> {
+        let report = self.gyro_integrated_rv.as_ref()?;
+        Some(report.as_report().decode().in_frame(self.frame))
+    }
+
+    /// Read the rotation vector as a `Quaternion`
+    pub fn quaternion(&self) -> Quaternion {
+        self.frame
+            .quaternion(Quaternion::from(self.latest.quaternion()))
+    }
+
+    /// Estimated heading accuracy of the rotation vector (radians)
+    pub fn heading_accuracy(&self) -> f32 {
+        self.latest.heading_accuracy()
+    }
+
+    /// Accuracy status of the rotation vector report:
+    /// 0 unreliable, 1 low, 2 medium, 3 high
+    pub fn rotation_vector_status(&self) -> u8 {
+        self.latest.rot_quaternion_status
+    }
+
+    /// Read the rotation vector as roll, pitch and yaw (radians),
+    /// along with its heading accuracy estimate
+    pub fn euler_angles(&self) -> EulerAngles {
+        EulerAngles::from_quaternion(
+            &self.quaternion(),
+            self.latest.heading_accuracy(),
+        )
+    }
+
+    /// Read the rotation vector as fixed-point roll, pitch and yaw,
+    /// computed without floating point arithmetic
+    pub fn euler_angles_fixed(&self) -> FixedEulerAngles {
+        FixedEulerAngles::from_q14(self.latest.rotation_quaternion)
+    }
+
+    /// Read the raw rotation vector quaternion, in `[i, j, k, real]` order,
+    /// with Q point `ROTATION_VECTOR_Q`
+    pub fn rotation_quaternion_raw(&self) -> [i16; 4] {
+        self.latest.rotation_quaternion
+    }
+
+    /// Read linear acceleration (m/s^2)
+    pub fn linear_accel(&self) -> [f32; 3] {
+        self.frame.vector(self.latest.linear_accel())
+    }
+
+    /// Read raw linear acceleration, with Q point `ACCEL_Q`
+    pub fn linear_accel_raw(&self) -> [i16; 3] {
+        self.latest.linear_accel
+    }
+
+    /// Read gyroscope data (rad/s)
+    pub fn gyro(&self) -> [f32; 3] {
+        self.frame.vector(self.latest.gyro())
+    }
+
+    /// Read raw gyroscope data, with Q point `GYRO_Q`
+    pub fn gyro_raw(&self) -> [i16; 3] {
+        self.latest.gyro
+    }
+}
+
+#[cfg(all(test, feature = "report-rotation"))]
+mod tests {
+    use super::*;
+    use crate::interface::i2c::DEFAULT_ADDRESS;
+    use crate::interface::mock_i2c_port::FakeI2cPort;
+    use crate::interface::I2cInterface;
+
+    // a sensor report packet carrying a rotation vector
+    const ROTATION_VECTOR_PACKET: [u8; 23] = [
+        23, 0, 3, 0, // header
+        0xFB, 0, 0, 0, 0, // base timestamp
+        0x05, 0x01, 0x03, 0x00, // report header
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x20, 0x00, 0x20, 0x00, 0x08,
+    ];
+
+    #[test]
+    fn test_split_transport_decoder() {
+        let mut port = FakeI2cPort::new();
+        // a packet too long to queue
+        let mut long_packet = [0u8; QUEUED_PACKET_LEN + 1];
+        long_packet[..4].copy_from_slice(&[0x01, 0x01, 3, 0]);
+        port.add_available_packet(&long_packet[..4]);
+        port.add_available_packet(&long_packet);
+        for _ in 0..3 {
+            port.add_available_packet(&ROTATION_VECTOR_PACKET[..4]);
+            port.add_available_packet(&ROTATION_VECTOR_PACKET);
+        }
+        let shub = BNO080::new_with_interface(I2cInterface::new(
+            port,
+            DEFAULT_ADDRESS,
+        ));
+        let mut queue = PacketQueue::<3>::new();
+        let (mut transport, mut decoder) = shub.split(&mut queue);
+
+        assert!(!transport.poll().unwrap());
+        assert_eq!(transport.corrupt_packets(), 1);
+        assert!(transport.poll().unwrap());
+        assert!(transport.poll().unwrap());
+        // the queue holds only two packets
+        assert!(!transport.poll().unwrap());
+        assert_eq!(transport.dropped_packets(), 1);
+        // nothing left to read
+        assert!(!transport.poll().unwrap());
+
+        assert_eq!(decoder.process_queued(), 2);
+        assert_eq!(
+            decoder.quaternion(),
+            Quaternion::from([0.0, 0.0, 0.5, 0.5])
+        );
+        assert_eq!(decoder.heading_accuracy(), 0.5);
+        assert_eq!(decoder.process_queued(), 0);
+    }
+
+    #[test]
+    fn test_decoder_gyro_integrated_rv() {
+        // identity orientation, turning at -1 rad/s about X
+        let packet: [u8; 18] =
+            [18, 0, 5, 9, 0, 0, 0, 0, 0, 0, 0, 0x40, 0, 0xFC, 0, 0, 0, 0];
+        let mut port = FakeI2cPort::new();
+        port.add_available_packet(&packet[..4]);
+        port.add_available_packet(&packet);
+        let shub = BNO080::new_with_interface(I2cInterface::new(
+            port,
+            DEFAULT_ADDRESS,
+        ));
+        let mut queue = PacketQueue::<3>::new();
+        let (mut transport, mut decoder) = shub.split(&mut queue);
+        assert!(transport.poll().unwrap());
+        assert_eq!(decoder.gyro_integrated_rv(), None);
+        assert_eq!(decoder.process_queued(), 1);
+        assert_eq!(
+            decoder.gyro_integrated_rv(),
+            Some(SensorReport::GyroIntegratedRotationVector {
+                quaternion: Quaternion::from([0.0, 0.0, 0.0, 1.0]),
+                angular_velocity: [-1.0, 0.0, 0.0],
+            })
+        );
+    }
+}
@@ -3,20 +3,108 @@ Copyright (c) 2020 Todd Stellanova
 LICENSE: BSD3 (see LICENSE file)
 */
 
-use crate::interface::{SensorInterface, PACKET_HEADER_LENGTH};
+// buffers are only accessed through checked accesses, so that malformed
+// traffic from the sensor hub cannot cause a panic
+#![cfg_attr(not(test), deny(clippy::indexing_slicing))]
+
+#[cfg(feature = "filter")]
+use crate::filter::{smooth_report, LowPass, SMOOTHED_REPORT_IDS};
+use crate::frs::{
+    FrsReadResponse, FrsReadStatus, FrsWriteResponse, GyroIntegratedRvConfig,
+    StaticCalibrationAgm, UartOutputFormat, FRS_GYRO_INTEGRATED_RV_CONFIG,
+    FRS_NOMINAL_CALIBRATION_AGM, FRS_SERIAL_NUMBER, FRS_STATIC_CALIBRATION_AGM,
+    FRS_UART_OUTPUT_FORMAT, FRS_WRITE_STATUS_BUSY, FRS_WRITE_STATUS_COMPLETED,
+    FRS_WRITE_STATUS_READY, FRS_WRITE_STATUS_RECEIVED,
+    FRS_WRITE_STATUS_RECORD_VALID, GYRO_INTEGRATED_RV_CONFIG_WORDS,
+    STATIC_CALIBRATION_AGM_WORDS,
+};
+#[cfg(feature = "report-gesture")]
+use crate::gesture::{Gesture, GestureCallback, GestureEvents};
+use crate::ids::{
+    Channel, ControlReportId, ExecutableCommand, ExecutableResponse,
+    SensorReportId,
+};
+#[cfg(test)]
+use crate::interface::{
+    mock_i2c_port::{FakeI2cPort, FakePacketQueue},
+    I2cInterface,
+};
+use crate::interface::{
+    NoDelay, SensorInterface, MAX_CARGO_DATA_LENGTH, PACKET_HEADER_LENGTH,
+};
+use crate::math::{EulerAngles, FixedEulerAngles, Frame, Quaternion};
+use crate::reports::{
+    report_interval_limits, CalibrationStatus, InputReports, LatestReports,
+    OwnedInputReport, ReportIntervalLimits, Sample, SensorReport,
+};
 
 use core::ops::Shr;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use embedded_hal::delay::DelayNs;
+use heapless::{Deque, Vec};
 #[cfg(feature = "rttdebug")]
 use panic_rtt_core::rprintln;
 
-const PACKET_SEND_BUF_LEN: usize = 256;
-const PACKET_RECV_BUF_LEN: usize = 1024;
+/// Default sizes of the packet send and receive buffers, see
+/// `BNO080::new_with_buffers`
+pub const PACKET_SEND_BUF_LEN: usize = 256;
+pub const PACKET_RECV_BUF_LEN: usize = 1024;
+
+/// Number of channels for which sequence numbers and statistics are kept.
+/// Only channels advertised by the sensor hub are used; the BNO080
+/// firmware advertises six.
+pub const MAX_CHANNELS: usize = 8;
+/// The channels used until the advertisement is received
+const DEFAULT_ADVERTISED_CHANNELS: u8 = 0b0011_1111;
+
+/// Longest each read waits while waiting for a command response
+/// (milliseconds)
+const COMMAND_RESPONSE_READ_MS: u32 = 150;
+
+/// Attempts at sending a write the hub was not ready for
+const NOT_READY_MAX_ATTEMPTS: u8 = 5;
+/// Back-off before sending a write the hub was not ready for again,
+/// doubled after each attempt (milliseconds)
+const NOT_READY_BACKOFF_MS: u32 = 10;
+
+/// Report interval of the calibrated reports during `run_calibration`
+const CALIBRATION_REPORT_INTERVAL_MS: u16 = 20;
+
+/// Report interval of the gesture detectors enabled by `enable_gesture`
+#[cfg(feature = "report-gesture")]
+const GESTURE_REPORT_INTERVAL_MS: u16 = 10;
+
+/// Number of decoded reports retained for `next_report`
+pub const REPORT_QUEUE_LEN: usize = 16;
 
-const NUM_CHANNELS: usize = 6;
+/// Number of report IDs, starting from zero, that can be decimated:
+/// all of the sensor reports
+const DECIMATED_REPORT_IDS: usize = 0x30;
+
+/// Decimation of one report ID: every `factor`th report is queued
+#[derive(Debug, Clone, Copy, Default)]
+struct Decimation {
+    factor: u8,
+    /// reports dropped since the last one queued
+    skipped: u8,
+}
+
+impl Decimation {
+    /// Count a report: is it queued, or dropped?
+    fn pass(&mut self) -> bool {
+        if self.skipped.saturating_add(1) >= self.factor {
+            self.skipped = 0;
+            true
+        } else {
+            self.skipped += 1;
+            false
+        }
+    }
+}
 
 #[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum WrapperError<E> {
     ///Communications error
     CommError(E),
@@ -26,812 +114,6536 @@ pub enum WrapperError<E> {
     InvalidFWVersion(u8),
     /// We expected some data but didn't receive any
     NoDataAvailable,
+    /// A command was refused by the sensor hub
+    CommandFailed(HubCommandError),
+    /// The connected firmware does not support this report ID
+    UnsupportedReport(u8),
+    /// An operation did not complete within the time allowed
+    Timeout,
+    /// A received packet did not fit in the receive buffer,
+    /// and has been discarded
+    BufferTooSmall,
+    /// A packet to send does not fit in the send buffer, or is larger
+    /// than the sensor hub advertised it accepts
+    PacketTooLarge,
+    /// A response arrived, but for a different command (this one)
+    UnexpectedReport(u8),
+    /// Packets were lost: on this channel, a packet with sequence number
+    /// `received` arrived when `expected` was due
+    SequenceGap {
+        channel: u8,
+        expected: u8,
+        received: u8,
+    },
+    /// A flash record (FRS) read or write failed, with this status
+    FrsError(u8),
+    /// The sensor hub did not advertise this channel
+    UnknownChannel(u8),
+    /// A received packet failed validation and has been discarded: its
+    /// header disagreed with the bytes read, or named a channel that was
+    /// not advertised
+    CorruptPacket,
+    /// The sensor hub did not enable a report at an acceptable rate:
+    /// it answered with this report interval (zero if disabled)
+    FeatureRejected { report_id: u8, interval_us: u32 },
+    /// The report interval requested is outside the sensor's limits
+    InvalidReportInterval {
+        report_id: u8,
+        interval_us: u32,
+        limits: ReportIntervalLimits,
+    },
+    /// More reports were declared to a `Bno080Builder` than it holds
+    TooManyReports,
 }
 
-pub struct BNO080<SI> {
-    pub(crate) sensor_interface: SI,
-    /// each communication channel with the device has its own sequence number
-    sequence_numbers: [u8; NUM_CHANNELS],
-    /// buffer for building and sending packet to the sensor hub
-    packet_send_buf: [u8; PACKET_SEND_BUF_LEN],
-    /// buffer for building packets received from the sensor hub
-    packet_recv_buf: [u8; PACKET_RECV_BUF_LEN],
+impl<E: core::fmt::Debug> core::fmt::Display for WrapperError<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::CommError(e) => write!(f, "communication error: {:?}", e),
+            Self::InvalidChipId(id) => write!(f, "invalid chip ID 0x{:x}", id),
+            Self::InvalidFWVersion(version) => {
+                write!(f, "unsupported firmware version {}", version)
+            }
+            Self::NoDataAvailable => write!(f, "no data available"),
+            Self::CommandFailed(error) => write!(
+                f,
+                "command 0x{:x} failed with status {}",
+                error.command(),
+                error.status()
+            ),
+            Self::UnsupportedReport(id) => {
+                write!(f, "report 0x{:x} not supported by firmware", id)
+            }
+            Self::Timeout => write!(f, "timed out"),
+            Self::BufferTooSmall => {
+                write!(f, "received packet larger than receive buffer")
+            }
+            Self::PacketTooLarge => {
+                write!(f, "packet larger than the hub accepts")
+            }
+            Self::UnexpectedReport(id) => {
+                write!(f, "unexpected response to command 0x{:x}", id)
+            }
+            Self::SequenceGap {
+                channel,
+                expected,
+                received,
+            } => write!(
+                f,
+                "sequence gap on channel {}: expected {}, received {}",
+                channel, expected, received
+            ),
+            Self::FrsError(status) => {
+                write!(f, "FRS operation failed with status {}", status)
+            }
+            Self::UnknownChannel(channel) => {
+                write!(f, "channel {} not advertised", channel)
+            }
+            Self::CorruptPacket => write!(f, "corrupt packet discarded"),
+            Self::FeatureRejected {
+                report_id,
+                interval_us,
+            } => write!(
+                f,
+                "report 0x{:x} not enabled as requested, interval {} us",
+                report_id, interval_us
+            ),
+            Self::InvalidReportInterval {
+                report_id,
+                interval_us,
+                limits,
+            } => write!(
+                f,
+                "report 0x{:x} interval {} us outside {}..={} us",
+                report_id, interval_us, limits.min_us, limits.max_us
+            ),
+            Self::TooManyReports => write!(f, "too many reports declared"),
+        }
+    }
+}
 
-    last_packet_len_received: usize,
-    /// has the device been succesfully reset
-    device_reset: bool,
-    /// has the product ID been verified
-    prod_id_verified: bool,
+#[cfg(feature = "std")]
+impl<E: core::fmt::Debug> std::error::Error for WrapperError<E> {}
 
-    init_received: bool,
+/// Called with the ID of an input report this crate does not know, and
+/// the rest of the sensor report payload, starting at that report.
+/// The reports that follow it cannot be parsed, because the length of the
+/// unknown report is not known; they are included in the payload.
+pub type UnknownReportHook = fn(report_id: u8, payload: &[u8]);
 
-    /// have we received the full advertisement
-    advert_received: bool,
+/// A monotonic clock in milliseconds, wrapping on overflow, e.g. read from
+/// a hardware timer. With a clock the driver measures the real time spent
+/// in its timeouts (during `init`, `drain`, `wait_for_report` and the like)
+/// instead of adding up the delays it requested.
+pub type MillisClock = fn() -> u32;
 
-    /// have we received an error list
-    error_list_received: bool,
-    last_error_received: u8,
+/// A monotonic clock in microseconds, wrapping on overflow, in the time
+/// base of the interrupt timestamps passed to `BNO080::on_interrupt_at`
+pub type MicrosClock = fn() -> u32;
 
-    last_chan_received: u8,
-    last_exec_chan_rid: u8,
-    last_command_chan_rid: u8,
+/// Measures the time spent waiting: with the `MillisClock` if one is set,
+/// otherwise by adding up the waits counted with `count`
+#[derive(Debug, Clone, Copy, Default)]
+struct Stopwatch {
+    clock: Option<MillisClock>,
+    start_ms: u32,
+    counted_ms: u32,
+}
 
-    /// Rotation vector as unit quaternion
-    rotation_quaternion: [f32; 4],
-    /// Heading accuracy of rotation vector (radians)
-    rot_quaternion_acc: f32,
+impl Stopwatch {
+    fn start(clock: Option<MillisClock>) -> Self {
+        Self {
+            clock,
+            start_ms: clock.map_or(0, |now| now()),
+            counted_ms: 0,
+        }
+    }
 
-    /// Linear acceleration vector
-    linear_accel: [f32; 3],
+    /// Count a wait, for when there is no clock
+    fn count(&mut self, ms: u32) {
+        self.counted_ms = self.counted_ms.saturating_add(ms);
+    }
 
-    /// Gyroscope calibrated data
-    gyro: [f32; 3],
+    fn elapsed_ms(&self) -> u32 {
+        match self.clock {
+            Some(now) => now().wrapping_sub(self.start_ms),
+            None => self.counted_ms,
+        }
+    }
 }
 
-impl<SI> BNO080<SI> {
-    pub fn new_with_interface(sensor_interface: SI) -> Self {
-        Self {
-            sensor_interface,
-            sequence_numbers: [0; NUM_CHANNELS],
-            packet_send_buf: [0; PACKET_SEND_BUF_LEN],
-            packet_recv_buf: [0; PACKET_RECV_BUF_LEN],
-            last_packet_len_received: 0,
-            device_reset: false,
-            prod_id_verified: false,
-            init_received: false,
-            advert_received: false,
-            error_list_received: false,
-            last_error_received: 0,
-            last_chan_received: 0,
-            last_exec_chan_rid: 0,
-            last_command_chan_rid: 0,
-            rotation_quaternion: [0.0; 4],
-            rot_quaternion_acc: 0.0,
-            linear_accel: [0.0; 3],
-            gyro: [0.0; 3],
-        }
+/// How packet writes and reads are retried after a communication error.
+/// Only an error that persists through all attempts is returned
+/// as `WrapperError::CommError`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RetryPolicy {
+    /// Total number of attempts per transfer, including the first
+    pub max_attempts: u8,
+    /// Delay before the first retry, doubled before each further retry.
+    /// Writes made by methods that take no delay source
+    /// (such as `enable_rotation_vector`) are retried without delay.
+    pub initial_backoff_ms: u32,
+}
+
+impl RetryPolicy {
+    /// Fail on the first error
+    pub const NONE: Self = Self {
+        max_attempts: 1,
+        initial_backoff_ms: 0,
+    };
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::NONE
     }
+}
 
-    /// Returns previously consumed serial sensor instance.
-    pub fn free(self) -> SI {
-        self.sensor_interface
+/// How long the driver waits for the sensor hub to answer (milliseconds).
+/// Without a clock, each read counts as the longest it could have waited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Timeouts {
+    /// Wait for the advertisement after a reset during initialization,
+    /// counting only time without packets
+    pub init_advertise_ms: u32,
+    /// Wait for the product ID response during initialization, counting
+    /// only time without packets
+    pub init_product_id_ms: u32,
+    /// Wait for the response to a command or FRS request
+    pub command_response_ms: u32,
+}
+
+impl Timeouts {
+    pub const DEFAULT: Self = Self {
+        init_advertise_ms: 300,
+        init_product_id_ms: 150,
+        command_response_ms: 1500,
+    };
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self::DEFAULT
     }
 }
 
-impl<SI, SE> BNO080<SI>
-where
-    SI: SensorInterface<SensorError = SE>,
-    SE: core::fmt::Debug,
-{
-    /// Consume all available messages on the port without processing them
-    pub fn eat_all_messages(&mut self, delay: &mut impl DelayNs) {
-        #[cfg(feature = "rttdebug")]
-        rprintln!("eat_n");
-        loop {
-            let msg_count = self.eat_one_message(delay);
-            if msg_count == 0 {
-                break;
-            }
-            //give some time to other parts of the system
-            delay.delay_ms(1);
-        }
+/// Set of activities for the personal activity classifier to classify,
+/// written into the sensor-specific configuration of its Set Feature command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ActivityClasses(pub u32);
+
+impl ActivityClasses {
+    pub const UNKNOWN: Self = Self(1 << 0);
+    pub const IN_VEHICLE: Self = Self(1 << 1);
+    pub const ON_BICYCLE: Self = Self(1 << 2);
+    pub const ON_FOOT: Self = Self(1 << 3);
+    pub const STILL: Self = Self(1 << 4);
+    pub const TILTING: Self = Self(1 << 5);
+    pub const WALKING: Self = Self(1 << 6);
+    pub const RUNNING: Self = Self(1 << 7);
+    pub const ON_STAIRS: Self = Self(1 << 8);
+    pub const ALL: Self = Self(0x1FF);
+
+    /// Raw enable mask
+    pub fn bits(self) -> u32 {
+        self.0
     }
 
-    /// Handle any messages with a timeout
-    pub fn handle_all_messages(
-        &mut self,
-        delay: &mut impl DelayNs,
-        timeout_ms: u8,
-    ) -> u32 {
-        let mut total_handled: u32 = 0;
-        loop {
-            let handled_count = self.handle_one_message(delay, timeout_ms);
-            if handled_count == 0 {
-                break;
-            } else {
-                total_handled += handled_count;
-                //give some time to other parts of the system
-                delay.delay_ms(1);
-            }
-        }
-        total_handled
+    /// Does this set include all activities in `other`?
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
     }
+}
 
-    /// return the number of messages handled
-    pub fn handle_one_message(
-        &mut self,
-        delay: &mut impl DelayNs,
-        max_ms: u8,
-    ) -> u32 {
-        let mut msg_count = 0;
+impl core::ops::BitOr for ActivityClasses {
+    type Output = Self;
 
-        let res = self.receive_packet_with_timeout(delay, max_ms);
-        if res.is_ok() {
-            let received_len = res.unwrap_or(0);
-            if received_len > 0 {
-                msg_count += 1;
-                self.handle_received_packet(received_len);
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Counters of the traffic with the sensor hub, for monitoring bus health
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CommStats {
+    /// Packets sent, indexed by channel number
+    pub packets_sent: [u32; MAX_CHANNELS],
+    /// Packets received, indexed by channel number
+    pub packets_received: [u32; MAX_CHANNELS],
+    /// Sensor report packets containing a report that could not be decoded
+    pub decode_errors: u32,
+    /// Packets discarded because they did not fit in the receive buffer
+    pub truncated_packets: u32,
+    /// Packets discarded as corrupt: failing packet validation, or too
+    /// short to handle
+    pub corrupt_packets: u32,
+    /// Reads that found no packet waiting
+    pub zero_length_reads: u32,
+    /// Transfers retried after a communication error, and writes sent
+    /// again because the hub was not ready for them
+    pub retries: u32,
+    /// Transfers that failed, after any retries
+    pub comm_errors: u32,
+    /// Times `ensure_reports_alive` reinitialized the sensor hub after
+    /// its reports stalled
+    pub watchdog_recoveries: u32,
+}
+
+/// Increment a statistics counter, wrapping on overflow
+fn count(counter: &mut u32) {
+    *counter = counter.wrapping_add(1);
+}
+
+/// Perform `op` until it succeeds or the retry policy is exhausted,
+/// counting retries and failures in `stats`
+fn with_retries<T, E, D: DelayNs>(
+    policy: RetryPolicy,
+    delay: &mut D,
+    stats: &mut CommStats,
+    mut op: impl FnMut(&mut D) -> Result<T, E>,
+) -> Result<T, WrapperError<E>> {
+    let mut backoff_ms = policy.initial_backoff_ms;
+    let mut attempt: u8 = 1;
+    loop {
+        match op(delay) {
+            Ok(val) => return Ok(val),
+            Err(e) => {
+                if attempt >= policy.max_attempts {
+                    count(&mut stats.comm_errors);
+                    return Err(WrapperError::CommError(e));
+                }
+                count(&mut stats.retries);
+                debug!("retry {}", attempt);
+                delay.delay_ms(backoff_ms);
+                backoff_ms = backoff_ms.saturating_mul(2);
+                attempt += 1;
             }
-        } else {
-            #[cfg(feature = "rttdebug")]
-            rprintln!("handle1 err {:?}", res);
         }
+    }
+}
 
-        msg_count
+/// The payload following the header of the packet of `packet_len` bytes
+/// at the start of `buf`, empty if there is none
+fn packet_payload(buf: &[u8], packet_len: usize) -> &[u8] {
+    buf.get(PACKET_HEADER_LENGTH..packet_len)
+        .unwrap_or_default()
+}
+
+/// Response to an SH-2 command request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CommandResponse {
+    /// The command this responds to
+    pub command: u8,
+    /// Sequence number of the command request this responds to
+    pub command_sequence: u8,
+    pub response_sequence: u8,
+    /// Command-specific response values R0-R10
+    pub values: [u8; 11],
+}
+
+/// The first `N` bytes of `payload`, with any missing bytes as zero
+fn zero_padded<const N: usize>(payload: &[u8]) -> [u8; N] {
+    let mut bytes = [0u8; N];
+    for (byte, value) in bytes.iter_mut().zip(payload) {
+        *byte = *value;
     }
+    bytes
+}
 
-    /// Receive and ignore one message,
-    /// returning the size of the packet received or zero
-    /// if there was no packet to read.
-    pub fn eat_one_message(&mut self, delay: &mut impl DelayNs) -> usize {
-        let res = self.receive_packet_with_timeout(delay, 150);
-        if let Ok(received_len) = res {
-            #[cfg(feature = "rttdebug")]
-            rprintln!("e1 {}", received_len);
-            received_len
-        } else {
-            #[cfg(feature = "rttdebug")]
-            rprintln!("e1 err {:?}", res);
-            0
+impl CommandResponse {
+    /// Parse the payload of a command response,
+    /// treating any missing trailing fields as zero
+    fn parse(payload: &[u8]) -> Self {
+        let bytes: [u8; 16] = zero_padded(payload);
+        let mut values = [0u8; 11];
+        values.copy_from_slice(&bytes[5..]);
+        Self {
+            command: bytes[2],
+            command_sequence: bytes[3],
+            response_sequence: bytes[4],
+            values,
         }
     }
 
-    fn handle_advertise_response(&mut self, received_len: usize) {
-        let payload_len = received_len - PACKET_HEADER_LENGTH;
-        let payload = &self.packet_recv_buf[PACKET_HEADER_LENGTH..received_len];
-        let mut cursor: usize = 1; //skip response type
+    /// Status (R0) of the commands that report one: zero on success
+    pub fn status(&self) -> u8 {
+        self.values[0]
+    }
+}
 
-        #[cfg(feature = "rttdebug")]
-        rprintln!("AdvRsp: {}", payload_len);
+/// A command the sensor hub refused, answering with a nonzero status (R0),
+/// as opposed to a fault of the driver or the bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum HubCommandError {
+    /// Configuring the motion engine calibration failed
+    CalibrationConfigFailed { status: u8 },
+    /// Saving the dynamic calibration data (DCD) failed
+    DcdSaveFailed { status: u8 },
+    /// Enabling or disabling the periodic DCD save failed
+    PeriodicDcdSaveFailed { status: u8 },
+    /// Setting the motion intent of the interactive calibration failed
+    MotionIntentFailed { status: u8 },
+    /// Another command failed
+    Other { command: u8, status: u8 },
+}
 
-        while cursor < payload_len {
-            let _tag: u8 = payload[cursor];
-            cursor += 1;
-            let len: u8 = payload[cursor];
-            cursor += 1;
-            //let val: u8 = payload + cursor;
-            cursor += len as usize;
-        }
+impl HubCommandError {
+    /// The refusal reported by a response to `command` with `status`,
+    /// or `None` for a status of zero
+    pub fn from_status(command: u8, status: u8) -> Option<Self> {
+        Some(match (command, status) {
+            (_, 0) => return None,
+            (SH2_CMD_ME_CALIBRATE, status) => {
+                Self::CalibrationConfigFailed { status }
+            }
+            (SH2_CMD_SAVE_DCD, status) => Self::DcdSaveFailed { status },
+            (SH2_CMD_PERIODIC_DCD_SAVE, status) => {
+                Self::PeriodicDcdSaveFailed { status }
+            }
+            (SH2_CMD_INTERACTIVE_CALIBRATION, status) => {
+                Self::MotionIntentFailed { status }
+            }
+            (command, status) => Self::Other { command, status },
+        })
+    }
 
-        self.advert_received = true;
+    /// The command that was refused
+    pub fn command(&self) -> u8 {
+        match *self {
+            Self::CalibrationConfigFailed { .. } => SH2_CMD_ME_CALIBRATE,
+            Self::DcdSaveFailed { .. } => SH2_CMD_SAVE_DCD,
+            Self::PeriodicDcdSaveFailed { .. } => SH2_CMD_PERIODIC_DCD_SAVE,
+            Self::MotionIntentFailed { .. } => SH2_CMD_INTERACTIVE_CALIBRATION,
+            Self::Other { command, .. } => command,
+        }
     }
 
-    fn read_u8_at_cursor(msg: &[u8], cursor: &mut usize) -> u8 {
-        let val = msg[*cursor];
-        *cursor += 1;
-        val
+    /// The nonzero status (R0) with which the command was refused
+    pub fn status(&self) -> u8 {
+        match *self {
+            Self::CalibrationConfigFailed { status }
+            | Self::DcdSaveFailed { status }
+            | Self::PeriodicDcdSaveFailed { status }
+            | Self::MotionIntentFailed { status }
+            | Self::Other { status, .. } => status,
+        }
     }
+}
+
+/// Number of SH-2 commands whose responses are kept apart, by command
+const COMMAND_RESPONSE_SLOTS: usize = 16;
+
+/// Oscillator clocking the sensor hub, as reported by `oscillator_type`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OscillatorType {
+    Internal,
+    ExternalCrystal,
+    ExternalClock,
+    /// A value not defined by the SH-2 Reference Manual
+    Unknown(u8),
+}
 
-    fn read_i16_at_cursor(msg: &[u8], cursor: &mut usize) -> i16 {
-        let val = (msg[*cursor] as i16) | ((msg[*cursor + 1] as i16) << 8);
-        *cursor += 2;
-        val
+impl From<u8> for OscillatorType {
+    fn from(val: u8) -> Self {
+        match val {
+            0 => Self::Internal,
+            1 => Self::ExternalCrystal,
+            2 => Self::ExternalClock,
+            other => Self::Unknown(other),
+        }
     }
+}
 
-    fn try_read_i16_at_cursor(msg: &[u8], cursor: &mut usize) -> Option<i16> {
-        let remaining = msg.len() - *cursor;
-        if remaining >= 2 {
-            let val = (msg[*cursor] as i16) | ((msg[*cursor + 1] as i16) << 8);
-            *cursor += 2;
-            Some(val)
-        } else {
-            None
+/// Threshold for "on change" reporting, in the units and fixed-point
+/// format of the report's values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ChangeSensitivity {
+    /// Report when a value changes by more than this amount
+    /// from the last value reported
+    Absolute(u16),
+    /// Report when a value changes by more than this fraction of the last
+    /// value reported, in the format defined by the SH-2 Reference Manual
+    Relative(u16),
+}
+
+/// Configuration of a report, as sent with the Set Feature command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FeatureConfig {
+    /// Interval between reports (microseconds), or zero to disable
+    pub report_interval_us: u32,
+    /// When set, the report is only sent when its value changes by more
+    /// than the threshold, at most once per report interval
+    pub change_sensitivity: Option<ChangeSensitivity>,
+    /// Keep the sensor running while the hub sleeps (see
+    /// `BNO080::sleep`), instead of suspending it.
+    /// Combined with a nonzero `batch_interval_us`, the hub buffers the
+    /// reports generated meanwhile and delivers them once the host is
+    /// awake and polls again; without batching, reports generated while
+    /// the host sleeps may be discarded.
+    pub always_on: bool,
+    /// Maximum time (microseconds) the hub may hold reports in its batch
+    /// buffer before interrupting the host, or zero to report immediately
+    pub batch_interval_us: u32,
+    /// Sensor-specific configuration word
+    pub sensor_specific: u32,
+}
+
+/// The configuration of a report, as the sensor hub reports it in the
+/// Get Feature Response following a Set Feature command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FeatureResponse {
+    pub report_id: u8,
+    /// Feature flags, as sent in the Set Feature command
+    pub flags: u8,
+    pub change_sensitivity: u16,
+    /// Interval between reports (microseconds), zero if disabled
+    pub report_interval_us: u32,
+    pub batch_interval_us: u32,
+    pub sensor_specific: u32,
+}
+
+impl FeatureResponse {
+    fn parse(payload: &[u8]) -> Self {
+        let bytes: [u8; 17] = zero_padded(payload);
+        let word = |index: usize| {
+            u32::from_le_bytes(zero_padded(
+                bytes.get(index..).unwrap_or_default(),
+            ))
+        };
+        Self {
+            report_id: bytes[1],
+            flags: bytes[2],
+            change_sensitivity: u16::from_le_bytes([bytes[3], bytes[4]]),
+            report_interval_us: word(5),
+            batch_interval_us: word(9),
+            sensor_specific: word(13),
         }
     }
+}
 
-    /// Read data values from a single input report
-    fn handle_one_input_report(
-        outer_cursor: usize,
-        msg: &[u8],
-    ) -> (usize, u8, i16, i16, i16, i16, i16) {
-        let mut cursor = outer_cursor;
+/// Set of axes to tare
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TareAxes(pub u8);
 
-        let feature_report_id = Self::read_u8_at_cursor(msg, &mut cursor);
-        let _rep_seq_num = Self::read_u8_at_cursor(msg, &mut cursor);
-        let _rep_status = Self::read_u8_at_cursor(msg, &mut cursor);
-        let _delay = Self::read_u8_at_cursor(msg, &mut cursor);
+impl TareAxes {
+    pub const X: Self = Self(1 << 0);
+    pub const Y: Self = Self(1 << 1);
+    pub const Z: Self = Self(1 << 2);
+    pub const ALL: Self = Self(0x07);
+}
 
-        let data1: i16 = Self::read_i16_at_cursor(msg, &mut cursor);
-        let data2: i16 = Self::read_i16_at_cursor(msg, &mut cursor);
-        let data3: i16 = Self::read_i16_at_cursor(msg, &mut cursor);
-        let data4: i16 =
-            Self::try_read_i16_at_cursor(msg, &mut cursor).unwrap_or(0);
-        let data5: i16 =
-            Self::try_read_i16_at_cursor(msg, &mut cursor).unwrap_or(0);
+impl core::ops::BitOr for TareAxes {
+    type Output = Self;
 
-        (cursor, feature_report_id, data1, data2, data3, data4, data5)
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
     }
+}
 
-    /// Handle parsing of an input report packet,
-    /// which may include multiple input reports
-    fn handle_sensor_reports(&mut self, received_len: usize) {
-        // Sensor input packets have the form:
-        // [u8; 5]  timestamp in microseconds for the packet?
-        // a sequence of n reports, each with four byte header
-        // u8 report ID
-        // u8 sequence number of report
+/// Rotation vector used as the basis of a tare
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TareBasis {
+    RotationVector = 0,
+    GameRotationVector = 1,
+    GeomagneticRotationVector = 2,
+    GyroIntegratedRotationVector = 3,
+    ArvrStabilizedRotationVector = 4,
+    ArvrStabilizedGameRotationVector = 5,
+}
 
-        // let mut report_count = 0;
-        let mut outer_cursor: usize = PACKET_HEADER_LENGTH + 5; //skip header, timestamp
-                                                                //TODO need to skip more above for a payload-level timestamp??
-        if received_len < outer_cursor {
-            #[cfg(feature = "rttdebug")]
-            rprintln!("bad lens: {} < {}", received_len, outer_cursor);
-            return;
-        }
+/// The motion the device is about to undergo, passed to the hub's
+/// interactive calibration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MotionIntent {
+    Unknown = 0,
+    /// Stationary, without vibration: allows fast gyro zero rate offset
+    /// (ZRO) calibration
+    StationaryNoVibration = 1,
+    StationaryWithVibration = 2,
+    InMotion = 3,
+    InMotionAccelerating = 4,
+}
 
-        let payload_len = received_len - outer_cursor;
-        if payload_len < 14 {
-            #[cfg(feature = "rttdebug")]
-            rprintln!(
-                "bad report: {:?}",
-                &self.packet_recv_buf[..PACKET_HEADER_LENGTH]
-            );
+/// Kind of measurement carried by a `WheelEncoderSample`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WheelDataType {
+    /// Accumulated encoder ticks
+    Position = 0,
+    /// Encoder ticks per unit time
+    Velocity = 1,
+}
 
-            return;
+/// One wheel encoder measurement, injected into the hub's dead reckoning
+/// fusion with `report_wheel_encoder`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct WheelEncoderSample {
+    /// Which wheel this measurement belongs to
+    pub wheel_index: u8,
+    /// Time of the measurement (microseconds), on the host's clock
+    pub timestamp_us: u32,
+    pub data: i16,
+    pub data_type: WheelDataType,
+}
+
+/// Per-sensor sample counters kept by the sensor hub, for diagnosing
+/// reports that arrive slower than the configured rate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SensorCounts {
+    /// Samples produced by the sensor
+    pub offered: u32,
+    /// Samples accepted by the hub
+    pub accepted: u32,
+    /// Samples taken while the sensor was enabled
+    pub on: u32,
+    /// Samples the hub attempted to report
+    pub attempted: u32,
+}
+
+/// A set of report IDs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ReportIdSet {
+    /// one bit per report ID
+    bits: [u32; 8],
+}
+
+impl ReportIdSet {
+    pub fn contains(&self, report_id: u8) -> bool {
+        self.bits
+            .get(usize::from(report_id / 32))
+            .is_some_and(|bits| bits & (1 << (report_id % 32)) != 0)
+    }
+
+    fn insert(&mut self, report_id: u8) {
+        if let Some(bits) = self.bits.get_mut(usize::from(report_id / 32)) {
+            *bits |= 1 << (report_id % 32);
         }
+    }
 
-        // there may be multiple reports per payload
-        while outer_cursor < payload_len {
-            //let start_cursor = outer_cursor;
-            let (inner_cursor, report_id, data1, data2, data3, data4, data5) =
-                Self::handle_one_input_report(
-                    outer_cursor,
-                    &self.packet_recv_buf[..received_len],
-                );
-            outer_cursor = inner_cursor;
-            // report_count += 1;
-            match report_id {
-                SENSOR_REPORTID_ROTATION_VECTOR => {
-                    self.update_rotation_quaternion(
-                        data1, data2, data3, data4, data5,
-                    );
-                }
-                SENSOR_REPORTID_LINEAR_ACCEL => {
-                    self.update_linear_accel(data1, data2, data3);
-                }
-                SENSOR_REPORTID_GYRO => {
-                    self.update_gyro_cal(data1, data2, data3);
-                }
-                _ => {
-                    // debug_println!("uhr: {:X}", report_id);
-                    // debug_println!("uhr: 0x{:X} {:?}  ", report_id, &self.packet_recv_buf[start_cursor..start_cursor+5]);
-                }
-            }
+    fn remove(&mut self, report_id: u8) {
+        if let Some(bits) = self.bits.get_mut(usize::from(report_id / 32)) {
+            *bits &= !(1 << (report_id % 32));
         }
+    }
 
-        //debug_println!("report_count: {}",report_count);
+    /// Is every report ID in this set also in `other`?
+    fn is_subset(&self, other: &Self) -> bool {
+        self.bits
+            .iter()
+            .zip(other.bits.iter())
+            .all(|(bits, other_bits)| bits & !other_bits == 0)
     }
 
-    /// Given a set of quaternion values in the Q-fixed-point format,
-    /// calculate and update the corresponding float values
-    fn update_rotation_quaternion(
-        &mut self,
-        q_i: i16,
-        q_j: i16,
-        q_k: i16,
-        q_r: i16,
-        q_a: i16,
-    ) {
-        //debug_println!("rquat {} {} {} {} {}", q_i, q_j, q_k, q_r, q_a);
-        self.rotation_quaternion = [
-            q14_to_f32(q_i),
-            q14_to_f32(q_j),
-            q14_to_f32(q_k),
-            q14_to_f32(q_r),
-        ];
-        self.rot_quaternion_acc = q12_to_f32(q_a);
+    /// The report IDs in the set, in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..=u8::MAX).filter(|report_id| self.contains(*report_id))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits.iter().all(|bits| *bits == 0)
     }
+}
+
+/// The reports supported by the connected firmware, derived from the
+/// report length table of the SHTP advertisement
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Capabilities {
+    supported_reports: ReportIdSet,
+}
 
-    /// Given a set of linear acceleration values in the Q-fixed-point format,
-    /// calculate and update the corresponding float values
-    fn update_linear_accel(&mut self, x: i16, y: i16, z: i16) {
-        let x = q8_to_f32(x);
-        let y = q8_to_f32(y);
-        let z = q8_to_f32(z);
+impl Capabilities {
+    /// Does the firmware support the input report, command or response
+    /// with this report ID?
+    pub fn supports_report(&self, report_id: u8) -> bool {
+        self.supported_reports.contains(report_id)
+    }
 
-        self.linear_accel = [x, y, z];
+    /// Parse the payload of an advertisement response, returning `None`
+    /// if it carries no report length table
+    fn from_advertisement(payload: &[u8]) -> Option<Self> {
+        let mut capabilities = None;
+        for (tag, value) in AdvertisementTags::new(payload) {
+            if tag == ADVERT_TAG_SH2_REPORT_LENGTHS {
+                let mut caps = Self::default();
+                // pairs of report ID and report length
+                for pair in value.chunks_exact(2) {
+                    let &[report_id, report_len] = pair else {
+                        continue;
+                    };
+                    if report_len > 0 {
+                        caps.supported_reports.insert(report_id);
+                    }
+                }
+                capabilities = Some(caps);
+            }
+        }
+        capabilities
     }
+}
+
+/// Parse the little-endian value of a maximum cargo tag of an
+/// advertisement response, including the packet header
+fn advertised_max_cargo(payload: &[u8], max_tag: u8) -> Option<usize> {
+    AdvertisementTags::new(payload)
+        .filter(|(tag, value)| {
+            *tag == max_tag && (1..=4).contains(&value.len())
+        })
+        .map(|(_, value)| {
+            value
+                .iter()
+                .rev()
+                .fold(0usize, |max, byte| (max << 8) | usize::from(*byte))
+        })
+        .last()
+}
 
-    /// Given a set of linear acceleration values in the Q-fixed-point format,
-    /// calculate and update the corresponding float values
-    fn update_gyro_cal(&mut self, x: i16, y: i16, z: i16) {
-        let x = q9_to_f32(x);
-        let y = q9_to_f32(y);
-        let z = q9_to_f32(z);
+/// Iterator over the tag, value pairs of an advertisement response
+struct AdvertisementTags<'a> {
+    payload: &'a [u8],
+    cursor: usize,
+}
 
-        self.gyro = [x, y, z];
+impl<'a> AdvertisementTags<'a> {
+    fn new(payload: &'a [u8]) -> Self {
+        //skip response type
+        Self { payload, cursor: 1 }
     }
+}
 
-    /// Handle one or more errors sent in response to a command
-    fn handle_cmd_resp_error_list(&mut self, received_len: usize) {
-        let payload_len = received_len - PACKET_HEADER_LENGTH;
-        let payload = &self.packet_recv_buf[PACKET_HEADER_LENGTH..received_len];
+impl<'a> Iterator for AdvertisementTags<'a> {
+    type Item = (u8, &'a [u8]);
 
-        self.error_list_received = true;
-        for cursor in 1..payload_len {
-            let err: u8 = payload[cursor];
-            self.last_error_received = err;
-            #[cfg(feature = "rttdebug")]
-            rprintln!("lerr: {:x}", err);
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = self.payload.get(self.cursor..)?;
+        let (&[tag, len], rest) = rest.split_first_chunk::<2>()?;
+        let value = rest.get(..usize::from(len)).unwrap_or(rest);
+        self.cursor += 2 + value.len();
+        Some((tag, value))
+    }
+}
+
+/// The channel numbers assigned by the sensor hub firmware, looked up by
+/// application and channel name in the advertisement
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ChannelMap {
+    /// SHTP command channel ("SHTP" / "control")
+    pub command: u8,
+    /// executable channel ("executable" / "device")
+    pub executable: u8,
+    /// sensor hub control channel ("sensorhub" / "control")
+    pub control: u8,
+    /// input sensor reports ("sensorhub" / "inputNormal")
+    pub input_normal: u8,
+    /// wake input sensor reports ("sensorhub" / "inputWake")
+    pub input_wake: u8,
+    /// gyro-integrated rotation vector ("sensorhub" / "inputGyroRv")
+    pub input_gyro_rv: u8,
+}
+
+impl Default for ChannelMap {
+    /// The channel numbers of the BNO080 firmware
+    fn default() -> Self {
+        Self {
+            command: Channel::Command.into(),
+            executable: Channel::Executable.into(),
+            control: Channel::HubControl.into(),
+            input_normal: Channel::SensorReports.into(),
+            input_wake: Channel::WakeReports.into(),
+            input_gyro_rv: Channel::GyroRotation.into(),
         }
     }
+}
 
-    pub fn handle_received_packet(&mut self, received_len: usize) {
-        let msg = &self.packet_recv_buf[..received_len];
-        let chan_num = msg[2];
-        //let _seq_num =  msg[3];
-        let report_id: u8 = if received_len > PACKET_HEADER_LENGTH {
-            msg[4]
-        } else {
-            0
-        };
+impl ChannelMap {
+    /// The channel assigned this number, if any
+    pub fn channel(&self, number: u8) -> Option<Channel> {
+        [
+            Channel::Command,
+            Channel::Executable,
+            Channel::HubControl,
+            Channel::SensorReports,
+            Channel::WakeReports,
+            Channel::GyroRotation,
+        ]
+        .into_iter()
+        .find(|channel| self.number(*channel) == number)
+    }
 
-        self.last_chan_received = chan_num;
-        match chan_num {
-            CHANNEL_COMMAND => match report_id {
-                CMD_RESP_ADVERTISEMENT => {
-                    self.handle_advertise_response(received_len);
-                }
-                CMD_RESP_ERROR_LIST => {
-                    self.handle_cmd_resp_error_list(received_len);
-                }
-                _ => {
-                    self.last_command_chan_rid = report_id;
-                    #[cfg(feature = "rttdebug")]
-                    rprintln!("unh cmd: {}", report_id);
-                }
-            },
-            CHANNEL_EXECUTABLE => match report_id {
-                EXECUTABLE_DEVICE_RESP_RESET_COMPLETE => {
-                    self.device_reset = true;
-                    #[cfg(feature = "rttdebug")]
-                    rprintln!("resp_reset {}", 1);
-                }
-                _ => {
-                    self.last_exec_chan_rid = report_id;
-                    #[cfg(feature = "rttdebug")]
-                    rprintln!("unh exe: {:x}", report_id);
+    /// The number assigned to this channel
+    pub fn number(&self, channel: Channel) -> u8 {
+        match channel {
+            Channel::Command => self.command,
+            Channel::Executable => self.executable,
+            Channel::HubControl => self.control,
+            Channel::SensorReports => self.input_normal,
+            Channel::WakeReports => self.input_wake,
+            Channel::GyroRotation => self.input_gyro_rv,
+        }
+    }
+
+    /// Parse the payload of an advertisement response. Channels missing
+    /// from the advertisement keep their default numbers.
+    fn from_advertisement(payload: &[u8]) -> Self {
+        // strip the NUL terminator of name strings
+        fn name(value: &[u8]) -> &[u8] {
+            value.split(|byte| *byte == 0).next().unwrap_or(value)
+        }
+
+        let mut channels = Self::default();
+        let mut app: &[u8] = &[];
+        let mut channel = None;
+        for (tag, value) in AdvertisementTags::new(payload) {
+            match tag {
+                ADVERT_TAG_APP_NAME => app = name(value),
+                ADVERT_TAG_NORMAL_CHANNEL | ADVERT_TAG_WAKE_CHANNEL => {
+                    channel = value.first().copied()
                 }
-            },
-            CHANNEL_HUB_CONTROL => {
-                match report_id {
-                    SHUB_COMMAND_RESP => {
-                        // 0xF1 / 241
-                        let cmd_resp = msg[6];
-                        if cmd_resp == SH2_STARTUP_INIT_UNSOLICITED {
-                            self.init_received = true;
-                        } else if cmd_resp == SH2_INIT_SYSTEM {
-                            self.init_received = true;
+                ADVERT_TAG_CHANNEL_NAME => {
+                    let Some(channel) = channel else { continue };
+                    let entry = match (app, name(value)) {
+                        (b"SHTP", b"control") => &mut channels.command,
+                        (b"executable", b"device") => &mut channels.executable,
+                        (b"sensorhub", b"control") => &mut channels.control,
+                        (b"sensorhub", b"inputNormal") => {
+                            &mut channels.input_normal
                         }
-                        #[cfg(feature = "rttdebug")]
-                        rprintln!("CMD_RESP: 0x{:X}", cmd_resp);
-                    }
-                    SHUB_PROD_ID_RESP => {
-                        #[cfg(feature = "rttdebug")]
-                        {
-                            //let reset_cause = msg[4 + 1];
-                            let sw_vers_major = msg[4 + 2];
-                            let sw_vers_minor = msg[4 + 3];
-                            rprintln!(
-                                "PID_RESP {}.{}",
-                                sw_vers_major,
-                                sw_vers_minor
-                            );
+                        (b"sensorhub", b"inputWake") => {
+                            &mut channels.input_wake
                         }
-
-                        self.prod_id_verified = true;
-                    }
-                    SHUB_GET_FEATURE_RESP => {
-                        // 0xFC
-                        #[cfg(feature = "rttdebug")]
-                        rprintln!("feat resp: {}", msg[5]);
-                    }
-                    _ => {
-                        #[cfg(feature = "rttdebug")]
-                        rprintln!(
-                            "unh hbc: 0x{:X} {:x?}",
-                            report_id,
-                            &msg[..PACKET_HEADER_LENGTH]
-                        );
-                    }
+                        (b"sensorhub", b"inputGyroRv") => {
+                            &mut channels.input_gyro_rv
+                        }
+                        _ => continue,
+                    };
+                    *entry = channel;
                 }
+                _ => {}
             }
-            CHANNEL_SENSOR_REPORTS => {
-                self.handle_sensor_reports(received_len);
-            }
-            _ => {
-                self.last_chan_received = chan_num;
-                #[cfg(feature = "rttdebug")]
-                rprintln!("unh chan 0x{:X}", chan_num);
+        }
+        channels
+    }
+
+    /// One bit for each channel number in the advertisement that can be
+    /// used, that is, below `MAX_CHANNELS`
+    fn advertised_channels(payload: &[u8]) -> u8 {
+        AdvertisementTags::new(payload)
+            .filter(|(tag, _)| {
+                *tag == ADVERT_TAG_NORMAL_CHANNEL
+                    || *tag == ADVERT_TAG_WAKE_CHANNEL
+            })
+            .filter_map(|(_, value)| value.first().copied())
+            .filter(|channel| (*channel as usize) < MAX_CHANNELS)
+            .fold(0, |mask, channel| mask | (1 << channel))
+    }
+}
+
+/// The header at the start of every SHTP packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ShtpHeader {
+    /// Length of the packet, including the header
+    pub length: u16,
+    /// Is this packet the continuation of a previous one?
+    pub continuation: bool,
+    pub channel: u8,
+    pub sequence: u8,
+}
+
+impl ShtpHeader {
+    pub(crate) fn parse(bytes: &[u8]) -> Self {
+        let [lsb, msb, channel, sequence] = zero_padded(bytes);
+        Self {
+            length: u16::from_le_bytes([lsb, msb & 0x7F]),
+            continuation: msb & 0x80 != 0,
+            channel,
+            sequence,
+        }
+    }
+}
+
+/// A packet borrowed from the buffer it was read into, such as the arena
+/// of `BNO080::read_all_available` or the receive buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Packet<'a> {
+    pub header: ShtpHeader,
+    /// The packet body following the header
+    pub payload: &'a [u8],
+}
+
+/// A packet copied out of the buffer it was read into, with room for
+/// a payload of `N` bytes, so that it can be handled later: for example
+/// passed from an interrupt handler to a task through a channel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OwnedPacket<const N: usize> {
+    pub header: ShtpHeader,
+    /// The packet body following the header
+    pub payload: Vec<u8, N>,
+}
+
+impl<const N: usize> OwnedPacket<N> {
+    /// Copy `packet`, or `None` if its payload is longer than `N` bytes
+    pub fn from_packet(packet: &Packet) -> Option<Self> {
+        Some(Self {
+            header: packet.header,
+            payload: Vec::from_slice(packet.payload).ok()?,
+        })
+    }
+
+    /// Borrow the packet
+    pub fn as_packet(&self) -> Packet<'_> {
+        Packet {
+            header: self.header,
+            payload: &self.payload,
+        }
+    }
+
+    /// Views of the input reports in the payload, for a packet received
+    /// on a sensor report channel (see `ChannelMap`)
+    pub fn input_reports(&self) -> InputReports<'_> {
+        InputReports::new(&self.payload)
+    }
+
+    /// The input reports in the payload, decoded, in the ENU frame of
+    /// the sensor hub
+    pub fn reports(&self) -> impl Iterator<Item = SensorReport> + '_ {
+        self.input_reports().map(|report| report.decode())
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<const N: usize> defmt::Format for OwnedPacket<N> {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "OwnedPacket {{ header: {}, payload: {=[u8]} }}",
+            self.header,
+            self.payload.as_slice()
+        )
+    }
+}
+
+/// Iterator over the packets stored one after the other in an arena
+#[derive(Debug, Clone)]
+pub struct ArenaPackets<'a> {
+    arena: &'a [u8],
+}
+
+impl<'a> Iterator for ArenaPackets<'a> {
+    type Item = Packet<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.arena.len() < PACKET_HEADER_LENGTH {
+            return None;
+        }
+        let header = ShtpHeader::parse(self.arena);
+        let packet_len = usize::from(header.length);
+        if !(PACKET_HEADER_LENGTH..=self.arena.len()).contains(&packet_len) {
+            return None;
+        }
+        let (packet, rest) = self.arena.split_at_checked(packet_len)?;
+        self.arena = rest;
+        Some(Packet {
+            header,
+            payload: packet_payload(packet, packet_len),
+        })
+    }
+}
+
+/// Sensor hub variant, identified by the firmware part number
+/// in the product ID
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SensorVariant {
+    Bno080,
+    Bno085,
+    Bno086,
+    /// Firmware with a part number this crate does not know about
+    Unknown(u32),
+}
+
+impl SensorVariant {
+    pub fn from_part_number(sw_part_number: u32) -> Self {
+        match sw_part_number {
+            BNO080_SW_PART_NUMBER => Self::Bno080,
+            BNO085_SW_PART_NUMBER => Self::Bno085,
+            BNO086_SW_PART_NUMBER => Self::Bno086,
+            other => Self::Unknown(other),
+        }
+    }
+
+    /// Does this variant's firmware support the report with this ID?
+    /// Assumes support when the variant is unknown.
+    pub fn supports_report(&self, report_id: u8) -> bool {
+        match self {
+            // ARVR-stabilized rotation vectors were introduced with the BNO085
+            Self::Bno080 => !matches!(
+                SensorReportId::try_from(report_id),
+                Ok(SensorReportId::ArvrRotationVector
+                    | SensorReportId::ArvrGameRotationVector)
+            ),
+            Self::Bno085 | Self::Bno086 | Self::Unknown(_) => true,
+        }
+    }
+}
+
+/// Product ID response from the sensor hub
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProductId {
+    /// Cause of the most recent reset
+    pub reset_cause: u8,
+    pub sw_version_major: u8,
+    pub sw_version_minor: u8,
+    pub sw_part_number: u32,
+    pub sw_build_number: u32,
+    pub sw_version_patch: u16,
+}
+
+impl ProductId {
+    /// The sensor hub variant running this firmware
+    pub fn variant(&self) -> SensorVariant {
+        SensorVariant::from_part_number(self.sw_part_number)
+    }
+
+    /// Parse the payload of a product ID response,
+    /// treating any missing trailing fields as zero
+    fn parse(payload: &[u8]) -> Self {
+        let bytes: [u8; 14] = zero_padded(payload);
+        Self {
+            reset_cause: bytes[1],
+            sw_version_major: bytes[2],
+            sw_version_minor: bytes[3],
+            sw_part_number: u32::from_le_bytes([
+                bytes[4], bytes[5], bytes[6], bytes[7],
+            ]),
+            sw_build_number: u32::from_le_bytes([
+                bytes[8], bytes[9], bytes[10], bytes[11],
+            ]),
+            sw_version_patch: u16::from_le_bytes([bytes[12], bytes[13]]),
+        }
+    }
+}
+
+/// Progress of the initialization handshake with the sensor hub,
+/// advanced by `BNO080::poll_init`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum InitState {
+    /// The sensor hub is to be set up and reset
+    #[default]
+    Reset,
+    /// Waiting for the advertisement and the unsolicited
+    /// initialization response
+    Advertise,
+    /// Waiting for the product ID response
+    ProductId,
+    /// The sensor hub is ready to be configured
+    Ready,
+}
+
+/// Health of the sensor hub as seen by the driver, for supervisory
+/// firmware to report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceStatus {
+    /// The hub reported that a reset completed
+    pub reset_seen: bool,
+    /// The hub answered the product ID request
+    pub product_verified: bool,
+    /// Cause of the most recent reset, from the product ID response:
+    /// 1 power on, 2 internal system reset, 3 watchdog,
+    /// 4 external reset, 5 other
+    pub last_reset_cause: Option<u8>,
+    /// Initialization has completed
+    pub init_complete: bool,
+    /// The reports enabled with a nonzero interval
+    pub enabled_reports: ReportIdSet,
+}
+
+/// The orientation, angular rate and linear acceleration for one tick of
+/// a flight controller's control loop
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AhrsSample {
+    /// Rotation vector
+    pub orientation: Quaternion,
+    /// Calibrated gyroscope (rad/s)
+    pub angular_rate: [f32; 3],
+    /// Linear acceleration, with gravity removed (m/s^2)
+    pub linear_accel: [f32; 3],
+    /// Accuracy estimate of the rotation vector: 0 unreliable, 1 low,
+    /// 2 medium, 3 high
+    pub accuracy: u8,
+    /// Timestamp of the rotation vector, in microseconds relative to the
+    /// host interrupt that announced its packet
+    pub timestamp_us: i32,
+    /// Largest difference between the timestamps of the three reports,
+    /// in microseconds. With the reports enabled at a shared rate, the
+    /// hub batches them together and this stays within one period.
+    pub skew_us: u32,
+}
+
+pub struct BNO080<
+    SI,
+    const SEND_BUF_LEN: usize = PACKET_SEND_BUF_LEN,
+    const RECV_BUF_LEN: usize = PACKET_RECV_BUF_LEN,
+> {
+    pub(crate) sensor_interface: SI,
+    /// each communication channel with the device has its own sequence number
+    sequence_numbers: [u8; MAX_CHANNELS],
+    /// buffer for building and sending packet to the sensor hub
+    packet_send_buf: [u8; SEND_BUF_LEN],
+    /// buffer for building packets received from the sensor hub
+    packet_recv_buf: [u8; RECV_BUF_LEN],
+
+    last_packet_len_received: usize,
+    /// has the device been succesfully reset
+    device_reset: bool,
+    /// sequence number expected of the next packet received on each channel
+    recv_sequence_numbers: [Option<u8>; MAX_CHANNELS],
+    /// the first sequence gap detected since the last `check_sequence`
+    sequence_gap: Option<(u8, u8, u8)>,
+    /// sequence number of the next SH-2 command request
+    command_sequence: u8,
+    /// the most recent response to an SH-2 command
+    last_command_response: Option<CommandResponse>,
+    /// the most recent refusal of a command by the sensor hub
+    last_hub_error: Option<HubCommandError>,
+    /// responses not yet consumed, indexed by command, so that the response
+    /// to one command is not mistaken for that of another
+    command_responses: [Option<CommandResponse>; COMMAND_RESPONSE_SLOTS],
+    /// the command sequence number of the latest request of each command
+    command_request_sequences: [u8; COMMAND_RESPONSE_SLOTS],
+    /// the most recent FRS read response, not yet consumed
+    last_frs_read: Option<FrsReadResponse>,
+    /// the most recent Get Feature Response
+    last_feature_response: Option<FeatureResponse>,
+    /// coordinate frame of the decoded outputs
+    frame: Frame,
+    /// the most recent FRS write response, not yet consumed
+    last_frs_write: Option<FrsWriteResponse>,
+    /// has the product ID been verified
+    prod_id_verified: bool,
+    /// the product ID reported by the sensor hub
+    product_id: Option<ProductId>,
+    /// the reports supported by the firmware, from the advertisement
+    capabilities: Option<Capabilities>,
+    /// the channel numbers, from the advertisement
+    channels: ChannelMap,
+    /// one bit per channel advertised by the sensor hub
+    advertised_channels: u8,
+    /// the reports enabled with a nonzero interval
+    enabled_reports: ReportIdSet,
+    /// the reports received since the stale report window started
+    received_reports: ReportIdSet,
+    /// time since the stale report window started (milliseconds)
+    stale_window_ms: u32,
+    /// number of input reports received
+    input_report_count: u32,
+
+    init_received: bool,
+    /// progress of the initialization handshake
+    init_state: InitState,
+    /// time spent waiting in the current initialization state
+    init_wait: Stopwatch,
+
+    /// have we received the full advertisement
+    advert_received: bool,
+    /// largest packet the sensor hub accepts, as advertised,
+    /// limited to the send buffer
+    max_write_len: usize,
+    /// largest packet the sensor hub sends, as advertised
+    max_read_len: Option<usize>,
+
+    /// have we received an error list
+    error_list_received: bool,
+    last_error_received: u8,
+
+    last_chan_received: u8,
+    last_exec_chan_rid: u8,
+    last_command_chan_rid: u8,
+
+    /// most recent decoded sensor reports
+    latest: LatestReports,
+    /// decoded sensor reports not yet taken by the application
+    report_queue: Deque<OwnedInputReport, REPORT_QUEUE_LEN>,
+    /// decimation of the queued reports, indexed by report ID
+    decimation: [Decimation; DECIMATED_REPORT_IDS],
+    /// configuration of the enabled reports, restored by the watchdog
+    report_configs: Vec<(u8, FeatureConfig), WATCHDOG_MAX_REPORTS>,
+    /// smoothing of the accelerometer and gyroscope outputs
+    #[cfg(feature = "filter")]
+    smoothing: [Option<LowPass>; SMOOTHED_REPORT_IDS.len()],
+    /// gestures delivered as events
+    #[cfg(feature = "report-gesture")]
+    gestures: GestureEvents,
+
+    /// how transient communication errors are retried
+    retry_policy: RetryPolicy,
+    /// how long to wait for the sensor hub to answer
+    timeouts: Timeouts,
+    /// called with reports whose ID this crate does not know
+    unknown_report_hook: Option<UnknownReportHook>,
+    /// measures the time spent in timeouts, if set
+    clock: Option<MillisClock>,
+    /// read only once the hub has signaled a packet with its interrupt
+    interrupt_driven: bool,
+    /// the hub has signaled a packet since the last read
+    interrupt_pending: bool,
+    /// set by the host's interrupt handler when the hub signals a packet
+    interrupt_flag: Option<&'static AtomicBool>,
+    /// reads the host time at which packets arrive, for the bus time
+    micros_clock: Option<MicrosClock>,
+    /// host time of the interrupt not yet matched with a packet
+    /// (microseconds)
+    interrupt_timestamp_us: Option<u32>,
+    /// time from the interrupt to the arrival of the packet in the
+    /// receive buffer (microseconds)
+    packet_bus_time_us: Option<u32>,
+    /// time from the interrupt to the arrival of the last packet timed
+    /// (microseconds)
+    last_bus_time_us: Option<u32>,
+    /// check each packet received for consistency before handling it
+    validate_packets: bool,
+    /// traffic counters
+    stats: CommStats,
+}
+
+impl<SI> BNO080<SI> {
+    pub fn new_with_interface(sensor_interface: SI) -> Self {
+        Self::new_with_buffers(sensor_interface)
+    }
+}
+
+impl<SI, const SEND_BUF_LEN: usize, const RECV_BUF_LEN: usize>
+    BNO080<SI, SEND_BUF_LEN, RECV_BUF_LEN>
+{
+    /// The send buffer holds the largest command the driver sends, and
+    /// the length of any packet fits in the 15 bit length field of the
+    /// header
+    const BUFFER_LENS_VALID: () = {
+        assert!(SEND_BUF_LEN >= PACKET_HEADER_LENGTH + 17);
+        assert!(SEND_BUF_LEN <= 0x7FFF);
+        assert!(RECV_BUF_LEN <= 0x7FFF);
+    };
+
+    /// Create the driver with packet buffers of the given sizes, e.g.
+    /// a smaller receive buffer for a hub with few reports enabled.
+    /// Packets longer than the receive buffer are discarded
+    /// (`WrapperError::BufferTooSmall`).
+    pub fn new_with_buffers(sensor_interface: SI) -> Self {
+        let () = Self::BUFFER_LENS_VALID;
+        Self {
+            sensor_interface,
+            sequence_numbers: [0; MAX_CHANNELS],
+            packet_send_buf: [0; SEND_BUF_LEN],
+            packet_recv_buf: [0; RECV_BUF_LEN],
+            last_packet_len_received: 0,
+            device_reset: false,
+            recv_sequence_numbers: [None; MAX_CHANNELS],
+            sequence_gap: None,
+            command_sequence: 0,
+            last_command_response: None,
+            last_hub_error: None,
+            command_responses: [None; COMMAND_RESPONSE_SLOTS],
+            command_request_sequences: [0; COMMAND_RESPONSE_SLOTS],
+            last_frs_read: None,
+            last_feature_response: None,
+            frame: Frame::Enu,
+            last_frs_write: None,
+            prod_id_verified: false,
+            product_id: None,
+            capabilities: None,
+            channels: ChannelMap::default(),
+            advertised_channels: DEFAULT_ADVERTISED_CHANNELS,
+            enabled_reports: ReportIdSet::default(),
+            received_reports: ReportIdSet::default(),
+            stale_window_ms: 0,
+            input_report_count: 0,
+            init_received: false,
+            init_state: InitState::Reset,
+            init_wait: Stopwatch::default(),
+            advert_received: false,
+            max_write_len: SEND_BUF_LEN,
+            max_read_len: None,
+            error_list_received: false,
+            last_error_received: 0,
+            last_chan_received: 0,
+            last_exec_chan_rid: 0,
+            last_command_chan_rid: 0,
+            latest: LatestReports::default(),
+            report_queue: Deque::new(),
+            decimation: [Decimation::default(); DECIMATED_REPORT_IDS],
+            report_configs: Vec::new(),
+            #[cfg(feature = "filter")]
+            smoothing: [None; SMOOTHED_REPORT_IDS.len()],
+            #[cfg(feature = "report-gesture")]
+            gestures: GestureEvents::default(),
+            retry_policy: RetryPolicy::NONE,
+            timeouts: Timeouts::DEFAULT,
+            unknown_report_hook: None,
+            clock: None,
+            interrupt_driven: false,
+            interrupt_pending: false,
+            interrupt_flag: None,
+            micros_clock: None,
+            interrupt_timestamp_us: None,
+            packet_bus_time_us: None,
+            last_bus_time_us: None,
+            validate_packets: false,
+            stats: CommStats::default(),
+        }
+    }
+
+    /// Forget everything learned from the sensor hub: sequence numbers,
+    /// state flags, the product ID, decoded and queued reports, and the
+    /// configuration of the enabled reports, which the hub forgets too.
+    /// The retry policy, unknown report hook, clock, interrupt-driven mode
+    /// and the traffic statistics are kept. Called by `init`,
+    /// so that a driver that lost synchronization with the hub can recover
+    /// by calling `init` again.
+    pub fn reset_driver_state(&mut self) {
+        self.sequence_numbers = [0; MAX_CHANNELS];
+        self.last_packet_len_received = 0;
+        self.device_reset = false;
+        self.recv_sequence_numbers = [None; MAX_CHANNELS];
+        self.sequence_gap = None;
+        self.command_sequence = 0;
+        self.last_command_response = None;
+        self.last_hub_error = None;
+        self.interrupt_timestamp_us = None;
+        self.packet_bus_time_us = None;
+        self.last_bus_time_us = None;
+        self.command_responses = [None; COMMAND_RESPONSE_SLOTS];
+        self.command_request_sequences = [0; COMMAND_RESPONSE_SLOTS];
+        self.last_frs_read = None;
+        self.last_frs_write = None;
+        self.prod_id_verified = false;
+        self.product_id = None;
+        self.capabilities = None;
+        self.channels = ChannelMap::default();
+        self.advertised_channels = DEFAULT_ADVERTISED_CHANNELS;
+        self.enabled_reports = ReportIdSet::default();
+        self.report_configs.clear();
+        self.received_reports = ReportIdSet::default();
+        self.stale_window_ms = 0;
+        self.input_report_count = 0;
+        self.init_received = false;
+        self.init_state = InitState::Reset;
+        self.init_wait = Stopwatch::start(self.clock);
+        self.advert_received = false;
+        self.max_write_len = SEND_BUF_LEN;
+        self.max_read_len = None;
+        self.error_list_received = false;
+        self.last_error_received = 0;
+        self.last_chan_received = 0;
+        self.last_exec_chan_rid = 0;
+        self.last_command_chan_rid = 0;
+        self.latest = LatestReports::default();
+        self.report_queue.clear();
+        for decimation in &mut self.decimation {
+            decimation.skipped = 0;
+        }
+        #[cfg(feature = "filter")]
+        for filter in self.smoothing.iter_mut().flatten() {
+            filter.reset();
+        }
+        #[cfg(feature = "report-gesture")]
+        self.gestures.clear();
+    }
+
+    /// The sensor interface, for example to find out which i2c address
+    /// was detected during `init`
+    pub fn sensor_interface(&self) -> &SI {
+        &self.sensor_interface
+    }
+
+    /// The channel numbers used by the connected firmware, as advertised
+    /// during `init`
+    pub fn channels(&self) -> ChannelMap {
+        self.channels
+    }
+
+    /// Counters of the traffic with the sensor hub since the driver was
+    /// created, or since `reset_stats`
+    pub fn stats(&self) -> CommStats {
+        self.stats
+    }
+
+    /// Restart the traffic counters from zero
+    pub fn reset_stats(&mut self) {
+        self.stats = CommStats::default();
+    }
+
+    /// Returns previously consumed serial sensor instance.
+    /// Combined with the `free` method of the interface, this
+    /// hands back the underlying bus and pins.
+    pub fn free(self) -> SI {
+        self.sensor_interface
+    }
+
+    /// Set how transient communication errors (such as the i2c NAKs
+    /// common right after the sensor hub resets) are retried
+    pub fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.retry_policy = retry_policy;
+    }
+
+    /// Set how long to wait for the sensor hub to answer, during
+    /// initialization and for command responses
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts = timeouts;
+    }
+
+    /// Set the clock with which timeouts are measured; without one,
+    /// the driver adds up the delays it requested
+    pub fn set_clock(&mut self, clock: Option<MillisClock>) {
+        self.clock = clock;
+    }
+
+    /// Convert the decoded orientation, angular rate and acceleration
+    /// outputs into `frame`, e.g. `Frame::Ned` for flight controllers.
+    /// The raw (fixed-point) outputs stay in the hub's native frame.
+    pub fn set_output_frame(&mut self, frame: Frame) {
+        self.frame = frame;
+    }
+
+    /// The coordinate frame of the decoded outputs
+    pub fn output_frame(&self) -> Frame {
+        self.frame
+    }
+
+    /// Check each packet received before handling it: the length in its
+    /// header must match the length read, and its channel must have been
+    /// advertised. Packets failing the checks, as read from a glitched
+    /// bus, are discarded with `WrapperError::CorruptPacket`.
+    pub fn set_packet_validation(&mut self, enabled: bool) {
+        self.validate_packets = enabled;
+    }
+
+    /// Register a function to be called whenever a sensor report packet
+    /// contains a report whose ID this crate does not know, so that reports
+    /// added by newer firmware can be parsed by the application
+    pub fn set_unknown_report_hook(&mut self, hook: Option<UnknownReportHook>) {
+        self.unknown_report_hook = hook;
+    }
+
+    /// The payload (the packet body following the SHTP header) of the
+    /// last packet received, borrowed from the receive buffer.
+    /// Empty if the last packet carried no payload.
+    pub fn last_packet_payload(&self) -> &[u8] {
+        packet_payload(&self.packet_recv_buf, self.last_packet_len_received)
+    }
+
+    /// The last packet received, borrowed from the receive buffer;
+    /// copy it with `OwnedPacket::from_packet` to handle it later
+    pub fn last_packet(&self) -> Option<Packet<'_>> {
+        if self.last_packet_len_received < PACKET_HEADER_LENGTH {
+            return None;
+        }
+        Some(Packet {
+            header: ShtpHeader::parse(&self.packet_recv_buf),
+            payload: self.last_packet_payload(),
+        })
+    }
+
+    /// Views of the input reports contained in the last packet received,
+    /// borrowed from the receive buffer without copying.
+    /// Empty if the last packet was not a sensor report packet.
+    pub fn last_input_reports(&self) -> InputReports<'_> {
+        let payload = self.last_packet_payload();
+        if !payload.is_empty()
+            && self.packet_recv_buf[2] == self.channels.input_normal
+        {
+            InputReports::new(payload)
+        } else {
+            InputReports::empty()
+        }
+    }
+
+    /// Take the oldest decoded report that has not been taken yet.
+    /// Every report received is retained, so that none of a batch of
+    /// reports is lost when the application polls slower than the hub
+    /// reports. Once `REPORT_QUEUE_LEN` reports are waiting, the oldest
+    /// is discarded to make room for each new one.
+    pub fn next_report(&mut self) -> Option<SensorReport> {
+        let frame = self.frame;
+        self.report_queue
+            .pop_front()
+            .map(|report| report.as_report().decode().in_frame(frame))
+    }
+
+    /// Take the oldest report, like `next_report`, but without decoding it,
+    /// so that its raw fixed-point values can be read without any
+    /// floating point arithmetic
+    pub fn next_raw_report(&mut self) -> Option<OwnedInputReport> {
+        self.report_queue.pop_front()
+    }
+
+    /// Queue only every `factor`th report with `report_id` for
+    /// `next_report`, `next_raw_report` and `wait_for_report`, so that a
+    /// sensor can run at a high rate for the hub's fusion while the
+    /// application sees a lower one. A factor of 0 or 1 queues every
+    /// report. The `latest_` getters still see every report.
+    /// Only the sensor reports, with IDs below 0x30, can be decimated.
+    pub fn set_decimation(&mut self, report_id: u8, factor: u8) {
+        if let Some(decimation) = self.decimation.get_mut(report_id as usize) {
+            *decimation = Decimation { factor, skipped: 0 };
+        }
+    }
+
+    /// Smooth the outputs of one of the accelerometer, calibrated
+    /// gyroscope, linear acceleration and uncalibrated gyroscope reports
+    /// with `filter`, or stop smoothing them with `None`. The smoothed
+    /// values are returned by `linear_accel`, `gyro` and the `latest_`
+    /// getters; `next_report` still yields each report as received.
+    /// Returns false, changing nothing, for any other report.
+    #[cfg(feature = "filter")]
+    pub fn set_smoothing(
+        &mut self,
+        report_id: u8,
+        filter: Option<LowPass>,
+    ) -> bool {
+        let Some(slot) = SMOOTHED_REPORT_IDS
+            .iter()
+            .position(|id| *id == report_id)
+            .and_then(|index| self.smoothing.get_mut(index))
+        else {
+            return false;
+        };
+        *slot = filter;
+        true
+    }
+
+    /// The smoothed value of a report, if its outputs are smoothed
+    fn smoothed(&self, report_id: u8) -> Option<[f32; 3]> {
+        #[cfg(feature = "filter")]
+        {
+            let index =
+                SMOOTHED_REPORT_IDS.iter().position(|id| *id == report_id)?;
+            self.smoothing.get(index).copied().flatten()?.value()
+        }
+        #[cfg(not(feature = "filter"))]
+        {
+            let _ = report_id;
+            None
+        }
+    }
+
+    /// The decimation factor of `report_id`, 1 if it is not decimated
+    pub fn decimation(&self, report_id: u8) -> u8 {
+        self.decimation
+            .get(report_id as usize)
+            .map_or(1, |decimation| decimation.factor.max(1))
+    }
+
+    /// Number of decoded reports waiting to be taken with `next_report`
+    pub fn reports_available(&self) -> usize {
+        self.report_queue.len()
+    }
+
+    /// Take the oldest queued report with `report_id`, keeping the order
+    /// of the other queued reports
+    fn take_queued_report(
+        &mut self,
+        report_id: u8,
+    ) -> Option<OwnedInputReport> {
+        let mut found = None;
+        for _ in 0..self.report_queue.len() {
+            let Some(report) = self.report_queue.pop_front() else {
+                break;
+            };
+            if found.is_none() && report.as_report().report_id() == report_id {
+                found = Some(report);
+            } else {
+                // cannot fail: an element was just taken out
+                let _ = self.report_queue.push_back(report);
             }
         }
+        found
+    }
+
+    /// The sequence number of the next packet sent on `channel`,
+    /// or `None` for a channel number the driver does not use
+    pub fn sequence_number(&self, channel: u8) -> Option<u8> {
+        if !self.is_channel_advertised(channel) {
+            return None;
+        }
+        self.sequence_numbers.get(channel as usize).copied()
+    }
+
+    /// Did the sensor hub advertise this channel (or, before the
+    /// advertisement is received, is it one of the default channels)?
+    pub fn is_channel_advertised(&self, channel: u8) -> bool {
+        (channel as usize) < MAX_CHANNELS
+            && self.advertised_channels & (1 << channel) != 0
+    }
+
+    /// Largest packet, header included, that can be sent: the size the
+    /// sensor hub advertised, limited to the send buffer.
+    /// Larger packets are refused with `WrapperError::PacketTooLarge`.
+    pub fn max_write_len(&self) -> usize {
+        self.max_write_len
+    }
+
+    /// Largest packet, header included, that the sensor hub advertised it
+    /// may send, once the advertisement has been received
+    pub fn max_read_len(&self) -> Option<usize> {
+        self.max_read_len
+    }
+
+    /// The sequence number of the next SH-2 command request
+    pub fn command_sequence_number(&self) -> u8 {
+        self.command_sequence
+    }
+
+    /// Number of input reports received since `init`, wrapping on overflow.
+    /// Comparing this count against the host interrupt count shows whether
+    /// interrupts are being serviced without data.
+    pub fn input_report_count(&self) -> u32 {
+        self.input_report_count
+    }
+
+    /// Watchdog for the report streams: call periodically, with the time
+    /// `elapsed_ms` since the previous call. Returns true once one of the
+    /// enabled reports has not been received for longer than
+    /// `expected_interval_ms` (detected within twice that time), as happens
+    /// when the sensor hub browns out and stops reporting.
+    pub fn reports_stale(
+        &mut self,
+        expected_interval_ms: u32,
+        elapsed_ms: u32,
+    ) -> bool {
+        self.stale_window_ms = self.stale_window_ms.saturating_add(elapsed_ms);
+        if self.enabled_reports.is_subset(&self.received_reports) {
+            // every stream is alive: start a new window
+            self.received_reports = ReportIdSet::default();
+            self.stale_window_ms = 0;
+            return false;
+        }
+        self.stale_window_ms > expected_interval_ms
+    }
+}
+
+impl<SI, SE, const SEND_BUF_LEN: usize, const RECV_BUF_LEN: usize>
+    BNO080<SI, SEND_BUF_LEN, RECV_BUF_LEN>
+where
+    SI: SensorInterface<SensorError = SE>,
+    SE: core::fmt::Debug,
+{
+    /// Consume all available messages on the port without processing them
+    pub fn eat_all_messages(&mut self, delay: &mut impl DelayNs) {
+        #[cfg(feature = "rttdebug")]
+        rprintln!("eat_n");
+        loop {
+            let msg_count = self.eat_one_message(delay);
+            if msg_count == 0 {
+                break;
+            }
+            //give some time to other parts of the system
+            delay.delay_ms(1);
+        }
+    }
+
+    /// Receive and ignore at most `max_messages` messages, stopping early
+    /// when there is no packet to read. Returns the number of messages eaten.
+    pub fn eat_messages_up_to(
+        &mut self,
+        delay: &mut impl DelayNs,
+        max_messages: u32,
+    ) -> u32 {
+        let mut eaten: u32 = 0;
+        while eaten < max_messages {
+            if self.eat_one_message(delay) == 0 {
+                break;
+            }
+            eaten += 1;
+            //give some time to other parts of the system
+            delay.delay_ms(1);
+        }
+        eaten
+    }
+
+    /// Handle messages until there is no packet to read, `max_packets`
+    /// have been handled, or about `max_ms` have been spent waiting.
+    /// Unlike `handle_all_messages`, this returns within a predictable
+    /// time even while the hub keeps sending (such as during an
+    /// advertisement storm). Returns the number of messages handled.
+    pub fn drain(
+        &mut self,
+        max_packets: u32,
+        max_ms: u32,
+        delay: &mut impl DelayNs,
+    ) -> u32 {
+        let mut handled: u32 = 0;
+        let mut stopwatch = Stopwatch::start(self.clock);
+        while handled < max_packets {
+            // the clock may pass `max_ms` at any reading
+            let remaining_ms = max_ms.saturating_sub(stopwatch.elapsed_ms());
+            if remaining_ms == 0 {
+                break;
+            }
+            let wait_ms = DRAIN_READ_TIMEOUT_MS.min(remaining_ms);
+            if self.handle_one_message(delay, wait_ms as u8) == 0 {
+                break;
+            }
+            handled += 1;
+            //give some time to other parts of the system
+            delay.delay_ms(1);
+            // count the longest the read could have waited
+            stopwatch.count(wait_ms + 1);
+        }
+        handled
+    }
+
+    /// Handle messages until a report with `report_id` arrives, and return
+    /// it decoded, or `WrapperError::Timeout` if none arrives within about
+    /// `timeout_ms`. The report must have been enabled. Other reports
+    /// received in the meantime stay queued for `next_report`.
+    pub fn wait_for_report(
+        &mut self,
+        report_id: u8,
+        timeout_ms: u32,
+        delay: &mut impl DelayNs,
+    ) -> Result<SensorReport, WrapperError<SE>> {
+        let mut stopwatch = Stopwatch::start(self.clock);
+        loop {
+            if let Some(report) = self.take_queued_report(report_id) {
+                return Ok(report.as_report().decode().in_frame(self.frame));
+            }
+            let elapsed_ms = stopwatch.elapsed_ms();
+            if elapsed_ms >= timeout_ms {
+                return Err(WrapperError::Timeout);
+            }
+            let wait_ms = DRAIN_READ_TIMEOUT_MS.min(timeout_ms - elapsed_ms);
+            let received_len =
+                self.receive_packet_with_timeout(delay, wait_ms as u8)?;
+            if received_len > 0 {
+                // a corrupt packet is counted and skipped
+                let _ = self.handle_received_packet(received_len);
+                //give some time to other parts of the system
+                delay.delay_ms(1);
+                stopwatch.count(1);
+            } else {
+                stopwatch.count(wait_ms);
+            }
+        }
+    }
+
+    /// Iterate over the incoming reports, decoded. Packets are received
+    /// and handled as the iterator is advanced. The iteration ends once no
+    /// packet has arrived for `REPORTS_IDLE_TIMEOUT_MS`.
+    pub fn reports<'a>(
+        &'a mut self,
+        delay: &'a mut impl DelayNs,
+    ) -> impl Iterator<Item = SensorReport> + 'a {
+        core::iter::from_fn(move || {
+            let mut idle = Stopwatch::start(self.clock);
+            loop {
+                if let Some(report) = self.next_report() {
+                    return Some(report);
+                }
+                if idle.elapsed_ms() >= REPORTS_IDLE_TIMEOUT_MS {
+                    return None;
+                }
+                let handled =
+                    self.handle_one_message(delay, DRAIN_READ_TIMEOUT_MS as u8);
+                if handled == 0 {
+                    idle.count(DRAIN_READ_TIMEOUT_MS);
+                } else {
+                    idle = Stopwatch::start(self.clock);
+                }
+            }
+        })
+    }
+
+    /// Wait for the next rotation vector report, as `wait_for_report`
+    pub fn wait_for_rotation_vector(
+        &mut self,
+        timeout_ms: u32,
+        delay: &mut impl DelayNs,
+    ) -> Result<Quaternion, WrapperError<SE>> {
+        match self.wait_for_report(
+            SensorReportId::RotationVector.into(),
+            timeout_ms,
+            delay,
+        )? {
+            SensorReport::RotationVector { quaternion, .. } => Ok(quaternion),
+            _ => Err(WrapperError::UnexpectedReport(
+                SensorReportId::RotationVector.into(),
+            )),
+        }
+    }
+
+    /// Handle any messages with a timeout
+    pub fn handle_all_messages(
+        &mut self,
+        delay: &mut impl DelayNs,
+        timeout_ms: u8,
+    ) -> u32 {
+        let mut total_handled: u32 = 0;
+        loop {
+            let handled_count = self.handle_one_message(delay, timeout_ms);
+            if handled_count == 0 {
+                break;
+            } else {
+                total_handled += handled_count;
+                //give some time to other parts of the system
+                delay.delay_ms(1);
+            }
+        }
+        total_handled
+    }
+
+    /// return the number of messages handled
+    pub fn handle_one_message(
+        &mut self,
+        delay: &mut impl DelayNs,
+        max_ms: u8,
+    ) -> u32 {
+        let mut msg_count = 0;
+
+        let res = self.receive_packet_with_timeout(delay, max_ms);
+        if res.is_ok() {
+            let received_len = res.unwrap_or(0);
+            if received_len > 0
+                && self.handle_received_packet(received_len).is_ok()
+            {
+                msg_count += 1;
+            }
+        } else {
+            #[cfg(feature = "rttdebug")]
+            rprintln!("handle1 err {:?}", res);
+        }
+
+        msg_count
+    }
+
+    /// Read one packet with `SensorInterface::read_packet_bulk`,
+    /// without waiting for data, and handle it.
+    /// Intended for high report rates (such as gyro-integrated rotation
+    /// vector at 1 kHz), when the host interrupt has signaled that data
+    /// is available. Returns the number of messages handled.
+    pub fn handle_one_message_bulk(&mut self) -> u32 {
+        if !self.read_permitted() {
+            return 0;
+        }
+        self.packet_recv_buf[0] = 0;
+        self.packet_recv_buf[1] = 0;
+        match self
+            .sensor_interface
+            .read_packet_bulk(&mut self.packet_recv_buf)
+        {
+            Ok(received_len) => {
+                if self.check_received_len(received_len).is_err() {
+                    return 0;
+                }
+                trace_received_packet(&self.packet_recv_buf, received_len);
+                if received_len > PACKET_HEADER_LENGTH
+                    && self.handle_received_packet(received_len).is_ok()
+                {
+                    1
+                } else {
+                    0
+                }
+            }
+            Err(_e) => {
+                count(&mut self.stats.comm_errors);
+                #[cfg(feature = "rttdebug")]
+                rprintln!("bulk err {:?}", _e);
+                0
+            }
+        }
+    }
+
+    /// Receive and ignore one message,
+    /// returning the size of the packet received or zero
+    /// if there was no packet to read.
+    pub fn eat_one_message(&mut self, delay: &mut impl DelayNs) -> usize {
+        let res = self.receive_packet_with_timeout(delay, 150);
+        #[cfg(feature = "rttdebug")]
+        rprintln!("e1 {:?}", res);
+        res.unwrap_or_default()
+    }
+
+    fn handle_advertise_response(&mut self, received_len: usize) {
+        let payload = packet_payload(&self.packet_recv_buf, received_len);
+
+        #[cfg(feature = "rttdebug")]
+        rprintln!("AdvRsp: {}", payload.len());
+
+        if let Some(capabilities) = Capabilities::from_advertisement(payload) {
+            self.capabilities = Some(capabilities);
+        }
+        self.channels = ChannelMap::from_advertisement(payload);
+        let advertised_channels = ChannelMap::advertised_channels(payload);
+        if advertised_channels != 0 {
+            self.advertised_channels = advertised_channels;
+        }
+        if let Some(max_len) =
+            advertised_max_cargo(payload, ADVERT_TAG_MAX_CARGO_WRITE)
+        {
+            // a maximum too small for any packet is ignored
+            if max_len > PACKET_HEADER_LENGTH {
+                self.max_write_len = max_len.min(SEND_BUF_LEN);
+            }
+        }
+        if let Some(max_len) =
+            advertised_max_cargo(payload, ADVERT_TAG_MAX_CARGO_READ)
+        {
+            self.max_read_len = Some(max_len);
+        }
+        self.advert_received = true;
+        debug!("advertisement received");
+    }
+
+    /// Handle parsing of an input report packet,
+    /// which may include multiple input reports
+    fn handle_sensor_reports(&mut self, received_len: usize) {
+        // Sensor input packets have the form:
+        // [u8; 5]  base timestamp reference
+        // a sequence of n reports, each with four byte header
+        // u8 report ID
+        // u8 sequence number of report
+        // u8 status
+        // u8 delay
+        let bus_time_us = self.packet_bus_time_us;
+        let mut position = InputReports::empty();
+        let unparsed = loop {
+            let payload = packet_payload(&self.packet_recv_buf, received_len);
+            let mut reports = position.resume(payload);
+            let Some(report) = reports.next() else {
+                break reports.remaining();
+            };
+            let report =
+                OwnedInputReport::new(&report, reports.timestamp_base())
+                    .with_bus_time(bus_time_us);
+            position = reports.resume(&[]);
+            self.handle_input_report(report);
+        };
+        if !unparsed.is_empty() {
+            count(&mut self.stats.decode_errors);
+        }
+        if let (Some(hook), Some(report_id)) =
+            (self.unknown_report_hook, unparsed.first())
+        {
+            hook(*report_id, unparsed);
+        }
+    }
+
+    /// Handle a gyro-integrated rotation vector packet, which carries one
+    /// report without a report header or timestamp, queuing it as a
+    /// report with ID 0x2A
+    fn handle_gyro_integrated_rv(&mut self, received_len: usize) {
+        let bus_time_us = self.packet_bus_time_us;
+        let payload = packet_payload(&self.packet_recv_buf, received_len);
+        let sequence = ShtpHeader::parse(&self.packet_recv_buf).sequence;
+        let Some(report) =
+            OwnedInputReport::gyro_integrated_rv(payload, sequence)
+                .map(|report| report.with_bus_time(bus_time_us))
+        else {
+            count(&mut self.stats.decode_errors);
+            return;
+        };
+        self.handle_input_report(report);
+    }
+
+    /// Handle one received input report: cache, count, smooth and detect
+    /// gestures in it, then queue it unless it is decimated, discarding
+    /// the oldest queued report if the queue is full
+    fn handle_input_report(&mut self, report: OwnedInputReport) {
+        let input = report.as_report();
+        let report_id = input.report_id();
+        self.latest.update_report(&input, report.timestamp_base());
+        self.received_reports.insert(report_id);
+        self.input_report_count = self.input_report_count.wrapping_add(1);
+        #[cfg(feature = "filter")]
+        smooth_report(&mut self.smoothing, &input);
+        #[cfg(feature = "report-gesture")]
+        if self.gestures.uses_report(report_id) {
+            let now_ms = self.clock.map(|now| now());
+            self.gestures.handle(&input.decode(), now_ms);
+        }
+        let decimation = self.decimation.get_mut(report_id as usize);
+        if !decimation.is_none_or(Decimation::pass) {
+            return;
+        }
+        if self.report_queue.is_full() {
+            self.report_queue.pop_front();
+        }
+        // cannot fail: there is room after discarding the oldest
+        let _ = self.report_queue.push_back(report);
+    }
+
+    /// Handle one or more errors sent in response to a command
+    fn handle_cmd_resp_error_list(&mut self, received_len: usize) {
+        let payload = packet_payload(&self.packet_recv_buf, received_len);
+
+        self.error_list_received = true;
+        for &err in payload.iter().skip(1) {
+            self.last_error_received = err;
+            #[cfg(feature = "rttdebug")]
+            rprintln!("lerr: {:x}", err);
+        }
+    }
+
+    /// Handle the packet of `received_len` bytes in the receive buffer.
+    /// A length that cannot be that of a packet, too short for the header
+    /// or longer than the buffer, fails with `WrapperError::CorruptPacket`.
+    pub fn handle_received_packet(
+        &mut self,
+        received_len: usize,
+    ) -> Result<(), WrapperError<SE>> {
+        if !(PACKET_HEADER_LENGTH..=RECV_BUF_LEN).contains(&received_len) {
+            debug!("cannot handle packet, len {}", received_len);
+            count(&mut self.stats.corrupt_packets);
+            return Err(WrapperError::CorruptPacket);
+        }
+        let header = ShtpHeader::parse(&self.packet_recv_buf);
+        let payload = packet_payload(&self.packet_recv_buf, received_len);
+        let chan_num = header.channel;
+        let seq_num = header.sequence;
+        if let Some(expected) =
+            self.recv_sequence_numbers.get_mut(chan_num as usize)
+        {
+            if let Some(expected_seq) = *expected {
+                if expected_seq != seq_num && self.sequence_gap.is_none() {
+                    self.sequence_gap = Some((chan_num, expected_seq, seq_num));
+                }
+            }
+            *expected = Some(seq_num.wrapping_add(1));
+        }
+        let report_id: u8 = payload.first().copied().unwrap_or(0);
+
+        self.last_chan_received = chan_num;
+        match self.channels.channel(chan_num) {
+            Some(Channel::Command) => match report_id {
+                CMD_RESP_ADVERTISEMENT => {
+                    self.handle_advertise_response(received_len);
+                }
+                CMD_RESP_ERROR_LIST => {
+                    self.handle_cmd_resp_error_list(received_len);
+                }
+                _ => {
+                    self.last_command_chan_rid = report_id;
+                    #[cfg(feature = "rttdebug")]
+                    rprintln!("unh cmd: {}", report_id);
+                }
+            },
+            Some(Channel::Executable) => {
+                match ExecutableResponse::try_from(report_id) {
+                    Ok(ExecutableResponse::ResetComplete) => {
+                        self.device_reset = true;
+                        debug!("device reset complete");
+                        #[cfg(feature = "rttdebug")]
+                        rprintln!("resp_reset {}", 1);
+                    }
+                    _ => {
+                        self.last_exec_chan_rid = report_id;
+                        #[cfg(feature = "rttdebug")]
+                        rprintln!("unh exe: {:x}", report_id);
+                    }
+                }
+            }
+            Some(Channel::HubControl) => {
+                match ControlReportId::try_from(report_id) {
+                    Ok(ControlReportId::CommandResponse) => {
+                        // 0xF1 / 241
+                        let response = CommandResponse::parse(payload);
+                        let cmd_resp = response.command;
+                        self.last_command_response = Some(response);
+                        if let Some(slot) =
+                            self.command_responses.get_mut(cmd_resp as usize)
+                        {
+                            *slot = Some(response);
+                        }
+                        if cmd_resp == SH2_STARTUP_INIT_UNSOLICITED
+                            || cmd_resp == SH2_INIT_SYSTEM
+                        {
+                            self.init_received = true;
+                            debug!(
+                                "init received, unsolicited: {}",
+                                cmd_resp == SH2_STARTUP_INIT_UNSOLICITED
+                            );
+                        }
+                        #[cfg(feature = "rttdebug")]
+                        rprintln!("CMD_RESP: 0x{:X}", cmd_resp);
+                    }
+                    Ok(ControlReportId::ProductIdResponse) => {
+                        let product_id = ProductId::parse(payload);
+                        #[cfg(feature = "rttdebug")]
+                        rprintln!(
+                            "PID_RESP {}.{}",
+                            product_id.sw_version_major,
+                            product_id.sw_version_minor
+                        );
+                        // the hub sends one response per firmware component,
+                        // the first describes the application firmware
+                        if self.product_id.is_none() {
+                            self.product_id = Some(product_id);
+                        }
+
+                        self.prod_id_verified = true;
+                        debug!("product id verified");
+                    }
+                    Ok(ControlReportId::FrsReadResponse) => {
+                        self.last_frs_read =
+                            Some(FrsReadResponse::parse(payload));
+                    }
+                    Ok(ControlReportId::FrsWriteResponse) => {
+                        self.last_frs_write =
+                            Some(FrsWriteResponse::parse(payload));
+                    }
+                    Ok(ControlReportId::GetFeatureResponse) => {
+                        // 0xFC
+                        #[cfg(feature = "rttdebug")]
+                        rprintln!("feat resp: {:?}", payload.get(1));
+                        self.last_feature_response =
+                            Some(FeatureResponse::parse(payload));
+                    }
+                    _ => {
+                        #[cfg(feature = "rttdebug")]
+                        rprintln!("unh hbc: 0x{:X} {:x?}", report_id, header);
+                    }
+                }
+            }
+            Some(Channel::SensorReports | Channel::WakeReports) => {
+                self.handle_sensor_reports(received_len);
+            }
+            Some(Channel::GyroRotation) => {
+                self.handle_gyro_integrated_rv(received_len);
+            }
+            _ => {
+                self.last_chan_received = chan_num;
+                #[cfg(feature = "rttdebug")]
+                rprintln!("unh chan 0x{:X}", chan_num);
+            }
+        }
+        Ok(())
+    }
+
+    /// The BNO080 starts up with all sensors disabled,
+    /// waiting for the application to configure it.
+    /// May be called again to recover from a loss of synchronization
+    /// with the sensor hub; all reports must then be enabled again.
+    /// Blocks until initialization completes; see `poll_init` to run it
+    /// incrementally instead.
+    pub fn init(
+        &mut self,
+        delay_source: &mut impl DelayNs,
+    ) -> Result<(), WrapperError<SE>> {
+        #[cfg(feature = "rttdebug")]
+        rprintln!("wrapper init");
+        self.init_state = InitState::Reset;
+        while self.poll_init(delay_source)? != InitState::Ready {}
+        Ok(())
+    }
+
+    /// Advance the initialization of the sensor hub by one step, and
+    /// return the state reached. Call it repeatedly (e.g. from a
+    /// cooperative scheduler) until it returns `InitState::Ready`.
+    /// Apart from the interface `setup` in the `Reset` state, each step
+    /// waits at most `INIT_POLL_READ_MS` for a packet.
+    /// Returns `WrapperError::Timeout` if the hub does not answer the
+    /// product ID request in time.
+    /// On error, initialization starts over from `InitState::Reset`.
+    pub fn poll_init(
+        &mut self,
+        delay: &mut impl DelayNs,
+    ) -> Result<InitState, WrapperError<SE>> {
+        let result = self.step_init(delay);
+        if result.is_err() {
+            self.init_state = InitState::Reset;
+        }
+        result
+    }
+
+    /// The progress of the initialization handshake
+    pub fn init_state(&self) -> InitState {
+        self.init_state
+    }
+
+    fn step_init(
+        &mut self,
+        delay: &mut impl DelayNs,
+    ) -> Result<InitState, WrapperError<SE>> {
+        match self.init_state {
+            InitState::Reset => {
+                self.reset_driver_state();
+                //Section 5.1.1.1 : On system startup, the SHTP control application will send
+                // its full advertisement response, unsolicited, to the host.
+                delay.delay_ms(1);
+                self.sensor_interface
+                    .setup(delay)
+                    .map_err(WrapperError::CommError)?;
+                if self.sensor_interface.requires_soft_reset() {
+                    delay.delay_ms(1);
+                    self.soft_reset()?;
+                }
+                self.init_state = InitState::Advertise;
+            }
+            InitState::Advertise => {
+                // we expect two messages after reset: the advertisement
+                // response, which lists the supported reports,
+                // and the unsolicited initialization response
+                self.poll_init_message(delay);
+                if (self.advert_received && self.init_received)
+                    || self.init_wait.elapsed_ms()
+                        >= self.timeouts.init_advertise_ms
+                {
+                    self.init_wait = Stopwatch::start(self.clock);
+                    if !self.prod_id_verified {
+                        self.request_product_id()?;
+                    }
+                    self.init_state = InitState::ProductId;
+                }
+            }
+            InitState::ProductId => {
+                if !self.prod_id_verified {
+                    self.poll_init_message(delay);
+                }
+                if self.prod_id_verified {
+                    self.init_state = InitState::Ready;
+                    debug!("init complete");
+                } else if self.init_wait.elapsed_ms()
+                    >= self.timeouts.init_product_id_ms
+                {
+                    return Err(WrapperError::Timeout);
+                }
+            }
+            InitState::Ready => {}
+        }
+        Ok(self.init_state)
+    }
+
+    /// Handle one message during initialization. Without a clock, each
+    /// poll counts as the longest it could have waited, so that a hub
+    /// sending other packets cannot hold up initialization indefinitely.
+    fn poll_init_message(&mut self, delay: &mut impl DelayNs) {
+        self.handle_one_message(delay, INIT_POLL_READ_MS as u8);
+        self.init_wait.count(INIT_POLL_READ_MS);
+    }
+
+    /// Tell the sensor to start reporting the fused rotation vector
+    /// on a regular cadence. Note that the maximum valid update rate
+    /// is 1 kHz, based on the max update rate of the sensor's gyros.
+    pub fn enable_rotation_vector(
+        &mut self,
+        millis_between_reports: u16,
+    ) -> Result<(), WrapperError<SE>> {
+        self.enable_report(
+            SensorReportId::RotationVector,
+            millis_between_reports,
+        )
+    }
+
+    /// Enables the rotation vector, with the interval between reports
+    /// in microseconds, for rates that are not a whole number of
+    /// milliseconds apart (such as 400 Hz)
+    pub fn enable_rotation_vector_us(
+        &mut self,
+        micros_between_reports: u32,
+    ) -> Result<(), WrapperError<SE>> {
+        self.enable_report_us(
+            SensorReportId::RotationVector.into(),
+            micros_between_reports,
+        )
+    }
+
+    /// Enables reporting of linear acceleration vector.
+    pub fn enable_linear_accel(
+        &mut self,
+        millis_between_reports: u16,
+    ) -> Result<(), WrapperError<SE>> {
+        self.enable_report(
+            SensorReportId::LinearAcceleration,
+            millis_between_reports,
+        )
+    }
+
+    /// Enables linear acceleration, with the interval between reports
+    /// in microseconds
+    pub fn enable_linear_accel_us(
+        &mut self,
+        micros_between_reports: u32,
+    ) -> Result<(), WrapperError<SE>> {
+        self.enable_report_us(
+            SensorReportId::LinearAcceleration.into(),
+            micros_between_reports,
+        )
+    }
+
+    /// Enables reporting of gyroscope data.
+    /// This is the uncalibrated gyroscope report, read by `gyro` and
+    /// `latest_gyro`, which also carries the bias estimate; `enable_ahrs`
+    /// enables the calibrated one instead.
+    pub fn enable_gyro(
+        &mut self,
+        millis_between_reports: u16,
+    ) -> Result<(), WrapperError<SE>> {
+        self.enable_report(
+            SensorReportId::GyroscopeUncalibrated,
+            millis_between_reports,
+        )
+    }
+
+    /// Enables gyroscope data, with the interval between reports
+    /// in microseconds
+    pub fn enable_gyro_us(
+        &mut self,
+        micros_between_reports: u32,
+    ) -> Result<(), WrapperError<SE>> {
+        self.enable_report_us(
+            SensorReportId::GyroscopeUncalibrated.into(),
+            micros_between_reports,
+        )
+    }
+
+    /// Enables the rotation vector, calibrated gyroscope and linear
+    /// acceleration at a shared rate, as read by `ahrs_sample`.
+    /// The calibrated gyroscope is not the report read by `gyro` and
+    /// `latest_gyro`; read it with `latest_calibrated_gyro`.
+    pub fn enable_ahrs(
+        &mut self,
+        millis_between_reports: u16,
+    ) -> Result<(), WrapperError<SE>> {
+        self.enable_ahrs_us(u32::from(millis_between_reports) * 1000)
+    }
+
+    /// Enables the reports read by `ahrs_sample`, with the interval
+    /// between reports in microseconds
+    pub fn enable_ahrs_us(
+        &mut self,
+        micros_between_reports: u32,
+    ) -> Result<(), WrapperError<SE>> {
+        for report_id in [
+            SensorReportId::RotationVector,
+            SensorReportId::GyroscopeCalibrated,
+            SensorReportId::LinearAcceleration,
+        ] {
+            self.enable_report_us(report_id.into(), micros_between_reports)?;
+        }
+        Ok(())
+    }
+
+    /// Enables pressure reports from a barometer on the hub's aux bus
+    pub fn enable_pressure(
+        &mut self,
+        millis_between_reports: u16,
+    ) -> Result<(), WrapperError<SE>> {
+        self.enable_report(SensorReportId::Pressure, millis_between_reports)
+    }
+
+    /// Enables ambient light reports from a light sensor on the
+    /// hub's aux bus
+    pub fn enable_ambient_light(
+        &mut self,
+        millis_between_reports: u16,
+    ) -> Result<(), WrapperError<SE>> {
+        self.enable_report(SensorReportId::AmbientLight, millis_between_reports)
+    }
+
+    /// Enables humidity reports from a humidity sensor on the
+    /// hub's aux bus
+    pub fn enable_humidity(
+        &mut self,
+        millis_between_reports: u16,
+    ) -> Result<(), WrapperError<SE>> {
+        self.enable_report(SensorReportId::Humidity, millis_between_reports)
+    }
+
+    /// Enables proximity reports from a proximity sensor on the
+    /// hub's aux bus
+    pub fn enable_proximity(
+        &mut self,
+        millis_between_reports: u16,
+    ) -> Result<(), WrapperError<SE>> {
+        self.enable_report(SensorReportId::Proximity, millis_between_reports)
+    }
+
+    /// Enables temperature reports, e.g. to compensate other sensors or
+    /// monitor the thermal state of the enclosure
+    pub fn enable_temperature(
+        &mut self,
+        millis_between_reports: u16,
+    ) -> Result<(), WrapperError<SE>> {
+        self.enable_report(SensorReportId::Temperature, millis_between_reports)
+    }
+
+    /// Enables heart rate reports from an optical heart rate monitor
+    /// attached to the hub's aux interface
+    pub fn enable_heart_rate_monitor(
+        &mut self,
+        millis_between_reports: u16,
+    ) -> Result<(), WrapperError<SE>> {
+        self.enable_report(
+            SensorReportId::HeartRateMonitor,
+            millis_between_reports,
+        )
+    }
+
+    /// Enables the personal activity classifier, classifying only
+    /// the given activities
+    pub fn enable_activity_classifier(
+        &mut self,
+        millis_between_reports: u16,
+        classes: ActivityClasses,
+    ) -> Result<(), WrapperError<SE>> {
+        self.enable_report_with_config(
+            SensorReportId::PersonalActivityClassifier.into(),
+            u32::from(millis_between_reports) * 1000,
+            classes.bits(),
+        )
+    }
+
+    /// Enable any report the firmware supports, including those without
+    /// a dedicated `enable_` method. Reports the firmware does not
+    /// support are refused with `WrapperError::UnsupportedReport`.
+    pub fn enable_report(
+        &mut self,
+        report_id: SensorReportId,
+        millis_between_reports: u16,
+    ) -> Result<(), WrapperError<SE>> {
+        self.enable_report_us(
+            report_id.into(),
+            u32::from(millis_between_reports) * 1000,
+        )
+    }
+
+    /// Enable a particular report, with the interval between reports
+    /// in microseconds
+    pub fn enable_report_us(
+        &mut self,
+        report_id: u8,
+        micros_between_reports: u32,
+    ) -> Result<(), WrapperError<SE>> {
+        self.enable_report_with_config(report_id, micros_between_reports, 0)
+    }
+
+    /// Enable a particular report, with a sensor-specific configuration word
+    fn enable_report_with_config(
+        &mut self,
+        report_id: u8,
+        micros_between_reports: u32,
+        sensor_config: u32,
+    ) -> Result<(), WrapperError<SE>> {
+        self.configure_report(
+            report_id,
+            &FeatureConfig {
+                report_interval_us: micros_between_reports,
+                sensor_specific: sensor_config,
+                ..FeatureConfig::default()
+            },
+        )
+    }
+
+    /// Configure a report with all fields of the Set Feature command.
+    /// A report interval of zero disables the report.
+    pub fn configure_report(
+        &mut self,
+        report_id: u8,
+        config: &FeatureConfig,
+    ) -> Result<(), WrapperError<SE>> {
+        #[cfg(feature = "rttdebug")]
+        rprintln!("enable_report 0x{:X}", report_id);
+        if let Some(capabilities) = self.capabilities {
+            if !capabilities.supports_report(report_id) {
+                return Err(WrapperError::UnsupportedReport(report_id));
+            }
+        }
+        if let Some(product_id) = self.product_id {
+            if !product_id.variant().supports_report(report_id) {
+                return Err(WrapperError::UnsupportedReport(report_id));
+            }
+        }
+        // an interval of zero disables the report
+        let interval_us = config.report_interval_us;
+        if let Some(limits) = report_interval_limits(report_id) {
+            if interval_us != 0 && !limits.contains(interval_us) {
+                return Err(WrapperError::InvalidReportInterval {
+                    report_id,
+                    interval_us,
+                    limits,
+                });
+            }
+        }
+
+        let (flags, sensitivity) = match config.change_sensitivity {
+            Some(ChangeSensitivity::Absolute(threshold)) => {
+                (FEATURE_FLAG_CHANGE_SENSITIVITY_ENABLED, threshold)
+            }
+            Some(ChangeSensitivity::Relative(threshold)) => (
+                FEATURE_FLAG_CHANGE_SENSITIVITY_ENABLED
+                    | FEATURE_FLAG_CHANGE_SENSITIVITY_RELATIVE,
+                threshold,
+            ),
+            None => (0, 0),
+        };
+        let flags = if config.always_on {
+            flags | FEATURE_FLAG_ALWAYS_ON
+        } else {
+            flags
+        };
+        let interval = config.report_interval_us;
+        let batch_interval = config.batch_interval_us;
+        let sensor_config = config.sensor_specific;
+        let cmd_body: [u8; 17] = [
+            ControlReportId::SetFeatureCommand.into(),
+            report_id,
+            flags,                      //feature flags
+            (sensitivity & 0xFF) as u8, //LSB change sensitivity
+            sensitivity.shr(8) as u8,   //MSB change sensitivity
+            (interval & 0xFFu32) as u8, // LSB report interval, microseconds
+            (interval.shr(8) & 0xFFu32) as u8,
+            (interval.shr(16) & 0xFFu32) as u8,
+            (interval.shr(24) & 0xFFu32) as u8, // MSB report interval
+            (batch_interval & 0xFFu32) as u8,   // LSB Batch Interval
+            (batch_interval.shr(8) & 0xFFu32) as u8,
+            (batch_interval.shr(16) & 0xFFu32) as u8,
+            (batch_interval.shr(24) & 0xFFu32) as u8, // MSB Batch interval
+            (sensor_config & 0xFFu32) as u8, // LSB sensor-specific config
+            (sensor_config.shr(8) & 0xFFu32) as u8,
+            (sensor_config.shr(16) & 0xFFu32) as u8,
+            (sensor_config.shr(24) & 0xFFu32) as u8, // MSB sensor-specific config
+        ];
+
+        //we simply blast out this configuration command and assume it'll succeed
+        self.send_packet(self.channels.control, &cmd_body)?;
+        // any error or success in configuration will arrive some time later
+        self.report_configs.retain(|(id, _)| *id != report_id);
+        if interval == 0 {
+            self.enabled_reports.remove(report_id);
+        } else {
+            self.enabled_reports.insert(report_id);
+            // when full, the report is not restored by the watchdog
+            let _ = self.report_configs.push((report_id, *config));
+        }
+
+        Ok(())
+    }
+
+    /// Enable a report, with the interval between reports in
+    /// microseconds, then wait for the sensor hub to confirm it as
+    /// `configure_report_confirmed` does
+    pub fn enable_report_confirmed(
+        &mut self,
+        report_id: u8,
+        micros_between_reports: u32,
+        timeout_ms: u32,
+        delay: &mut impl DelayNs,
+    ) -> Result<FeatureResponse, WrapperError<SE>> {
+        self.configure_report_confirmed(
+            report_id,
+            &FeatureConfig {
+                report_interval_us: micros_between_reports,
+                ..FeatureConfig::default()
+            },
+            timeout_ms,
+            delay,
+        )
+    }
+
+    /// Configure a report as `configure_report` does, then wait up to
+    /// `timeout_ms` for the Get Feature Response the sensor hub sends
+    /// back, and return the configuration it applied.
+    /// The hub may adjust the interval to a rate its sensor supports;
+    /// if it leaves the report disabled, or sets an interval more than
+    /// twice the one requested, this fails with
+    /// `WrapperError::FeatureRejected`.
+    pub fn configure_report_confirmed(
+        &mut self,
+        report_id: u8,
+        config: &FeatureConfig,
+        timeout_ms: u32,
+        delay: &mut impl DelayNs,
+    ) -> Result<FeatureResponse, WrapperError<SE>> {
+        self.last_feature_response = None;
+        self.configure_report(report_id, config)?;
+        let mut stopwatch = Stopwatch::start(self.clock);
+        let response = loop {
+            if let Some(response) = self.last_feature_response.take() {
+                if response.report_id == report_id {
+                    break response;
+                }
+            }
+            let elapsed_ms = stopwatch.elapsed_ms();
+            if elapsed_ms >= timeout_ms {
+                return Err(WrapperError::Timeout);
+            }
+            let wait_ms = DRAIN_READ_TIMEOUT_MS.min(timeout_ms - elapsed_ms);
+            if self.handle_one_message(delay, wait_ms as u8) == 0 {
+                stopwatch.count(wait_ms);
+            }
+        };
+
+        let requested_us = config.report_interval_us;
+        let granted_us = response.report_interval_us;
+        if requested_us != 0
+            && (granted_us == 0 || granted_us / 2 > requested_us)
+        {
+            if granted_us == 0 {
+                self.enabled_reports.remove(report_id);
+                self.report_configs.retain(|(id, _)| *id != report_id);
+            }
+            return Err(WrapperError::FeatureRejected {
+                report_id,
+                interval_us: granted_us,
+            });
+        }
+        Ok(response)
+    }
+
+    /// Stop a report, by setting its report interval to zero
+    pub fn disable_report(
+        &mut self,
+        report_id: u8,
+    ) -> Result<(), WrapperError<SE>> {
+        self.configure_report(report_id, &FeatureConfig::default())
+    }
+
+    /// Stop all reports enabled through this driver, for example before
+    /// entering a low-power state
+    pub fn disable_all_reports(&mut self) -> Result<(), WrapperError<SE>> {
+        let enabled_reports = self.enabled_reports;
+        for report_id in enabled_reports.iter() {
+            self.disable_report(report_id)?;
+        }
+        Ok(())
+    }
+
+    /// Has this report been enabled through this driver?
+    pub fn is_report_enabled(&self, report_id: u8) -> bool {
+        self.enabled_reports.contains(report_id)
+    }
+
+    /// Deliver `gesture` as events, to its callback and to `take_gesture`,
+    /// enabling the report it is detected in. Detections less than
+    /// `debounce_ms` after the last event are ignored, which needs a
+    /// clock set with `set_clock`; without one, every detection is an
+    /// event.
+    #[cfg(feature = "report-gesture")]
+    pub fn enable_gesture(
+        &mut self,
+        gesture: Gesture,
+        debounce_ms: u32,
+    ) -> Result<(), WrapperError<SE>> {
+        let report_id = gesture.report_id();
+        if !self.is_report_enabled(report_id.into()) {
+            self.enable_report(report_id, GESTURE_REPORT_INTERVAL_MS)?;
+            self.gestures.set_enabled_report(gesture);
+        }
+        self.gestures.enable(gesture, debounce_ms);
+        Ok(())
+    }
+
+    /// Stop delivering `gesture` as events, disabling the report it is
+    /// detected in if `enable_gesture` enabled it and no other enabled
+    /// gesture shares it
+    #[cfg(feature = "report-gesture")]
+    pub fn disable_gesture(
+        &mut self,
+        gesture: Gesture,
+    ) -> Result<(), WrapperError<SE>> {
+        self.gestures.disable(gesture);
+        if self.gestures.release_report(gesture) {
+            self.disable_report(gesture.report_id().into())?;
+        }
+        Ok(())
+    }
+
+    /// Register a function to be called with each event of `gesture`,
+    /// from within `handle_one_message` and `handle_all_messages`
+    #[cfg(feature = "report-gesture")]
+    pub fn set_gesture_callback(
+        &mut self,
+        gesture: Gesture,
+        callback: Option<GestureCallback>,
+    ) {
+        self.gestures.set_callback(gesture, callback);
+    }
+
+    /// Has an event of `gesture` occurred since the last call?
+    /// For firmware polling its inputs rather than using callbacks.
+    #[cfg(feature = "report-gesture")]
+    pub fn take_gesture(&mut self, gesture: Gesture) -> bool {
+        self.gestures.take(gesture)
+    }
+
+    /// Prepare a packet for sending, in our send buffer
+    fn prep_send_packet(
+        &mut self,
+        channel: u8,
+        body_data: &[u8],
+    ) -> Result<usize, WrapperError<SE>> {
+        if !self.is_channel_advertised(channel) {
+            return Err(WrapperError::UnknownChannel(channel));
+        }
+        let body_len = body_data.len();
+
+        let packet_length = body_len + PACKET_HEADER_LENGTH;
+        if packet_length > self.max_write_len {
+            return Err(WrapperError::PacketTooLarge);
+        }
+        let Some(sequence) = self.sequence_numbers.get_mut(channel as usize)
+        else {
+            return Err(WrapperError::UnknownChannel(channel));
+        };
+        let Some((header, body)) = self
+            .packet_send_buf
+            .get_mut(..packet_length)
+            .and_then(|packet| packet.split_first_chunk_mut())
+        else {
+            return Err(WrapperError::PacketTooLarge);
+        };
+        *header = [
+            (packet_length & 0xFF) as u8, //LSB
+            packet_length.shr(8) as u8,   //MSB
+            channel,
+            *sequence,
+        ];
+        body.copy_from_slice(body_data);
+        // sequence numbers wrap around, per SHTP
+        *sequence = sequence.wrapping_add(1);
+
+        Ok(packet_length)
+    }
+
+    /// Send a packet with this body on `channel`, adding the SHTP header.
+    /// A low-level escape hatch for reports and commands this crate does
+    /// not support; returns the length of the packet sent.
+    pub fn send_raw(
+        &mut self,
+        channel: u8,
+        body: &[u8],
+    ) -> Result<usize, WrapperError<SE>> {
+        self.send_packet(channel, body)
+    }
+
+    /// Receive one packet, waiting up to `max_ms` for it, without handling
+    /// it: the driver state (such as decoded reports) is not updated.
+    /// Returns the packet header and the payload that follows it,
+    /// or `None` if no packet arrived.
+    pub fn receive_raw(
+        &mut self,
+        delay: &mut impl DelayNs,
+        max_ms: u8,
+    ) -> Result<Option<(ShtpHeader, &[u8])>, WrapperError<SE>> {
+        let packet_len = self.receive_packet_with_timeout(delay, max_ms)?;
+        if packet_len < PACKET_HEADER_LENGTH {
+            return Ok(None);
+        }
+        let header = ShtpHeader::parse(&self.packet_recv_buf);
+        Ok(Some((header, self.last_packet_payload())))
+    }
+
+    /// Read all the packets the sensor hub has pending, without waiting
+    /// between them and without handling them (as `receive_raw`), storing
+    /// each in `arena` after the previous one. Returns the packets read.
+    /// The burst ends when no packet is pending, or when a packet does not
+    /// fit in the rest of the arena; that packet is then left in the receive
+    /// buffer, as the `last_packet_payload`. In interrupt-driven mode only
+    /// the packet signaled by `on_interrupt` is read.
+    pub fn read_all_available<'a>(
+        &mut self,
+        arena: &'a mut [u8],
+    ) -> ArenaPackets<'a> {
+        let mut filled = 0;
+        while self.read_permitted() {
+            self.packet_recv_buf[0] = 0;
+            self.packet_recv_buf[1] = 0;
+            let Ok(packet_len) = with_retries(
+                self.retry_policy,
+                &mut NoDelay,
+                &mut self.stats,
+                |_| {
+                    self.sensor_interface.read_packet(&mut self.packet_recv_buf)
+                },
+            ) else {
+                break;
+            };
+            if self.check_received_len(packet_len).is_err()
+                || packet_len < PACKET_HEADER_LENGTH
+            {
+                break;
+            }
+            trace_received_packet(&self.packet_recv_buf, packet_len);
+            let (Some(slot), Some(packet)) = (
+                arena.get_mut(filled..filled + packet_len),
+                self.packet_recv_buf.get(..packet_len),
+            ) else {
+                break;
+            };
+            slot.copy_from_slice(packet);
+            filled += packet_len;
+        }
+        let arena: &'a [u8] = arena;
+        ArenaPackets {
+            arena: arena.get(..filled).unwrap_or_default(),
+        }
+    }
+
+    /// Send packet from our packet send buf
+    fn send_packet(
+        &mut self,
+        channel: u8,
+        body_data: &[u8],
+    ) -> Result<usize, WrapperError<SE>> {
+        let packet_length = self.prep_send_packet(channel, body_data)?;
+        let packet = self
+            .packet_send_buf
+            .get(..packet_length)
+            .unwrap_or_default();
+        trace_sent_packet(packet);
+        // no delay source is available here, so retries are immediate
+        with_retries(self.retry_policy, &mut NoDelay, &mut self.stats, |_| {
+            self.sensor_interface.write_packet(packet)
+        })?;
+        if let Some(sent) = self.stats.packets_sent.get_mut(channel as usize) {
+            count(sent);
+        }
+        Ok(packet_length)
+    }
+
+    /// Switch to interrupt-driven operation, in which the driver never
+    /// reads speculatively: a read only touches the bus after `on_interrupt`
+    /// or the interrupt flag has reported that the hub asserted its
+    /// interrupt line (INTN), and otherwise returns at once with no packet,
+    /// without waiting.
+    /// Enable it after `init`, whose handshake relies on waiting reads.
+    pub fn set_interrupt_driven(&mut self, enabled: bool) {
+        self.interrupt_driven = enabled;
+    }
+
+    /// Report that the hub asserted its interrupt line (INTN), so that the
+    /// next read in interrupt-driven mode goes to the bus. An interrupt
+    /// handler without access to the driver sets the interrupt flag
+    /// (`set_interrupt_flag`) instead.
+    pub fn on_interrupt(&mut self) {
+        self.interrupt_pending = true;
+    }
+
+    /// Use `flag` to learn of the hub's interrupt in interrupt-driven mode:
+    /// the EXTI interrupt handler of the host pin sets it, typically as a
+    /// `static`, and the driver clears it when it reads the packet
+    pub fn set_interrupt_flag(&mut self, flag: Option<&'static AtomicBool>) {
+        self.interrupt_flag = flag;
+    }
+
+    /// Report the interrupt as `on_interrupt`, along with the host time
+    /// (microseconds) at which INTN asserted, e.g. from a timer's input
+    /// capture. Once a clock in the same time base is set with
+    /// `set_micros_clock`, the next packet read is timed from the
+    /// interrupt to its arrival. For a sensor report packet, this adds up
+    /// with the hub's delay to the end-to-end latency of each of its
+    /// reports (`OwnedInputReport::latency_us`).
+    /// The hub holds INTN asserted until the packet is read, so of several
+    /// interrupts before a read, the first is the one that announced it.
+    pub fn on_interrupt_at(&mut self, host_timestamp_us: u32) {
+        self.on_interrupt();
+        self.interrupt_timestamp_us.get_or_insert(host_timestamp_us);
+    }
+
+    /// Set the clock with which the arrival of timestamped packets is
+    /// timed, in the time base of `on_interrupt_at`
+    pub fn set_micros_clock(&mut self, clock: Option<MicrosClock>) {
+        self.micros_clock = clock;
+    }
+
+    /// Time from the timestamped interrupt to the arrival of the packet
+    /// it announced (microseconds), for the last packet timed
+    pub fn last_bus_time_us(&self) -> Option<u32> {
+        self.last_bus_time_us
+    }
+
+    /// Time the packet of `packet_len` bytes that has just been read from
+    /// the interrupt timestamp, which every read uses up: a read that finds
+    /// no packet means the interrupt announced none
+    fn time_received_packet(&mut self, packet_len: usize) {
+        let interrupt_us = self.interrupt_timestamp_us.take();
+        self.packet_bus_time_us = None;
+        if packet_len == 0 {
+            return;
+        }
+        if let (Some(interrupt_us), Some(now)) =
+            (interrupt_us, self.micros_clock)
+        {
+            self.packet_bus_time_us = Some(now().wrapping_sub(interrupt_us));
+            self.last_bus_time_us = self.packet_bus_time_us;
+        }
+    }
+
+    /// Does the hub have a packet ready to be read? In interrupt-driven
+    /// mode, whether `on_interrupt` was called since the last read;
+    /// otherwise as reported by the sensor interface
+    pub fn data_ready(&mut self) -> bool {
+        if self.interrupt_driven {
+            self.interrupt_pending
+                || self
+                    .interrupt_flag
+                    .is_some_and(|flag| flag.load(Ordering::Acquire))
+        } else {
+            self.sensor_interface.data_ready()
+        }
+    }
+
+    /// Whether a read may go to the bus, consuming a pending interrupt
+    /// in interrupt-driven mode
+    fn read_permitted(&mut self) -> bool {
+        if !self.interrupt_driven {
+            return true;
+        }
+        // INTN stays asserted until the packet is read, so the handler
+        // cannot set the flag again before it is cleared
+        let flagged = self.interrupt_flag.is_some_and(|flag| {
+            let set = flag.load(Ordering::Acquire);
+            if set {
+                flag.store(false, Ordering::Release);
+            }
+            set
+        });
+        core::mem::take(&mut self.interrupt_pending) || flagged
+    }
+
+    /// Read one packet into the receive buffer
+    pub(crate) fn receive_packet_with_timeout(
+        &mut self,
+        delay: &mut impl DelayNs,
+        max_ms: u8,
+    ) -> Result<usize, WrapperError<SE>> {
+        // #[cfg(feature = "rttdebug")]
+        // rprintln!("r_p");
+
+        if !self.read_permitted() {
+            return Ok(0);
+        }
+        self.packet_recv_buf[0] = 0;
+        self.packet_recv_buf[1] = 0;
+        let packet_len =
+            with_retries(self.retry_policy, delay, &mut self.stats, |delay| {
+                self.sensor_interface.read_packet_with_timeout(
+                    &mut self.packet_recv_buf,
+                    delay,
+                    max_ms,
+                )
+            })?;
+
+        self.check_received_len(packet_len)?;
+        trace_received_packet(&self.packet_recv_buf, packet_len);
+
+        Ok(packet_len)
+    }
+
+    /// Record the length of the packet just received, rejecting a packet
+    /// whose cargo did not fit in the receive buffer. The interface has
+    /// already read and discarded the remainder of such a packet.
+    fn check_received_len(
+        &mut self,
+        packet_len: usize,
+    ) -> Result<(), WrapperError<SE>> {
+        self.time_received_packet(packet_len);
+        if self.validate_packets && !self.packet_consistent(packet_len) {
+            debug!("discarded corrupt packet, len {}", packet_len);
+            count(&mut self.stats.corrupt_packets);
+            self.last_packet_len_received = 0;
+            return Err(WrapperError::CorruptPacket);
+        }
+        if packet_len > RECV_BUF_LEN {
+            debug!("discarded oversize packet, len {}", packet_len);
+            count(&mut self.stats.truncated_packets);
+            self.last_packet_len_received = 0;
+            return Err(WrapperError::BufferTooSmall);
+        }
+        if packet_len < PACKET_HEADER_LENGTH {
+            count(&mut self.stats.zero_length_reads);
+        } else if let Some(received) = self
+            .stats
+            .packets_received
+            .get_mut(self.packet_recv_buf[2] as usize)
+        {
+            count(received);
+        }
+        self.last_packet_len_received = packet_len;
+        Ok(())
+    }
+
+    /// Does the packet just received look intact?
+    fn packet_consistent(&self, packet_len: usize) -> bool {
+        if packet_len == 0 {
+            return true;
+        }
+        // an all-ones length is what a floating bus reads
+        let max_len = PACKET_HEADER_LENGTH + MAX_CARGO_DATA_LENGTH;
+        if !(PACKET_HEADER_LENGTH..=max_len).contains(&packet_len) {
+            return false;
+        }
+        let header = ShtpHeader::parse(&self.packet_recv_buf);
+        usize::from(header.length) == packet_len
+            && self.is_channel_advertised(header.channel)
+    }
+
+    /// Ask the sensor hub for its product ID, which `handle_received_packet`
+    /// records when the response arrives
+    fn request_product_id(&mut self) -> Result<(), WrapperError<SE>> {
+        #[cfg(feature = "rttdebug")]
+        rprintln!("request PID...");
+        let cmd_body: [u8; 2] = [
+            ControlReportId::ProductIdRequest.into(),
+            0, //reserved
+        ];
+        self.send_packet(self.channels.control, cmd_body.as_ref())?;
+        Ok(())
+    }
+
+    /// Read normalized quaternion:
+    /// QX normalized quaternion – X, or Heading | range: 0.0 – 1.0 ( ±π )
+    /// QY normalized quaternion – Y, or Pitch   | range: 0.0 – 1.0 ( ±π/2 )
+    /// QZ normalized quaternion – Z, or Roll    | range: 0.0 – 1.0 ( ±π )
+    /// QW normalized quaternion – W, or 0.0     | range: 0.0 – 1.0
+    pub fn rotation_quaternion(&self) -> Result<[f32; 4], WrapperError<SE>> {
+        Ok(self.quaternion().into())
+    }
+
+    /// Read the raw rotation vector quaternion, in `[i, j, k, real]` order,
+    /// with Q point `ROTATION_VECTOR_Q`
+    pub fn rotation_quaternion_raw(&self) -> [i16; 4] {
+        self.latest.rotation_quaternion
+    }
+
+    /// Read the rotation vector as a `Quaternion`, which converts into
+    /// `mint::Quaternion` and `nalgebra::UnitQuaternion` when the `mint`
+    /// or `nalgebra` features are enabled
+    pub fn quaternion(&self) -> Quaternion {
+        self.frame
+            .quaternion(Quaternion::from(self.latest.quaternion()))
+    }
+
+    /// Estimated heading accuracy of the rotation vector (radians),
+    /// for weighting the orientation by its confidence
+    pub fn heading_accuracy(&self) -> f32 {
+        self.latest.heading_accuracy()
+    }
+
+    /// Read the raw heading accuracy estimate, with Q point
+    /// `HEADING_ACCURACY_Q`
+    pub fn heading_accuracy_raw(&self) -> i16 {
+        self.latest.rot_quaternion_acc
+    }
+
+    /// Accuracy status of the rotation vector report:
+    /// 0 unreliable, 1 low, 2 medium, 3 high
+    pub fn rotation_vector_status(&self) -> u8 {
+        self.latest.rot_quaternion_status
+    }
+
+    /// Read the rotation vector as roll, pitch and yaw (radians),
+    /// along with its heading accuracy estimate
+    pub fn euler_angles(&self) -> EulerAngles {
+        EulerAngles::from_quaternion(
+            &self.quaternion(),
+            self.latest.heading_accuracy(),
+        )
+    }
+
+    /// Compass heading (degrees clockwise from north, range [0, 360))
+    /// from the rotation vector, or the geomagnetic rotation vector if
+    /// only that is enabled. `magnetic_declination` (degrees, east
+    /// positive) is added to give the heading from true north.
+    pub fn heading_degrees(&self, magnetic_declination: f32) -> Option<f32> {
+        let rotation = self.latest_rotation_vector().or_else(|| {
+            self.latest_quaternion(
+                SensorReportId::GeomagneticRotationVector.into(),
+            )
+        })?;
+        // back into the hub's frame: the conversion is its own inverse
+        let rotation = self.frame.quaternion(rotation.value);
+        Some(rotation.heading_degrees(magnetic_declination))
+    }
+
+    /// Read the rotation vector as fixed-point roll, pitch and yaw,
+    /// computed without floating point arithmetic
+    pub fn euler_angles_fixed(&self) -> FixedEulerAngles {
+        FixedEulerAngles::from_q14(self.latest.rotation_quaternion)
+    }
+
+    /// The product ID reported by the sensor hub during `init`
+    pub fn product_id(&self) -> Option<ProductId> {
+        self.product_id
+    }
+
+    /// The health of the sensor hub as seen by the driver
+    pub fn status(&self) -> DeviceStatus {
+        DeviceStatus {
+            reset_seen: self.device_reset,
+            product_verified: self.prod_id_verified,
+            last_reset_cause: self
+                .product_id
+                .map(|product_id| product_id.reset_cause),
+            init_complete: self.init_state == InitState::Ready,
+            enabled_reports: self.enabled_reports,
+        }
+    }
+
+    /// The sensor hub variant detected during `init`
+    pub fn variant(&self) -> Option<SensorVariant> {
+        self.product_id.map(|product_id| product_id.variant())
+    }
+
+    /// The reports supported by the connected firmware, as advertised
+    /// during `init`
+    pub fn capabilities(&self) -> Option<Capabilities> {
+        self.capabilities
+    }
+
+    /// Read linear acceleration (m/s^2)
+    pub fn linear_accel(&self) -> Result<[f32; 3], WrapperError<SE>> {
+        let value = self
+            .smoothed(SensorReportId::LinearAcceleration.into())
+            .unwrap_or(self.latest.linear_accel());
+        Ok(self.frame.vector(value))
+    }
+
+    /// Read raw linear acceleration, with Q point `ACCEL_Q`
+    pub fn linear_accel_raw(&self) -> [i16; 3] {
+        self.latest.linear_accel
+    }
+
+    /// Read gyroscope data (rad/s), from the uncalibrated gyroscope
+    /// report enabled by `enable_gyro`, with the bias not removed
+    pub fn gyro(&self) -> Result<[f32; 3], WrapperError<SE>> {
+        let value = self
+            .smoothed(SensorReportId::GyroscopeUncalibrated.into())
+            .unwrap_or(self.latest.gyro());
+        Ok(self.frame.vector(value))
+    }
+
+    /// Read raw gyroscope data, with Q point `GYRO_Q`
+    pub fn gyro_raw(&self) -> [i16; 3] {
+        self.latest.gyro
+    }
+
+    /// The most recent accelerometer report (m/s^2), if any was received.
+    /// Each of the `latest_` getters keeps its own sample, so that any
+    /// subset of the enabled reports can be polled at its own rate.
+    pub fn latest_accel(&self) -> Option<Sample<[f32; 3]>> {
+        self.latest_vector(SensorReportId::Accelerometer.into(), q8_to_f32)
+    }
+
+    /// The most recent linear acceleration report (m/s^2)
+    pub fn latest_linear_accel(&self) -> Option<Sample<[f32; 3]>> {
+        self.latest_vector(SensorReportId::LinearAcceleration.into(), q8_to_f32)
+    }
+
+    /// The most recent gravity report (m/s^2)
+    pub fn latest_gravity(&self) -> Option<Sample<[f32; 3]>> {
+        self.latest_vector(SensorReportId::Gravity.into(), q8_to_f32)
+    }
+
+    /// The most recent uncalibrated gyroscope report (rad/s), as enabled
+    /// by `enable_gyro`
+    pub fn latest_gyro(&self) -> Option<Sample<[f32; 3]>> {
+        self.latest_vector(
+            SensorReportId::GyroscopeUncalibrated.into(),
+            q9_to_f32,
+        )
+    }
+
+    /// The most recent calibrated gyroscope report (rad/s), as enabled
+    /// by `enable_ahrs` and used in `ahrs_sample`
+    pub fn latest_calibrated_gyro(&self) -> Option<Sample<[f32; 3]>> {
+        self.latest_vector(
+            SensorReportId::GyroscopeCalibrated.into(),
+            q9_to_f32,
+        )
+    }
+
+    /// The most recent gyroscope bias (zero-rate offset) estimate (rad/s),
+    /// from the uncalibrated gyroscope report
+    pub fn gyro_bias(&self) -> Option<Sample<[f32; 3]>> {
+        let sample = self.latest.gyro_bias.as_ref()?;
+        Some(
+            sample.map(|[x, y, z, _]| {
+                self.frame.vector([x, y, z].map(q9_to_f32))
+            }),
+        )
+    }
+
+    /// The most recent raw gyroscope bias estimate, with Q point `GYRO_Q`
+    pub fn gyro_bias_raw(&self) -> Option<Sample<[i16; 3]>> {
+        let sample = self.latest.gyro_bias.as_ref()?;
+        Some(sample.map(|[x, y, z, _]| [x, y, z]))
+    }
+
+    /// The most recent calibrated magnetic field report (uTesla)
+    pub fn latest_mag(&self) -> Option<Sample<[f32; 3]>> {
+        self.latest_vector(SensorReportId::MagneticField.into(), q4_to_f32)
+    }
+
+    /// The most recent rotation vector report
+    pub fn latest_rotation_vector(&self) -> Option<Sample<Quaternion>> {
+        self.latest_quaternion(SensorReportId::RotationVector.into())
+    }
+
+    /// The most recent game rotation vector report
+    pub fn latest_game_rotation_vector(&self) -> Option<Sample<Quaternion>> {
+        self.latest_quaternion(SensorReportId::GameRotationVector.into())
+    }
+
+    /// The most recent rotation vector, calibrated gyroscope and linear
+    /// acceleration reports combined, once each has been received.
+    /// The angular rate is that of the calibrated gyroscope report
+    /// (`latest_calibrated_gyro`), not of the uncalibrated one read by
+    /// `gyro` and `latest_gyro`.
+    pub fn ahrs_sample(&self) -> Option<AhrsSample> {
+        let orientation = self.latest_rotation_vector()?;
+        let angular_rate = self.latest_calibrated_gyro()?;
+        let linear_accel = self.latest_linear_accel()?;
+        let timestamps = [
+            orientation.timestamp_us,
+            angular_rate.timestamp_us,
+            linear_accel.timestamp_us,
+        ];
+        let earliest = timestamps.iter().min().copied().unwrap_or(0);
+        let latest = timestamps.iter().max().copied().unwrap_or(0);
+        Some(AhrsSample {
+            orientation: orientation.value,
+            angular_rate: angular_rate.value,
+            linear_accel: linear_accel.value,
+            accuracy: orientation.accuracy,
+            timestamp_us: orientation.timestamp_us,
+            skew_us: latest.abs_diff(earliest),
+        })
+    }
+
+    /// The most recent pressure report from an external barometer
+    /// (hectopascals)
+    pub fn latest_pressure(&self) -> Option<Sample<f32>> {
+        self.latest_u32(SensorReportId::Pressure.into(), q20_to_f32)
+    }
+
+    /// The most recent ambient light report from an external sensor (lux)
+    pub fn latest_ambient_light(&self) -> Option<Sample<f32>> {
+        self.latest_u32(SensorReportId::AmbientLight.into(), q8_u32_to_f32)
+    }
+
+    /// The most recent humidity report from an external sensor (percent)
+    pub fn latest_humidity(&self) -> Option<Sample<f32>> {
+        let sample = self.latest.sample(SensorReportId::Humidity.into())?;
+        Some(sample.map(|[value, ..]| q8_to_f32(value)))
+    }
+
+    /// The most recent proximity report from an external sensor
+    /// (centimeters)
+    pub fn latest_proximity(&self) -> Option<Sample<f32>> {
+        let sample = self.latest.sample(SensorReportId::Proximity.into())?;
+        Some(sample.map(|[value, ..]| q4_to_f32(value)))
+    }
+
+    /// The most recent temperature report (degrees Celsius)
+    pub fn latest_temperature(&self) -> Option<Sample<f32>> {
+        let sample = self.latest.sample(SensorReportId::Temperature.into())?;
+        Some(sample.map(|[value, ..]| q7_to_f32(value)))
+    }
+
+    /// The most recent heart rate monitor report (beats per minute)
+    pub fn latest_heart_rate(&self) -> Option<Sample<u16>> {
+        let sample = self.latest.heart_rate.as_ref()?;
+        Some(sample.map(|[value, ..]| value as u16))
+    }
+
+    /// A report whose value is 32 bit, kept as two 16 bit values
+    fn latest_u32(
+        &self,
+        report_id: u8,
+        convert: fn(u32) -> f32,
+    ) -> Option<Sample<f32>> {
+        let sample = self.latest.sample(report_id)?;
+        Some(sample.map(|[low, high, ..]| {
+            convert(u32::from(low as u16) | (u32::from(high as u16) << 16))
+        }))
+    }
+
+    fn latest_vector(
+        &self,
+        report_id: u8,
+        convert: fn(i16) -> f32,
+    ) -> Option<Sample<[f32; 3]>> {
+        let sample = self.latest.sample(report_id)?;
+        let smoothed = self.smoothed(report_id);
+        Some(sample.map(|[x, y, z, _]| {
+            self.frame
+                .vector(smoothed.unwrap_or([x, y, z].map(convert)))
+        }))
+    }
+
+    fn latest_quaternion(&self, report_id: u8) -> Option<Sample<Quaternion>> {
+        let sample = self.latest.sample(report_id)?;
+        Some(sample.map(|values| {
+            self.frame
+                .quaternion(Quaternion::from(values.map(q14_to_f32)))
+        }))
+    }
+
+    /// Accuracy status of the most recent calibrated accelerometer,
+    /// gyroscope and magnetometer reports
+    pub fn calibration_status(&self) -> CalibrationStatus {
+        self.latest.calibration
+    }
+
+    /// Guided calibration, following the Hillcrest calibration procedure:
+    /// enables dynamic calibration of all three sensors, enables their
+    /// calibrated reports, and waits for all of them to reach high accuracy
+    /// while the device is moved through the calibration motions.
+    /// Then saves the dynamic calibration data (DCD) to flash.
+    /// Whether it succeeds or not, the calibrated reports and the dynamic
+    /// calibration are then configured as they were before.
+    /// `timeout_ms` limits the time spent waiting for reports;
+    /// time spent reading them is not counted, unless a clock is set.
+    pub fn run_calibration(
+        &mut self,
+        delay: &mut impl DelayNs,
+        timeout_ms: u32,
+    ) -> Result<(), WrapperError<SE>> {
+        debug!("calibration start");
+        let previous_calibration = self.me_calibration(delay)?;
+        let previous_reports = CALIBRATED_REPORT_IDS.map(|report_id| {
+            let report_id = u8::from(report_id);
+            let config = self
+                .report_configs
+                .iter()
+                .find(|(id, _)| *id == report_id)
+                .map(|(_, config)| *config);
+            (report_id, config)
+        });
+        // enable accel, gyro and mag calibration, leaving the planar
+        // accelerometer and on-table modes as they were
+        let [_, _, _, planar, on_table] = previous_calibration;
+        self.configure_me_calibration([1, 1, 1, planar, on_table], delay)?;
+
+        let result = self.wait_for_calibration(delay, timeout_ms);
+        let mut restored = Ok(());
+        for (report_id, config) in previous_reports {
+            let config = config.unwrap_or_default();
+            restored = restored.and(self.configure_report(report_id, &config));
+        }
+        let result = result.and(restored).and_then(|()| self.save_dcd(delay));
+        let restored =
+            self.configure_me_calibration(previous_calibration, delay);
+        result.and(restored)
+    }
+
+    /// Enable the calibrated reports, and wait for all of them to reach
+    /// high accuracy
+    fn wait_for_calibration(
+        &mut self,
+        delay: &mut impl DelayNs,
+        timeout_ms: u32,
+    ) -> Result<(), WrapperError<SE>> {
+        for report_id in CALIBRATED_REPORT_IDS {
+            self.enable_report(report_id, CALIBRATION_REPORT_INTERVAL_MS)?;
+        }
+
+        self.latest.calibration = CalibrationStatus::default();
+        let mut stopwatch = Stopwatch::start(self.clock);
+        while !self.latest.calibration.is_complete() {
+            if stopwatch.elapsed_ms() >= timeout_ms {
+                return Err(WrapperError::Timeout);
+            }
+            if self.handle_one_message(delay, 1) == 0 {
+                delay.delay_ms(CALIBRATION_REPORT_INTERVAL_MS as u32);
+                stopwatch.count(CALIBRATION_REPORT_INTERVAL_MS as u32);
+            }
+        }
+        debug!("calibration complete");
+        Ok(())
+    }
+
+    /// Which of the accelerometer, gyroscope and magnetometer are
+    /// dynamically calibrated, and whether the planar accelerometer and
+    /// on-table calibration modes are on, as flags (nonzero if enabled)
+    fn me_calibration(
+        &mut self,
+        delay: &mut impl DelayNs,
+    ) -> Result<[u8; 5], WrapperError<SE>> {
+        self.send_command(SH2_CMD_ME_CALIBRATE, &[0, 0, 0, SH2_ME_CAL_GET])?;
+        let response =
+            self.checked_command_response(SH2_CMD_ME_CALIBRATE, delay)?;
+        Ok(zero_padded(&response.values[1..]))
+    }
+
+    /// Configure the dynamic calibration as given by `enabled`, in the
+    /// order returned by `me_calibration`
+    fn configure_me_calibration(
+        &mut self,
+        enabled: [u8; 5],
+        delay: &mut impl DelayNs,
+    ) -> Result<(), WrapperError<SE>> {
+        let [accel, gyro, mag, planar, on_table] = enabled;
+        self.send_command(
+            SH2_CMD_ME_CALIBRATE,
+            &[accel, gyro, mag, SH2_ME_CAL_CONFIG, planar, on_table],
+        )?;
+        self.check_command_response(SH2_CMD_ME_CALIBRATE, delay)
+    }
+
+    /// Wait until the gyroscope bias (zero-rate offset) estimate has
+    /// settled, as before arming a drone: until it has changed by no more
+    /// than `tolerance` (rad/s) on any axis over
+    /// `GYRO_BIAS_SETTLED_REPORTS` consecutive uncalibrated gyroscope
+    /// reports, which must have been enabled. Keep the device still while
+    /// waiting. Returns the settled bias.
+    /// `timeout_ms` limits the time spent waiting for reports;
+    /// time spent reading them is not counted, unless a clock is set.
+    pub fn wait_for_gyro_bias_settled(
+        &mut self,
+        tolerance: f32,
+        timeout_ms: u32,
+        delay: &mut impl DelayNs,
+    ) -> Result<[f32; 3], WrapperError<SE>> {
+        let mut last: Option<Sample<[f32; 3]>> = None;
+        let mut settled_reports: u32 = 0;
+        let mut stopwatch = Stopwatch::start(self.clock);
+        while settled_reports < GYRO_BIAS_SETTLED_REPORTS {
+            if stopwatch.elapsed_ms() >= timeout_ms {
+                return Err(WrapperError::Timeout);
+            }
+            if self.handle_one_message(delay, 1) == 0 {
+                delay.delay_ms(1);
+                stopwatch.count(1);
+                continue;
+            }
+            let Some(bias) = self.gyro_bias() else {
+                continue;
+            };
+            if last.is_some_and(|last| last.sequence == bias.sequence) {
+                // not a new report
+                continue;
+            }
+            let stable = last.is_some_and(|last| {
+                bias.value
+                    .iter()
+                    .zip(last.value)
+                    .all(|(value, last)| (value - last).abs() <= tolerance)
+            });
+            settled_reports = if stable { settled_reports + 1 } else { 0 };
+            last = Some(bias);
+        }
+        debug!("gyro bias settled");
+        Ok(last.map(|bias| bias.value).unwrap_or_default())
+    }
+
+    /// Save the dynamic calibration data (DCD) to the sensor hub's flash now
+    pub fn save_dcd(
+        &mut self,
+        delay: &mut impl DelayNs,
+    ) -> Result<(), WrapperError<SE>> {
+        self.send_command(SH2_CMD_SAVE_DCD, &[])?;
+        self.check_command_response(SH2_CMD_SAVE_DCD, delay)
+    }
+
+    /// Enable or disable the sensor hub's periodic saving of dynamic
+    /// calibration data (DCD) to flash. The flash writes can delay reports,
+    /// so applications sensitive to latency spikes may disable them and
+    /// persist calibration explicitly with `save_dcd`.
+    pub fn set_periodic_dcd_save(
+        &mut self,
+        enabled: bool,
+        delay: &mut impl DelayNs,
+    ) -> Result<(), WrapperError<SE>> {
+        // P0: 0 enables, 1 disables
+        let disable = if enabled { 0 } else { 1 };
+        self.send_command(SH2_CMD_PERIODIC_DCD_SAVE, &[disable])?;
+        self.check_command_response(SH2_CMD_PERIODIC_DCD_SAVE, delay)
+    }
+
+    /// Clear the dynamic calibration data (DCD) in RAM and reset the
+    /// sensor hub, which then starts calibrating from the saved
+    /// calibration, or from scratch after `restore_nominal_calibration`.
+    /// Use it to recover when corrupted calibration keeps the orientation
+    /// off. Waits for the reset as `soft_reset_and_wait`, and all reports
+    /// must then be enabled again.
+    pub fn clear_calibration_and_reset(
+        &mut self,
+        timeout_ms: u32,
+        delay: &mut impl DelayNs,
+    ) -> Result<(), WrapperError<SE>> {
+        // the hub resets without responding to the command
+        self.reset_and_wait(timeout_ms, delay, |hub| {
+            hub.send_command(SH2_CMD_CLEAR_DCD_AND_RESET, &[])
+        })
+    }
+
+    /// Read the flash record (FRS) of type `frs_type` into `words`,
+    /// returning the number of words read: zero if the record is empty.
+    /// Fails with `BufferTooSmall` if the record is longer than `words`.
+    pub fn read_frs(
+        &mut self,
+        frs_type: u16,
+        words: &mut [u32],
+        delay: &mut impl DelayNs,
+    ) -> Result<usize, WrapperError<SE>> {
+        let [type_lsb, type_msb] = frs_type.to_le_bytes();
+        // read from offset zero, the whole record (block size zero)
+        let request = [
+            ControlReportId::FrsReadRequest.into(),
+            0,
+            0,
+            0,
+            type_lsb,
+            type_msb,
+            0,
+            0,
+        ];
+        self.last_frs_read = None;
+        self.send_packet(self.channels.control, &request)?;
+
+        let mut len: usize = 0;
+        loop {
+            let response = self.wait_for_frs_read(delay)?;
+            if response.frs_type != frs_type {
+                continue;
+            }
+            let status = response.read_status();
+            if let FrsReadStatus::Failed(status) = status {
+                return Err(WrapperError::FrsError(status));
+            }
+            if status == FrsReadStatus::Empty {
+                return Ok(0);
+            }
+            let data_len = (response.data_len as usize).min(2);
+            for (i, value) in response.data.iter().take(data_len).enumerate() {
+                let index = response.offset as usize + i;
+                *words.get_mut(index).ok_or(WrapperError::BufferTooSmall)? =
+                    *value;
+                len = len.max(index + 1);
+            }
+            if status == FrsReadStatus::Completed {
+                return Ok(len);
+            }
+        }
+    }
+
+    /// Write `words` as the flash record (FRS) of type `frs_type`,
+    /// replacing the record. Writing no words erases the record.
+    pub fn write_frs(
+        &mut self,
+        frs_type: u16,
+        words: &[u32],
+        delay: &mut impl DelayNs,
+    ) -> Result<(), WrapperError<SE>> {
+        let [len_lsb, len_msb] = (words.len() as u16).to_le_bytes();
+        let [type_lsb, type_msb] = frs_type.to_le_bytes();
+        let request = [
+            ControlReportId::FrsWriteRequest.into(),
+            0,
+            len_lsb,
+            len_msb,
+            type_lsb,
+            type_msb,
+        ];
+        let response = self.send_frs_write(&request, delay)?;
+        match response.status {
+            FRS_WRITE_STATUS_READY => {}
+            FRS_WRITE_STATUS_COMPLETED if words.is_empty() => return Ok(()),
+            status => return Err(WrapperError::FrsError(status)),
+        }
+
+        // two words per write data request
+        for (chunk_index, chunk) in words.chunks(2).enumerate() {
+            let [offset_lsb, offset_msb] =
+                ((chunk_index * 2) as u16).to_le_bytes();
+            let mut request = [0u8; 12];
+            request[0] = ControlReportId::FrsWriteData.into();
+            request[2] = offset_lsb;
+            request[3] = offset_msb;
+            for (slot, word) in request[4..].chunks_exact_mut(4).zip(chunk) {
+                slot.copy_from_slice(&word.to_le_bytes());
+            }
+            let response = self.send_frs_write(&request, delay)?;
+            match response.status {
+                FRS_WRITE_STATUS_RECEIVED | FRS_WRITE_STATUS_RECORD_VALID => {}
+                FRS_WRITE_STATUS_COMPLETED => return Ok(()),
+                status => return Err(WrapperError::FrsError(status)),
+            }
+        }
+
+        // the hub validates the record before completing the write
+        loop {
+            match self.wait_for_frs_write(delay)?.status {
+                FRS_WRITE_STATUS_RECORD_VALID => {}
+                FRS_WRITE_STATUS_COMPLETED => return Ok(()),
+                status => return Err(WrapperError::FrsError(status)),
+            }
+        }
+    }
+
+    /// Handle incoming messages until an FRS read response arrives
+    fn wait_for_frs_read(
+        &mut self,
+        delay: &mut impl DelayNs,
+    ) -> Result<FrsReadResponse, WrapperError<SE>> {
+        let mut wait = Stopwatch::start(self.clock);
+        while wait.elapsed_ms() < self.timeouts.command_response_ms {
+            if let Some(response) = self.last_frs_read.take() {
+                return Ok(response);
+            }
+            self.poll_command_response(delay, &mut wait);
+        }
+        self.last_frs_read
+            .take()
+            .ok_or(WrapperError::NoDataAvailable)
+    }
+
+    /// Send an FRS write or write data request and wait for its
+    /// response, one request at a time. A request the hub is not ready
+    /// for is sent again after a back-off: one answered busy, or one the
+    /// hub rejected with an error list on the command channel, as when
+    /// its receive buffer was full.
+    fn send_frs_write(
+        &mut self,
+        request: &[u8],
+        delay: &mut impl DelayNs,
+    ) -> Result<FrsWriteResponse, WrapperError<SE>> {
+        let mut backoff_ms = NOT_READY_BACKOFF_MS;
+        let mut attempt: u8 = 1;
+        loop {
+            self.last_frs_write = None;
+            self.error_list_received = false;
+            self.send_packet(self.channels.control, request)?;
+            let result = self.wait_for_frs_write(delay);
+            let not_ready = match &result {
+                Ok(response) => response.status == FRS_WRITE_STATUS_BUSY,
+                Err(_) => self.error_list_received,
+            };
+            // a last busy response fails the write as any other status
+            if !not_ready || attempt >= NOT_READY_MAX_ATTEMPTS {
+                return result;
+            }
+            debug!("hub not ready, write again {}", attempt);
+            count(&mut self.stats.retries);
+            delay.delay_ms(backoff_ms);
+            backoff_ms = backoff_ms.saturating_mul(2);
+            attempt += 1;
+        }
+    }
+
+    /// Handle incoming messages until an FRS write response arrives, or
+    /// the hub reports an error on the command channel
+    fn wait_for_frs_write(
+        &mut self,
+        delay: &mut impl DelayNs,
+    ) -> Result<FrsWriteResponse, WrapperError<SE>> {
+        let mut wait = Stopwatch::start(self.clock);
+        while wait.elapsed_ms() < self.timeouts.command_response_ms {
+            if let Some(response) = self.last_frs_write.take() {
+                return Ok(response);
+            }
+            if self.error_list_received {
+                return Err(WrapperError::NoDataAvailable);
+            }
+            self.poll_command_response(delay, &mut wait);
+        }
+        self.last_frs_write
+            .take()
+            .ok_or(WrapperError::NoDataAvailable)
+    }
+
+    /// Read the static calibration of the accelerometer, gyroscope and
+    /// magnetometer, or `None` if none has been provisioned
+    pub fn static_calibration(
+        &mut self,
+        delay: &mut impl DelayNs,
+    ) -> Result<Option<StaticCalibrationAgm>, WrapperError<SE>> {
+        let mut words = [0u32; STATIC_CALIBRATION_AGM_WORDS];
+        let len =
+            self.read_frs(FRS_STATIC_CALIBRATION_AGM, &mut words, delay)?;
+        Ok(StaticCalibrationAgm::from_words(
+            words.get(..len).unwrap_or_default(),
+        ))
+    }
+
+    /// Provision the static calibration of the accelerometer, gyroscope
+    /// and magnetometer, as during production test.
+    /// It takes effect after the next reset of the sensor hub.
+    pub fn set_static_calibration(
+        &mut self,
+        calibration: &StaticCalibrationAgm,
+        delay: &mut impl DelayNs,
+    ) -> Result<(), WrapperError<SE>> {
+        self.write_frs(
+            FRS_STATIC_CALIBRATION_AGM,
+            &calibration.to_words(),
+            delay,
+        )
+    }
+
+    /// Read the configuration of the gyro-integrated rotation vector,
+    /// or `None` if the hub uses its default
+    pub fn gyro_integrated_rv_config(
+        &mut self,
+        delay: &mut impl DelayNs,
+    ) -> Result<Option<GyroIntegratedRvConfig>, WrapperError<SE>> {
+        let mut words = [0u32; GYRO_INTEGRATED_RV_CONFIG_WORDS];
+        let len =
+            self.read_frs(FRS_GYRO_INTEGRATED_RV_CONFIG, &mut words, delay)?;
+        Ok(GyroIntegratedRvConfig::from_words(
+            words.get(..len).unwrap_or_default(),
+        ))
+    }
+
+    /// Write the configuration of the gyro-integrated rotation vector,
+    /// or erase it with `None` to restore the default.
+    /// It takes effect after the next reset of the sensor hub.
+    pub fn set_gyro_integrated_rv_config(
+        &mut self,
+        config: Option<&GyroIntegratedRvConfig>,
+        delay: &mut impl DelayNs,
+    ) -> Result<(), WrapperError<SE>> {
+        match config {
+            Some(config) => self.write_frs(
+                FRS_GYRO_INTEGRATED_RV_CONFIG,
+                &config.to_words(),
+                delay,
+            ),
+            None => self.write_frs(FRS_GYRO_INTEGRATED_RV_CONFIG, &[], delay),
+        }
+    }
+
+    /// Read the output format selected for the sensor hub's UART: SHTP,
+    /// the default, if the record is empty
+    pub fn uart_output_format(
+        &mut self,
+        delay: &mut impl DelayNs,
+    ) -> Result<UartOutputFormat, WrapperError<SE>> {
+        let mut words = [0u32; 1];
+        let len = self.read_frs(FRS_UART_OUTPUT_FORMAT, &mut words, delay)?;
+        Ok(words
+            .get(..len)
+            .and_then(<[u32]>::first)
+            .map_or(UartOutputFormat::Shtp, |word| {
+                UartOutputFormat::from_word(*word)
+            }))
+    }
+
+    /// Select the output format of the sensor hub's UART, switching it
+    /// between SHTP and UART-RVC during provisioning, or erase the
+    /// selection with `None` to restore the default.
+    /// It takes effect after the next reset of the sensor hub, which then
+    /// no longer speaks SHTP on its UART if RVC was selected.
+    pub fn set_uart_output_format(
+        &mut self,
+        format: Option<UartOutputFormat>,
+        delay: &mut impl DelayNs,
+    ) -> Result<(), WrapperError<SE>> {
+        match format {
+            Some(format) => self.write_frs(
+                FRS_UART_OUTPUT_FORMAT,
+                &[format.to_word()],
+                delay,
+            ),
+            None => self.write_frs(FRS_UART_OUTPUT_FORMAT, &[], delay),
+        }
+    }
+
+    /// Read the serial number of the sensor hub, which identifies the unit
+    /// (e.g. in a per-unit calibration database), or `None` if it has no
+    /// serial number record
+    pub fn serial_number(
+        &mut self,
+        delay: &mut impl DelayNs,
+    ) -> Result<Option<u32>, WrapperError<SE>> {
+        let mut words = [0u32; 1];
+        let len = self.read_frs(FRS_SERIAL_NUMBER, &mut words, delay)?;
+        Ok(if len == 0 { None } else { Some(words[0]) })
+    }
+
+    /// The serial number of the sensor hub as little endian bytes,
+    /// as it is stored in flash
+    pub fn serial_number_bytes(
+        &mut self,
+        delay: &mut impl DelayNs,
+    ) -> Result<Option<[u8; 4]>, WrapperError<SE>> {
+        Ok(self.serial_number(delay)?.map(u32::to_le_bytes))
+    }
+
+    /// Restore the factory default calibration, when the calibration in use
+    /// has gone bad (e.g. the heading is wildly wrong): copies the nominal
+    /// calibration record over the static calibration, or erases the static
+    /// calibration if there is no nominal record.
+    /// It takes effect after the next reset of the sensor hub.
+    pub fn restore_nominal_calibration(
+        &mut self,
+        delay: &mut impl DelayNs,
+    ) -> Result<(), WrapperError<SE>> {
+        let mut words = [0u32; STATIC_CALIBRATION_AGM_WORDS];
+        let len =
+            self.read_frs(FRS_NOMINAL_CALIBRATION_AGM, &mut words, delay)?;
+        self.write_frs(
+            FRS_STATIC_CALIBRATION_AGM,
+            words.get(..len).unwrap_or_default(),
+            delay,
+        )
+    }
+
+    /// Query which oscillator clocks the sensor hub, which determines
+    /// the timing accuracy of reports
+    pub fn oscillator_type(
+        &mut self,
+        delay: &mut impl DelayNs,
+    ) -> Result<OscillatorType, WrapperError<SE>> {
+        self.send_command(SH2_CMD_OSCILLATOR, &[])?;
+        let response =
+            self.wait_for_command_response(SH2_CMD_OSCILLATOR, delay)?;
+        Ok(OscillatorType::from(response.values[0]))
+    }
+
+    /// Read the sample counters of the sensor producing `report_id`
+    pub fn sensor_counts(
+        &mut self,
+        report_id: u8,
+        delay: &mut impl DelayNs,
+    ) -> Result<SensorCounts, WrapperError<SE>> {
+        self.send_command(SH2_CMD_COUNTS, &[SH2_COUNTS_GET, report_id])?;
+        let mut counts = SensorCounts::default();
+        // the counters are delivered in two responses
+        for _ in 0..2 {
+            let response =
+                self.wait_for_command_response(SH2_CMD_COUNTS, delay)?;
+            let values = &response.values;
+            let first = u32::from_le_bytes([
+                values[3], values[4], values[5], values[6],
+            ]);
+            let second = u32::from_le_bytes([
+                values[7], values[8], values[9], values[10],
+            ]);
+            if response.response_sequence == 0 {
+                counts.offered = first;
+                counts.accepted = second;
+            } else {
+                counts.on = first;
+                counts.attempted = second;
+            }
+        }
+        Ok(counts)
+    }
+
+    /// Reset the sample counters of the sensor producing `report_id`
+    pub fn clear_sensor_counts(
+        &mut self,
+        report_id: u8,
+    ) -> Result<(), WrapperError<SE>> {
+        self.send_command(SH2_CMD_COUNTS, &[SH2_COUNTS_CLEAR, report_id])
+    }
+
+    /// Tell the hub's interactive calibration what motion to expect,
+    /// for example that a drone is stationary before takeoff
+    pub fn set_motion_intent(
+        &mut self,
+        intent: MotionIntent,
+        delay: &mut impl DelayNs,
+    ) -> Result<(), WrapperError<SE>> {
+        self.send_command(SH2_CMD_INTERACTIVE_CALIBRATION, &[intent as u8])?;
+        self.check_command_response(SH2_CMD_INTERACTIVE_CALIBRATION, delay)
+    }
+
+    /// Inject a wheel encoder measurement for on-chip dead reckoning.
+    /// Only firmware with dead reckoning support (such as that of the
+    /// BNO086) uses these measurements; other firmware ignores them.
+    pub fn report_wheel_encoder(
+        &mut self,
+        sample: &WheelEncoderSample,
+    ) -> Result<(), WrapperError<SE>> {
+        let timestamp = sample.timestamp_us.to_le_bytes();
+        let data = sample.data.to_le_bytes();
+        let body: [u8; 9] = [
+            SensorReportId::WheelEncoder.into(),
+            sample.wheel_index,
+            timestamp[0],
+            timestamp[1],
+            timestamp[2],
+            timestamp[3],
+            data[0],
+            data[1],
+            sample.data_type as u8,
+        ];
+        self.send_packet(self.channels.control, &body)?;
+        Ok(())
+    }
+
+    /// Tare the given axes now, so that the current orientation of the
+    /// basis rotation vector becomes the reference orientation.
+    /// The tare is lost when the hub resets, unless made persistent
+    /// with `persist_tare`.
+    pub fn tare_now(
+        &mut self,
+        axes: TareAxes,
+        basis: TareBasis,
+    ) -> Result<(), WrapperError<SE>> {
+        self.send_command(SH2_CMD_TARE, &[SH2_TARE_NOW, axes.0, basis as u8])
+    }
+
+    /// Zero the heading: tare only the Z axis, using the rotation vector
+    /// as basis. This is the usual tare for ground robots.
+    pub fn tare_z_axis_now(&mut self) -> Result<(), WrapperError<SE>> {
+        self.tare_now(TareAxes::Z, TareBasis::RotationVector)
+    }
+
+    /// Save the current tare to flash, so that it survives a reset
+    pub fn persist_tare(&mut self) -> Result<(), WrapperError<SE>> {
+        self.send_command(SH2_CMD_TARE, &[SH2_TARE_PERSIST])
+    }
+
+    /// Apply a reorientation of the sensor axes immediately, without
+    /// writing the System Orientation record to flash.
+    /// The setting is lost when the hub resets.
+    pub fn set_reorientation(
+        &mut self,
+        orientation: Quaternion,
+    ) -> Result<(), WrapperError<SE>> {
+        let mut params = [0u8; 9];
+        params[0] = SH2_TARE_SET_REORIENTATION;
+        let components = [
+            orientation.i,
+            orientation.j,
+            orientation.k,
+            orientation.real,
+        ];
+        for (chunk, component) in params[1..].chunks_mut(2).zip(components) {
+            chunk.copy_from_slice(&f32_to_q14(component).to_le_bytes());
+        }
+        self.send_command(SH2_CMD_TARE, &params)
+    }
+
+    /// Send an SH-2 command request, with up to nine parameter bytes
+    fn send_command(
+        &mut self,
+        command: u8,
+        params: &[u8],
+    ) -> Result<(), WrapperError<SE>> {
+        let mut cmd_body = [0u8; 12];
+        cmd_body[0] = ControlReportId::CommandRequest.into();
+        cmd_body[1] = self.command_sequence;
+        cmd_body[2] = command;
+        for (slot, param) in cmd_body[3..].iter_mut().zip(params) {
+            *slot = *param;
+        }
+        if let (Some(slot), Some(request_sequence)) = (
+            self.command_responses.get_mut(command as usize),
+            self.command_request_sequences.get_mut(command as usize),
+        ) {
+            *slot = None;
+            *request_sequence = self.command_sequence;
+        }
+        self.command_sequence = self.command_sequence.wrapping_add(1);
+        self.last_command_response = None;
+        self.send_packet(self.channels.control, &cmd_body)?;
+        Ok(())
+    }
+
+    /// Handle incoming messages until a response to `command` arrives
+    fn wait_for_command_response(
+        &mut self,
+        command: u8,
+        delay: &mut impl DelayNs,
+    ) -> Result<CommandResponse, WrapperError<SE>> {
+        let mut wait = Stopwatch::start(self.clock);
+        while wait.elapsed_ms() < self.timeouts.command_response_ms {
+            if let Some(response) = self.take_command_response(command) {
+                return Ok(response);
+            }
+            self.poll_command_response(delay, &mut wait);
+        }
+        if let Some(response) = self.take_command_response(command) {
+            return Ok(response);
+        }
+        match self.last_command_response {
+            Some(response) => {
+                Err(WrapperError::UnexpectedReport(response.command))
+            }
+            None => Err(WrapperError::NoDataAvailable),
+        }
+    }
+
+    /// Handle one message while waiting for a response
+    fn poll_command_response(
+        &mut self,
+        delay: &mut impl DelayNs,
+        wait: &mut Stopwatch,
+    ) {
+        self.handle_one_message(delay, COMMAND_RESPONSE_READ_MS as u8);
+        wait.count(COMMAND_RESPONSE_READ_MS);
+    }
+
+    /// Take the response to the latest request of `command`, if it has
+    /// arrived. Responses carrying the command sequence number of an
+    /// earlier request are discarded.
+    fn take_command_response(
+        &mut self,
+        command: u8,
+    ) -> Option<CommandResponse> {
+        let response =
+            self.command_responses.get_mut(command as usize)?.take()?;
+        if self.command_request_sequences.get(command as usize)
+            != Some(&response.command_sequence)
+        {
+            debug!("stale response to command {}", command);
+            return None;
+        }
+        Some(response)
+    }
+
+    /// The most recent response to an SH-2 command, of any command
+    pub fn last_command_response(&self) -> Option<CommandResponse> {
+        self.last_command_response
+    }
+
+    /// Report the first gap in the sequence numbers of received packets
+    /// since the previous call, which shows that packets were lost.
+    /// A packet the hub sent in several transfers may also show as a gap.
+    pub fn check_sequence(&mut self) -> Result<(), WrapperError<SE>> {
+        match self.sequence_gap.take() {
+            Some((channel, expected, received)) => {
+                Err(WrapperError::SequenceGap {
+                    channel,
+                    expected,
+                    received,
+                })
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Wait for the response to `command`, and check its status (R0).
+    /// A refusal is kept for `last_hub_error`.
+    fn check_command_response(
+        &mut self,
+        command: u8,
+        delay: &mut impl DelayNs,
+    ) -> Result<(), WrapperError<SE>> {
+        self.checked_command_response(command, delay).map(|_| ())
+    }
+
+    /// The response to `command`, as `check_command_response`
+    fn checked_command_response(
+        &mut self,
+        command: u8,
+        delay: &mut impl DelayNs,
+    ) -> Result<CommandResponse, WrapperError<SE>> {
+        let response = self.wait_for_command_response(command, delay)?;
+        match HubCommandError::from_status(command, response.status()) {
+            None => Ok(response),
+            Some(error) => {
+                debug!("command 0x{:x} refused", command);
+                self.last_hub_error = Some(error);
+                Err(WrapperError::CommandFailed(error))
+            }
+        }
+    }
+
+    /// The most recent command the sensor hub refused, which failed with
+    /// `WrapperError::CommandFailed`. Other errors are faults of the
+    /// driver or the bus, and leave this unchanged.
+    pub fn last_hub_error(&self) -> Option<HubCommandError> {
+        self.last_hub_error
+    }
+
+    /// Tell the sensor to reset.
+    /// Normally applications should not need to call this directly,
+    /// as it is called during `init`.
+    pub fn soft_reset(&mut self) -> Result<(), WrapperError<SE>> {
+        // #[cfg(feature = "rttdebug")]
+        // rprintln!("soft_reset");
+        debug!("soft reset");
+        let data: [u8; 1] = [ExecutableCommand::Reset.into()];
+        // send command packet and ignore received packets
+        let received_len = self
+            .send_and_receive_packet(self.channels.executable, data.as_ref())?;
+        if received_len > 0 {
+            self.handle_received_packet(received_len)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reset the sensor hub, as `soft_reset`, then wait until it reports
+    /// that the reset completed and advertises itself again, or fail with
+    /// `WrapperError::Timeout` if that takes longer than `timeout_ms`.
+    /// All reports must then be enabled again.
+    pub fn soft_reset_and_wait(
+        &mut self,
+        timeout_ms: u32,
+        delay: &mut impl DelayNs,
+    ) -> Result<(), WrapperError<SE>> {
+        self.reset_and_wait(timeout_ms, delay, Self::soft_reset)
+    }
+
+    /// Make the sensor hub reset with `reset`, then wait for the reset
+    /// as `soft_reset_and_wait`
+    fn reset_and_wait(
+        &mut self,
+        timeout_ms: u32,
+        delay: &mut impl DelayNs,
+        reset: impl FnOnce(&mut Self) -> Result<(), WrapperError<SE>>,
+    ) -> Result<(), WrapperError<SE>> {
+        self.device_reset = false;
+        self.advert_received = false;
+        // the hub starts over with its sequence numbers
+        self.recv_sequence_numbers = [None; MAX_CHANNELS];
+        reset(self)?;
+        let mut stopwatch = Stopwatch::start(self.clock);
+        while !(self.device_reset && self.advert_received) {
+            let elapsed_ms = stopwatch.elapsed_ms();
+            if elapsed_ms >= timeout_ms {
+                return Err(WrapperError::Timeout);
+            }
+            let wait_ms = DRAIN_READ_TIMEOUT_MS.min(timeout_ms - elapsed_ms);
+            if self.handle_one_message(delay, wait_ms as u8) == 0 {
+                stopwatch.count(wait_ms);
+            }
+        }
+        debug!("reset verified");
+        Ok(())
+    }
+
+    /// Watchdog for unattended operation: waits for the next input
+    /// report, and if none arrives within `WATCHDOG_STALL_INTERVALS` of
+    /// the longest interval of the enabled reports, resets and
+    /// reinitializes the sensor hub and enables the same reports again.
+    /// Returns whether it recovered; recoveries are counted in
+    /// `CommStats::watchdog_recoveries`. Reports taken before the call
+    /// do not count, so call it in place of handling messages, and not
+    /// while the hub sleeps.
+    pub fn ensure_reports_alive(
+        &mut self,
+        delay: &mut impl DelayNs,
+    ) -> Result<bool, WrapperError<SE>> {
+        let Some(longest_us) = self
+            .report_configs
+            .iter()
+            .map(|(_, config)| {
+                config.report_interval_us.max(config.batch_interval_us)
+            })
+            .max()
+        else {
+            return Ok(false);
+        };
+        let stall_ms = longest_us
+            .div_ceil(1000)
+            .saturating_mul(WATCHDOG_STALL_INTERVALS)
+            .max(1);
+        let report_count = self.input_report_count;
+        let mut stopwatch = Stopwatch::start(self.clock);
+        while self.input_report_count == report_count {
+            let elapsed_ms = stopwatch.elapsed_ms();
+            if elapsed_ms >= stall_ms {
+                debug!("reports stalled, reinitializing");
+                let report_configs = self.report_configs.clone();
+                if let Err(e) = self.init(delay) {
+                    // keep the reports to restore for the next attempt
+                    self.report_configs = report_configs;
+                    return Err(e);
+                }
+                for (report_id, config) in &report_configs {
+                    self.configure_report(*report_id, config)?;
+                }
+                count(&mut self.stats.watchdog_recoveries);
+                return Ok(true);
+            }
+            let wait_ms = DRAIN_READ_TIMEOUT_MS.min(stall_ms - elapsed_ms);
+            if self.handle_one_message(delay, wait_ms as u8) == 0 {
+                stopwatch.count(wait_ms);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Put the sensor hub to sleep: only sensors configured as
+    /// `always_on` keep running
+    pub fn sleep(&mut self) -> Result<(), WrapperError<SE>> {
+        self.send_packet(
+            self.channels.executable,
+            &[ExecutableCommand::Sleep.into()],
+        )?;
+        Ok(())
+    }
+
+    /// Wake the sensor hub from sleep, resuming all enabled sensors
+    pub fn wake(&mut self) -> Result<(), WrapperError<SE>> {
+        self.send_packet(
+            self.channels.executable,
+            &[ExecutableCommand::On.into()],
+        )?;
+        Ok(())
+    }
+
+    /// Send a packet and receive the response
+    fn send_and_receive_packet(
+        &mut self,
+        channel: u8,
+        body_data: &[u8],
+    ) -> Result<usize, WrapperError<SE>> {
+        let send_packet_length = self.prep_send_packet(channel, body_data)?;
+        let packet = self
+            .packet_send_buf
+            .get(..send_packet_length)
+            .unwrap_or_default();
+        trace_sent_packet(packet);
+
+        let recv_packet_length = self
+            .sensor_interface
+            .send_and_receive_packet(packet, &mut self.packet_recv_buf)
+            .map_err(|e| {
+                count(&mut self.stats.comm_errors);
+                WrapperError::CommError(e)
+            })?;
+        if let Some(sent) = self.stats.packets_sent.get_mut(channel as usize) {
+            count(sent);
+        }
+        self.check_received_len(recv_packet_length)?;
+        trace_received_packet(&self.packet_recv_buf, recv_packet_length);
+
+        #[cfg(feature = "rttdebug")]
+        rprintln!("srcv {} {}", send_packet_length, recv_packet_length);
+
+        Ok(recv_packet_length)
+    }
+}
+
+/// Trace the SHTP header fields of a packet about to be sent
+fn trace_sent_packet(packet: &[u8]) {
+    if let &[_, _, channel, sequence, report_id, ..] = packet {
+        trace!(
+            "send chan {} seq {} rid 0x{:x} len {}",
+            channel,
+            sequence,
+            report_id,
+            packet.len()
+        );
+    }
+}
+
+/// Trace the SHTP header fields of a packet received into `recv_buf`
+fn trace_received_packet(recv_buf: &[u8], packet_len: usize) {
+    if let Some(&[_, _, channel, sequence, report_id, ..]) =
+        recv_buf.get(..packet_len)
+    {
+        trace!(
+            "recv chan {} seq {} rid 0x{:x} len {}",
+            channel,
+            sequence,
+            report_id,
+            packet_len
+        );
+    }
+}
+
+#[cfg(test)]
+impl BNO080<I2cInterface<FakeI2cPort>> {
+    /// Packets written to the fake i2c port, for testing porpoises
+    pub(crate) fn sent_packets(&self) -> &FakePacketQueue {
+        &self.sensor_interface.i2c_port().sent_packets
+    }
+}
+
+/// Longest `drain` waits for each packet (milliseconds)
+const DRAIN_READ_TIMEOUT_MS: u32 = 10;
+/// Longest each `poll_init` step waits for a packet (milliseconds)
+pub const INIT_POLL_READ_MS: u32 = 1;
+/// Number of consecutive reports over which the gyroscope bias must stay
+/// within tolerance for `wait_for_gyro_bias_settled`
+pub const GYRO_BIAS_SETTLED_REPORTS: u32 = 10;
+/// The iterator returned by `reports` ends once no packet has arrived
+/// for this long (milliseconds)
+pub const REPORTS_IDLE_TIMEOUT_MS: u32 = 100;
+/// `ensure_reports_alive` recovers once no report has arrived for this
+/// many of the longest report intervals
+pub const WATCHDOG_STALL_INTERVALS: u32 = 4;
+/// Number of report configurations kept for `ensure_reports_alive` to
+/// restore; reports enabled beyond these are not restored
+pub const WATCHDOG_MAX_REPORTS: usize = 16;
+
+const Q4_SCALE: f32 = 1.0 / ((1 << 4) as f32);
+const Q20_SCALE: f32 = 1.0 / ((1 << 20) as f32);
+const Q7_SCALE: f32 = 1.0 / ((1 << 7) as f32);
+const Q8_SCALE: f32 = 1.0 / ((1 << 8) as f32);
+const Q9_SCALE: f32 = 1.0 / ((1 << 9) as f32);
+#[cfg(feature = "report-rotation")]
+const Q10_SCALE: f32 = 1.0 / ((1 << 10) as f32);
+const Q12_SCALE: f32 = 1.0 / ((1 << 12) as f32);
+const Q14_SCALE: f32 = 1.0 / ((1 << 14) as f32);
+
+pub(crate) fn q14_to_f32(q_val: i16) -> f32 {
+    // let qq_val =  fpa::I2F14(q_val).unwrap();
+    // return f32(qq_val)
+    (q_val as f32) * Q14_SCALE
+}
+
+/// Convert to Q point 14, saturating outside of ±2.0
+pub(crate) fn f32_to_q14(val: f32) -> i16 {
+    libm::roundf(val / Q14_SCALE).clamp(i16::MIN as f32, i16::MAX as f32) as i16
+}
+
+pub(crate) fn q12_to_f32(q_val: i16) -> f32 {
+    (q_val as f32) * Q12_SCALE
+}
+
+pub(crate) fn q4_to_f32(q_val: i16) -> f32 {
+    (q_val as f32) * Q4_SCALE
+}
+
+pub(crate) fn q7_to_f32(q_val: i16) -> f32 {
+    (q_val as f32) * Q7_SCALE
+}
+
+pub(crate) fn q8_to_f32(q_val: i16) -> f32 {
+    (q_val as f32) * Q8_SCALE
+}
+
+pub(crate) fn q9_to_f32(q_val: i16) -> f32 {
+    (q_val as f32) * Q9_SCALE
+}
+
+#[cfg(feature = "report-rotation")]
+pub(crate) fn q10_to_f32(q_val: i16) -> f32 {
+    (q_val as f32) * Q10_SCALE
+}
+
+pub(crate) fn q20_to_f32(q_val: u32) -> f32 {
+    (q_val as f32) * Q20_SCALE
+}
+
+pub(crate) fn q8_u32_to_f32(q_val: u32) -> f32 {
+    (q_val as f32) * Q8_SCALE
+}
+
+// Command Channel requests / responses
+
+// Commands
+//const CMD_GET_ADVERTISEMENT: u8 = 0;
+//const CMD_SEND_ERROR_LIST: u8 = 1;
+
+/// Responses
+const CMD_RESP_ADVERTISEMENT: u8 = 0;
+/// Advertisement tags of the largest packet, header included, that the
+/// sensor hub accepts (write) and sends (read)
+const ADVERT_TAG_MAX_CARGO_WRITE: u8 = 0x02;
+const ADVERT_TAG_MAX_CARGO_READ: u8 = 0x03;
+/// Advertisement tags of the channel number (of a normal or wake channel),
+/// application name and channel name
+const ADVERT_TAG_NORMAL_CHANNEL: u8 = 0x06;
+const ADVERT_TAG_WAKE_CHANNEL: u8 = 0x07;
+const ADVERT_TAG_APP_NAME: u8 = 0x08;
+const ADVERT_TAG_CHANNEL_NAME: u8 = 0x09;
+/// Advertisement tag of the SH-2 report length table
+const ADVERT_TAG_SH2_REPORT_LENGTHS: u8 = 0x81;
+const CMD_RESP_ERROR_LIST: u8 = 1;
+
+/// Set Feature flags
+const FEATURE_FLAG_CHANGE_SENSITIVITY_RELATIVE: u8 = 1 << 0;
+const FEATURE_FLAG_CHANGE_SENSITIVITY_ENABLED: u8 = 1 << 1;
+const FEATURE_FLAG_ALWAYS_ON: u8 = 1 << 3;
+// some mysterious responses we sometimes get:
+// 0x78, 0x7C
+
+/// Calibrated accelerometer, gyroscope and magnetometer, whose status
+/// carries the accuracy used to monitor calibration
+const CALIBRATED_REPORT_IDS: [SensorReportId; 3] = [
+    SensorReportId::Accelerometer,
+    SensorReportId::GyroscopeCalibrated,
+    SensorReportId::MagneticField,
+];
+
+/// Firmware part numbers reported in the product ID, as listed (1000-3606,
+/// 1000-4563, 1000-5062) in the vendor's firmware release notes of the
+/// BNO080, the BNO085 and the BNO086
+const BNO080_SW_PART_NUMBER: u32 = 10003606;
+const BNO085_SW_PART_NUMBER: u32 = 10004563;
+const BNO086_SW_PART_NUMBER: u32 = 10005062;
+
+/// Commands and subcommands
+const SH2_INIT_UNSOLICITED: u8 = 0x80;
+const SH2_CMD_COUNTS: u8 = 0x02;
+const SH2_COUNTS_GET: u8 = 0x00;
+const SH2_COUNTS_CLEAR: u8 = 0x01;
+const SH2_CMD_TARE: u8 = 0x03;
+const SH2_TARE_NOW: u8 = 0x00;
+const SH2_TARE_PERSIST: u8 = 0x01;
+const SH2_TARE_SET_REORIENTATION: u8 = 0x02;
+const SH2_CMD_SAVE_DCD: u8 = 0x06;
+const SH2_CMD_ME_CALIBRATE: u8 = 0x07;
+const SH2_ME_CAL_CONFIG: u8 = 0x00;
+const SH2_ME_CAL_GET: u8 = 0x01;
+const SH2_CMD_PERIODIC_DCD_SAVE: u8 = 0x09;
+const SH2_CMD_OSCILLATOR: u8 = 0x0A;
+const SH2_CMD_CLEAR_DCD_AND_RESET: u8 = 0x0B;
+const SH2_CMD_INTERACTIVE_CALIBRATION: u8 = 0x0E;
+const SH2_CMD_INITIALIZE: u8 = 4;
+const SH2_INIT_SYSTEM: u8 = 1;
+const SH2_STARTUP_INIT_UNSOLICITED: u8 =
+    SH2_CMD_INITIALIZE | SH2_INIT_UNSOLICITED;
+
+#[cfg(test)]
+mod tests {
+    // use super::*;
+    extern crate std;
+    use super::{FakeI2cPort, I2cInterface};
+    use crate::frs::{
+        GyroIntegratedRvConfig, GyroRvReference, StaticCalibrationAgm,
+        UartOutputFormat,
+    };
+    use crate::ids::{Channel, SensorReportId};
+    use crate::interface::i2c::{ALTERNATE_ADDRESS, DEFAULT_ADDRESS};
+    use crate::interface::mock_i2c_port::FakeDelay;
+    use crate::interface::{I2cAddress, PACKET_HEADER_LENGTH};
+    use crate::math::Quaternion;
+    use crate::wrapper::{
+        f32_to_q14, q14_to_f32, ActivityClasses, ArenaPackets,
+        ChangeSensitivity, ChannelMap, CommStats, DeviceStatus, FeatureConfig,
+        HubCommandError, InitState, MotionIntent, OscillatorType, RetryPolicy,
+        SensorCounts, SensorVariant, ShtpHeader, TareAxes, TareBasis,
+        WheelDataType, WheelEncoderSample, WrapperError, BNO080, MAX_CHANNELS,
+        NOT_READY_MAX_ATTEMPTS, PACKET_RECV_BUF_LEN, PACKET_SEND_BUF_LEN,
+    };
+    use std::format;
+
+    fn fake_hub() -> BNO080<I2cInterface<FakeI2cPort>> {
+        BNO080::new_with_interface(I2cInterface::new(
+            FakeI2cPort::new(),
+            DEFAULT_ADDRESS,
+        ))
+    }
+
+    #[test]
+    fn test_qval_conversions() {
+        let q_val = f32_to_q14(0.5);
+        let float_val = q14_to_f32(q_val);
+        assert_eq!(float_val, 0.5);
+    }
+
+    #[test]
+    fn test_send_reset() {
+        let mut shub = fake_hub();
+        assert!(shub.soft_reset().is_ok());
+
+        let sent = shub.sent_packets();
+        assert_eq!(sent.len(), 1);
+        // header, then the executable channel reset command
+        assert_eq!(sent[0].as_slice(), [5, 0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn test_send_set_feature() {
+        let mut shub = fake_hub();
+        assert!(shub.enable_rotation_vector(10).is_ok());
+        assert!(shub.enable_gyro(10).is_ok());
+
+        let sent = shub.sent_packets();
+        assert_eq!(sent.len(), 2);
+        let expected: [u8; 21] = [
+            21, 0, 2, 0, // header
+            0xFD, 0x05, // set feature, rotation vector
+            0, 0, 0, // flags, change sensitivity
+            0x10, 0x27, 0, 0, // 10000 us report interval
+            0, 0, 0, 0, // batch interval
+            0, 0, 0, 0, // sensor-specific config
+        ];
+        assert_eq!(sent[0].as_slice(), expected);
+        // sequence number on the control channel increments
+        assert_eq!(sent[1].as_slice()[3], 1);
+        assert_eq!(sent[1].as_slice()[5], 0x07);
+    }
+
+    #[test]
+    fn test_enable_activity_classifier() {
+        let mut shub = fake_hub();
+        let classes = ActivityClasses::WALKING
+            | ActivityClasses::RUNNING
+            | ActivityClasses::ON_STAIRS;
+        assert!(shub.enable_activity_classifier(1000, classes).is_ok());
+
+        let sent = shub.sent_packets()[0].as_slice();
+        assert_eq!(sent[5], 0x1E);
+        // sensor-specific config carries the enable mask
+        assert_eq!(sent[17..21], [0xC0, 0x01, 0, 0]);
+    }
+
+    fn queue_packet(
+        shub: &mut BNO080<I2cInterface<FakeI2cPort>>,
+        packet: &[u8],
+    ) {
+        let port = shub.sensor_interface.i2c_port_mut();
+        // the hub repeats the header when the whole packet is read
+        port.add_available_packet(&packet[..4]);
+        port.add_available_packet(packet);
+    }
+
+    /// Queue the hub's side of the initialization handshake: the
+    /// advertisement, the unsolicited initialization response, and the
+    /// product ID response
+    fn queue_init_handshake(shub: &mut BNO080<I2cInterface<FakeI2cPort>>) {
+        queue_packet(shub, &[5, 0, 0, 0, 0]);
+        queue_packet(shub, &[8, 0, 2, 0, 0xF1, 0, 0x84, 0]);
+        let pid_response: [u8; 20] = [
+            20, 0, 2, 1, 0xF8, 0, 3, 2, 0xFE, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        queue_packet(shub, &pid_response);
+    }
+
+    #[test]
+    fn test_run_calibration() {
+        let mut shub = fake_hub();
+        // only the magnetometer was calibrated so far, in on-table mode
+        let mut get_response = command_response(0x07, 0);
+        get_response[12] = 1;
+        get_response[14] = 1;
+        let mut me_cal_response = command_response(0x07, 0);
+        me_cal_response[7] = 1;
+        let reports: [u8; 39] = [
+            39, 0, 3, 0, // header
+            0xFB, 0, 0, 0, 0, // base timestamp
+            0x01, 0, 0x03, 0, 0, 0, 0, 0, 0, 0, // accelerometer
+            0x02, 0, 0x03, 0, 0, 0, 0, 0, 0, 0, // gyroscope calibrated
+            0x03, 0, 0x03, 0, 0, 0, 0, 0, 0, 0, // mag field calibrated
+        ];
+        let mut dcd_response = command_response(0x06, 0);
+        // responding to the third command request
+        dcd_response[7] = 2;
+        let mut restore_response = command_response(0x07, 0);
+        restore_response[7] = 3;
+        queue_packet(&mut shub, &get_response);
+        queue_packet(&mut shub, &me_cal_response);
+        queue_packet(&mut shub, &reports);
+        queue_packet(&mut shub, &dcd_response);
+        queue_packet(&mut shub, &restore_response);
+
+        assert!(shub.run_calibration(&mut FakeDelay {}, 1000).is_ok());
+        assert!(shub.calibration_status().is_complete());
+
+        let sent = shub.sent_packets();
+        // get and set ME calibration, 3 enables, 3 disables, save DCD,
+        // and the ME calibration set back
+        assert_eq!(sent.len(), 10);
+        assert_eq!(sent[0].as_slice()[4..11], [0xF2, 0, 0x07, 0, 0, 0, 1]);
+        assert_eq!(
+            sent[1].as_slice()[4..13],
+            [0xF2, 1, 0x07, 1, 1, 1, 0, 0, 1]
+        );
+        assert_eq!(sent[5].as_slice()[4..6], [0xFD, 0x01]);
+        assert_eq!(sent[8].as_slice()[4..7], [0xF2, 2, 0x06]);
+        assert_eq!(
+            sent[9].as_slice()[4..13],
+            [0xF2, 3, 0x07, 0, 0, 1, 0, 0, 1]
+        );
+        assert!(!shub.is_report_enabled(0x01));
+    }
+
+    #[test]
+    fn test_command_response_routing() {
+        let mut shub = fake_hub();
+        // a failed response to another command arrives first
+        let mut dcd_response = command_response(0x06, 1);
+        dcd_response[5] = 3;
+        let mut response = command_response(0x09, 0);
+        response[3] = 1;
+        queue_packet(&mut shub, &dcd_response);
+        queue_packet(&mut shub, &response);
+        assert!(shub.set_periodic_dcd_save(false, &mut FakeDelay {}).is_ok());
+        let last = shub.last_command_response().unwrap();
+        assert_eq!(last.command, 0x09);
+        assert_eq!(last.status(), 0);
+        // the earlier response does not answer a later request
+        assert!(matches!(
+            shub.save_dcd(&mut FakeDelay {}),
+            Err(WrapperError::NoDataAvailable)
+        ));
+    }
+
+    #[test]
+    fn test_command_sequence_matching() {
+        let mut shub = fake_hub();
+        let mut response = command_response(0x06, 0);
+        queue_packet(&mut shub, &response);
+        assert!(shub.save_dcd(&mut FakeDelay {}).is_ok());
+
+        // a late duplicate of the response to the first request, then
+        // the failed response to the second
+        response[9] = 1;
+        queue_packet(&mut shub, &response);
+        response[3] = 2;
+        response[7] = 1;
+        queue_packet(&mut shub, &response);
+        assert!(matches!(
+            shub.save_dcd(&mut FakeDelay {}),
+            Err(WrapperError::CommandFailed(
+                HubCommandError::DcdSaveFailed { status: 1 }
+            ))
+        ));
+        assert_eq!(
+            shub.last_hub_error(),
+            Some(HubCommandError::DcdSaveFailed { status: 1 })
+        );
+        let sent = shub.sent_packets();
+        assert_eq!(sent[1].as_slice()[4..7], [0xF2, 1, 0x06]);
+    }
+
+    #[test]
+    fn test_periodic_dcd_save() {
+        let mut shub = fake_hub();
+        let mut response = command_response(0x09, 0);
+        queue_packet(&mut shub, &response);
+        assert!(shub.set_periodic_dcd_save(false, &mut FakeDelay {}).is_ok());
+        assert_eq!(shub.sent_packets()[0].as_slice()[4..8], [0xF2, 0, 0x09, 1]);
+
+        // a nonzero status is reported as failure
+        response[7] = 1;
+        response[9] = 1;
+        queue_packet(&mut shub, &response);
+        assert!(matches!(
+            shub.set_periodic_dcd_save(true, &mut FakeDelay {}),
+            Err(WrapperError::CommandFailed(
+                HubCommandError::PeriodicDcdSaveFailed { status: 1 }
+            ))
+        ));
+        assert_eq!(
+            shub.last_hub_error(),
+            Some(HubCommandError::PeriodicDcdSaveFailed { status: 1 })
+        );
+        assert_eq!(
+            HubCommandError::from_status(0x0B, 2),
+            Some(HubCommandError::Other {
+                command: 0x0B,
+                status: 2
+            })
+        );
+        assert_eq!(HubCommandError::from_status(0x06, 0), None);
+        let error = HubCommandError::from_status(0x0E, 3).unwrap();
+        assert_eq!(error, HubCommandError::MotionIntentFailed { status: 3 });
+        assert_eq!((error.command(), error.status()), (0x0E, 3));
+        assert_eq!(shub.sent_packets()[1].as_slice()[4..8], [0xF2, 1, 0x09, 0]);
+    }
+
+    #[test]
+    fn test_oscillator_type() {
+        let mut shub = fake_hub();
+        let response = command_response(0x0A, 1);
+        queue_packet(&mut shub, &response);
+        assert_eq!(
+            shub.oscillator_type(&mut FakeDelay {}).unwrap(),
+            OscillatorType::ExternalCrystal
+        );
+        assert_eq!(shub.sent_packets()[0].as_slice()[4..7], [0xF2, 0, 0x0A]);
+    }
+
+    #[test]
+    fn test_sensor_counts() {
+        let mut shub = fake_hub();
+        let mut response = command_response(0x02, 0);
+        response[10] = 0x05; // sensor ID
+        response[12] = 100; // offered
+        response[16] = 90; // accepted
+        queue_packet(&mut shub, &response);
+        response[8] = 1; // response sequence
+        response[12] = 80; // on
+        response[16] = 70; // attempted
+        queue_packet(&mut shub, &response);
+
+        let counts = shub.sensor_counts(0x05, &mut FakeDelay {}).unwrap();
+        assert_eq!(
+            counts,
+            SensorCounts {
+                offered: 100,
+                accepted: 90,
+                on: 80,
+                attempted: 70,
+            }
+        );
+        assert!(shub.clear_sensor_counts(0x05).is_ok());
+        let sent = shub.sent_packets();
+        assert_eq!(sent[0].as_slice()[4..9], [0xF2, 0, 0x02, 0, 0x05]);
+        assert_eq!(sent[1].as_slice()[4..9], [0xF2, 1, 0x02, 1, 0x05]);
+    }
+
+    #[test]
+    fn test_set_motion_intent() {
+        let mut shub = fake_hub();
+        let response = command_response(0x0E, 0);
+        queue_packet(&mut shub, &response);
+        assert!(shub
+            .set_motion_intent(
+                MotionIntent::StationaryNoVibration,
+                &mut FakeDelay {}
+            )
+            .is_ok());
+        assert_eq!(shub.sent_packets()[0].as_slice()[4..8], [0xF2, 0, 0x0E, 1]);
+    }
+
+    #[test]
+    fn test_report_wheel_encoder() {
+        let mut shub = fake_hub();
+        let sample = WheelEncoderSample {
+            wheel_index: 1,
+            timestamp_us: 0x0102_0304,
+            data: -2,
+            data_type: WheelDataType::Velocity,
+        };
+        assert!(shub.report_wheel_encoder(&sample).is_ok());
+        assert_eq!(
+            shub.sent_packets()[0].as_slice(),
+            [13, 0, 2, 0, 0x2E, 1, 4, 3, 2, 1, 0xFE, 0xFF, 1]
+        );
+    }
+
+    #[test]
+    fn test_capabilities_from_advertisement() {
+        let mut shub = fake_hub();
+        assert!(shub.capabilities().is_none());
+        shub.packet_recv_buf[..ADVERTISING_PACKET_FULL.len()]
+            .copy_from_slice(&ADVERTISING_PACKET_FULL);
+        shub.handle_received_packet(ADVERTISING_PACKET_FULL.len())
+            .unwrap();
+
+        let capabilities = shub.capabilities().unwrap();
+        assert!(capabilities.supports_report(0x05));
+        assert!(capabilities.supports_report(0xF8));
+        // advertised with length zero
+        assert!(!capabilities.supports_report(0x17));
+        // not advertised
+        assert!(!capabilities.supports_report(0x30));
+
+        assert!(shub.enable_rotation_vector(10).is_ok());
+        assert!(matches!(
+            shub.enable_report_us(0x30, 10_000),
+            Err(WrapperError::UnsupportedReport(0x30))
+        ));
+    }
+
+    #[test]
+    fn test_read_all_available() {
+        let mut shub = fake_hub();
+        queue_packet(&mut shub, &LINEAR_ACCEL_PACKET);
+        queue_packet(&mut shub, &ROTATION_VECTOR_PACKET);
+        let mut arena = [0u8; 64];
+        let packets: std::vec::Vec<_> =
+            shub.read_all_available(&mut arena).collect();
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].header.channel, 3);
+        assert_eq!(packets[0].payload, &LINEAR_ACCEL_PACKET[4..]);
+        assert_eq!(packets[1].payload, &ROTATION_VECTOR_PACKET[4..]);
+        // the packets are not handled
+        assert!(shub.latest_linear_accel().is_none());
+
+        // the second packet does not fit in the arena
+        queue_packet(&mut shub, &LINEAR_ACCEL_PACKET);
+        queue_packet(&mut shub, &ROTATION_VECTOR_PACKET);
+        let mut arena = [0u8; 32];
+        assert_eq!(shub.read_all_available(&mut arena).count(), 1);
+        assert_eq!(shub.last_packet_payload(), &ROTATION_VECTOR_PACKET[4..]);
+        assert_eq!(shub.read_all_available(&mut arena).count(), 0);
+    }
+
+    #[cfg(feature = "report-accel")]
+    #[test]
+    fn test_owned_packet() {
+        use crate::reports::SensorReport;
+        use crate::wrapper::OwnedPacket;
+        let mut shub = fake_hub();
+        assert!(shub.last_packet().is_none());
+        queue_packet(&mut shub, &LINEAR_ACCEL_PACKET);
+        assert_eq!(shub.handle_one_message(&mut FakeDelay {}, 1), 1);
+        let packet = shub.last_packet().unwrap();
+        assert!(OwnedPacket::<8>::from_packet(&packet).is_none());
+
+        let owned = OwnedPacket::<32>::from_packet(&packet).unwrap();
+        assert_eq!(owned.as_packet(), packet);
+        assert_eq!(owned.header.channel, 3);
+        let reports: std::vec::Vec<_> = owned.reports().collect();
+        assert_eq!(reports.len(), 1);
+        assert!(matches!(reports[0], SensorReport::LinearAccel(_)));
+    }
+
+    #[test]
+    fn test_channels_from_advertisement() {
+        let mut shub = fake_hub();
+        shub.packet_recv_buf[..ADVERTISING_PACKET_FULL.len()]
+            .copy_from_slice(&ADVERTISING_PACKET_FULL);
+        shub.handle_received_packet(ADVERTISING_PACKET_FULL.len())
+            .unwrap();
+        assert_eq!(shub.channels(), ChannelMap::default());
+
+        // firmware reporting sensor input on channel 6
+        let mut advert = std::vec::Vec::from([0, 0, 0, 0, 0x00]);
+        advert.extend_from_slice(b"\x08\x0asensorhub\0\x06\x01\x06");
+        advert.extend_from_slice(b"\x09\x0cinputNormal\0");
+        advert[0] = advert.len() as u8;
+        shub.packet_recv_buf[..advert.len()].copy_from_slice(&advert);
+        shub.handle_received_packet(advert.len()).unwrap();
+        assert_eq!(shub.channels().input_normal, 6);
+        assert!(shub.is_channel_advertised(6));
+        assert!(!shub.is_channel_advertised(3));
+        assert_eq!(shub.channels().control, ChannelMap::default().control);
+
+        let mut packet = LINEAR_ACCEL_PACKET;
+        packet[2] = 6;
+        queue_packet(&mut shub, &packet);
+        shub.handle_all_messages(&mut FakeDelay {}, 1);
+        assert!(shub.latest_linear_accel().is_some());
+    }
+
+    #[test]
+    fn test_variant_detection() {
+        let mut shub = fake_hub();
+        let mut response = [0u8; 20];
+        response[..6].copy_from_slice(&[20, 0, 2, 0, 0xF8, 0]);
+        // BNO080 part number
+        response[8..12].copy_from_slice(&10003606u32.to_le_bytes());
+        shub.packet_recv_buf[..20].copy_from_slice(&response);
+        shub.handle_received_packet(20).unwrap();
+        assert_eq!(shub.variant(), Some(SensorVariant::Bno080));
+        assert!(matches!(
+            shub.enable_report(SensorReportId::ArvrRotationVector, 10),
+            Err(WrapperError::UnsupportedReport(0x28))
+        ));
+
+        let mut shub = fake_hub();
+        response[8..12].copy_from_slice(&10004563u32.to_le_bytes());
+        shub.packet_recv_buf[..20].copy_from_slice(&response);
+        shub.handle_received_packet(20).unwrap();
+        assert_eq!(shub.variant(), Some(SensorVariant::Bno085));
+        assert!(shub
+            .enable_report(SensorReportId::ArvrRotationVector, 10)
+            .is_ok());
+
+        let mut shub = fake_hub();
+        response[8..12].copy_from_slice(&10005062u32.to_le_bytes());
+        shub.packet_recv_buf[..20].copy_from_slice(&response);
+        shub.handle_received_packet(20).unwrap();
+        assert_eq!(shub.variant(), Some(SensorVariant::Bno086));
+        assert!(shub
+            .enable_report(SensorReportId::ArvrRotationVector, 10)
+            .is_ok());
+        assert_eq!(
+            SensorVariant::from_part_number(10003608),
+            SensorVariant::Unknown(10003608)
+        );
+    }
+
+    #[test]
+    fn test_change_sensitivity() {
+        let mut shub = fake_hub();
+        // magnetometer reports only on a change of more than 2 uT (Q point 4)
+        let config = FeatureConfig {
+            report_interval_us: 10_000,
+            change_sensitivity: Some(ChangeSensitivity::Absolute(2 << 4)),
+            ..FeatureConfig::default()
+        };
+        assert!(shub.configure_report(0x03, &config).is_ok());
+        let relative = FeatureConfig {
+            change_sensitivity: Some(ChangeSensitivity::Relative(0x0102)),
+            ..config
+        };
+        assert!(shub.configure_report(0x03, &relative).is_ok());
+
+        let sent = shub.sent_packets();
+        assert_eq!(
+            sent[0].as_slice()[4..11],
+            [0xFD, 0x03, 0x02, 32, 0, 0x10, 0x27]
+        );
+        assert_eq!(sent[1].as_slice()[6..9], [0x03, 0x02, 0x01]);
+    }
+
+    #[test]
+    fn test_always_on_batching() {
+        let mut shub = fake_hub();
+        let config = FeatureConfig {
+            report_interval_us: 10_000,
+            always_on: true,
+            batch_interval_us: 1_000_000,
+            ..FeatureConfig::default()
+        };
+        assert!(shub.configure_report(0x05, &config).is_ok());
+        let sent = shub.sent_packets()[0].as_slice();
+        assert_eq!(sent[6], 0x08);
+        assert_eq!(sent[13..17], 1_000_000u32.to_le_bytes());
+
+        assert!(shub.sleep().is_ok());
+        assert!(shub.wake().is_ok());
+        assert_eq!(shub.sent_packets()[1].as_slice(), [5, 0, 1, 0, 3]);
+        assert_eq!(shub.sent_packets()[2].as_slice(), [5, 0, 1, 1, 2]);
+    }
+
+    #[test]
+    fn test_reset_driver_state() {
+        let mut shub = fake_hub();
+        let mut product_id = [0u8; 20];
+        product_id[..6].copy_from_slice(&[20, 0, 2, 0, 0xF8, 0]);
+        queue_packet(&mut shub, &product_id);
+        assert!(shub.enable_rotation_vector(10).is_ok());
+        assert_eq!(shub.handle_one_message(&mut FakeDelay {}, 1), 1);
+        assert!(shub.product_id().is_some());
+
+        shub.reset_driver_state();
+        assert!(shub.product_id().is_none());
+        assert_eq!(shub.reports_available(), 0);
+        // no reports are enabled for the watchdog to restore
+        assert!(!shub.is_report_enabled(0x05));
+        assert!(!shub.ensure_reports_alive(&mut FakeDelay {}).unwrap());
+        // sequence numbers start over
+        assert!(shub.enable_rotation_vector(10).is_ok());
+        assert_eq!(shub.sent_packets()[1].as_slice()[3], 0);
+    }
+
+    #[test]
+    fn test_disable_all_reports() {
+        let mut shub = fake_hub();
+        assert!(shub.enable_rotation_vector(10).is_ok());
+        assert!(shub.enable_gyro(10).is_ok());
+        assert!(shub.enable_linear_accel(10).is_ok());
+        assert!(shub.disable_report(0x04).is_ok());
+        assert!(!shub.is_report_enabled(0x04));
+        assert!(shub.is_report_enabled(0x05));
+
+        assert!(shub.disable_all_reports().is_ok());
+        assert!(!shub.is_report_enabled(0x05));
+        assert!(!shub.is_report_enabled(0x07));
+        let sent = shub.sent_packets();
+        assert_eq!(sent.len(), 6);
+        // zero report interval, in ascending order of report ID
+        assert_eq!(sent[4].as_slice()[5..13], [0x05, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(sent[5].as_slice()[5], 0x07);
+    }
+
+    #[cfg(feature = "report-rotation")]
+    #[test]
+    fn test_heading_accuracy() {
+        let packet: [u8; 23] = [
+            23, 0, 3, 0, // header
+            0xFB, 0, 0, 0, 0, // base timestamp
+            0x05, 0x01, 0x02, 0x00, // report header, medium accuracy
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x20, 0x00, 0x20, 0x00, 0x04,
+        ];
+        let mut shub = fake_hub();
+        queue_packet(&mut shub, &packet);
+        assert_eq!(shub.handle_one_message(&mut FakeDelay {}, 1), 1);
+        assert_eq!(shub.heading_accuracy(), 0.25);
+        assert_eq!(shub.rotation_vector_status(), 2);
+    }
+
+    #[test]
+    fn test_tare() {
+        let mut shub = fake_hub();
+        assert!(shub.tare_z_axis_now().is_ok());
+        assert!(shub
+            .tare_now(TareAxes::X | TareAxes::Y, TareBasis::GameRotationVector)
+            .is_ok());
+        assert!(shub.persist_tare().is_ok());
+        let sent = shub.sent_packets();
+        assert_eq!(sent[0].as_slice()[4..10], [0xF2, 0, 0x03, 0, 0x04, 0]);
+        assert_eq!(sent[1].as_slice()[4..10], [0xF2, 1, 0x03, 0, 0x03, 1]);
+        assert_eq!(sent[2].as_slice()[4..8], [0xF2, 2, 0x03, 1]);
+    }
+
+    #[test]
+    fn test_set_reorientation() {
+        let mut shub = fake_hub();
+        let orientation = Quaternion::from([0.0, 0.0, -0.5, 0.5]);
+        assert!(shub.set_reorientation(orientation).is_ok());
+        assert_eq!(
+            shub.sent_packets()[0].as_slice()[4..16],
+            [0xF2, 0, 0x03, 0x02, 0, 0, 0, 0, 0x00, 0xE0, 0x00, 0x20]
+        );
+    }
+
+    #[test]
+    fn test_calibration_timeout() {
+        let mut shub = fake_hub();
+        // the accelerometer report was enabled before, at 50 ms
+        shub.enable_report(SensorReportId::Accelerometer, 50)
+            .unwrap();
+        let mut get_response = command_response(0x07, 0);
+        get_response[10..13].copy_from_slice(&[1, 1, 0]);
+        let mut me_cal_response = command_response(0x07, 0);
+        me_cal_response[7] = 1;
+        let mut restore_response = command_response(0x07, 0);
+        restore_response[7] = 2;
+        queue_packet(&mut shub, &get_response);
+        queue_packet(&mut shub, &me_cal_response);
+        queue_packet(&mut shub, &restore_response);
+        assert!(matches!(
+            shub.run_calibration(&mut FakeDelay {}, 100),
+            Err(WrapperError::Timeout)
+        ));
+
+        // the reports and calibration are configured as before
+        let sent = shub.sent_packets();
+        let accel = sent[sent.len() - 4].as_slice();
+        assert_eq!(accel[4..6], [0xFD, 0x01]);
+        assert_eq!(accel[9..13], 50_000u32.to_le_bytes());
+        assert!(shub.is_report_enabled(0x01));
+        assert!(!shub.is_report_enabled(0x02));
+        assert!(!shub.is_report_enabled(0x03));
+        assert_eq!(
+            sent[sent.len() - 1].as_slice()[4..11],
+            [0xF2, 2, 0x07, 1, 1, 0, 0]
+        );
+    }
+
+    #[cfg(all(feature = "report-accel", feature = "report-rotation"))]
+    #[test]
+    fn test_wait_for_report() {
+        use crate::reports::SensorReport;
+        let mut shub = fake_hub();
+        queue_packet(&mut shub, &LINEAR_ACCEL_PACKET);
+        queue_packet(&mut shub, &ROTATION_VECTOR_PACKET);
+        let report = shub.wait_for_report(0x05, 100, &mut FakeDelay {});
+        assert!(matches!(report, Ok(SensorReport::RotationVector { .. })));
+        // the linear acceleration report received first is still queued
+        assert_eq!(
+            shub.next_report(),
+            Some(SensorReport::LinearAccel([1.0, 0.0, 0.0]))
+        );
+
+        assert!(matches!(
+            shub.wait_for_rotation_vector(30, &mut FakeDelay {}),
+            Err(WrapperError::Timeout)
+        ));
+    }
+
+    #[test]
+    fn test_clock_timeout() {
+        use core::sync::atomic::{AtomicU32, Ordering};
+        static NOW_MS: AtomicU32 = AtomicU32::new(u32::MAX - 60);
+        // each reading of the clock advances it by 50 ms, and wraps
+        fn clock() -> u32 {
+            NOW_MS.fetch_add(50, Ordering::Relaxed)
+        }
+
+        let mut shub = fake_hub();
+        assert!(matches!(
+            shub.wait_for_report(0x05, 100, &mut FakeDelay {}),
+            Err(WrapperError::Timeout)
+        ));
+        // counted: ten reads waiting up to 10 ms each
+        assert_eq!(shub.stats().zero_length_reads, 10);
+
+        shub.reset_stats();
+        shub.set_clock(Some(clock));
+        assert!(matches!(
+            shub.wait_for_report(0x05, 100, &mut FakeDelay {}),
+            Err(WrapperError::Timeout)
+        ));
+        assert_eq!(shub.stats().zero_length_reads, 1);
+    }
+
+    #[test]
+    fn test_interrupt_driven() {
+        let mut shub = fake_hub();
+        shub.set_interrupt_driven(true);
+        queue_packet(&mut shub, &LINEAR_ACCEL_PACKET);
+        assert!(!shub.data_ready());
+        // no interrupt: no read at all
+        assert_eq!(shub.handle_one_message(&mut FakeDelay {}, 10), 0);
+        assert_eq!(shub.handle_one_message_bulk(), 0);
+        assert_eq!(shub.stats().packets_received, [0; 8]);
+        assert_eq!(shub.stats().zero_length_reads, 0);
+
+        shub.on_interrupt();
+        assert!(shub.data_ready());
+        assert_eq!(shub.handle_one_message(&mut FakeDelay {}, 10), 1);
+        assert!(!shub.data_ready());
+        assert_eq!(shub.handle_one_message(&mut FakeDelay {}, 10), 0);
+        assert_eq!(shub.stats().zero_length_reads, 0);
+
+        // reported by an interrupt handler through the flag
+        use core::sync::atomic::{AtomicBool, Ordering};
+        static INTERRUPT: AtomicBool = AtomicBool::new(false);
+        shub.set_interrupt_flag(Some(&INTERRUPT));
+        queue_packet(&mut shub, &LINEAR_ACCEL_PACKET);
+        assert_eq!(shub.handle_one_message(&mut FakeDelay {}, 10), 0);
+        INTERRUPT.store(true, Ordering::Release);
+        assert!(shub.data_ready());
+        assert_eq!(shub.handle_one_message(&mut FakeDelay {}, 10), 1);
+        assert!(!INTERRUPT.load(Ordering::Acquire));
+        assert!(!shub.data_ready());
+    }
+
+    #[test]
+    fn test_interrupt_latency() {
+        use core::sync::atomic::{AtomicU32, Ordering};
+        static NOW_US: AtomicU32 = AtomicU32::new(0);
+        fn clock() -> u32 {
+            NOW_US.load(Ordering::Relaxed)
+        }
+        // base timestamp 1 ms before the interrupt, report delay 0.2 ms
+        let mut packet = LINEAR_ACCEL_PACKET;
+        packet[5] = 10;
+        packet[12] = 2;
+        let mut shub = fake_hub();
+        shub.set_interrupt_driven(true);
+        shub.set_micros_clock(Some(clock));
+
+        // read 300 us after the interrupt
+        queue_packet(&mut shub, &packet);
+        shub.on_interrupt_at(u32::MAX - 100);
+        NOW_US.store(199, Ordering::Relaxed);
+        assert_eq!(shub.handle_one_message(&mut FakeDelay {}, 10), 1);
+        assert_eq!(shub.last_bus_time_us(), Some(300));
+        let report = shub.next_raw_report().unwrap();
+        assert_eq!(report.bus_time_us(), Some(300));
+        assert_eq!(report.latency_us(), Some(300 + 800));
+
+        // an interrupt without a timestamp is not timed
+        queue_packet(&mut shub, &packet);
+        shub.on_interrupt();
+        assert_eq!(shub.handle_one_message(&mut FakeDelay {}, 10), 1);
+        assert_eq!(shub.next_raw_report().unwrap().latency_us(), None);
+
+        // the timestamp belongs to the control packet (an error list) read
+        // after it, not to the report that follows
+        queue_packet(&mut shub, &[6, 0, 2, 0, 1, 1]);
+        shub.on_interrupt_at(1000);
+        NOW_US.store(1050, Ordering::Relaxed);
+        assert_eq!(shub.handle_one_message(&mut FakeDelay {}, 10), 1);
+        assert_eq!(shub.last_bus_time_us(), Some(50));
+        queue_packet(&mut shub, &packet);
+        shub.on_interrupt();
+        assert_eq!(shub.handle_one_message(&mut FakeDelay {}, 10), 1);
+        assert_eq!(shub.next_raw_report().unwrap().bus_time_us(), None);
+
+        // of two interrupts before a read, the first announced the packet
+        queue_packet(&mut shub, &packet);
+        shub.on_interrupt_at(2000);
+        shub.on_interrupt_at(2100);
+        NOW_US.store(2400, Ordering::Relaxed);
+        assert_eq!(shub.handle_one_message(&mut FakeDelay {}, 10), 1);
+        assert_eq!(shub.next_raw_report().unwrap().bus_time_us(), Some(400));
+    }
+
+    #[test]
+    fn test_soft_reset_and_wait() {
+        let mut shub = fake_hub();
+        assert!(matches!(
+            shub.soft_reset_and_wait(100, &mut FakeDelay {}),
+            Err(WrapperError::Timeout)
+        ));
+        assert_eq!(shub.sent_packets()[0].as_slice()[2..5], [1, 0, 1]);
+
+        // reset complete, then the advertisement
+        queue_packet(&mut shub, &[5, 0, 1, 0, 1]);
+        queue_packet(&mut shub, &[5, 0, 0, 0, 0]);
+        assert!(shub.soft_reset_and_wait(100, &mut FakeDelay {}).is_ok());
+    }
+
+    #[test]
+    fn test_status() {
+        let mut shub = fake_hub();
+        assert_eq!(shub.status(), DeviceStatus::default());
+        let pid_response: [u8; 20] = [
+            20, 0, 2, 1, 0xF8, 4, 3, 2, 0xFE, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        queue_packet(&mut shub, &pid_response);
+        assert!(shub.init(&mut FakeDelay {}).is_ok());
+        assert!(shub.enable_rotation_vector(10).is_ok());
+        let status = shub.status();
+        assert!(status.product_verified);
+        assert!(status.init_complete);
+        assert!(!status.reset_seen);
+        assert_eq!(status.last_reset_cause, Some(4));
+        assert!(status.enabled_reports.iter().eq([0x05]));
+    }
+
+    /// A sensor report packet carrying one uncalibrated gyroscope report
+    #[cfg(feature = "report-gyro")]
+    fn gyro_uncalibrated_packet(sequence: u8, bias_x: i16) -> [u8; 25] {
+        let mut packet = [0u8; 25];
+        packet[..4].copy_from_slice(&[25, 0, 3, 0]);
+        packet[4] = 0xFB;
+        packet[9..13].copy_from_slice(&[0x07, sequence, 0x03, 0x00]);
+        packet[19..21].copy_from_slice(&bias_x.to_le_bytes());
+        packet
+    }
+
+    #[cfg(feature = "report-gyro")]
+    #[test]
+    fn test_gyro_bias_settled() {
+        use crate::wrapper::{q9_to_f32, GYRO_BIAS_SETTLED_REPORTS};
+        let mut shub = fake_hub();
+        assert!(shub.gyro_bias().is_none());
+        // the bias still drifts for the first reports, then is stable
+        // for one report too few
+        for (sequence, bias_x) in [(0, 40), (1, 20), (2, 0)] {
+            queue_packet(
+                &mut shub,
+                &gyro_uncalibrated_packet(sequence, bias_x),
+            );
+        }
+        for sequence in 3..3 + GYRO_BIAS_SETTLED_REPORTS as u8 {
+            queue_packet(&mut shub, &gyro_uncalibrated_packet(sequence, 8));
+        }
+        let tolerance = 4.0 * q9_to_f32(1);
+        assert!(matches!(
+            shub.wait_for_gyro_bias_settled(tolerance, 100, &mut FakeDelay {}),
+            Err(WrapperError::Timeout)
+        ));
+
+        for sequence in 20..21 + GYRO_BIAS_SETTLED_REPORTS as u8 {
+            queue_packet(&mut shub, &gyro_uncalibrated_packet(sequence, 9));
+        }
+        let bias = shub
+            .wait_for_gyro_bias_settled(tolerance, 100, &mut FakeDelay {})
+            .unwrap();
+        assert_eq!(bias, [q9_to_f32(9), 0.0, 0.0]);
+        assert_eq!(shub.gyro_bias_raw().unwrap().value, [9, 0, 0]);
+    }
+
+    #[test]
+    fn test_enable_report_us() {
+        let mut shub = fake_hub();
+        // 400 Hz is not a whole number of milliseconds
+        assert!(shub.enable_rotation_vector_us(2500).is_ok());
+        let sent = &shub.sensor_interface.i2c_port().sent_packets;
+        assert_eq!(sent[0].as_slice()[4..6], [0xFD, 0x05]);
+        assert_eq!(sent[0].as_slice()[9..13], 2500u32.to_le_bytes());
+        assert!(shub.is_report_enabled(0x05));
+    }
+
+    #[test]
+    fn test_report_interval_limits() {
+        let mut shub = fake_hub();
+        // the rotation vector runs at most at 400 Hz
+        assert!(matches!(
+            shub.enable_rotation_vector(1),
+            Err(WrapperError::InvalidReportInterval {
+                report_id: 0x05,
+                interval_us: 1000,
+                ..
+            })
+        ));
+        assert!(shub.sensor_interface.i2c_port().sent_packets.is_empty());
+        assert!(shub.enable_rotation_vector(3).is_ok());
+        assert!(shub.disable_report(0x05).is_ok());
+        // the gyro-integrated rotation vector runs at up to 1 kHz
+        assert!(shub
+            .enable_report(SensorReportId::GyroIntegratedRotationVector, 1)
+            .is_ok());
+    }
+
+    /// A control channel packet carrying an FRS read response
+    fn frs_read_response(
+        status: u8,
+        offset: u16,
+        data: &[u32],
+        frs_type: u16,
+    ) -> [u8; 20] {
+        let mut packet = [0u8; 20];
+        packet[..4].copy_from_slice(&[20, 0, 2, 0]);
+        packet[4] = 0xF3;
+        packet[5] = ((data.len() as u8) << 4) | status;
+        packet[6..8].copy_from_slice(&offset.to_le_bytes());
+        for (i, word) in data.iter().enumerate() {
+            packet[8 + i * 4..12 + i * 4].copy_from_slice(&word.to_le_bytes());
+        }
+        packet[16..18].copy_from_slice(&frs_type.to_le_bytes());
+        packet
+    }
+
+    /// A control channel packet carrying an FRS write response
+    fn frs_write_response(status: u8, offset: u16) -> [u8; 8] {
+        let [offset_lsb, offset_msb] = offset.to_le_bytes();
+        [8, 0, 2, 0, 0xF5, status, offset_lsb, offset_msb]
+    }
+
+    /// A control channel packet carrying the response to the first
+    /// request of `command`, with `status` (R0)
+    fn command_response(command: u8, status: u8) -> [u8; 20] {
+        let mut packet = [0u8; 20];
+        packet[..7].copy_from_slice(&[20, 0, 2, 0, 0xF1, 0, command]);
+        packet[9] = status;
+        packet
+    }
+
+    #[test]
+    fn test_static_calibration() {
+        let mut calibration = StaticCalibrationAgm::default();
+        calibration.accel.scale = [[1 << 30, 0, 0], [0, 1 << 30, 0], [0, 0, 1]];
+        calibration.mag.offset = [-3, 4, 5];
+        let words = calibration.to_words();
+
+        let mut shub = fake_hub();
+        queue_packet(&mut shub, &frs_write_response(4, 0));
+        for chunk in 0..words.len() / 2 - 1 {
+            queue_packet(&mut shub, &frs_write_response(0, chunk as u16 * 2));
+        }
+        queue_packet(&mut shub, &frs_write_response(8, 0));
+        queue_packet(&mut shub, &frs_write_response(3, 0));
+        assert!(shub
+            .set_static_calibration(&calibration, &mut FakeDelay {})
+            .is_ok());
+        let sent = shub.sent_packets();
+        assert_eq!(sent.len(), 1 + words.len() / 2);
+        // write request: length 36 words, record type 0x7979
+        assert_eq!(sent[0].as_slice()[4..10], [0xF7, 0, 36, 0, 0x79, 0x79]);
+        // the last write data request, at offset 34
+        let last = sent[words.len() / 2].as_slice();
+        assert_eq!(last[4..8], [0xF6, 0, 34, 0]);
+        assert_eq!(last[8..12], 4i32.to_le_bytes());
+        assert_eq!(last[12..16], 5i32.to_le_bytes());
+
+        for offset in (0..words.len()).step_by(2) {
+            let status = if offset + 2 == words.len() { 3 } else { 0 };
+            queue_packet(
+                &mut shub,
+                &frs_read_response(
+                    status,
+                    offset as u16,
+                    &words[offset..offset + 2],
+                    0x7979,
+                ),
+            );
+        }
+        assert_eq!(
+            shub.static_calibration(&mut FakeDelay {}).unwrap(),
+            Some(calibration)
+        );
+
+        // an empty record
+        queue_packet(&mut shub, &frs_read_response(5, 0, &[], 0x7979));
+        assert_eq!(shub.static_calibration(&mut FakeDelay {}).unwrap(), None);
+        // an unrecognized record type
+        queue_packet(&mut shub, &frs_read_response(1, 0, &[], 0x7979));
+        assert!(matches!(
+            shub.static_calibration(&mut FakeDelay {}),
+            Err(WrapperError::FrsError(1))
+        ));
+    }
+
+    #[test]
+    fn test_frs_write_not_ready() {
+        let words = [1, 2, 3, 4];
+        let mut shub = fake_hub();
+        // the write request is answered busy, then accepted
+        queue_packet(&mut shub, &frs_write_response(2, 0));
+        queue_packet(&mut shub, &frs_write_response(4, 0));
+        // the first write data request is rejected with an error list
+        queue_packet(&mut shub, &[6, 0, 0, 0, 0x01, 0x05]);
+        queue_packet(&mut shub, &frs_write_response(0, 0));
+        queue_packet(&mut shub, &frs_write_response(0, 2));
+        queue_packet(&mut shub, &frs_write_response(3, 0));
+        assert!(shub.write_frs(0x7979, &words, &mut FakeDelay {}).is_ok());
+        let sent = shub.sent_packets();
+        assert_eq!(sent.len(), 5);
+        assert_eq!(sent[0].as_slice()[4..10], sent[1].as_slice()[4..10]);
+        assert_eq!(sent[2].as_slice()[4..16], sent[3].as_slice()[4..16]);
+        assert_eq!(sent[4].as_slice()[4..8], [0xF6, 0, 2, 0]);
+        assert_eq!(shub.stats().retries, 2);
+
+        // a hub that stays busy
+        for _ in 0..NOT_READY_MAX_ATTEMPTS {
+            queue_packet(&mut shub, &frs_write_response(2, 0));
+        }
+        assert!(matches!(
+            shub.write_frs(0x7979, &words, &mut FakeDelay {}),
+            Err(WrapperError::FrsError(2))
+        ));
+    }
+
+    #[test]
+    fn test_restore_nominal_calibration() {
+        let mut shub = fake_hub();
+        // the nominal record is empty: the static calibration is erased
+        queue_packet(&mut shub, &frs_read_response(5, 0, &[], 0x4D4D));
+        queue_packet(&mut shub, &frs_write_response(3, 0));
+        assert!(shub.restore_nominal_calibration(&mut FakeDelay {}).is_ok());
+        let sent = shub.sent_packets();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(
+            sent[0].as_slice()[4..12],
+            [0xF4, 0, 0, 0, 0x4D, 0x4D, 0, 0]
+        );
+        assert_eq!(sent[1].as_slice()[4..10], [0xF7, 0, 0, 0, 0x79, 0x79]);
+    }
+
+    #[test]
+    fn test_gyro_integrated_rv_config() {
+        let config = GyroIntegratedRvConfig {
+            reference: GyroRvReference::RotationVector,
+            sync_interval_us: 20_000,
+            max_error: 0.5,
+            prediction: 0.03125,
+            alpha: 0.25,
+            beta: 0.125,
+            gamma: 0.0625,
+        };
+        let words = config.to_words();
+        let mut shub = fake_hub();
+        queue_packet(&mut shub, &frs_write_response(4, 0));
+        queue_packet(&mut shub, &frs_write_response(0, 0));
+        queue_packet(&mut shub, &frs_write_response(0, 2));
+        queue_packet(&mut shub, &frs_write_response(0, 4));
+        queue_packet(&mut shub, &frs_write_response(3, 0));
+        assert!(shub
+            .set_gyro_integrated_rv_config(Some(&config), &mut FakeDelay {})
+            .is_ok());
+        let sent = shub.sent_packets();
+        assert_eq!(sent[0].as_slice()[4..10], [0xF7, 0, 7, 0, 0xA2, 0xA1]);
+        assert_eq!(sent[1].as_slice()[8..12], 0x0204u32.to_le_bytes());
+        assert_eq!(sent[1].as_slice()[12..16], 20_000u32.to_le_bytes());
+
+        for offset in (0..words.len()).step_by(2) {
+            let end = (offset + 2).min(words.len());
+            let status = if end == words.len() { 3 } else { 0 };
+            queue_packet(
+                &mut shub,
+                &frs_read_response(
+                    status,
+                    offset as u16,
+                    &words[offset..end],
+                    0xA1A2,
+                ),
+            );
+        }
+        assert_eq!(
+            shub.gyro_integrated_rv_config(&mut FakeDelay {}).unwrap(),
+            Some(config)
+        );
+
+        queue_packet(&mut shub, &frs_write_response(3, 0));
+        assert!(shub
+            .set_gyro_integrated_rv_config(None, &mut FakeDelay {})
+            .is_ok());
+    }
+
+    #[test]
+    fn test_uart_output_format() {
+        let mut shub = fake_hub();
+        queue_packet(&mut shub, &frs_write_response(4, 0));
+        queue_packet(&mut shub, &frs_write_response(3, 0));
+        assert!(shub
+            .set_uart_output_format(
+                Some(UartOutputFormat::Rvc),
+                &mut FakeDelay {}
+            )
+            .is_ok());
+        let sent = shub.sent_packets();
+        assert_eq!(sent[0].as_slice()[4..10], [0xF7, 0, 1, 0, 0xA1, 0xA1]);
+        assert_eq!(sent[1].as_slice()[4..12], [0xF6, 0, 0, 0, 1, 0, 0, 0]);
+
+        queue_packet(&mut shub, &frs_read_response(3, 0, &[1], 0xA1A1));
+        assert_eq!(
+            shub.uart_output_format(&mut FakeDelay {}).unwrap(),
+            UartOutputFormat::Rvc
+        );
+        // an empty record: the default
+        queue_packet(&mut shub, &frs_read_response(5, 0, &[], 0xA1A1));
+        assert_eq!(
+            shub.uart_output_format(&mut FakeDelay {}).unwrap(),
+            UartOutputFormat::Shtp
+        );
+        queue_packet(&mut shub, &frs_read_response(3, 0, &[7], 0xA1A1));
+        assert_eq!(
+            shub.uart_output_format(&mut FakeDelay {}).unwrap(),
+            UartOutputFormat::Unknown(7)
+        );
+    }
+
+    #[test]
+    fn test_serial_number() {
+        let mut shub = fake_hub();
+        queue_packet(
+            &mut shub,
+            &frs_read_response(3, 0, &[0x1234_5678], 0x4B4B),
+        );
+        assert_eq!(
+            shub.serial_number(&mut FakeDelay {}).unwrap(),
+            Some(0x1234_5678)
+        );
+        assert_eq!(
+            shub.sent_packets()[0].as_slice()[4..12],
+            [0xF4, 0, 0, 0, 0x4B, 0x4B, 0, 0]
+        );
+        queue_packet(
+            &mut shub,
+            &frs_read_response(3, 0, &[0x1234_5678], 0x4B4B),
+        );
+        assert_eq!(
+            shub.serial_number_bytes(&mut FakeDelay {}).unwrap(),
+            Some([0x78, 0x56, 0x34, 0x12])
+        );
+        queue_packet(&mut shub, &frs_read_response(5, 0, &[], 0x4B4B));
+        assert_eq!(shub.serial_number(&mut FakeDelay {}).unwrap(), None);
+    }
+
+    #[cfg(feature = "report-rotation")]
+    #[test]
+    fn test_reports_iterator() {
+        use crate::reports::SensorReport;
+        let mut shub = fake_hub();
+        queue_packet(&mut shub, &ROTATION_VECTOR_PACKET);
+        queue_packet(&mut shub, &LINEAR_ACCEL_PACKET);
+        queue_packet(&mut shub, &LINEAR_ACCEL_PACKET);
+        let mut delay = FakeDelay {};
+        let mut reports = shub.reports(&mut delay);
+        assert!(matches!(
+            reports.next(),
+            Some(SensorReport::RotationVector { .. })
+        ));
+        assert_eq!(reports.count(), 2);
+    }
+
+    #[cfg(all(feature = "report-accel", feature = "report-rotation"))]
+    #[test]
+    fn test_report_queue() {
+        use crate::reports::SensorReport;
+        use crate::wrapper::REPORT_QUEUE_LEN;
+        // two reports in one packet: rotation vector, linear acceleration
+        let packet: [u8; 33] = [
+            33, 0, 3, 0, // header
+            0xFB, 0, 0, 0, 0, // base timestamp
+            0x05, 0x01, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x20, 0x00,
+            0x20, 0x00, 0x08, // rotation vector
+            0x04, 0x01, 0x03, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+        ];
+        let mut shub = fake_hub();
+        for _ in 0..REPORT_QUEUE_LEN {
+            shub.sensor_interface
+                .i2c_port_mut()
+                .add_available_packet(&packet[..4]);
+            shub.sensor_interface
+                .i2c_port_mut()
+                .add_available_packet(&packet);
+        }
+        assert_eq!(shub.reports_available(), 0);
+        assert_eq!(shub.handle_one_message(&mut FakeDelay {}, 1), 1);
+        assert_eq!(shub.reports_available(), 2);
+        assert!(matches!(
+            shub.next_report(),
+            Some(SensorReport::RotationVector { .. })
+        ));
+        assert_eq!(
+            shub.next_report(),
+            Some(SensorReport::LinearAccel([1.0, 0.0, 0.0]))
+        );
+        assert_eq!(shub.next_report(), None);
+
+        // a burst longer than the queue keeps the newest reports
+        shub.handle_all_messages(&mut FakeDelay {}, 1);
+        assert_eq!(shub.reports_available(), REPORT_QUEUE_LEN);
+    }
+
+    #[cfg(all(feature = "report-accel", feature = "report-rotation"))]
+    #[test]
+    fn test_fixed_point_readings() {
+        use crate::reports::{
+            SensorReport, HEADING_ACCURACY_Q, ROTATION_VECTOR_Q,
+        };
+        let packet: [u8; 33] = [
+            33, 0, 3, 0, // header
+            0xFB, 0, 0, 0, 0, // base timestamp
+            0x05, 0x01, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x20, 0x00,
+            0x20, 0x00, 0x08, // rotation vector
+            0x04, 0x01, 0x03, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+        ];
+        let mut shub = fake_hub();
+        queue_packet(&mut shub, &packet);
+        assert_eq!(shub.handle_one_message(&mut FakeDelay {}, 1), 1);
+
+        assert_eq!(shub.rotation_quaternion_raw(), [0, 0, 0x2000, 0x2000]);
+        assert_eq!(shub.heading_accuracy_raw(), 0x0800);
+        assert_eq!(shub.linear_accel_raw(), [0x0100, 0, 0]);
+        assert_eq!(shub.rotation_quaternion().unwrap(), [0.0, 0.0, 0.5, 0.5]);
+
+        let report = shub.next_raw_report().unwrap();
+        let report = report.as_report();
+        assert_eq!(report.report_id(), 0x05);
+        assert_eq!(report.value(3), Some(0x2000));
+        assert_eq!(report.q_point(3), Some(ROTATION_VECTOR_Q));
+        assert_eq!(report.q_point(4), Some(HEADING_ACCURACY_Q));
+        assert_eq!(report.q_point(5), None);
+        assert_eq!(
+            shub.next_report(),
+            Some(SensorReport::LinearAccel([1.0, 0.0, 0.0]))
+        );
+    }
+
+    #[test]
+    fn test_reports_stale() {
+        let mut shub = fake_hub();
+        shub.enable_rotation_vector(10).unwrap();
+        shub.enable_linear_accel(10).unwrap();
+
+        // only the rotation vector arrives
+        queue_packet(&mut shub, &ROTATION_VECTOR_PACKET);
+        shub.handle_all_messages(&mut FakeDelay {}, 1);
+        assert_eq!(shub.input_report_count(), 1);
+        assert!(!shub.reports_stale(50, 20));
+        assert!(!shub.reports_stale(50, 20));
+        assert!(shub.reports_stale(50, 20));
+
+        // both streams alive again
+        queue_packet(&mut shub, &LINEAR_ACCEL_PACKET);
+        shub.handle_all_messages(&mut FakeDelay {}, 1);
+        assert!(!shub.reports_stale(50, 20));
+        assert!(!shub.reports_stale(50, 20));
+    }
+
+    #[test]
+    fn test_latest_samples() {
+        let mut shub = fake_hub();
+        assert!(shub.latest_linear_accel().is_none());
+        queue_packet(&mut shub, &LINEAR_ACCEL_PACKET);
+        queue_packet(&mut shub, &ROTATION_VECTOR_PACKET);
+        shub.handle_all_messages(&mut FakeDelay {}, 1);
+
+        // polling one report does not lose the other
+        let accel = shub.latest_linear_accel().unwrap();
+        assert_eq!(accel.value, [1.0, 0.0, 0.0]);
+        assert_eq!(accel.sequence, 1);
+        assert_eq!(accel.accuracy, 3);
+        assert_eq!(accel.timestamp_us, 0);
+        let rotation = shub.latest_rotation_vector().unwrap();
+        assert_eq!(rotation.value, Quaternion::from([0.0, 0.0, 0.5, 0.5]));
+        assert!(shub.latest_gravity().is_none());
+    }
+
+    #[cfg(all(feature = "report-accel", feature = "report-rotation"))]
+    #[test]
+    fn test_report_decimation() {
+        use crate::reports::SensorReport;
+        let mut shub = fake_hub();
+        shub.set_decimation(0x04, 3);
+        assert_eq!(shub.decimation(0x04), 3);
+        assert_eq!(shub.decimation(0x05), 1);
+        for _ in 0..7 {
+            queue_packet(&mut shub, &LINEAR_ACCEL_PACKET);
+            queue_packet(&mut shub, &ROTATION_VECTOR_PACKET);
+        }
+        shub.handle_all_messages(&mut FakeDelay {}, 1);
+        // the 3rd and 6th linear acceleration reports, all rotation vectors
+        let mut linear_accel = 0;
+        let mut rotation_vector = 0;
+        while let Some(report) = shub.next_report() {
+            match report {
+                SensorReport::LinearAccel(_) => linear_accel += 1,
+                SensorReport::RotationVector { .. } => rotation_vector += 1,
+                _ => {}
+            }
+        }
+        assert_eq!((linear_accel, rotation_vector), (2, 7));
+        assert!(shub.latest_linear_accel().is_some());
+
+        shub.set_decimation(0x04, 0);
+        queue_packet(&mut shub, &LINEAR_ACCEL_PACKET);
+        shub.handle_all_messages(&mut FakeDelay {}, 1);
+        assert_eq!(shub.reports_available(), 1);
+    }
+
+    #[cfg(all(feature = "filter", feature = "report-accel"))]
+    #[test]
+    fn test_output_smoothing() {
+        use crate::filter::LowPass;
+        use crate::reports::SensorReport;
+
+        let mut shub = fake_hub();
+        assert!(!shub.set_smoothing(0x05, Some(LowPass::new(0.5))));
+        assert!(shub.set_smoothing(0x04, Some(LowPass::new(0.5))));
+        queue_packet(&mut shub, &LINEAR_ACCEL_PACKET);
+        shub.handle_all_messages(&mut FakeDelay {}, 1);
+        // linear acceleration of 0 m/s^2
+        let mut packet = LINEAR_ACCEL_PACKET;
+        packet[14] = 0;
+        queue_packet(&mut shub, &packet);
+        shub.handle_all_messages(&mut FakeDelay {}, 1);
+        assert_eq!(shub.linear_accel().unwrap(), [0.5, 0.0, 0.0]);
+        assert_eq!(shub.latest_linear_accel().unwrap().value, [0.5, 0.0, 0.0]);
+        // the queued reports are not smoothed
+        assert_eq!(
+            shub.next_report(),
+            Some(SensorReport::LinearAccel([1.0, 0.0, 0.0]))
+        );
+
+        shub.set_smoothing(0x04, None);
+        assert_eq!(shub.linear_accel().unwrap(), [0.0, 0.0, 0.0]);
     }
 
-    /// The BNO080 starts up with all sensors disabled,
-    /// waiting for the application to configure it.
-    pub fn init(
-        &mut self,
-        delay_source: &mut impl DelayNs,
-    ) -> Result<(), WrapperError<SE>> {
-        #[cfg(feature = "rttdebug")]
-        rprintln!("wrapper init");
+    #[cfg(feature = "report-rotation")]
+    #[test]
+    fn test_gyro_integrated_rv_channel() {
+        use crate::reports::SensorReport;
+        // identity orientation, turning at -1 rad/s about X
+        let packet: [u8; 18] =
+            [18, 0, 5, 9, 0, 0, 0, 0, 0, 0, 0, 0x40, 0, 0xFC, 0, 0, 0, 0];
+        let mut shub = fake_hub();
+        queue_packet(&mut shub, &packet);
+        let mut truncated = packet;
+        truncated[0] = 10;
+        queue_packet(&mut shub, &truncated[..10]);
+        shub.handle_all_messages(&mut FakeDelay {}, 1);
+        assert_eq!(
+            shub.next_report(),
+            Some(SensorReport::GyroIntegratedRotationVector {
+                quaternion: Quaternion {
+                    i: 0.0,
+                    j: 0.0,
+                    k: 0.0,
+                    real: 1.0,
+                },
+                angular_velocity: [-1.0, 0.0, 0.0],
+            })
+        );
+        // the truncated packet is not queued
+        assert_eq!(shub.next_report(), None);
+        assert_eq!(shub.stats().decode_errors, 1);
+    }
 
-        //Section 5.1.1.1 : On system startup, the SHTP control application will send
-        // its full advertisement response, unsolicited, to the host.
-        delay_source.delay_ms(1);
-        self.sensor_interface
-            .setup(delay_source)
-            .map_err(WrapperError::CommError)?;
-
-        if self.sensor_interface.requires_soft_reset() {
-            delay_source.delay_ms(1);
-            self.soft_reset()?;
-            delay_source.delay_ms(150);
-            self.eat_all_messages(delay_source);
-            delay_source.delay_ms(50);
-            self.eat_all_messages(delay_source);
-        } else {
-            // we only expect two messages after reset:
-            // eat the advertisement response
-            self.eat_one_message(delay_source);
-            // eat the unsolicited initialization response
-            self.eat_one_message(delay_source);
+    #[cfg(feature = "report-gesture")]
+    #[test]
+    fn test_gesture_events() {
+        use crate::gesture::Gesture;
+        use crate::reports::SensorReport;
+        use core::sync::atomic::{AtomicU32, Ordering};
+        static NOW_MS: AtomicU32 = AtomicU32::new(0);
+        static TAPS: AtomicU32 = AtomicU32::new(0);
+        fn clock() -> u32 {
+            NOW_MS.load(Ordering::Relaxed)
+        }
+        fn on_tap(gesture: Gesture, report: &SensorReport) {
+            assert_eq!(gesture, Gesture::Tap);
+            assert!(matches!(report, SensorReport::Tap { double: false, .. }));
+            TAPS.fetch_add(1, Ordering::Relaxed);
         }
+        // a single tap in the positive Z direction
+        let tap_packet: [u8; 14] =
+            [14, 0, 3, 0, 0xFB, 0, 0, 0, 0, 0x10, 1, 0, 0, 0x30];
 
-        self.verify_product_id(delay_source)?;
-        //self.eat_all_messages(delay_source);
+        let mut shub = fake_hub();
+        shub.set_clock(Some(clock));
+        shub.enable_gesture(Gesture::Tap, 250).unwrap();
+        shub.enable_gesture(Gesture::DoubleTap, 250).unwrap();
+        assert!(shub.is_report_enabled(0x10));
+        assert_eq!(shub.sent_packets().len(), 1);
+        shub.set_gesture_callback(Gesture::Tap, Some(on_tap));
 
-        Ok(())
-    }
+        queue_packet(&mut shub, &tap_packet);
+        NOW_MS.store(100, Ordering::Relaxed);
+        shub.handle_all_messages(&mut FakeDelay {}, 1);
+        // a repeat within the debounce window
+        queue_packet(&mut shub, &tap_packet);
+        NOW_MS.store(300, Ordering::Relaxed);
+        shub.handle_all_messages(&mut FakeDelay {}, 1);
+        assert_eq!(TAPS.load(Ordering::Relaxed), 1);
+        assert!(shub.take_gesture(Gesture::Tap));
+        assert!(!shub.take_gesture(Gesture::Tap));
+        assert!(!shub.take_gesture(Gesture::DoubleTap));
 
-    /// Tell the sensor to start reporting the fused rotation vector
-    /// on a regular cadence. Note that the maximum valid update rate
-    /// is 1 kHz, based on the max update rate of the sensor's gyros.
-    pub fn enable_rotation_vector(
-        &mut self,
-        millis_between_reports: u16,
-    ) -> Result<(), WrapperError<SE>> {
-        self.enable_report(
-            SENSOR_REPORTID_ROTATION_VECTOR,
-            millis_between_reports,
-        )
-    }
+        queue_packet(&mut shub, &tap_packet);
+        NOW_MS.store(350, Ordering::Relaxed);
+        shub.handle_all_messages(&mut FakeDelay {}, 1);
+        assert_eq!(TAPS.load(Ordering::Relaxed), 2);
 
-    /// Enables reporting of linear acceleration vector.
-    pub fn enable_linear_accel(
-        &mut self,
-        millis_between_reports: u16,
-    ) -> Result<(), WrapperError<SE>> {
-        self.enable_report(SENSOR_REPORTID_LINEAR_ACCEL, millis_between_reports)
-    }
+        // the tap detector stays enabled for the double tap
+        shub.disable_gesture(Gesture::Tap).unwrap();
+        assert!(shub.is_report_enabled(0x10));
+        shub.disable_gesture(Gesture::DoubleTap).unwrap();
+        assert!(!shub.is_report_enabled(0x10));
 
-    /// Enables reporting of gyroscope data.
-    pub fn enable_gyro(
-        &mut self,
-        millis_between_reports: u16,
-    ) -> Result<(), WrapperError<SE>> {
-        self.enable_report(SENSOR_REPORTID_GYRO, millis_between_reports)
+        // a detector enabled by the application stays enabled
+        shub.enable_report(SensorReportId::ShakeDetector, 100)
+            .unwrap();
+        shub.enable_gesture(Gesture::Shake, 250).unwrap();
+        shub.disable_gesture(Gesture::Shake).unwrap();
+        assert!(shub.is_report_enabled(0x19));
+        assert_eq!(shub.sent_packets().len(), 3);
     }
 
-    /// Enable a particular report
-    fn enable_report(
-        &mut self,
-        report_id: u8,
-        millis_between_reports: u16,
-    ) -> Result<(), WrapperError<SE>> {
-        #[cfg(feature = "rttdebug")]
-        rprintln!("enable_report 0x{:X}", report_id);
-
-        let micros_between_reports: u32 =
-            (millis_between_reports as u32) * 1000;
-        let cmd_body: [u8; 17] = [
-            SHUB_REPORT_SET_FEATURE_CMD,
-            report_id,
-            0,                                        //feature flags
-            0,                                        //LSB change sensitivity
-            0,                                        //MSB change sensitivity
-            (micros_between_reports & 0xFFu32) as u8, // LSB report interval, microseconds
-            (micros_between_reports.shr(8) & 0xFFu32) as u8,
-            (micros_between_reports.shr(16) & 0xFFu32) as u8,
-            (micros_between_reports.shr(24) & 0xFFu32) as u8, // MSB report interval
-            0, // LSB Batch Interval
-            0,
-            0,
-            0, // MSB Batch interval
-            0, // LSB sensor-specific config
-            0,
-            0,
-            0, // MSB sensor-specific config
+    #[test]
+    fn test_raw_timestamp_fields() {
+        // base timestamp 1 ms before the interrupt, then two linear
+        // acceleration reports with delays of 0.2 ms and 0.7 ms
+        let packet: [u8; 29] = [
+            29, 0, 3, 0, 0xFB, 10, 0, 0, 0, 0x04, 1, 3, 2, 0, 1, 0, 0, 0, 0,
+            0x04, 2, 3, 7, 0, 1, 0, 0, 0, 0,
         ];
+        let mut shub = fake_hub();
+        queue_packet(&mut shub, &packet);
+        shub.handle_all_messages(&mut FakeDelay {}, 1);
 
-        //we simply blast out this configuration command and assume it'll succeed
-        self.send_packet(CHANNEL_HUB_CONTROL, &cmd_body)?;
-        // any error or success in configuration will arrive some time later
+        let accel = shub.latest_linear_accel().unwrap();
+        assert_eq!(accel.timestamp_base, -10);
+        assert_eq!(accel.delay, 7);
+        assert_eq!(accel.timestamp_us, -300);
 
-        Ok(())
+        let first = shub.next_raw_report().unwrap();
+        let second = shub.next_raw_report().unwrap();
+        assert_eq!(first.timestamp_base(), -10);
+        assert_eq!(first.timestamp_us(), -800);
+        assert_eq!(second.as_report().delay() - first.as_report().delay(), 5);
+        assert_eq!(second.timestamp_us(), -300);
     }
 
-    /// Prepare a packet for sending, in our send buffer
-    fn prep_send_packet(&mut self, channel: u8, body_data: &[u8]) -> usize {
-        let body_len = body_data.len();
+    #[test]
+    fn test_ahrs_sample() {
+        let mut shub = fake_hub();
+        shub.enable_ahrs_us(2500).unwrap();
+        let sent = shub.sent_packets();
+        assert_eq!(sent.len(), 3);
+        assert_eq!(sent[1].as_slice()[5], 0x02);
+        assert_eq!(sent[1].as_slice()[9..13], 2500u32.to_le_bytes());
 
-        let packet_length = body_len + PACKET_HEADER_LENGTH;
-        let packet_header = [
-            (packet_length & 0xFF) as u8, //LSB
-            packet_length.shr(8) as u8,   //MSB
-            channel,
-            self.sequence_numbers[channel as usize],
-        ];
-        self.sequence_numbers[channel as usize] += 1;
+        queue_packet(&mut shub, &LINEAR_ACCEL_PACKET);
+        queue_packet(&mut shub, &ROTATION_VECTOR_PACKET);
+        shub.handle_all_messages(&mut FakeDelay {}, 1);
+        assert!(shub.ahrs_sample().is_none());
 
-        self.packet_send_buf[..PACKET_HEADER_LENGTH]
-            .copy_from_slice(packet_header.as_ref());
-        self.packet_send_buf[PACKET_HEADER_LENGTH..packet_length]
-            .copy_from_slice(body_data);
+        // calibrated gyroscope, 0.5 rad/s about z, 200 us after the
+        // packet's base timestamp
+        let packet: [u8; 19] = [
+            19, 0, 3, 2, 0xFB, 0, 0, 0, 0, 0x02, 1, 3, 2, 0, 0, 0, 0, 0, 0x01,
+        ];
+        queue_packet(&mut shub, &packet);
+        shub.handle_all_messages(&mut FakeDelay {}, 1);
+        let sample = shub.ahrs_sample().unwrap();
+        assert_eq!(sample.orientation, Quaternion::from([0.0, 0.0, 0.5, 0.5]));
+        assert_eq!(sample.angular_rate, [0.0, 0.0, 0.5]);
+        assert_eq!(sample.linear_accel, [1.0, 0.0, 0.0]);
+        assert_eq!(sample.timestamp_us, 0);
+        assert_eq!(sample.skew_us, 200);
+        assert_eq!(
+            shub.latest_calibrated_gyro().unwrap().value,
+            sample.angular_rate
+        );
+        assert!(shub.latest_gyro().is_none());
+    }
 
-        packet_length
+    #[cfg(all(feature = "report-accel", feature = "report-rotation"))]
+    #[test]
+    fn test_ned_output_frame() {
+        use crate::math::Frame;
+        use crate::reports::SensorReport;
+        let mut shub = fake_hub();
+        shub.set_output_frame(Frame::Ned);
+        queue_packet(&mut shub, &LINEAR_ACCEL_PACKET);
+        queue_packet(&mut shub, &ROTATION_VECTOR_PACKET);
+        shub.handle_all_messages(&mut FakeDelay {}, 1);
+        assert_eq!(shub.linear_accel().unwrap(), [0.0, 1.0, -0.0]);
+        assert_eq!(shub.latest_linear_accel().unwrap().value, [0.0, 1.0, -0.0]);
+        assert_eq!(
+            shub.next_report(),
+            Some(SensorReport::LinearAccel([0.0, 1.0, -0.0]))
+        );
+        let ned = Quaternion::from([0.0, 0.0, -0.5, 0.5]);
+        assert_eq!(shub.quaternion(), ned);
+        assert_eq!(shub.latest_rotation_vector().unwrap().value, ned);
+        // the raw values and the compass heading are unaffected
+        assert_eq!(shub.linear_accel_raw(), [0x100, 0, 0]);
+        shub.set_output_frame(Frame::Enu);
+        let heading = shub.heading_degrees(0.0);
+        shub.set_output_frame(Frame::Ned);
+        assert_eq!(shub.heading_degrees(0.0), heading);
     }
 
-    /// Send packet from our packet send buf
-    fn send_packet(
-        &mut self,
-        channel: u8,
-        body_data: &[u8],
-    ) -> Result<usize, WrapperError<SE>> {
-        let packet_length = self.prep_send_packet(channel, body_data);
-        self.sensor_interface
-            .write_packet(&self.packet_send_buf[..packet_length])
-            .map_err(WrapperError::CommError)?;
-        Ok(packet_length)
+    #[cfg(all(feature = "report-environment", feature = "report-heart-rate"))]
+    #[test]
+    fn test_latest_external_sensors() {
+        let mut shub = fake_hub();
+        assert!(shub.latest_pressure().is_none());
+        // pressure 1013.25 hPa, proximity 2.5 cm
+        let packet: [u8; 18] = [
+            18, 0, 3, 0, 0x0A, 1, 2, 0, 0x00, 0x00, 0x54, 0x3F, 0x0D, 1, 0, 0,
+            0x28, 0x00,
+        ];
+        queue_packet(&mut shub, &packet);
+        shub.handle_all_messages(&mut FakeDelay {}, 1);
+        let pressure = shub.latest_pressure().unwrap();
+        assert_eq!(pressure.value, 1013.25);
+        assert_eq!(pressure.accuracy, 2);
+        assert_eq!(shub.latest_proximity().unwrap().value, 2.5);
+        assert!(shub.latest_humidity().is_none());
+
+        // 25.5 degrees C
+        let packet: [u8; 10] = [10, 0, 3, 1, 0x0E, 2, 0, 0, 0xC0, 0x0C];
+        queue_packet(&mut shub, &packet);
+        shub.handle_all_messages(&mut FakeDelay {}, 1);
+        assert_eq!(shub.latest_temperature().unwrap().value, 25.5);
+
+        assert!(shub.latest_heart_rate().is_none());
+        let packet: [u8; 10] = [10, 0, 3, 2, 0x23, 5, 0, 0, 61, 0];
+        queue_packet(&mut shub, &packet);
+        shub.handle_all_messages(&mut FakeDelay {}, 1);
+        assert_eq!(shub.latest_heart_rate().unwrap().value, 61);
     }
 
-    /// Read one packet into the receive buffer
-    pub(crate) fn receive_packet_with_timeout(
-        &mut self,
-        delay: &mut impl DelayNs,
-        max_ms: u8,
-    ) -> Result<usize, WrapperError<SE>> {
-        // #[cfg(feature = "rttdebug")]
-        // rprintln!("r_p");
+    #[test]
+    fn test_poll_init() {
+        let mut shub = fake_hub();
+        assert_eq!(shub.init_state(), InitState::Reset);
+        assert_eq!(
+            shub.poll_init(&mut FakeDelay {}).unwrap(),
+            InitState::Advertise
+        );
+        // the soft reset
+        assert_eq!(shub.sent_packets().len(), 1);
 
-        self.packet_recv_buf[0] = 0;
-        self.packet_recv_buf[1] = 0;
-        let packet_len = self
-            .sensor_interface
-            .read_with_timeout(&mut self.packet_recv_buf, delay, max_ms)
-            .map_err(WrapperError::CommError)?;
+        // advertisement, then the unsolicited initialization response
+        queue_packet(&mut shub, &[5, 0, 0, 0, 0x00]);
+        assert_eq!(
+            shub.poll_init(&mut FakeDelay {}).unwrap(),
+            InitState::Advertise
+        );
+        queue_packet(&mut shub, &[8, 0, 2, 0, 0xF1, 0, 0x84, 0]);
+        assert_eq!(
+            shub.poll_init(&mut FakeDelay {}).unwrap(),
+            InitState::ProductId
+        );
+        assert_eq!(shub.sent_packets()[1].as_slice()[4..6], [0xF9, 0]);
 
-        self.last_packet_len_received = packet_len;
-        // #[cfg(feature = "rttdebug")]
-        // rprintln!("recv {}", packet_len);
+        assert_eq!(
+            shub.poll_init(&mut FakeDelay {}).unwrap(),
+            InitState::ProductId
+        );
+        let pid_response: [u8; 20] = [
+            20, 0, 2, 1, 0xF8, 0, 3, 2, 0xFE, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        queue_packet(&mut shub, &pid_response);
+        assert_eq!(
+            shub.poll_init(&mut FakeDelay {}).unwrap(),
+            InitState::Ready
+        );
+        assert_eq!(
+            shub.poll_init(&mut FakeDelay {}).unwrap(),
+            InitState::Ready
+        );
+    }
 
-        Ok(packet_len)
+    #[test]
+    fn test_clear_calibration_and_reset() {
+        let mut shub = fake_hub();
+        assert!(matches!(
+            shub.clear_calibration_and_reset(100, &mut FakeDelay {}),
+            Err(WrapperError::Timeout)
+        ));
+
+        // reset complete, then the advertisement
+        queue_packet(&mut shub, &[5, 0, 1, 0, 1]);
+        queue_packet(&mut shub, &[5, 0, 0, 0, 0]);
+        shub.clear_calibration_and_reset(100, &mut FakeDelay {})
+            .unwrap();
+        let sent = shub.sent_packets();
+        assert_eq!(sent[1].as_slice()[4..7], [0xF2, 1, 0x0B]);
+        // no second reset
+        assert_eq!(sent.len(), 2);
     }
 
-    /// Verify that the sensor returns an expected chip ID
-    fn verify_product_id(
-        &mut self,
-        delay: &mut impl DelayNs,
-    ) -> Result<(), WrapperError<SE>> {
-        #[cfg(feature = "rttdebug")]
-        rprintln!("request PID...");
-        let cmd_body: [u8; 2] = [
-            SHUB_PROD_ID_REQ, //request product ID
-            0,                //reserved
-        ];
+    #[test]
+    fn test_ensure_reports_alive() {
+        let mut shub = fake_hub();
+        // no reports enabled: nothing to watch
+        assert!(!shub.ensure_reports_alive(&mut FakeDelay {}).unwrap());
+        shub.enable_linear_accel(10).unwrap();
+        shub.enable_rotation_vector_us(2500).unwrap();
+        shub.disable_report(0x05).unwrap();
+        queue_packet(&mut shub, &LINEAR_ACCEL_PACKET);
+        assert!(!shub.ensure_reports_alive(&mut FakeDelay {}).unwrap());
+        assert_eq!(shub.sent_packets().len(), 3);
 
-        // for some reason, reading PID right sending request does not work with i2c
-        if self.sensor_interface.requires_soft_reset() {
-            self.send_packet(CHANNEL_HUB_CONTROL, cmd_body.as_ref())?;
-        } else {
-            let response_size = self.send_and_receive_packet(
-                CHANNEL_HUB_CONTROL,
-                cmd_body.as_ref(),
-            )?;
-            if response_size > 0 {
-                self.handle_received_packet(response_size);
-            }
-        };
+        // no report for 40 ms, and the hub does not answer the reset
+        assert!(matches!(
+            shub.ensure_reports_alive(&mut FakeDelay {}),
+            Err(WrapperError::Timeout)
+        ));
+        assert_eq!(shub.stats().watchdog_recoveries, 0);
 
-        // process all incoming messages until we get a product id (or no more data)
-        while !self.prod_id_verified {
-            #[cfg(feature = "rttdebug")]
-            rprintln!("read PID");
-            let msg_count = self.handle_one_message(delay, 150u8);
-            if msg_count < 1 {
-                break;
-            }
+        // reinitialize, and enable linear acceleration again.
+        // Each reading of the clock advances it by 50 ms, so that the
+        // stall is seen before the hub's answers are handled.
+        use core::sync::atomic::{AtomicU32, Ordering};
+        static NOW_MS: AtomicU32 = AtomicU32::new(0);
+        fn clock() -> u32 {
+            NOW_MS.fetch_add(50, Ordering::Relaxed)
         }
+        shub.set_clock(Some(clock));
+        queue_init_handshake(&mut shub);
+        assert!(shub.ensure_reports_alive(&mut FakeDelay {}).unwrap());
+        assert_eq!(shub.stats().watchdog_recoveries, 1);
+        assert!(shub.is_report_enabled(0x04));
+        assert!(!shub.is_report_enabled(0x05));
+        let sent = shub.sent_packets();
+        let last = sent[sent.len() - 1].as_slice();
+        assert_eq!(last[4..6], [0xFD, 0x04]);
+        assert_eq!(last[9..13], 10_000u32.to_le_bytes());
+    }
 
-        if !self.prod_id_verified {
-            return Err(WrapperError::InvalidChipId(0));
+    #[test]
+    fn test_poll_init_product_id_timeout() {
+        let mut shub = fake_hub();
+        let mut state = InitState::Reset;
+        let mut polls = 0;
+        // no advertisement: moves on after the advertisement timeout
+        while state != InitState::ProductId {
+            state = shub.poll_init(&mut FakeDelay {}).unwrap();
+            polls += 1;
         }
-        Ok(())
+        assert!(polls > 100);
+        let result = loop {
+            match shub.poll_init(&mut FakeDelay {}) {
+                Ok(state) => assert_eq!(state, InitState::ProductId),
+                Err(e) => break e,
+            }
+        };
+        assert!(matches!(result, WrapperError::Timeout));
+        assert_eq!(shub.init_state(), InitState::Reset);
     }
 
-    /// Read normalized quaternion:
-    /// QX normalized quaternion – X, or Heading | range: 0.0 – 1.0 ( ±π )
-    /// QY normalized quaternion – Y, or Pitch   | range: 0.0 – 1.0 ( ±π/2 )
-    /// QZ normalized quaternion – Z, or Roll    | range: 0.0 – 1.0 ( ±π )
-    /// QW normalized quaternion – W, or 0.0     | range: 0.0 – 1.0
-    pub fn rotation_quaternion(&self) -> Result<[f32; 4], WrapperError<SE>> {
-        Ok(self.rotation_quaternion)
-    }
+    #[test]
+    fn test_init_timeout_under_traffic() {
+        let mut shub = fake_hub();
+        // a hub that keeps sending reports, but never the product ID
+        for _ in 0..500 {
+            queue_packet(&mut shub, &LINEAR_ACCEL_PACKET);
+        }
+        assert!(matches!(
+            shub.init(&mut FakeDelay {}),
+            Err(WrapperError::Timeout)
+        ));
+        assert_eq!(shub.handle_one_message(&mut FakeDelay {}, 1), 1);
 
-    pub fn heading_accuracy(&self) -> f32 {
-        self.rot_quaternion_acc
+        let mut shub = fake_hub();
+        queue_init_handshake(&mut shub);
+        assert!(shub.init(&mut FakeDelay {}).is_ok());
+        assert!(shub.status().product_verified);
     }
 
-    /// Read linear acceleration (m/s^2)
-    pub fn linear_accel(&self) -> Result<[f32; 3], WrapperError<SE>> {
-        Ok(self.linear_accel)
+    #[test]
+    fn test_bounded_drain() {
+        let mut shub = fake_hub();
+        for _ in 0..4 {
+            queue_packet(&mut shub, &LINEAR_ACCEL_PACKET);
+        }
+        assert_eq!(shub.eat_messages_up_to(&mut FakeDelay {}, 1), 1);
+        assert_eq!(shub.drain(2, 500, &mut FakeDelay {}), 2);
+        // the time budget allows only one read
+        assert_eq!(shub.drain(10, 5, &mut FakeDelay {}), 1);
+        assert_eq!(shub.drain(10, 500, &mut FakeDelay {}), 0);
+        assert_eq!(shub.eat_messages_up_to(&mut FakeDelay {}, 10), 0);
+
+        // a clock that passes the budget between two readings
+        use core::sync::atomic::{AtomicU32, Ordering};
+        static NOW_MS: AtomicU32 = AtomicU32::new(0);
+        fn clock() -> u32 {
+            NOW_MS.fetch_add(40, Ordering::Relaxed)
+        }
+        shub.set_clock(Some(clock));
+        for _ in 0..3 {
+            queue_packet(&mut shub, &LINEAR_ACCEL_PACKET);
+        }
+        assert_eq!(shub.drain(10, 60, &mut FakeDelay {}), 1);
     }
 
-    /// Read gyroscope data (rad/s)
-    pub fn gyro(&self) -> Result<[f32; 3], WrapperError<SE>> {
-        Ok(self.gyro)
+    #[test]
+    fn test_packet_validation() {
+        let mut shub = fake_hub();
+        shub.set_packet_validation(true);
+        queue_packet(&mut shub, &LINEAR_ACCEL_PACKET);
+        assert_eq!(shub.handle_one_message(&mut FakeDelay {}, 1), 1);
+
+        // the header claims more bytes than were read
+        let mut packet = LINEAR_ACCEL_PACKET;
+        packet[0] = 0xFF;
+        packet[1] = 0xFF;
+        shub.packet_recv_buf[..packet.len()].copy_from_slice(&packet);
+        assert!(matches!(
+            shub.check_received_len(packet.len()),
+            Err(WrapperError::CorruptPacket)
+        ));
+        assert!(matches!(
+            shub.check_received_len(0xFFFF),
+            Err(WrapperError::CorruptPacket)
+        ));
+        // a channel that was not advertised
+        let mut packet = LINEAR_ACCEL_PACKET;
+        packet[2] = 7;
+        shub.packet_recv_buf[..packet.len()].copy_from_slice(&packet);
+        assert!(matches!(
+            shub.check_received_len(packet.len()),
+            Err(WrapperError::CorruptPacket)
+        ));
+        assert_eq!(shub.stats().corrupt_packets, 3);
+        assert!(shub.last_packet_payload().is_empty());
+
+        shub.set_packet_validation(false);
+        assert!(shub.check_received_len(packet.len()).is_ok());
     }
 
-    /// Tell the sensor to reset.
-    /// Normally applications should not need to call this directly,
-    /// as it is called during `init`.
-    pub fn soft_reset(&mut self) -> Result<(), WrapperError<SE>> {
-        // #[cfg(feature = "rttdebug")]
-        // rprintln!("soft_reset");
-        let data: [u8; 1] = [EXECUTABLE_DEVICE_CMD_RESET];
-        // send command packet and ignore received packets
-        let received_len =
-            self.send_and_receive_packet(CHANNEL_EXECUTABLE, data.as_ref())?;
-        if received_len > 0 {
-            self.handle_received_packet(received_len);
+    #[test]
+    fn test_short_packets() {
+        let mut shub = fake_hub();
+        for len in [1, 2, 3, PACKET_RECV_BUF_LEN + 1] {
+            assert!(matches!(
+                shub.handle_received_packet(len),
+                Err(WrapperError::CorruptPacket)
+            ));
         }
+        assert_eq!(shub.stats().corrupt_packets, 4);
 
-        Ok(())
+        // a header alone, on each channel
+        for channel in 0..MAX_CHANNELS as u8 {
+            shub.packet_recv_buf[..4].copy_from_slice(&[4, 0, channel, 0]);
+            assert!(shub.handle_received_packet(4).is_ok());
+        }
+
+        // a header claiming a packet of three bytes, read from the bus
+        shub.sensor_interface
+            .i2c_port_mut()
+            .add_available_packet(&[3, 0, 2, 0]);
+        assert_eq!(shub.handle_one_message(&mut FakeDelay {}, 1), 0);
     }
 
-    /// Send a packet and receive the response
-    fn send_and_receive_packet(
-        &mut self,
-        channel: u8,
-        body_data: &[u8],
-    ) -> Result<usize, WrapperError<SE>> {
-        let send_packet_length = self.prep_send_packet(channel, body_data);
-        // #[cfg(feature = "rttdebug")]
-        // rprintln!("srcv {} ...", send_packet_length);
+    #[test]
+    fn test_fuzz_received_packets() {
+        let mut shub = fake_hub();
+        let mut state: u32 = 0x2545_F491;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        };
+        for _ in 0..5000 {
+            let len = (random() % 48) as usize;
+            for byte in &mut shub.packet_recv_buf[..len] {
+                *byte = random() as u8;
+            }
+            if len > 2 {
+                // mostly advertised channels and known report IDs
+                shub.packet_recv_buf[2] = (random() % 7) as u8;
+            }
+            if len > 4 && random() % 2 == 0 {
+                let report_ids = [0x00, 0xF1, 0xF3, 0xF5, 0xF8, 0xFB];
+                shub.packet_recv_buf[4] =
+                    report_ids[random() as usize % report_ids.len()];
+            }
+            let _ = shub.handle_received_packet(len);
+        }
 
-        let recv_packet_length = self
-            .sensor_interface
-            .send_and_receive_packet(
-                self.packet_send_buf[..send_packet_length].as_ref(),
-                &mut self.packet_recv_buf,
+        // the same random bytes, read as a stream from the bus
+        for _ in 0..200 {
+            let mut packet = [0u8; 32];
+            for byte in &mut packet {
+                *byte = random() as u8;
+            }
+            packet[0] = (random() % 33) as u8;
+            packet[1] = 0;
+            let len = packet[0] as usize;
+            if len > 4 {
+                queue_packet(&mut shub, &packet[..len]);
+            } else {
+                shub.sensor_interface
+                    .i2c_port_mut()
+                    .add_available_packet(&packet[..4]);
+            }
+            shub.handle_one_message(&mut FakeDelay {}, 1);
+        }
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn test_handle_arbitrary_packets(
+            packets in proptest::collection::vec(
+                (
+                    0u8..MAX_CHANNELS as u8,
+                    proptest::sample::select(std::vec![
+                        0x00, 0xF1, 0xF3, 0xF5, 0xF8, 0xFB,
+                    ]),
+                    proptest::collection::vec(0u8..=0xFF, 0..64),
+                ),
+                1..8,
             )
-            .map_err(WrapperError::CommError)?;
+        ) {
+            let mut shub = fake_hub();
+            for (channel, report_id, body) in packets {
+                let len = PACKET_HEADER_LENGTH + 1 + body.len();
+                let [len_low, len_high] = (len as u16).to_le_bytes();
+                shub.packet_recv_buf[..5].copy_from_slice(&[
+                    len_low, len_high, channel, 0, report_id,
+                ]);
+                shub.packet_recv_buf[5..len].copy_from_slice(&body);
+                let handled = shub.handle_received_packet(len);
+                proptest::prop_assert!(handled.is_ok());
+            }
+            let _ = shub.next_report();
+            let _ = shub.euler_angles();
+        }
+    }
 
-        #[cfg(feature = "rttdebug")]
-        rprintln!("srcv {} {}", send_packet_length, recv_packet_length);
+    #[test]
+    fn test_configure_report_confirmed() {
+        let mut shub = fake_hub();
+        let mut response: [u8; 21] = [
+            21, 0, 2, 0, 0xFC, 0x05, 0, 0, 0, 0x88, 0x13, 0, 0, 0, 0, 0, 0, 0,
+            0, 0, 0,
+        ];
+        // a response for another report is not the confirmation
+        let mut other = response;
+        other[5] = 0x04;
+        queue_packet(&mut shub, &other);
+        queue_packet(&mut shub, &response);
+        let granted = shub
+            .enable_report_confirmed(0x05, 10_000, 100, &mut FakeDelay {})
+            .unwrap();
+        assert_eq!(granted.report_id, 0x05);
+        assert_eq!(granted.report_interval_us, 5000);
 
-        Ok(recv_packet_length)
+        // the hub leaves the report disabled
+        response[3] = 1;
+        response[9..11].copy_from_slice(&[0, 0]);
+        queue_packet(&mut shub, &response);
+        assert!(matches!(
+            shub.enable_report_confirmed(0x05, 10_000, 100, &mut FakeDelay {}),
+            Err(WrapperError::FeatureRejected {
+                report_id: 0x05,
+                interval_us: 0
+            })
+        ));
+        assert!(!shub.is_report_enabled(0x05));
+
+        assert!(matches!(
+            shub.enable_report_confirmed(0x05, 10_000, 20, &mut FakeDelay {}),
+            Err(WrapperError::Timeout)
+        ));
     }
-}
 
-const Q8_SCALE: f32 = 1.0 / ((1 << 8) as f32);
-const Q9_SCALE: f32 = 1.0 / ((1 << 9) as f32);
-const Q12_SCALE: f32 = 1.0 / ((1 << 12) as f32);
-const Q14_SCALE: f32 = 1.0 / ((1 << 14) as f32);
+    #[test]
+    fn test_malformed_traffic() {
+        let mut shub = fake_hub();
+        let command_response = command_response(0x84, 0);
+        let mut frs_response = [0u8; 20];
+        frs_response[..5].copy_from_slice(&[20, 0, 2, 0, 0xF3]);
+        let packets: [&[u8]; 5] = [
+            &ADVERTISING_PACKET_FULL,
+            &LINEAR_ACCEL_PACKET,
+            &ROTATION_VECTOR_PACKET,
+            &command_response,
+            &frs_response,
+        ];
+        // every truncation of valid packets, on every channel, with the
+        // header still announcing the full length
+        for packet in packets {
+            for len in 0..=packet.len() {
+                for channel in 0..=MAX_CHANNELS as u8 {
+                    shub.packet_recv_buf[..len].copy_from_slice(&packet[..len]);
+                    shub.packet_recv_buf[2] = channel;
+                    let _ = shub.handle_received_packet(len);
+                }
+            }
+        }
 
-fn q14_to_f32(q_val: i16) -> f32 {
-    // let qq_val =  fpa::I2F14(q_val).unwrap();
-    // return f32(qq_val)
-    (q_val as f32) * Q14_SCALE
-}
+        // noise, with lengths up to beyond the receive buffer
+        let mut state: u32 = 0x2545_F491;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        };
+        for _ in 0..500 {
+            let len = next() as usize % (PACKET_RECV_BUF_LEN + 8);
+            for byte in shub.packet_recv_buf.iter_mut().take(len) {
+                *byte = next() as u8;
+            }
+            shub.packet_recv_buf[2] = next() as u8 % MAX_CHANNELS as u8;
+            let _ = shub.handle_received_packet(len);
+        }
+        // and through the interface, with headers matching the packets
+        for _ in 0..50 {
+            let len = 4 + next() as usize % 200;
+            let mut packet: std::vec::Vec<u8> =
+                (0..len).map(|_| next() as u8).collect();
+            packet[..2].copy_from_slice(&(len as u16).to_le_bytes());
+            queue_packet(&mut shub, &packet);
+        }
+        shub.handle_all_messages(&mut FakeDelay {}, 1);
 
-fn q12_to_f32(q_val: i16) -> f32 {
-    (q_val as f32) * Q12_SCALE
-}
+        // what was received can still be inspected
+        while shub.next_report().is_some() {}
+        for report in shub.last_input_reports() {
+            let _ = report.decode();
+        }
+        let _ = (shub.euler_angles(), shub.quaternion(), shub.product_id());
+        let _ = shub.last_packet_payload();
+        // a header announcing more than the arena holds
+        let packets = ArenaPackets {
+            arena: &[0xFF, 0x00, 3, 0, 0xFB],
+        };
+        assert_eq!(packets.count(), 0);
+    }
 
-fn q8_to_f32(q_val: i16) -> f32 {
-    (q_val as f32) * Q8_SCALE
-}
+    #[test]
+    fn test_packet_length_checks() {
+        let mut shub = fake_hub();
+        assert!(matches!(
+            shub.send_packet(
+                u8::from(Channel::HubControl),
+                &[0; PACKET_SEND_BUF_LEN]
+            ),
+            Err(WrapperError::PacketTooLarge)
+        ));
+        assert_eq!(shub.sent_packets().len(), 0);
+        assert!(matches!(
+            shub.check_received_len(PACKET_RECV_BUF_LEN + 1),
+            Err(WrapperError::BufferTooSmall)
+        ));
+        assert!(shub.last_packet_payload().is_empty());
+    }
 
-fn q9_to_f32(q_val: i16) -> f32 {
-    (q_val as f32) * Q9_SCALE
-}
+    #[test]
+    fn test_advertised_max_cargo() {
+        let mut shub = fake_hub();
+        shub.packet_recv_buf[..ADVERTISING_PACKET_FULL.len()]
+            .copy_from_slice(&ADVERTISING_PACKET_FULL);
+        shub.handle_received_packet(ADVERTISING_PACKET_FULL.len())
+            .unwrap();
+        assert_eq!(shub.max_write_len(), 256);
+        assert_eq!(shub.max_read_len(), Some(0x7FFF));
 
-// The BNO080 supports six communication channels:
-const CHANNEL_COMMAND: u8 = 0;
-/// the SHTP command channel
-const CHANNEL_EXECUTABLE: u8 = 1;
-/// executable channel
-const CHANNEL_HUB_CONTROL: u8 = 2;
-/// sensor hub control channel
-const CHANNEL_SENSOR_REPORTS: u8 = 3;
-/// input sensor reports (non-wake, not gyroRV)
-//const  CHANNEL_WAKE_REPORTS: usize = 4; /// wake input sensor reports (for sensors configured as wake up sensors)
-//const  CHANNEL_GYRO_ROTATION: usize = 5; ///  gyro rotation vector (gyroRV)
+        // a hub accepting packets of at most 16 bytes
+        let mut advert = std::vec::Vec::from([0, 0, 0, 0, 0x00]);
+        advert.extend_from_slice(b"\x02\x02\x10\x00\x03\x02\x00\x01");
+        advert[0] = advert.len() as u8;
+        shub.packet_recv_buf[..advert.len()].copy_from_slice(&advert);
+        shub.handle_received_packet(advert.len()).unwrap();
+        assert_eq!(shub.max_write_len(), 16);
+        assert_eq!(shub.max_read_len(), Some(256));
+        let control = u8::from(Channel::HubControl);
+        assert!(matches!(
+            shub.send_packet(control, &[0; 13]),
+            Err(WrapperError::PacketTooLarge)
+        ));
+        assert!(shub.send_packet(control, &[0; 12]).is_ok());
+        assert_eq!(shub.sent_packets().len(), 1);
 
-/// Command Channel requests / responses
+        shub.reset_driver_state();
+        assert_eq!(shub.max_write_len(), PACKET_SEND_BUF_LEN);
+        assert_eq!(shub.max_read_len(), None);
+    }
 
-// Commands
-//const CMD_GET_ADVERTISEMENT: u8 = 0;
-//const CMD_SEND_ERROR_LIST: u8 = 1;
+    #[test]
+    fn test_sequence_number_wraps() {
+        let mut shub = fake_hub();
+        for _ in 0..300 {
+            shub.send_packet(u8::from(Channel::HubControl), &[0xF9, 0])
+                .unwrap();
+        }
+        assert_eq!(
+            shub.sequence_number(u8::from(Channel::HubControl)),
+            Some(44)
+        );
+        let sent = shub.sent_packets();
+        assert_eq!(sent[255].as_slice()[3], 255);
+        assert_eq!(sent[256].as_slice()[3], 0);
+        assert_eq!(shub.sequence_number(0xFF), None);
+        assert!(matches!(
+            shub.send_packet(6, &[0xF9, 0]),
+            Err(WrapperError::UnknownChannel(6))
+        ));
+    }
 
-/// Responses
-const CMD_RESP_ADVERTISEMENT: u8 = 0;
-const CMD_RESP_ERROR_LIST: u8 = 1;
+    #[test]
+    fn test_sequence_gap() {
+        let mut shub = fake_hub();
+        let mut packet = LINEAR_ACCEL_PACKET;
+        for seq in [4, 5, 7] {
+            packet[3] = seq;
+            queue_packet(&mut shub, &packet);
+        }
+        shub.handle_all_messages(&mut FakeDelay {}, 1);
+        let gap = shub.check_sequence().unwrap_err();
+        assert!(matches!(
+            gap,
+            WrapperError::SequenceGap {
+                channel: 3,
+                expected: 6,
+                received: 7
+            }
+        ));
+        assert_eq!(
+            format!("{}", gap),
+            "sequence gap on channel 3: expected 6, received 7"
+        );
+        assert!(shub.check_sequence().is_ok());
+    }
+
+    #[test]
+    fn test_multiple_instances() {
+        use core::cell::RefCell;
+        use embedded_hal_bus::i2c::RefCellDevice;
 
-/// SHTP constants
+        // two hubs on one bus, with SA0 low and high
+        let bus = RefCell::new(FakeI2cPort::new());
+        let mut first = BNO080::new_with_interface(I2cInterface::with_address(
+            RefCellDevice::new(&bus),
+            I2cAddress::Default,
+        ));
+        let mut second =
+            BNO080::new_with_interface(I2cInterface::with_address(
+                RefCellDevice::new(&bus),
+                I2cAddress::Alternate,
+            ));
+        first.enable_rotation_vector(10).unwrap();
+        first.enable_linear_accel(10).unwrap();
+        second.enable_rotation_vector(10).unwrap();
+        // each driver numbers its own packets
+        let sent = &bus.borrow().sent_packets;
+        assert_eq!(sent.len(), 3);
+        assert_eq!((sent[0].addr, sent[0].as_slice()[3]), (DEFAULT_ADDRESS, 0));
+        assert_eq!((sent[1].addr, sent[1].as_slice()[3]), (DEFAULT_ADDRESS, 1));
+        assert_eq!(
+            (sent[2].addr, sent[2].as_slice()[3]),
+            (ALTERNATE_ADDRESS, 0)
+        );
+        assert!(!second.is_report_enabled(0x04));
 
-/// Report ID for Product ID request
-const SHUB_PROD_ID_REQ: u8 = 0xF9;
-/// Report ID for Product ID response
-const SHUB_PROD_ID_RESP: u8 = 0xF8;
-const SHUB_GET_FEATURE_RESP: u8 = 0xFC;
-const SHUB_REPORT_SET_FEATURE_CMD: u8 = 0xFD;
-// const SHUB_GET_FEATURE_REQ: u8 = 0xFE;
-// const SHUB_FORCE_SENSOR_FLUSH: u8 = 0xF0;
-const SHUB_COMMAND_RESP: u8 = 0xF1;
-//const SHUB_COMMAND_REQ:u8 =  0xF2;
+        // two hubs on separate buses, each with its own sequence numbers
+        let mut left = fake_hub();
+        let mut right = BNO080::new_with_interface(I2cInterface::new(
+            FakeI2cPort::new(),
+            ALTERNATE_ADDRESS,
+        ));
+        let mut packet = LINEAR_ACCEL_PACKET;
+        for seq in [4, 5] {
+            packet[3] = seq;
+            queue_packet(&mut left, &packet);
+        }
+        let mut packet = ROTATION_VECTOR_PACKET;
+        packet[3] = 9;
+        queue_packet(&mut right, &packet);
+        left.handle_all_messages(&mut FakeDelay {}, 1);
+        right.handle_all_messages(&mut FakeDelay {}, 1);
+        // a shared sequence state would see a gap from 5 to 9
+        assert!(left.check_sequence().is_ok());
+        assert!(right.check_sequence().is_ok());
+        assert!(left.latest_linear_accel().is_some());
+        assert!(left.latest_rotation_vector().is_none());
+        assert!(right.latest_rotation_vector().is_some());
+        assert!(right.latest_linear_accel().is_none());
+    }
 
-// some mysterious responses we sometimes get:
-// 0x78, 0x7C
+    #[test]
+    fn test_unknown_report_hook() {
+        use core::sync::atomic::{AtomicU8, Ordering};
+        static UNKNOWN_ID: AtomicU8 = AtomicU8::new(0);
+        fn hook(report_id: u8, payload: &[u8]) {
+            assert_eq!(payload.len(), 4);
+            UNKNOWN_ID.store(report_id, Ordering::Relaxed);
+        }
 
-/// Report IDs from SH2 Reference Manual:
-// 0x01 accelerometer (m/s^2 including gravity): Q point 8
-// 0x02 gyroscope calibrated (rad/s): Q point 9
-// 0x03 mag field calibrated (uTesla): Q point 4
-/// Linear acceleration (m/s^2 minus gravity): Q point 8
-const SENSOR_REPORTID_LINEAR_ACCEL: u8 = 0x04;
-
-/// Unit quaternion rotation vector, Q point 12, with heading accuracy estimate (radians)
-const SENSOR_REPORTID_ROTATION_VECTOR: u8 = 0x05;
-// const SENSOR_REPORTID_GRAVITY: u8 = 0x06; // Q point 8
-/// Gyroscope uncalibrated (rad/s): Q point 9
-const SENSOR_REPORTID_GYRO: u8 = 0x07;
-// 0x08 game rotation vector : Q point 14
-// 0x09 geomagnetic rotation vector: Q point 14 for quaternion, Q point 12 for heading accuracy
-// 0x0A pressure (hectopascals) from external baro: Q point 20
-// 0x0B ambient light (lux) from external sensor: Q point 8
-// 0x0C humidity (percent) from external sensor: Q point 8
-// 0x0D proximity (centimeters) from external sensor: Q point 4
-// 0x0E temperature (degrees C) from external sensor: Q point 7
-
-/// executable/device channel responses
-/// Figure 1-27: SHTP executable commands and response
-// const EXECUTABLE_DEVICE_CMD_UNKNOWN: u8 =  0;
-const EXECUTABLE_DEVICE_CMD_RESET: u8 = 1;
-//const EXECUTABLE_DEVICE_CMD_ON: u8 =   2;
-//const EXECUTABLE_DEVICE_CMD_SLEEP =  3;
-
-/// Response to CMD_RESET
-const EXECUTABLE_DEVICE_RESP_RESET_COMPLETE: u8 = 1;
+        let packet: [u8; 23] = [
+            23, 0, 3, 0, // header
+            0xFB, 0, 0, 0, 0, // base timestamp
+            0x04, 0x01, 0x03, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x7E,
+            0x01, 0x00, 0x00, // unknown report
+        ];
+        let mut shub = fake_hub();
+        shub.set_unknown_report_hook(Some(hook));
+        queue_packet(&mut shub, &packet);
+        shub.handle_all_messages(&mut FakeDelay {}, 1);
+        assert_eq!(UNKNOWN_ID.load(Ordering::Relaxed), 0x7E);
+        assert_eq!(shub.reports_available(), 1);
+    }
 
-/// Commands and subcommands
-const SH2_INIT_UNSOLICITED: u8 = 0x80;
-const SH2_CMD_INITIALIZE: u8 = 4;
-const SH2_INIT_SYSTEM: u8 = 1;
-const SH2_STARTUP_INIT_UNSOLICITED: u8 =
-    SH2_CMD_INITIALIZE | SH2_INIT_UNSOLICITED;
+    #[test]
+    fn test_raw_packets() {
+        let mut shub = fake_hub();
+        assert_eq!(
+            shub.send_raw(u8::from(Channel::HubControl), &[0xF9, 0])
+                .unwrap(),
+            6
+        );
+        assert_eq!(shub.sent_packets()[0].as_slice(), [6, 0, 2, 0, 0xF9, 0]);
 
-#[cfg(test)]
-mod tests {
-    // use super::*;
-    // use crate::interface::i2c::DEFAULT_ADDRESS;
-    // use crate::interface::mock_i2c_port::FakeI2cPort;
-    // use crate::wrapper::{q14_to_f32, BNO080, Q14_SCALE};
-    use crate::wrapper::{q14_to_f32, Q14_SCALE};
+        queue_packet(&mut shub, &LINEAR_ACCEL_PACKET);
+        let (header, payload) =
+            shub.receive_raw(&mut FakeDelay {}, 1).unwrap().unwrap();
+        assert_eq!(
+            header,
+            ShtpHeader {
+                length: 19,
+                continuation: false,
+                channel: 3,
+                sequence: 0,
+            }
+        );
+        assert_eq!(payload, &LINEAR_ACCEL_PACKET[4..]);
+        // raw packets are not handled
+        assert!(shub.latest_linear_accel().is_none());
+        assert!(shub.receive_raw(&mut FakeDelay {}, 1).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_retry_policy() {
+        let mut shub = fake_hub();
+        shub.sensor_interface.i2c_port_mut().fail_transactions = 1;
+        assert!(shub.enable_rotation_vector(10).is_err());
+
+        shub.set_retry_policy(RetryPolicy {
+            max_attempts: 3,
+            initial_backoff_ms: 1,
+        });
+        shub.sensor_interface.i2c_port_mut().fail_transactions = 2;
+        assert!(shub.enable_rotation_vector(10).is_ok());
+        assert_eq!(shub.sent_packets().len(), 1);
 
-    // use crate::interface::I2cInterface;
+        shub.sensor_interface.i2c_port_mut().fail_transactions = 3;
+        assert!(shub.enable_rotation_vector(10).is_err());
 
-    fn f32_to_q14(input: f32) -> i16 {
-        (input / Q14_SCALE) as i16
+        let stats = shub.stats();
+        assert_eq!(stats.retries, 4);
+        assert_eq!(stats.comm_errors, 2);
+        assert_eq!(stats.packets_sent[2], 1);
     }
 
     #[test]
-    fn test_qval_conversions() {
-        let q_val = f32_to_q14(0.5);
-        let float_val = q14_to_f32(q_val);
-        assert_eq!(float_val, 0.5);
+    fn test_comm_stats() {
+        let mut shub = fake_hub();
+        queue_packet(&mut shub, &ROTATION_VECTOR_PACKET);
+        let mut unknown_report = LINEAR_ACCEL_PACKET;
+        unknown_report[9] = 0x7E;
+        queue_packet(&mut shub, &unknown_report);
+        assert_eq!(shub.handle_all_messages(&mut FakeDelay {}, 1), 2);
+        assert!(shub.enable_rotation_vector(10).is_ok());
+
+        let stats = shub.stats();
+        assert_eq!(stats.packets_received[3], 2);
+        assert_eq!(stats.packets_sent[2], 1);
+        assert_eq!(stats.decode_errors, 1);
+        // the read that ended handle_all_messages
+        assert_eq!(stats.zero_length_reads, 1);
+        assert_eq!(stats.retries, 0);
+
+        shub.reset_stats();
+        assert_eq!(shub.stats(), CommStats::default());
     }
 
     // #[test]
@@ -869,30 +6681,46 @@ mod tests {
     //     assert_eq!(msg_count, 1, "wrong msg_count");
     // }
 
+    // a sensor report packet carrying a rotation vector
+    const ROTATION_VECTOR_PACKET: [u8; 23] = [
+        23, 0, 3, 0, // header
+        0xFB, 0, 0, 0, 0, // base timestamp
+        0x05, 0x01, 0x03, 0x00, // report header
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x20, 0x00, 0x20, 0x00, 0x08,
+    ];
+
+    // a sensor report packet carrying linear acceleration
+    const LINEAR_ACCEL_PACKET: [u8; 19] = [
+        19, 0, 3, 0, // header
+        0xFB, 0, 0, 0, 0, // base timestamp
+        0x04, 0x01, 0x03, 0x00, // report header
+        0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+    ];
+
     // Actual advertising packet received from sensor:
-    // pub const ADVERTISING_PACKET_FULL: [u8; 276] = [
-    //     0x14, 0x81, 0x00, 0x01, 0x00, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00, 0x80,
-    //     0x06, 0x31, 0x2e, 0x30, 0x2e, 0x30, 0x00, 0x02, 0x02, 0x00, 0x01, 0x03,
-    //     0x02, 0xff, 0x7f, 0x04, 0x02, 0x00, 0x01, 0x05, 0x02, 0xff, 0x7f, 0x08,
-    //     0x05, 0x53, 0x48, 0x54, 0x50, 0x00, 0x06, 0x01, 0x00, 0x09, 0x08, 0x63,
-    //     0x6f, 0x6e, 0x74, 0x72, 0x6f, 0x6c, 0x00, 0x01, 0x04, 0x01, 0x00, 0x00,
-    //     0x00, 0x08, 0x0b, 0x65, 0x78, 0x65, 0x63, 0x75, 0x74, 0x61, 0x62, 0x6c,
-    //     0x65, 0x00, 0x06, 0x01, 0x01, 0x09, 0x07, 0x64, 0x65, 0x76, 0x69, 0x63,
-    //     0x65, 0x00, 0x01, 0x04, 0x02, 0x00, 0x00, 0x00, 0x08, 0x0a, 0x73, 0x65,
-    //     0x6e, 0x73, 0x6f, 0x72, 0x68, 0x75, 0x62, 0x00, 0x06, 0x01, 0x02, 0x09,
-    //     0x08, 0x63, 0x6f, 0x6e, 0x74, 0x72, 0x6f, 0x6c, 0x00, 0x06, 0x01, 0x03,
-    //     0x09, 0x0c, 0x69, 0x6e, 0x70, 0x75, 0x74, 0x4e, 0x6f, 0x72, 0x6d, 0x61,
-    //     0x6c, 0x00, 0x07, 0x01, 0x04, 0x09, 0x0a, 0x69, 0x6e, 0x70, 0x75, 0x74,
-    //     0x57, 0x61, 0x6b, 0x65, 0x00, 0x06, 0x01, 0x05, 0x09, 0x0c, 0x69, 0x6e,
-    //     0x70, 0x75, 0x74, 0x47, 0x79, 0x72, 0x6f, 0x52, 0x76, 0x00, 0x80, 0x06,
-    //     0x31, 0x2e, 0x31, 0x2e, 0x30, 0x00, 0x81, 0x64, 0xf8, 0x10, 0xf5, 0x04,
-    //     0xf3, 0x10, 0xf1, 0x10, 0xfb, 0x05, 0xfa, 0x05, 0xfc, 0x11, 0xef, 0x02,
-    //     0x01, 0x0a, 0x02, 0x0a, 0x03, 0x0a, 0x04, 0x0a, 0x05, 0x0e, 0x06, 0x0a,
-    //     0x07, 0x10, 0x08, 0x0c, 0x09, 0x0e, 0x0a, 0x08, 0x0b, 0x08, 0x0c, 0x06,
-    //     0x0d, 0x06, 0x0e, 0x06, 0x0f, 0x10, 0x10, 0x05, 0x11, 0x0c, 0x12, 0x06,
-    //     0x13, 0x06, 0x14, 0x10, 0x15, 0x10, 0x16, 0x10, 0x17, 0x00, 0x18, 0x08,
-    //     0x19, 0x06, 0x1a, 0x00, 0x1b, 0x00, 0x1c, 0x06, 0x1d, 0x00, 0x1e, 0x10,
-    //     0x1f, 0x00, 0x20, 0x00, 0x21, 0x00, 0x22, 0x00, 0x23, 0x00, 0x24, 0x00,
-    //     0x25, 0x00, 0x26, 0x00, 0x27, 0x00, 0x28, 0x0e, 0x29, 0x0c, 0x2a, 0x0e,
-    // ];
+    const ADVERTISING_PACKET_FULL: [u8; 276] = [
+        0x14, 0x81, 0x00, 0x01, 0x00, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00, 0x80,
+        0x06, 0x31, 0x2e, 0x30, 0x2e, 0x30, 0x00, 0x02, 0x02, 0x00, 0x01, 0x03,
+        0x02, 0xff, 0x7f, 0x04, 0x02, 0x00, 0x01, 0x05, 0x02, 0xff, 0x7f, 0x08,
+        0x05, 0x53, 0x48, 0x54, 0x50, 0x00, 0x06, 0x01, 0x00, 0x09, 0x08, 0x63,
+        0x6f, 0x6e, 0x74, 0x72, 0x6f, 0x6c, 0x00, 0x01, 0x04, 0x01, 0x00, 0x00,
+        0x00, 0x08, 0x0b, 0x65, 0x78, 0x65, 0x63, 0x75, 0x74, 0x61, 0x62, 0x6c,
+        0x65, 0x00, 0x06, 0x01, 0x01, 0x09, 0x07, 0x64, 0x65, 0x76, 0x69, 0x63,
+        0x65, 0x00, 0x01, 0x04, 0x02, 0x00, 0x00, 0x00, 0x08, 0x0a, 0x73, 0x65,
+        0x6e, 0x73, 0x6f, 0x72, 0x68, 0x75, 0x62, 0x00, 0x06, 0x01, 0x02, 0x09,
+        0x08, 0x63, 0x6f, 0x6e, 0x74, 0x72, 0x6f, 0x6c, 0x00, 0x06, 0x01, 0x03,
+        0x09, 0x0c, 0x69, 0x6e, 0x70, 0x75, 0x74, 0x4e, 0x6f, 0x72, 0x6d, 0x61,
+        0x6c, 0x00, 0x07, 0x01, 0x04, 0x09, 0x0a, 0x69, 0x6e, 0x70, 0x75, 0x74,
+        0x57, 0x61, 0x6b, 0x65, 0x00, 0x06, 0x01, 0x05, 0x09, 0x0c, 0x69, 0x6e,
+        0x70, 0x75, 0x74, 0x47, 0x79, 0x72, 0x6f, 0x52, 0x76, 0x00, 0x80, 0x06,
+        0x31, 0x2e, 0x31, 0x2e, 0x30, 0x00, 0x81, 0x64, 0xf8, 0x10, 0xf5, 0x04,
+        0xf3, 0x10, 0xf1, 0x10, 0xfb, 0x05, 0xfa, 0x05, 0xfc, 0x11, 0xef, 0x02,
+        0x01, 0x0a, 0x02, 0x0a, 0x03, 0x0a, 0x04, 0x0a, 0x05, 0x0e, 0x06, 0x0a,
+        0x07, 0x10, 0x08, 0x0c, 0x09, 0x0e, 0x0a, 0x08, 0x0b, 0x08, 0x0c, 0x06,
+        0x0d, 0x06, 0x0e, 0x06, 0x0f, 0x10, 0x10, 0x05, 0x11, 0x0c, 0x12, 0x06,
+        0x13, 0x06, 0x14, 0x10, 0x15, 0x10, 0x16, 0x10, 0x17, 0x00, 0x18, 0x08,
+        0x19, 0x06, 0x1a, 0x00, 0x1b, 0x00, 0x1c, 0x06, 0x1d, 0x00, 0x1e, 0x10,
+        0x1f, 0x00, 0x20, 0x00, 0x21, 0x00, 0x22, 0x00, 0x23, 0x00, 0x24, 0x00,
+        0x25, 0x00, 0x26, 0x00, 0x27, 0x00, 0x28, 0x0e, 0x29, 0x0c, 0x2a, 0x0e,
+    ];
 }